@@ -0,0 +1,67 @@
+//! Filtering pipeline benchmarks on synthetic organizations, to tell whether evaluation or the
+//! API dominates a large scan. Run with `cargo bench`.
+
+use chrono::{Duration, Utc};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::{json, Value};
+use tfe_cleanup::config::Config;
+use tfe_cleanup::staleness::{self, Policy};
+
+/// `count` workspaces spread over a few organizations, with a mix of ages, VCS connections,
+/// opt-out markers and names the policies below match.
+fn workspaces(count: usize) -> Vec<Value> {
+    let now = Utc::now();
+    (0..count)
+        .map(|i| {
+            let name = match i % 4 {
+                0 => format!("app-pr-{}", i),
+                1 => format!("prod-service-{}", i),
+                2 => format!("sandbox-{}", i),
+                _ => format!("team-{}-shared", i),
+            };
+            let last_activity = (i % 7 != 0).then(|| (now - Duration::days((i % 400) as i64)).to_rfc3339());
+            json!({
+                "id": format!("ws-{}", i),
+                "attributes": {
+                    "name": name,
+                    "description": if i % 50 == 0 { "Conference demo [keep]" } else { "" },
+                    "tag-names": ["team:platform", format!("cost-center:{}", i % 20)],
+                    "created-at": (now - Duration::days(500)).to_rfc3339(),
+                    "last-activity-at": last_activity,
+                    "vcs-repo": if i % 3 == 0 { json!({ "identifier": "acme/infra" }) } else { Value::Null },
+                },
+                "relationships": { "organization": { "data": { "id": format!("org-{}", i % 5) } } }
+            })
+        })
+        .collect()
+}
+
+fn policies() -> Vec<(&'static str, Policy)> {
+    let config = Config {
+        no_vcs_stale_after_days: Some(30),
+        exclude: vec!["^prod-".to_string(), "-shared$".to_string()],
+        include: vec![r"^(app-pr-\d+|sandbox-.*)$".to_string()],
+        ..Config::default()
+    };
+    vec![
+        ("default", Policy::default()),
+        ("patterns", Policy::from_config(&config).expect("benchmark patterns are valid")),
+    ]
+}
+
+fn filtering(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filtering");
+    for count in [1_000, 10_000] {
+        let workspaces = workspaces(count);
+        group.throughput(Throughput::Elements(count as u64));
+        for (name, policy) in policies() {
+            group.bench_with_input(BenchmarkId::new(name, count), &workspaces, |b, workspaces| {
+                b.iter(|| staleness::filter_old_inactive_accounts(black_box(workspaces), &policy))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, filtering);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+## Spaltenüberschriften des Berichts
+
+column-name = Name
+column-last-activity = Letzte Aktivität
+column-org = Organisation
+column-id = Workspace-ID
+column-project = Projekt
+column-last-run = Letzter Run
+column-resources = Ressourcen
+column-cost = Geschätzte monatliche Kosten
+column-owner = Verantwortliche
+column-created = Erstellt
+column-updated = Geändert
+column-terraform-version = Terraform-Version
+column-vcs-repo = VCS-Repository
+column-execution-mode = Ausführungsmodus
+column-tags = Tags
+column-locked = Gesperrt
+column-description = Beschreibung
+column-inactive-for = Inaktiv seit
+column-last-activity-local = Letzte Aktivität (lokal)
+column-staleness-basis = Grundlage der Bewertung
+column-resource-types = Ressourcentypen
+column-last-changed-by = Zuletzt geändert von
+column-contacts = Kontakte der Verantwortlichen
+
+## HTML-Bericht
+
+html-title = Veraltete TFE-Workspaces
+html-summary = { $stale } von { $total } Workspaces seit { $days } Tagen ohne Aktivität, erstellt { $generated }.
+html-errors = Fehler
+html-errors-intro = Diese Organisationen konnten nicht gescannt werden und fehlen in den Ergebnissen.
+html-secrets = Mögliche Secrets im Klartext
+html-secrets-intro = Nicht als sensibel markierte Variablen, deren Werte wie Zugangsdaten aussehen.
+
+## Bericht auf stdout
+
+report-stale = Workspaces älter als { $days } Tage ohne Aktivität:
+report-stale-workspace = { $workspace }  letzte Aktivität { $when }
+report-no-activity-data = Workspaces ohne Aktivitätsdaten, erstellt vor mehr als { $days } Tagen:
+report-no-activity-data-workspace = { $workspace }  keine Aktivitätsdaten, erstellt { $when }
+report-opted-out = Workspaces, die ihre Verantwortlichen von der Bereinigung ausgenommen haben:
+report-errors = Fehler: Diese Organisationen konnten nicht gescannt werden und fehlen in den Ergebnissen:
+report-ages = Tage seit der letzten Aktivität der gescannten Workspaces:
+report-secrets = Mögliche Secrets im Klartext in nicht sensiblen Variablen:
+
+## Eingabeaufforderungen; die Antworten bleiben y, n und m
+
+prompt-cleanup = Terraform-Bereinigung durchführen? (y/n, oder m, um stattdessen zu migrieren):
+prompt-new-name = Neuer Name:
+prompt-target-project = Zielprojekt:
+prompt-revoke-grants = Diese { $count } Berechtigungen entziehen? (y/n):
+prompt-move-workspaces = Diese { $count } Workspaces in das Projekt { $project } verschieben? (y/n):
+prompt-queue-destroy = { $count } Destroy-Runs einreihen? (y/n):
+
+## Zeitspannen, z. B. „3 Wochen“ in Berichten und „vor 3 Wochen“ neben Zeitstempeln
+
+age-days = { $count ->
+    [one] { $count } Tag
+   *[other] { $count } Tage
+}
+age-weeks = { $count ->
+    [one] { $count } Woche
+   *[other] { $count } Wochen
+}
+age-months = { $count ->
+    [one] { $count } Monat
+   *[other] { $count } Monate
+}
+age-years = { $count ->
+    [one] { $count } Jahr
+   *[other] { $count } Jahre
+}
+ago-days = { $count ->
+    [one] vor { $count } Tag
+   *[other] vor { $count } Tagen
+}
+ago-weeks = { $count ->
+    [one] vor { $count } Woche
+   *[other] vor { $count } Wochen
+}
+ago-months = { $count ->
+    [one] vor { $count } Monat
+   *[other] vor { $count } Monaten
+}
+ago-years = { $count ->
+    [one] vor { $count } Jahr
+   *[other] vor { $count } Jahren
+}
+age-unknown = unbekannt lange
+
+## Ausgaben der Befehle
+
+csv-created = Die CSV-Datei '{ $file }' wurde erstellt.
+nothing-queued = Nichts eingereiht.
+nothing-revoked = Nichts entzogen.
+nothing-moved = Nichts verschoben.
+moved-workspaces = { $moved } Workspaces verschoben, { $failed } fehlgeschlagen.
+default-project-by-owner = Workspaces im Standardprojekt, nach Verantwortlichen:
+migrate-workspace = Migriere { $workspace }, { $age } ohne Aktivität (leer lassen, um nichts zu ändern)
+migrate-skipped = { $workspace } übersprungen
+secret-variable = { $category }-Variable
+plan-exports-would-delete = { $count } Plan-Exporte würden gelöscht.
+plan-exports-deleted = { $count } Plan-Exporte gelöscht.
+versions-would-prune = Würde { $states } State-Versionen ({ $size }) und { $configurations } Konfigurationsversionen (Größe nicht gemeldet) entfernen.
+versions-pruned = { $states } State-Versionen ({ $size }) und { $configurations } Konfigurationsversionen (Größe nicht gemeldet) entfernt.
+providers-would-delete = { $versions } Provider-Versionen und { $keys } GPG-Schlüssel würden gelöscht.
+providers-deleted = { $versions } Provider-Versionen und { $keys } GPG-Schlüssel gelöscht.
+stale-secrets = Sicherheit: Sensible Variablen, die seit { $days } Tagen nicht rotiert wurden:
+stale-secret = { $workspace }: { $key } ({ $category }, zuletzt geändert { $changed })
+no-vcs = Workspaces ohne VCS-Verbindung und ohne API-Runs in { $days } Tagen:
+workspace-last-activity = { $workspace } (letzte Aktivität { $when })
+last-activity-never = nie
+deleted-branches = Workspaces, die Branches folgen, die es nicht mehr gibt:
+deleted-branches-skipped = { $count } Workspaces übersprungen: Ihr VCS-Anbieter wird nicht unterstützt oder hat kein Token.
+manifest-differences = Workspaces, die vom Manifest { $path } abweichen:
+manifest-unlisted = { $workspace }: { $difference } (letzte Aktivität { $when })
+stale-grants = Admin-Berechtigungen für Teams, die es nicht mehr gibt oder die seit { $days } Tagen ungenutzt sind:
+stale-grant = { $workspace }: Team { $id } { $team } ({ $reason })
+grant-revoked = Admin-Zugriff von Team { $id } auf { $workspace } entzogen
+
+## Löschfenster und Change Requests
+
+window-closed-nothing-queued = Außerhalb der erlaubten Löschfenster; nichts wurde eingereiht.
+window-closed-queued = Außerhalb der erlaubten Löschfenster; { $count } Workspaces sind in '{ $file }' eingereiht.
+window-next-open = Das nächste Fenster öffnet um { $at }.
+window-waiting = Warte auf das Löschfenster, das um { $at } öffnet...
+change-request-created = Change Request { $number } erstellt; warte auf Genehmigung...
+change-request-approved = Change Request { $number } genehmigt.
+
+## Bereinigung
+
+cleanup-partial-scan = Keine Bereinigung: Nicht jede Organisation konnte gescannt werden.
+cleanup-script-written = Die Befehle für { $count } Workspaces wurden nach { $path } geschrieben; nichts wurde geändert.
+cleanup-skipped-by-flag = Bereinigung übersprungen (--no-cleanup).
+cleanup-proceeding = Starte die Terraform-Bereinigung...
+cleanup-skipped = Bereinigung übersprungen. Sie kann später manuell ausgeführt werden.
+cleanup-workspace = Bereinige den Workspace für das Konto: { $workspace }
+already-handled = Bereits erledigt { $workspace }: { $reason }
+handled-action = { $action } am { $at }
+handled-gone = existiert nicht mehr
+holding-back = { $workspace } zurückgehalten: von { $streak } aufeinanderfolgenden Scans markiert, { $required } erforderlich
+leaving-alone = { $workspace } bleibt unberührt: Team { $team } hat keinen Admin-Zugriff darauf
+org-failures = { $threshold } aufeinanderfolgende Workspaces von { $org } fehlgeschlagen; keine weiteren Aktionen in dieser Organisation.
+cleanup-outcome = { $completed } bereinigt ({ $deleted } gelöscht, { $reclaimed } State freigegeben), { $handled } bereits erledigt, { $stopped } angehalten, { $failed } fehlgeschlagen.
+cleanup-out-of-scope = { $count } von Hand eingereihte bleiben unberührt, da Team { $team } keinen Admin-Zugriff darauf hat.
+cleanup-held = { $count } zurückgehalten, bis sie in { $streak } aufeinanderfolgenden Scans veraltet waren.
+circuit-breaker-tripped = Schutzschalter für { $org } nach { $threshold } aufeinanderfolgenden Fehlern ausgelöst; { $skipped } Workspaces unberührt.
+
+## Zusammenfassung des Laufs; die Bezeichnungen werden auf die längste aufgefüllt
+
+summary-title = Zusammenfassung
+summary-duration = Dauer
+summary-api-calls = API-Aufrufe
+summary-workspaces = Workspaces
+summary-rate-limit-sleeps = { $count } Pausen wegen Ratenbegrenzung
+summary-workspace-counts = { $scanned } gescannt, { $flagged } markiert, { $excluded } ausgenommen, { $deleted } gelöscht, { $failed } fehlgeschlagen
+summary-reclaimed = Freigegeben
@@ -0,0 +1,158 @@
+## Report column headers, also used to find columns when reading a report back
+
+column-name = Name
+column-last-activity = Last Activity
+column-org = Organization
+column-id = Workspace ID
+column-project = Project
+column-last-run = Last Run
+column-resources = Resources
+column-cost = Estimated Monthly Cost
+column-owner = Owners
+column-created = Created
+column-updated = Updated
+column-terraform-version = Terraform Version
+column-vcs-repo = VCS Repository
+column-execution-mode = Execution Mode
+column-tags = Tags
+column-locked = Locked
+column-description = Description
+column-inactive-for = Inactive For
+column-last-activity-local = Last Activity (Local)
+column-staleness-basis = Staleness Basis
+column-resource-types = Resource Types
+column-last-changed-by = Last Changed By
+column-contacts = Owner Contacts
+
+## HTML report
+
+html-title = Stale TFE workspaces
+html-summary = { $stale } of { $total } workspaces without activity for { $days } days, generated { $generated }.
+html-errors = Errors
+html-errors-intro = These organizations could not be scanned and are missing from the results.
+html-secrets = Potential plaintext secrets
+html-secrets-intro = Non-sensitive variables whose values look like credentials.
+
+## Stdout report
+
+report-stale = Workspaces older than { $days } days with no activity:
+report-stale-workspace = { $workspace }  last activity { $when }
+report-no-activity-data = Workspaces with no activity data, created more than { $days } days ago:
+report-no-activity-data-workspace = { $workspace }  no activity data, created { $when }
+report-opted-out = Workspaces opted out of cleanup by their owners:
+report-errors = Errors: these organizations could not be scanned and are missing from the results:
+report-ages = Days since last activity of the workspaces scanned:
+report-secrets = Potential plaintext secrets in non-sensitive variables:
+
+## Prompts; answers stay y, n and m in every language
+
+prompt-cleanup = Do you want to perform Terraform cleanup? (y/n, or m to migrate instead):
+prompt-new-name = New name:
+prompt-target-project = Target project:
+prompt-revoke-grants = Revoke these { $count } grants? (y/n):
+prompt-move-workspaces = Move these { $count } workspaces to project { $project }? (y/n):
+prompt-queue-destroy = Queue { $count } destroy runs? (y/n):
+
+## Ages, e.g. "3 weeks" in reports and "3 weeks ago" next to timestamps
+
+age-days = { $count ->
+    [one] { $count } day
+   *[other] { $count } days
+}
+age-weeks = { $count ->
+    [one] { $count } week
+   *[other] { $count } weeks
+}
+age-months = { $count ->
+    [one] { $count } month
+   *[other] { $count } months
+}
+age-years = { $count ->
+    [one] { $count } year
+   *[other] { $count } years
+}
+ago-days = { $count ->
+    [one] { $count } day ago
+   *[other] { $count } days ago
+}
+ago-weeks = { $count ->
+    [one] { $count } week ago
+   *[other] { $count } weeks ago
+}
+ago-months = { $count ->
+    [one] { $count } month ago
+   *[other] { $count } months ago
+}
+ago-years = { $count ->
+    [one] { $count } year ago
+   *[other] { $count } years ago
+}
+age-unknown = an unknown time
+
+## Command output
+
+csv-created = CSV file '{ $file }' has been created.
+nothing-queued = Nothing queued.
+nothing-revoked = Nothing revoked.
+nothing-moved = Nothing moved.
+moved-workspaces = Moved { $moved } workspaces, { $failed } failed.
+default-project-by-owner = Workspaces in the default project, by owner:
+migrate-workspace = Migrating { $workspace }, stale for { $age } (leave blank to keep as is)
+migrate-skipped = Skipped { $workspace }
+secret-variable = { $category } variable
+plan-exports-would-delete = { $count } plan exports would be deleted.
+plan-exports-deleted = Deleted { $count } plan exports.
+versions-would-prune = Would prune { $states } state versions ({ $size }) and { $configurations } configuration versions (size not reported).
+versions-pruned = Pruned { $states } state versions ({ $size }) and { $configurations } configuration versions (size not reported).
+providers-would-delete = { $versions } provider versions and { $keys } GPG keys would be deleted.
+providers-deleted = Deleted { $versions } provider versions and { $keys } GPG keys.
+stale-secrets = Security: sensitive variables not rotated in { $days } days:
+stale-secret = { $workspace }: { $key } ({ $category }, last changed { $changed })
+no-vcs = Workspaces without a VCS connection and no API-driven runs in { $days } days:
+workspace-last-activity = { $workspace } (last activity { $when })
+last-activity-never = never
+deleted-branches = Workspaces tracking branches that no longer exist:
+deleted-branches-skipped = { $count } workspaces skipped: their VCS provider is unsupported or has no token.
+manifest-differences = Workspaces differing from the manifest { $path }:
+manifest-unlisted = { $workspace }: { $difference } (last activity { $when })
+stale-grants = Admin grants to teams that no longer exist or were unused for { $days } days:
+stale-grant = { $workspace }: team { $id } { $team } ({ $reason })
+grant-revoked = Revoked admin access of team { $id } to { $workspace }
+
+## Deletion windows and change requests
+
+window-closed-nothing-queued = Outside the allowed deletion windows; nothing was queued.
+window-closed-queued = Outside the allowed deletion windows; { $count } workspaces are queued in '{ $file }'.
+window-next-open = Next window opens at { $at }.
+window-waiting = Waiting for the deletion window opening at { $at }...
+change-request-created = Created change request { $number }; waiting for approval...
+change-request-approved = Change request { $number } approved.
+
+## Cleanup
+
+cleanup-partial-scan = Not cleaning up: not every organization could be scanned.
+cleanup-script-written = Wrote the commands for { $count } workspaces to { $path }; nothing was changed.
+cleanup-skipped-by-flag = Cleanup skipped (--no-cleanup).
+cleanup-proceeding = Proceeding with Terraform cleanup...
+cleanup-skipped = Cleanup skipped. You can run the cleanup later manually.
+cleanup-workspace = Cleaning up workspace for account: { $workspace }
+already-handled = Already handled { $workspace }: { $reason }
+handled-action = { $action } at { $at }
+handled-gone = no longer exists
+holding-back = Holding back { $workspace }: flagged by { $streak } consecutive scans, { $required } required
+leaving-alone = Leaving { $workspace } alone: team { $team } has no admin access to it
+org-failures = { $threshold } consecutive workspaces of { $org } failed; no further actions in that organization.
+cleanup-outcome = { $completed } cleaned up ({ $deleted } deleted, { $reclaimed } of state reclaimed), { $handled } already handled, { $stopped } stopped, { $failed } failed.
+cleanup-out-of-scope = { $count } queued by hand left alone, as team { $team } has no admin access to them.
+cleanup-held = { $count } held back until they have been stale for { $streak } consecutive scans.
+circuit-breaker-tripped = Circuit breaker tripped for { $org } after { $threshold } consecutive failures; { $skipped } workspaces left untouched.
+
+## Run summary; labels are padded to the longest
+
+summary-title = Run summary
+summary-duration = Duration
+summary-api-calls = API calls
+summary-workspaces = Workspaces
+summary-rate-limit-sleeps = { $count } rate-limit sleeps
+summary-workspace-counts = { $scanned } scanned, { $flagged } flagged, { $excluded } excluded, { $deleted } deleted, { $failed } failed
+summary-reclaimed = Reclaimed
@@ -0,0 +1,663 @@
+use crate::archive::{self, ArchiveOutcome};
+use crate::config::{ActionKind, ActionsConfig, NotificationConfig};
+use crate::delete::{self, DeleteOutcome};
+use crate::events::EventEmitter;
+use crate::history::{self, History};
+use crate::kill_switch::KillSwitch;
+use crate::tfe::{self, ApiError, TfeClient};
+use crate::script::Shell;
+use crate::window::{self, DeletionWindow};
+use crate::{destroy, hibernate, notify, redact, staleness};
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Groups of stale workspaces that can be handled differently.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Stale,
+    NoActivityData,
+    NoVcs,
+}
+
+impl Category {
+    pub fn of(workspace: &Value) -> Category {
+        if staleness::lacks_activity_data(workspace) {
+            Category::NoActivityData
+        } else if !staleness::is_vcs_backed(workspace) {
+            Category::NoVcs
+        } else {
+            Category::Stale
+        }
+    }
+}
+
+/// Result of an action that didn't fail.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The action is done; the message says what happened.
+    Done(String),
+    /// The remaining actions must not run for this workspace, e.g. TFE refused a safe delete.
+    Stop(String),
+    /// The action had nothing to do, e.g. a notification without channels, and isn't recorded.
+    Skipped(String),
+}
+
+pub struct ActionContext<'a> {
+    pub client: &'a TfeClient,
+    pub history: &'a History,
+    pub events: Option<&'a EventEmitter>,
+    /// Checked before every destructive action; an engaged switch aborts the run.
+    pub kill_switch: Option<&'a KillSwitch>,
+    /// Also checked before every destructive action; the run stops once all have closed. Empty
+    /// when deletions are allowed at any time.
+    pub windows: &'a [DeletionWindow],
+}
+
+/// Something done to a stale workspace as one step of its category's pipeline.
+#[async_trait]
+pub trait Action: Send + Sync {
+    /// Name under which the action is recorded in the history, e.g. "deleted".
+    fn recorded_as(&self) -> &'static str;
+
+    /// Whether the action destroys something that can't be put back, e.g. the workspace or its
+    /// resources.
+    fn destructive(&self) -> bool {
+        false
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>>;
+
+    /// Commands of `shell` doing the same as `apply`, for `--emit-script`.
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+}
+
+fn workspace_id(workspace: &Value) -> Result<&str, Box<dyn Error + Send + Sync>> {
+    workspace["id"].as_str().ok_or_else(|| "workspace id unknown; include 'org' in the CSV".into())
+}
+
+fn workspace_name(workspace: &Value) -> &str {
+    workspace["attributes"]["name"].as_str().unwrap_or("")
+}
+
+/// Deletes through the safe-delete API. Where TFE has no safe delete, the workspace is deleted
+/// with `terraform workspace delete` if a terraform binary is given, or through the plain API if
+/// it reports no resources and `delete_if_empty` allows that; otherwise it fails. Workspaces
+/// without an organization can only be deleted with the binary.
+pub struct Delete {
+    pub terraform_bin: Option<PathBuf>,
+    /// Checking the resource count and deleting are two requests, so a run applying in between
+    /// leaves its resources orphaned; hence opt-in.
+    pub delete_if_empty: bool,
+}
+
+impl Delete {
+    fn terraform_delete(bin: &Path, name: &str) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let output = Command::new(bin).args(["workspace", "delete", name]).output()
+            .map_err(|e| format!("cannot run {}: {}", bin.display(), e))?;
+        if output.status.success() {
+            Ok(Outcome::Done(format!("Successfully deleted workspace for {}", name)))
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string().into())
+        }
+    }
+}
+
+fn no_org_error(name: &str) -> Box<dyn Error + Send + Sync> {
+    format!("{} has no organization; add an Organization column to the CSV, or pass --terraform-bin to delete it with the terraform CLI", name).into()
+}
+
+#[async_trait]
+impl Action for Delete {
+    fn recorded_as(&self) -> &'static str {
+        "deleted"
+    }
+
+    fn destructive(&self) -> bool {
+        true
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let (org, name) = (tfe::workspace_org(workspace), workspace_name(workspace));
+
+        // Without an organization the API can't address the workspace; only the CLI can
+        if org.is_empty() {
+            return match &self.terraform_bin {
+                Some(bin) => Delete::terraform_delete(bin, name),
+                None => Err(no_org_error(name)),
+            };
+        }
+
+        let outcome = match delete::safe_delete(context.client, org, name).await? {
+            DeleteOutcome::Unsupported => match &self.terraform_bin {
+                Some(bin) => return Delete::terraform_delete(bin, name),
+                None if self.delete_if_empty => delete::delete_if_empty(context.client, org, name).await?,
+                None => return Err(format!("TFE has no safe delete for {}/{}; pass --terraform-bin, or --delete-if-empty \
+                    to delete it through the plain API if it manages no resources", org, name).into()),
+            },
+            outcome => outcome,
+        };
+
+        match outcome {
+            DeleteOutcome::Deleted => Ok(Outcome::Done(format!("Successfully deleted workspace for {}", name))),
+            DeleteOutcome::Refused(reason) => {
+                eprintln!("  To remove it anyway, {}", delete::destroy_then_delete_hint(org, name));
+                Ok(Outcome::Stop(format!("TFE refused to delete {}/{}: {}", org, name, reason)))
+            }
+            DeleteOutcome::Unsupported => Err(format!("TFE cannot delete {}/{}", org, name).into()),
+        }
+    }
+
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let (org, name) = (tfe::workspace_org(workspace), workspace_name(workspace));
+        if org.is_empty() {
+            let bin = self.terraform_bin.as_ref().ok_or_else(|| no_org_error(name))?;
+            return Ok(vec![shell.run(&bin.to_string_lossy(), &["workspace", "delete", name])]);
+        }
+        let path = format!("/organizations/{}/workspaces/{}/actions/safe-delete", org, name);
+        Ok(vec![shell.api("POST", &path, Some(&json!({})))])
+    }
+}
+
+/// Locks the workspace so nobody can run it while its fate is decided.
+pub struct Lock;
+
+#[async_trait]
+impl Action for Lock {
+    fn recorded_as(&self) -> &'static str {
+        "locked"
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let path = format!("/workspaces/{}/actions/lock", workspace_id(workspace)?);
+        match context.client.post(&path, &lock_reason()).await {
+            Ok(_) => Ok(Outcome::Done(format!("Locked {}", workspace_name(workspace)))),
+            Err(e) => match e.downcast_ref::<ApiError>() {
+                Some(api_err) if api_err.status == StatusCode::CONFLICT => {
+                    Ok(Outcome::Done(format!("{} is already locked", workspace_name(workspace))))
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let path = format!("/workspaces/{}/actions/lock", workspace_id(workspace)?);
+        // Unlike `apply`, an already locked workspace fails the script
+        Ok(vec![shell.api("POST", &path, Some(&lock_reason()))])
+    }
+}
+
+fn lock_reason() -> Value {
+    json!({ "reason": "Stale workspace locked by tfe_cleanup" })
+}
+
+/// Adds a tag marking the workspace as stale.
+pub struct Tag {
+    pub tag: String,
+}
+
+#[async_trait]
+impl Action for Tag {
+    fn recorded_as(&self) -> &'static str {
+        "tagged"
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let path = format!("/workspaces/{}/relationships/tags", workspace_id(workspace)?);
+        context.client.post(&path, &self.body()).await?;
+        Ok(Outcome::Done(format!("Tagged {} with {}", workspace_name(workspace), self.tag)))
+    }
+
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let path = format!("/workspaces/{}/relationships/tags", workspace_id(workspace)?);
+        Ok(vec![shell.api("POST", &path, Some(&self.body()))])
+    }
+}
+
+impl Tag {
+    fn body(&self) -> Value {
+        json!({ "data": [{ "type": "tags", "attributes": { "name": self.tag } }] })
+    }
+}
+
+/// Tells the notification channels about the workspace, if there are any.
+pub struct Notify {
+    pub notifications: NotificationConfig,
+}
+
+#[async_trait]
+impl Action for Notify {
+    fn recorded_as(&self) -> &'static str {
+        "notified"
+    }
+
+    async fn apply(&self, _context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        if self.notifications.slack_webhook.is_none() && self.notifications.teams_webhook.is_none() {
+            return Ok(Outcome::Skipped(format!("No notification channels to notify about {}", workspace_name(workspace))));
+        }
+        notify::notify_text(&self.notifications, &notify_message(workspace)).await?;
+        Ok(Outcome::Done(format!("Notified about {}", workspace_name(workspace))))
+    }
+
+    /// The webhooks are secrets, so the script reads them from `SLACK_WEBHOOK_URL` and
+    /// `TEAMS_WEBHOOK_URL`.
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let body = json!({ "text": notify_message(workspace) });
+        let mut commands = Vec::new();
+        if self.notifications.slack_webhook.is_some() {
+            commands.push(shell.webhook("SLACK_WEBHOOK_URL", &body));
+        }
+        if self.notifications.teams_webhook.is_some() {
+            commands.push(shell.webhook("TEAMS_WEBHOOK_URL", &body));
+        }
+        Ok(commands)
+    }
+}
+
+fn notify_message(workspace: &Value) -> String {
+    format!("Stale workspace {}/{} is being cleaned up by tfe_cleanup", tfe::workspace_org(workspace), workspace_name(workspace))
+}
+
+/// Queues a destroy run. Use the `destroy` subcommand to stagger many of them.
+pub struct QueueDestroy;
+
+#[async_trait]
+impl Action for QueueDestroy {
+    fn recorded_as(&self) -> &'static str {
+        "destroy-queued"
+    }
+
+    fn destructive(&self) -> bool {
+        true
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let (org, name) = (tfe::workspace_org(workspace), workspace_name(workspace));
+        let run_id = destroy::queue_destroy(context.client, org, name).await?;
+        Ok(Outcome::Done(format!("Queued destroy run {} for {}", run_id, name)))
+    }
+
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(vec![shell.api("POST", "/runs", Some(&destroy::destroy_run(workspace_id(workspace)?)))])
+    }
+}
+
+/// Queues a destroy run but keeps the workspace so it can be woken later; see `hibernate`.
+pub struct Hibernate;
+
+#[async_trait]
+impl Action for Hibernate {
+    fn recorded_as(&self) -> &'static str {
+        "hibernated"
+    }
+
+    fn destructive(&self) -> bool {
+        true
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let run_id = hibernate::hibernate(context.client, context.history, workspace).await?;
+        Ok(Outcome::Done(format!("Hibernated {}: queued destroy run {}", workspace_name(workspace), run_id)))
+    }
+
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let workspace_id = workspace_id(workspace)?;
+        Ok(vec![
+            "# Not recorded in the history, so `tfe_cleanup wake` can't wake this workspace".to_string(),
+            shell.api("POST", "/runs", Some(&destroy::destroy_run(workspace_id))),
+            shell.api("POST", &format!("/workspaces/{}/relationships/tags", workspace_id), Some(&hibernate::tag_body())),
+        ])
+    }
+}
+
+/// Downloads and verifies the current state. A state that can't be verified fails the
+/// pipeline so the workspace isn't deleted without a backup.
+pub struct Archive {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl Action for Archive {
+    fn recorded_as(&self) -> &'static str {
+        "archived"
+    }
+
+    async fn apply(&self, context: &ActionContext<'_>, workspace: &Value) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let name = workspace_name(workspace);
+        match archive::archive_state(context.client, workspace, &self.dir).await {
+            Ok(ArchiveOutcome::Archived(path)) => Ok(Outcome::Done(format!("Archived state of {} to {}", name, path.display()))),
+            Ok(ArchiveOutcome::NoState) => Ok(Outcome::Done(format!("Workspace {} has no state to archive", name))),
+            Err(e) => Err(format!("its state archive could not be verified: {}", e).into()),
+        }
+    }
+
+    /// Needs `jq`. Unlike `apply`, the download isn't verified, and a workspace without state
+    /// fails the script.
+    fn script(&self, shell: Shell, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let dir = self.dir.join(tfe::workspace_org(workspace));
+        let path = dir.join(format!("{}.tfstate", workspace_name(workspace)));
+        let state_version = format!("/workspaces/{}/current-state-version", workspace_id(workspace)?);
+        let mut commands = vec![shell.make_dir(&dir.to_string_lossy())];
+        commands.extend(shell.download_state(&state_version, &path.to_string_lossy()));
+        Ok(commands)
+    }
+}
+
+/// The action pipeline of each category.
+pub struct Pipelines {
+    stale: Vec<Box<dyn Action>>,
+    no_activity_data: Vec<Box<dyn Action>>,
+    no_vcs: Vec<Box<dyn Action>>,
+}
+
+impl Pipelines {
+    /// Builds the configured pipelines. `archive_dir` (from `--archive-state`) makes every
+    /// pipeline archive the state first, unless it already archives. `terraform_bin` (from
+    /// `--terraform-bin`) is the only external command deletions may use.
+    pub fn new(
+        config: &ActionsConfig,
+        notifications: &NotificationConfig,
+        archive_dir: Option<&Path>,
+        terraform_bin: Option<&Path>,
+        delete_if_empty: bool,
+    ) -> Pipelines {
+        let build = |kinds: &[ActionKind]| -> Vec<Box<dyn Action>> {
+            let mut kinds = kinds.to_vec();
+            if archive_dir.is_some() && !kinds.contains(&ActionKind::Archive) {
+                kinds.insert(0, ActionKind::Archive);
+            }
+
+            kinds.iter()
+                .map(|kind| -> Box<dyn Action> {
+                    match kind {
+                        ActionKind::Delete => Box::new(Delete { terraform_bin: terraform_bin.map(Path::to_path_buf), delete_if_empty }),
+                        ActionKind::Lock => Box::new(Lock),
+                        ActionKind::Tag => Box::new(Tag { tag: config.tag.clone() }),
+                        ActionKind::Notify => Box::new(Notify { notifications: notifications.clone() }),
+                        ActionKind::QueueDestroy => Box::new(QueueDestroy),
+                        ActionKind::Hibernate => Box::new(Hibernate),
+                        ActionKind::Archive => Box::new(Archive {
+                            dir: archive_dir.map_or_else(|| config.archive_dir.clone(), Path::to_path_buf),
+                        }),
+                    }
+                })
+                .collect()
+        };
+
+        Pipelines {
+            stale: build(&config.stale),
+            no_activity_data: build(&config.no_activity_data),
+            no_vcs: build(&config.no_vcs),
+        }
+    }
+
+    pub fn for_category(&self, category: Category) -> &[Box<dyn Action>] {
+        match category {
+            Category::Stale => &self.stale,
+            Category::NoActivityData => &self.no_activity_data,
+            Category::NoVcs => &self.no_vcs,
+        }
+    }
+}
+
+/// Whether the pipeline deletes the workspace.
+pub fn deletes(actions: &[Box<dyn Action>]) -> bool {
+    actions.iter().any(|action| action.recorded_as() == "deleted")
+}
+
+/// How a workspace's pipeline ended.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineResult {
+    /// Every action ran; lists what each recorded.
+    Completed(Vec<&'static str>),
+    Stopped,
+    Failed,
+}
+
+/// Runs the actions in order, printing, recording and emitting each outcome, until one stops or fails.
+pub async fn run_pipeline(
+    actions: &[Box<dyn Action>],
+    context: &ActionContext<'_>,
+    workspace: &Value,
+) -> Result<PipelineResult, Box<dyn Error + Send + Sync>> {
+    let (org, name, history) = (tfe::workspace_org(workspace), workspace_name(workspace), context.history);
+    let mut completed = Vec::new();
+
+    for action in actions {
+        if action.destructive() {
+            window::check(context.windows, Utc::now())?;
+            if let Some(kill_switch) = context.kill_switch {
+                kill_switch.check().await?;
+            }
+        }
+        match action.apply(context, workspace).await {
+            Ok(Outcome::Done(message)) => {
+                println!("{}", message);
+                history.record_action(org, name, action.recorded_as(), history::SUCCEEDED)?;
+                if let Some(events) = context.events {
+                    events.action(action.recorded_as(), workspace, history::SUCCEEDED, &message).await;
+                }
+                completed.push(action.recorded_as());
+            }
+            Ok(Outcome::Stop(reason)) => {
+                println!("{}", reason);
+                history.record_action(org, name, action.recorded_as(), history::REFUSED)?;
+                if let Some(events) = context.events {
+                    events.action(action.recorded_as(), workspace, history::REFUSED, &reason).await;
+                }
+                return Ok(PipelineResult::Stopped);
+            }
+            Ok(Outcome::Skipped(message)) => println!("{}", message),
+            Err(e) => {
+                println!("Failed to {} {}: {}", action.recorded_as(), name, redact::scrub(&e.to_string()));
+                history.record_action(org, name, action.recorded_as(), history::FAILED)?;
+                if let Some(events) = context.events {
+                    events.action(action.recorded_as(), workspace, history::FAILED, &e.to_string()).await;
+                }
+                return Ok(PipelineResult::Failed);
+            }
+        }
+    }
+
+    Ok(PipelineResult::Completed(completed))
+}
+
+/// The commands of the actions in `shell`, in order. An action that can't be scripted ends the
+/// list with a comment saying why, as a failure ends the pipeline.
+pub fn script_pipeline(actions: &[Box<dyn Action>], shell: Shell, workspace: &Value) -> Vec<String> {
+    let mut commands = Vec::new();
+    for action in actions {
+        match action.script(shell, workspace) {
+            Ok(more) => commands.extend(more),
+            Err(e) => {
+                commands.push(format!("# Cannot script the remaining actions: {} failed: {}", action.recorded_as(), e));
+                break;
+            }
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WindowConfig;
+    use mockito::{mock, server_url, Matcher};
+
+    fn workspace(id: &str, name: &str) -> Value {
+        json!({
+            "id": id,
+            "attributes": {
+                "name": name,
+                "last-activity-at": "2020-01-01T00:00:00Z",
+                "vcs-repo": { "identifier": "acme/infra" }
+            },
+            "relationships": { "organization": { "data": { "id": "actions-org" } } }
+        })
+    }
+
+    #[test]
+    fn test_category_of() {
+        assert_eq!(Category::of(&workspace("ws-1", "app")), Category::Stale);
+        assert_eq!(Category::of(&json!({ "attributes": { "last-activity-at": "2020-01-01T00:00:00Z" } })), Category::NoVcs);
+        assert_eq!(Category::of(&json!({ "attributes": { "created-at": "2020-01-01T00:00:00Z" } })), Category::NoActivityData);
+    }
+
+    #[test]
+    fn test_archive_dir_flag_prepends_archive() {
+        let pipelines = Pipelines::new(&ActionsConfig::default(), &NotificationConfig::default(), Some(Path::new("backups")), None, false);
+        let recorded: Vec<&str> = pipelines.for_category(Category::Stale).iter().map(|a| a.recorded_as()).collect();
+        assert_eq!(recorded, vec!["archived", "deleted"]);
+    }
+
+    #[test]
+    fn test_script_pipeline() {
+        let config = ActionsConfig {
+            stale: vec![ActionKind::Tag, ActionKind::QueueDestroy, ActionKind::Delete],
+            no_vcs: vec![ActionKind::Lock, ActionKind::Notify, ActionKind::Delete],
+            ..ActionsConfig::default()
+        };
+        let pipelines = Pipelines::new(&config, &NotificationConfig::default(), None, None, false);
+
+        let commands = script_pipeline(pipelines.for_category(Category::Stale), Shell::Posix, &workspace("ws-script", "legacy"));
+        assert_eq!(commands.len(), 3);
+        assert!(commands[0].contains("'/workspaces/ws-script/relationships/tags' -d '{\"data\":[{\"attributes\":{\"name\":\"tfe-cleanup-stale\"}"));
+        assert!(commands[1].contains("'/runs' -d ") && commands[1].contains("\"is-destroy\":true"));
+        assert!(commands[2].contains("'/organizations/actions-org/workspaces/legacy/actions/safe-delete'"));
+
+        // Notify has no channel, so it has no commands
+        let commands = script_pipeline(pipelines.for_category(Category::NoVcs), Shell::Posix, &workspace("ws-script", "legacy"));
+        assert_eq!(commands.len(), 2);
+        assert!(commands[0].contains("'/workspaces/ws-script/actions/lock'"));
+        assert!(commands[1].contains("'/organizations/actions-org/workspaces/legacy/actions/safe-delete'"));
+
+        let unlisted = json!({ "attributes": { "name": "hand-added" } });
+        let delete = Delete { terraform_bin: None, delete_if_empty: false };
+        assert!(delete.script(Shell::Posix, &unlisted).unwrap_err().to_string().contains("--terraform-bin"));
+        let delete = Delete { terraform_bin: Some(PathBuf::from("/opt/terraform")), delete_if_empty: false };
+        assert_eq!(delete.script(Shell::Posix, &unlisted).unwrap(), vec!["'/opt/terraform' 'workspace' 'delete' 'hand-added'"]);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_runs_in_order_and_stops_on_failure() {
+        let tag = mock("POST", "/api/v2/workspaces/ws-actions/relationships/tags")
+            .match_body(Matcher::Regex("tfe-cleanup-stale".into()))
+            .with_status(204)
+            .expect(1)
+            .create();
+        let lock = mock("POST", "/api/v2/workspaces/ws-actions/actions/lock")
+            .with_status(403)
+            .expect(1)
+            .create();
+        let delete = mock("POST", "/api/v2/organizations/actions-org/workspaces/legacy/actions/safe-delete")
+            .expect(0)
+            .create();
+        let config = ActionsConfig {
+            stale: vec![ActionKind::Tag, ActionKind::Notify, ActionKind::Lock, ActionKind::Delete],
+            ..ActionsConfig::default()
+        };
+        let pipelines = Pipelines::new(&config, &NotificationConfig::default(), None, None, false);
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+        let context = ActionContext { client: &client, history: &history, events: None, kill_switch: None, windows: &[] };
+        let result = run_pipeline(pipelines.for_category(Category::Stale), &context, &workspace("ws-actions", "legacy"))
+            .await
+            .unwrap();
+
+        // Notify has no channels and is passed over; Lock fails, so Delete never runs
+        assert_eq!(result, PipelineResult::Failed);
+        tag.assert();
+        lock.assert();
+        delete.assert();
+        assert_eq!(history.handled_action("actions-org", "legacy").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_unverifiable_archive_fails() {
+        let _state_version = mock("GET", "/api/v2/workspaces/ws-unverifiable/current-state-version")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "serial": 3 } } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+        let context = ActionContext { client: &client, history: &history, events: None, kill_switch: None, windows: &[] };
+        let archive = Archive { dir: std::env::temp_dir().join("tfe_cleanup_unverifiable_archive") };
+        let error = archive.apply(&context, &workspace("ws-unverifiable", "unverifiable")).await.unwrap_err();
+
+        assert!(error.to_string().contains("could not be verified"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_delete_without_safe_delete_is_opt_in() {
+        let _unsupported = mock("POST", "/api/v2/organizations/actions-org/workspaces/no-safe-delete/actions/safe-delete")
+            .with_status(404)
+            .create();
+        let _workspace = mock("GET", "/api/v2/organizations/actions-org/workspaces/no-safe-delete")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "name": "no-safe-delete", "resource-count": 0 } } }).to_string())
+            .create();
+        let deleted = mock("DELETE", "/api/v2/organizations/actions-org/workspaces/no-safe-delete")
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+        let context = ActionContext { client: &client, history: &history, events: None, kill_switch: None, windows: &[] };
+        let workspace = workspace("ws-no-safe-delete", "no-safe-delete");
+
+        let refused = Delete { terraform_bin: None, delete_if_empty: false }.apply(&context, &workspace).await.unwrap_err();
+        assert!(refused.to_string().contains("--delete-if-empty"), "{}", refused);
+        let outcome = Delete { terraform_bin: None, delete_if_empty: true }.apply(&context, &workspace).await.unwrap();
+        assert!(matches!(outcome, Outcome::Done(_)));
+        deleted.assert();
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch_aborts_before_destructive_actions() {
+        let tag = mock("POST", "/api/v2/workspaces/ws-killed/relationships/tags").with_status(204).expect(1).create();
+        let delete = mock("POST", "/api/v2/organizations/actions-org/workspaces/killed/actions/safe-delete").expect(0).create();
+        let _switch = mock("GET", "/actions-kill-switch").with_status(200).with_body("stop").create();
+        let config = ActionsConfig { stale: vec![ActionKind::Tag, ActionKind::Delete], ..ActionsConfig::default() };
+        let pipelines = Pipelines::new(&config, &NotificationConfig::default(), None, None, false);
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+        let kill_switch = KillSwitch::new(&format!("{}/actions-kill-switch", server_url())).unwrap();
+        let context = ActionContext { client: &client, history: &history, events: None, kill_switch: Some(&kill_switch), windows: &[] };
+        let err = run_pipeline(pipelines.for_category(Category::Stale), &context, &workspace("ws-killed", "killed")).await.unwrap_err();
+
+        assert!(err.to_string().contains("engaged"), "{}", err);
+        tag.assert();
+        delete.assert();
+    }
+
+    #[tokio::test]
+    async fn test_closed_window_stops_before_destructive_actions() {
+        let tag = mock("POST", "/api/v2/workspaces/ws-late/relationships/tags").with_status(204).expect(1).create();
+        let delete = mock("POST", "/api/v2/organizations/actions-org/workspaces/late/actions/safe-delete").expect(0).create();
+        let config = ActionsConfig { stale: vec![ActionKind::Tag, ActionKind::Delete], ..ActionsConfig::default() };
+        let pipelines = Pipelines::new(&config, &NotificationConfig::default(), None, None, false);
+        // Opens and closes at the same minute, so it is never open
+        let closed = WindowConfig { days: vec!["mon".to_string()], start: "02:00".to_string(), end: "02:00".to_string() };
+        let windows = window::parse_windows(&[closed]).unwrap();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+        let context = ActionContext { client: &client, history: &history, events: None, kill_switch: None, windows: &windows };
+        let err = run_pipeline(pipelines.for_category(Category::Stale), &context, &workspace("ws-late", "late")).await.unwrap_err();
+
+        assert!(err.to_string().contains("deletion window has closed"), "{}", err);
+        tag.assert();
+        delete.assert();
+    }
+}
@@ -0,0 +1,164 @@
+use crate::staleness;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Upper bounds (exclusive, in days) and labels of the histogram's buckets.
+const BUCKETS: &[(i64, &str)] = &[
+    (30, "< 30 days"),
+    (90, "30-89 days"),
+    (180, "90-179 days"),
+    (365, "180-364 days"),
+    (i64::MAX, ">= 1 year"),
+];
+
+/// Width of the longest bar of the text histogram.
+const BAR_WIDTH: usize = 30;
+
+/// Days since the workspace's last activity, or since its creation if it never had any.
+pub fn days_inactive(workspace: &Value, now: DateTime<Utc>) -> Option<i64> {
+    staleness::activity_basis(workspace).map(|(_, _, date)| (now - date.with_timezone(&Utc)).num_days().max(0))
+}
+
+/// Number of workspaces by days inactive. A scan keeps these rather than every workspace's age,
+/// so it needs at most one entry per distinct age.
+pub type AgeCounts = BTreeMap<i64, usize>;
+
+/// How long the scanned workspaces have been inactive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgeStats {
+    /// Workspaces with a last activity or creation date.
+    pub workspaces: usize,
+    pub p50_days: i64,
+    pub p90_days: i64,
+    pub max_days: i64,
+    pub buckets: Vec<AgeBucket>,
+    /// Change since the previous run recorded in the history, if any.
+    pub trend: Option<AgeTrend>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgeBucket {
+    pub label: String,
+    pub count: usize,
+}
+
+/// Differences from the previous run: positive means more workspaces, or older ones.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AgeTrend {
+    pub workspaces: i64,
+    pub p50_days: i64,
+    pub p90_days: i64,
+    pub max_days: i64,
+}
+
+/// The nearest-rank percentile of `total` counted values.
+fn percentile(counts: &AgeCounts, total: usize, percent: usize) -> i64 {
+    let rank = (total * percent).div_ceil(100).max(1);
+    let mut seen = 0;
+    counts.iter()
+        .find(|&(_, &count)| {
+            seen += count;
+            seen >= rank
+        })
+        .map_or(0, |(&age, _)| age)
+}
+
+impl AgeStats {
+    /// Statistics of listed ages, as fixtures have them.
+    #[cfg(test)]
+    pub fn new(ages: &[i64]) -> AgeStats {
+        let mut counts = AgeCounts::new();
+        for &age in ages {
+            *counts.entry(age).or_default() += 1;
+        }
+        AgeStats::from_counts(&counts)
+    }
+
+    pub fn from_counts(counts: &AgeCounts) -> AgeStats {
+        let total = counts.values().sum();
+        let mut lower = 0;
+        let buckets = BUCKETS.iter()
+            .map(|&(upper, label)| {
+                let count = counts.range(lower..upper).map(|(_, count)| count).sum();
+                lower = upper;
+                AgeBucket { label: label.to_string(), count }
+            })
+            .collect();
+
+        AgeStats {
+            workspaces: total,
+            p50_days: percentile(counts, total, 50),
+            p90_days: percentile(counts, total, 90),
+            max_days: counts.keys().next_back().copied().unwrap_or(0),
+            buckets,
+            trend: None,
+        }
+    }
+
+    /// Adds the trend against the previous run's statistics.
+    pub fn compared_to(mut self, previous: Option<&AgeStats>) -> AgeStats {
+        self.trend = previous.map(|previous| AgeTrend {
+            workspaces: self.workspaces as i64 - previous.workspaces as i64,
+            p50_days: self.p50_days - previous.p50_days,
+            p90_days: self.p90_days - previous.p90_days,
+            max_days: self.max_days - previous.max_days,
+        });
+        self
+    }
+
+    /// A compact text histogram, one line per bucket, followed by the percentiles and trend.
+    pub fn render(&self) -> Vec<String> {
+        let largest = self.buckets.iter().map(|bucket| bucket.count).max().unwrap_or(0);
+        let label_width = self.buckets.iter().map(|bucket| bucket.label.len()).max().unwrap_or(0);
+        let mut lines: Vec<String> = self.buckets.iter()
+            .map(|bucket| {
+                let bar = if largest == 0 { 0 } else { (bucket.count * BAR_WIDTH).div_ceil(largest) };
+                format!("{:<label_width$}  {:>5}  {}", bucket.label, bucket.count, "#".repeat(bar), label_width = label_width)
+                    .trim_end().to_string()
+            })
+            .collect();
+        lines.push(format!("p50 {} days, p90 {} days, max {} days", self.p50_days, self.p90_days, self.max_days));
+        if let Some(trend) = &self.trend {
+            lines.push(format!("Since the previous run: {:+} workspaces, p50 {:+} days, p90 {:+} days, max {:+} days",
+                trend.workspaces, trend.p50_days, trend.p90_days, trend.max_days));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_age_stats() {
+        let ages = [3, 10, 45, 100, 120, 200, 400, 800, 20, 5];
+        let stats = AgeStats::new(&ages);
+
+        assert_eq!((stats.workspaces, stats.p50_days, stats.p90_days, stats.max_days), (10, 45, 400, 800));
+        let counts: Vec<usize> = stats.buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, vec![4, 1, 2, 1, 2]);
+
+        let previous = AgeStats::new(&[3, 10, 45, 90]);
+        let stats = stats.compared_to(Some(&previous));
+        assert_eq!(stats.trend, Some(AgeTrend { workspaces: 6, p50_days: 35, p90_days: 310, max_days: 710 }));
+        assert_eq!(stats.render(), vec![
+            "< 30 days         4  ##############################",
+            "30-89 days        1  ########",
+            "90-179 days       2  ###############",
+            "180-364 days      1  ########",
+            ">= 1 year         2  ###############",
+            "p50 45 days, p90 400 days, max 800 days",
+            "Since the previous run: +6 workspaces, p50 +35 days, p90 +310 days, max +710 days",
+        ]);
+    }
+
+    #[test]
+    fn test_empty_age_stats() {
+        let stats = AgeStats::new(&[]);
+        assert_eq!((stats.p50_days, stats.max_days), (0, 0));
+        assert_eq!(stats.render()[0], "< 30 days         0");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::tfe::{self, ApiError, TfeClient};
+use md5::{Digest, Md5};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub enum ArchiveOutcome {
+    Archived(PathBuf),
+    NoState,
+}
+
+/// Checks a downloaded state file against what the state version reports about itself.
+/// The serial must always match; size and MD5 are compared when the API reports them.
+pub fn verify_state(bytes: &[u8], state_version: &Value) -> Result<(), String> {
+    let attributes = &state_version["attributes"];
+
+    let state: Value = serde_json::from_slice(bytes)
+        .map_err(|e| format!("downloaded state is not valid JSON: {}", e))?;
+
+    let expected_serial = attributes["serial"].as_i64().ok_or("state version reports no serial")?;
+    match state["serial"].as_i64() {
+        Some(serial) if serial == expected_serial => {}
+        Some(serial) => return Err(format!("serial mismatch: expected {}, downloaded {}", expected_serial, serial)),
+        None => return Err("downloaded state has no serial".to_string()),
+    }
+
+    if let Some(size) = attributes["size"].as_u64() {
+        if size != bytes.len() as u64 {
+            return Err(format!("size mismatch: expected {} bytes, downloaded {}", size, bytes.len()));
+        }
+    }
+
+    if let Some(expected_md5) = attributes["md5"].as_str() {
+        let actual_md5 = format!("{:x}", Md5::digest(bytes));
+        if !expected_md5.eq_ignore_ascii_case(&actual_md5) {
+            return Err(format!("checksum mismatch: expected md5 {}, downloaded {}", expected_md5, actual_md5));
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the state with `serial` of workspace `org/name` is archived under `dir`.
+pub fn archive_path(dir: &Path, org: &str, name: &str, serial: i64) -> PathBuf {
+    dir.join(org).join(format!("{}-{}.tfstate", name, serial))
+}
+
+/// Downloads the current state of a workspace into `dir/<org>/<workspace>-<serial>.tfstate`
+/// after verifying it. Any verification failure is returned as an error so the caller can
+/// refuse to delete the workspace.
+pub async fn archive_state(
+    client: &TfeClient,
+    workspace: &Value,
+    dir: &Path,
+) -> Result<ArchiveOutcome, Box<dyn Error + Send + Sync>> {
+    let workspace_id = workspace["id"].as_str().ok_or("workspace has no id")?;
+    let name = workspace["attributes"]["name"].as_str().unwrap_or(workspace_id);
+
+    let state_version = match client.get(&format!("/workspaces/{}/current-state-version", workspace_id)).await {
+        Ok(response) => response["data"].clone(),
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND => return Ok(ArchiveOutcome::NoState),
+            _ => return Err(e),
+        },
+    };
+
+    let url = state_version["attributes"]["hosted-state-download-url"]
+        .as_str()
+        .ok_or("state version has no download URL")?;
+    let bytes = client.download(url).await?;
+    verify_state(&bytes, &state_version)?;
+
+    let serial = state_version["attributes"]["serial"].as_i64().unwrap_or_default();
+    let path = archive_path(dir, tfe::workspace_org(workspace), name, serial);
+    if let Some(org_dir) = path.parent() {
+        fs::create_dir_all(org_dir)?;
+    }
+    fs::write(&path, &bytes)?;
+
+    if fs::read(&path)? != bytes {
+        return Err(format!("archived state at {} does not match the download", path.display()).into());
+    }
+
+    Ok(ArchiveOutcome::Archived(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use serde_json::json;
+
+    fn state_version(serial: i64, state: &str) -> Value {
+        json!({
+            "attributes": {
+                "serial": serial,
+                "size": state.len(),
+                "md5": format!("{:x}", Md5::digest(state.as_bytes()))
+            }
+        })
+    }
+
+    #[test]
+    fn test_verify_state_accepts_matching_download() {
+        let state = r#"{"version":4,"serial":7}"#;
+        assert!(verify_state(state.as_bytes(), &state_version(7, state)).is_ok());
+    }
+
+    #[test]
+    fn test_verify_state_rejects_serial_mismatch() {
+        let state = r#"{"version":4,"serial":6}"#;
+        let err = verify_state(state.as_bytes(), &state_version(7, state)).unwrap_err();
+        assert!(err.contains("serial mismatch"));
+    }
+
+    #[test]
+    fn test_verify_state_rejects_checksum_mismatch() {
+        let state = r#"{"version":4,"serial":7}"#;
+        let mut version = state_version(7, state);
+        version["attributes"]["md5"] = json!("00000000000000000000000000000000");
+        let err = verify_state(state.as_bytes(), &version).unwrap_err();
+        assert!(err.contains("checksum mismatch"));
+    }
+
+    #[tokio::test]
+    async fn test_archive_state_writes_verified_file() {
+        let state = r#"{"version":4,"serial":3}"#;
+        let mut version = state_version(3, state);
+        version["attributes"]["hosted-state-download-url"] = json!(format!("{}/archivist/sv-archive", server_url()));
+        let _current = mock("GET", "/api/v2/workspaces/ws-archive/current-state-version")
+            .with_status(200)
+            .with_body(json!({ "data": version }).to_string())
+            .create();
+        let _download = mock("GET", "/archivist/sv-archive")
+            .with_status(200)
+            .with_body(state)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let workspace = json!({
+            "id": "ws-archive",
+            "attributes": { "name": "app" },
+            "relationships": { "organization": { "data": { "id": "archive-org" } } }
+        });
+
+        let outcome = archive_state(&client, &workspace, dir.path()).await.unwrap();
+
+        let expected = dir.path().join("archive-org").join("app-3.tfstate");
+        assert_eq!(outcome, ArchiveOutcome::Archived(expected.clone()));
+        assert_eq!(fs::read_to_string(expected).unwrap(), state);
+    }
+}
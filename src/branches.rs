@@ -0,0 +1,267 @@
+use crate::staleness;
+use crate::tfe::{self, TfeClient};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+
+pub const GITHUB_API: &str = "https://api.github.com";
+pub const GITLAB_API: &str = "https://gitlab.com/api/v4";
+
+/// A VCS provider's API and the token used to read branches from it.
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub api: String,
+    pub token: String,
+}
+
+/// The providers branches can be checked against. Workspaces connected to another provider,
+/// or to one without a token, are skipped.
+#[derive(Debug, Default)]
+pub struct Providers {
+    pub github: Option<Provider>,
+    pub gitlab: Option<Provider>,
+}
+
+impl Providers {
+    /// Reads `GITHUB_TOKEN` and `GITLAB_TOKEN`, with `GITHUB_API_URL` and `GITLAB_API_URL` for
+    /// self-hosted instances.
+    pub fn from_env() -> Providers {
+        let provider = |token_var: &str, api_var: &str, default_api: &str| {
+            env::var(token_var).ok().map(|token| Provider {
+                api: env::var(api_var).unwrap_or_else(|_| default_api.to_string()),
+                token,
+            })
+        };
+        Providers {
+            github: provider("GITHUB_TOKEN", "GITHUB_API_URL", GITHUB_API),
+            gitlab: provider("GITLAB_TOKEN", "GITLAB_API_URL", GITLAB_API),
+        }
+    }
+
+    /// The provider of a workspace's `vcs-repo.service-provider`, if it is supported and has a token.
+    pub fn for_service(&self, service_provider: &str) -> Option<(Kind, &Provider)> {
+        match service_provider {
+            "github" | "github_enterprise" | "github_app" => self.github.as_ref().map(|provider| (Kind::GitHub, provider)),
+            service if service.starts_with("gitlab") => self.gitlab.as_ref().map(|provider| (Kind::GitLab, provider)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    GitHub,
+    GitLab,
+}
+
+/// What became of the tracked branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Missing {
+    Branch,
+    /// The repository itself is gone, or the token can't see it.
+    Repository,
+}
+
+impl Missing {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Missing::Branch => "branch deleted",
+            Missing::Repository => "repository not found",
+        }
+    }
+}
+
+/// A VCS-backed workspace tracking a branch that no longer exists.
+#[derive(Debug, PartialEq)]
+pub struct DeletedBranch {
+    pub org: String,
+    pub workspace: String,
+    pub repository: String,
+    pub branch: String,
+    pub missing: Missing,
+    pub last_activity: String,
+}
+
+/// `api` with the path segments appended, each percent-encoded, so that repository
+/// identifiers and branch names containing `/` stay single segments where needed.
+pub fn endpoint(api: &str, segments: &[&str]) -> Result<Url, Box<dyn Error + Send + Sync>> {
+    let mut url = Url::parse(api)?;
+    url.path_segments_mut().map_err(|_| format!("{} is not a valid API URL", api))?.pop_if_empty().extend(segments);
+    Ok(url)
+}
+
+/// A GET of the provider's API, authenticated as that provider expects.
+pub fn get(http: &reqwest::Client, kind: Kind, provider: &Provider, url: Url) -> RequestBuilder {
+    let request = match kind {
+        Kind::GitHub => http.get(url).bearer_auth(&provider.token).header("Accept", "application/vnd.github+json"),
+        Kind::GitLab => http.get(url).header("PRIVATE-TOKEN", &provider.token),
+    };
+    request.header("User-Agent", concat!("tfe_cleanup/", env!("CARGO_PKG_VERSION")))
+}
+
+/// Whether the URL exists: `true` on success, `false` on 404, an error otherwise.
+async fn exists(http: &reqwest::Client, kind: Kind, provider: &Provider, url: Url) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let response = get(http, kind, provider, url.clone()).send().await.map_err(|e| e.without_url())?;
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        status => Err(format!("{} returned {}", url.path(), status).into()),
+    }
+}
+
+/// Checks the branch against the provider, telling a deleted branch from a missing repository.
+async fn check_branch(
+    http: &reqwest::Client,
+    kind: Kind,
+    provider: &Provider,
+    repository: &str,
+    branch: &str,
+) -> Result<Option<Missing>, Box<dyn Error + Send + Sync>> {
+    let (branch_url, repository_url) = match kind {
+        Kind::GitHub => {
+            let base: Vec<&str> = std::iter::once("repos").chain(repository.split('/')).collect();
+            let branch_segments: Vec<&str> = base.iter().copied().chain(["branches", branch]).collect();
+            (endpoint(&provider.api, &branch_segments)?, endpoint(&provider.api, &base)?)
+        }
+        // GitLab addresses projects by their encoded full path
+        Kind::GitLab => (
+            endpoint(&provider.api, &["projects", repository, "repository", "branches", branch])?,
+            endpoint(&provider.api, &["projects", repository])?,
+        ),
+    };
+
+    if exists(http, kind, provider, branch_url).await? {
+        return Ok(None);
+    }
+    if exists(http, kind, provider, repository_url).await? {
+        Ok(Some(Missing::Branch))
+    } else {
+        Ok(Some(Missing::Repository))
+    }
+}
+
+/// Lists the VCS-backed workspaces of `org` whose tracked branch no longer exists. Workspaces
+/// tracking the default branch (no branch set) can't be affected and aren't checked. Returns
+/// the findings and how many workspaces were skipped for lack of a supported provider.
+pub async fn find_deleted_branches(
+    client: &TfeClient,
+    providers: &Providers,
+    org: &str,
+) -> Result<(Vec<DeletedBranch>, usize), Box<dyn Error + Send + Sync>> {
+    let http = reqwest::Client::new();
+    // Feature-branch workflows point many workspaces at the same branches
+    let mut checked: HashMap<(String, String), Option<Missing>> = HashMap::new();
+    let (mut findings, mut skipped) = (Vec::new(), 0);
+
+    for workspace in tfe::list_workspaces(client, org).await? {
+        if !staleness::is_vcs_backed(&workspace) {
+            continue;
+        }
+        let vcs = &workspace["attributes"]["vcs-repo"];
+        let (repository, branch) = (vcs["identifier"].as_str().unwrap_or(""), vcs["branch"].as_str().unwrap_or(""));
+        if repository.is_empty() || branch.is_empty() {
+            continue;
+        }
+        let Some((kind, provider)) = providers.for_service(vcs["service-provider"].as_str().unwrap_or("")) else {
+            skipped += 1;
+            continue;
+        };
+
+        let key = (repository.to_string(), branch.to_string());
+        let missing = match checked.get(&key) {
+            Some(missing) => *missing,
+            None => {
+                let missing = check_branch(&http, kind, provider, repository, branch).await?;
+                checked.insert(key, missing);
+                missing
+            }
+        };
+
+        if let Some(missing) = missing {
+            findings.push(DeletedBranch {
+                org: org.to_string(),
+                workspace: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+                repository: repository.to_string(),
+                branch: branch.to_string(),
+                missing,
+                last_activity: workspace["attributes"]["last-activity-at"].as_str().unwrap_or("").to_string(),
+            });
+        }
+    }
+
+    Ok((findings, skipped))
+}
+
+pub fn create_deleted_branches_csv(findings: &[DeletedBranch], path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["Organization", "Workspace", "Repository", "Branch", "Finding", "Last Activity"])?;
+
+    for finding in findings {
+        wtr.write_record([
+            &finding.org, &finding.workspace, &finding.repository, &finding.branch,
+            finding.missing.label(), &finding.last_activity,
+        ])?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_endpoint_encodes_segments() {
+        let url = endpoint("https://gitlab.example.com/api/v4/", &["projects", "acme/infra", "repository", "branches", "feature/x"]).unwrap();
+        assert_eq!(url.as_str(), "https://gitlab.example.com/api/v4/projects/acme%2Finfra/repository/branches/feature%2Fx");
+    }
+
+    #[tokio::test]
+    async fn test_find_deleted_branches() {
+        let vcs = |identifier: &str, branch: &str, provider: &str| json!({
+            "identifier": identifier, "branch": branch, "service-provider": provider
+        });
+        let _workspaces = mock("GET", "/api/v2/organizations/branches-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "name": "feature-a", "vcs-repo": vcs("acme/branches-app", "feature/a", "github") } },
+                { "attributes": { "name": "feature-a-copy", "vcs-repo": vcs("acme/branches-app", "feature/a", "github") } },
+                { "attributes": { "name": "release", "vcs-repo": vcs("acme/branches-app", "release", "github") } },
+                { "attributes": { "name": "main", "vcs-repo": vcs("acme/branches-app", "", "github") } },
+                { "attributes": { "name": "gone", "vcs-repo": vcs("acme/branches-gone", "dev", "github") } },
+                { "attributes": { "name": "bitbucket", "vcs-repo": vcs("acme/bb", "dev", "bitbucket_hosted") } },
+                { "attributes": { "name": "cli", "vcs-repo": null } }
+            ] }).to_string())
+            .create();
+        let deleted = mock("GET", "/github/repos/acme/branches-app/branches/feature%2Fa")
+            .match_header("authorization", "Bearer gh-token")
+            .with_status(404)
+            .expect(1)
+            .create();
+        let _release = mock("GET", "/github/repos/acme/branches-app/branches/release").with_status(200).create();
+        let _app = mock("GET", "/github/repos/acme/branches-app").with_status(200).create();
+        let _gone_branch = mock("GET", "/github/repos/acme/branches-gone/branches/dev").with_status(404).create();
+        let _gone = mock("GET", "/github/repos/acme/branches-gone").with_status(404).create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let providers = Providers {
+            github: Some(Provider { api: format!("{}/github", server_url()), token: "gh-token".to_string() }),
+            gitlab: None,
+        };
+        let (findings, skipped) = find_deleted_branches(&client, &providers, "branches-org").await.unwrap();
+
+        let found: Vec<(&str, Missing)> = findings.iter().map(|finding| (finding.workspace.as_str(), finding.missing)).collect();
+        assert_eq!(found, vec![
+            ("feature-a", Missing::Branch),
+            ("feature-a-copy", Missing::Branch),
+            ("gone", Missing::Repository),
+        ]);
+        assert_eq!(skipped, 1);
+        deleted.assert();
+    }
+}
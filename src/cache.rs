@@ -0,0 +1,248 @@
+use crate::config::Config;
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::Cell;
+use serde_json::Value;
+use std::error::Error;
+use std::future::Future;
+use std::path::Path;
+
+/// Per-workspace API lookups that are slow enough to be worth keeping between runs, such as
+/// cost estimates. Lookups that depend on other workspaces, like remote state consumers, are
+/// never cached: those workspaces change without touching this one.
+pub const COST: &str = "cost";
+pub const OWNERS: &str = "owners";
+pub const RESOURCE_TYPES: &str = "resource_types";
+pub const PLAINTEXT_SECRETS: &str = "plaintext_secrets";
+pub const LAST_RUN: &str = "last_run";
+
+/// Lookups derived only from the workspace's current state and run. Their entries stay valid
+/// for as long as neither changes, however old they are.
+const STATE_DERIVED: &[&str] = &[COST, RESOURCE_TYPES, LAST_RUN];
+
+/// How many lookups a cache answered and how many it had to fetch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Percentage of lookups answered from the cache, 0 when there were none.
+    pub hit_rate: f64,
+}
+
+impl CacheStats {
+    pub fn new(hits: u64, misses: u64) -> CacheStats {
+        let hit_rate = match hits + misses {
+            0 => 0.0,
+            total => (hits as f64 * 1000.0 / total as f64).round() / 10.0,
+        };
+        CacheStats { hits, misses, hit_rate }
+    }
+
+    /// e.g. "40 of 50 lookups reused from the cache (80.0%)".
+    pub fn describe(&self) -> String {
+        format!("{} of {} lookups reused from the cache ({:.1}%)", self.hits, self.hits + self.misses, self.hit_rate)
+    }
+}
+
+/// The version of the workspace a `kind` entry was computed for. State-derived lookups are
+/// keyed by the current state version, which changes with every state serial, and the current
+/// run; the others by `updated-at`. `None` when the workspace can't be keyed.
+fn version_key(workspace: &Value, kind: &str) -> Option<(String, bool)> {
+    if STATE_DERIVED.contains(&kind) {
+        let relationship = |name: &str| workspace["relationships"][name]["data"]["id"].as_str().unwrap_or("none").to_string();
+        return Some((format!("state:{}:run:{}", relationship("current-state-version"), relationship("current-run")), false));
+    }
+    workspace["attributes"]["updated-at"].as_str().map(|updated_at| (updated_at.to_string(), true))
+}
+
+/// Results of per-workspace lookups, stored in the history database so a `cleanup` shortly
+/// after a `scan` doesn't look everything up again. State-derived entries are used while the
+/// workspace's state version and current run are unchanged; the others while its `updated-at`
+/// is unchanged and the entry is younger than `lookup_cache_minutes`.
+pub struct LookupCache {
+    /// `None` when caching is turned off.
+    conn: Option<Connection>,
+    max_age: Duration,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl LookupCache {
+    pub fn open(path: &Path, max_age: Duration) -> Result<LookupCache, Box<dyn Error + Send + Sync>> {
+        LookupCache::init(Connection::open(path)?, max_age)
+    }
+
+    pub fn from_config(config: &Config) -> Result<LookupCache, Box<dyn Error + Send + Sync>> {
+        if config.lookup_cache_minutes <= 0 {
+            return Ok(LookupCache { conn: None, max_age: Duration::zero(), hits: Cell::new(0), misses: Cell::new(0) });
+        }
+        LookupCache::open(&config.history_db, Duration::minutes(config.lookup_cache_minutes))
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory(max_age: Duration) -> Result<LookupCache, Box<dyn Error + Send + Sync>> {
+        LookupCache::init(Connection::open_in_memory()?, max_age)
+    }
+
+    fn init(conn: Connection, max_age: Duration) -> Result<LookupCache, Box<dyn Error + Send + Sync>> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS lookups (
+                workspace_id TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                value TEXT NOT NULL,
+                cached_at TEXT NOT NULL,
+                PRIMARY KEY (workspace_id, kind)
+            );",
+        )?;
+        Ok(LookupCache { conn: Some(conn), max_age, hits: Cell::new(0), misses: Cell::new(0) })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats::new(self.hits.get(), self.misses.get())
+    }
+
+    /// The entry for the workspace at `version`, subject to the age limit if `expires`. The
+    /// `updated_at` column holds whichever version key the entry was computed for.
+    fn cached(&self, conn: &Connection, workspace_id: &str, kind: &str, version: &str, expires: bool) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let entry: Option<(String, String)> = conn.query_row(
+            "SELECT value, cached_at FROM lookups WHERE workspace_id = ?1 AND kind = ?2 AND updated_at = ?3",
+            params![workspace_id, kind, version],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?;
+
+        Ok(entry.filter(|(_, cached_at)| !expires || DateTime::parse_from_rfc3339(cached_at)
+            .is_ok_and(|cached_at| Utc::now() - cached_at.with_timezone(&Utc) < self.max_age))
+            .map(|(value, _)| value))
+    }
+
+    /// The `kind` lookup for the workspace from the cache, or from `fetch`, whose result is then
+    /// cached. Workspaces that can't be keyed, e.g. without an id, are always fetched.
+    pub async fn get_or_fetch<T, F, Fut>(&self, workspace: &Value, kind: &str, fetch: F) -> Result<T, Box<dyn Error + Send + Sync>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error + Send + Sync>>>,
+    {
+        let (Some(conn), Some(workspace_id), Some((version, expires))) = (&self.conn, workspace["id"].as_str(), version_key(workspace, kind)) else {
+            self.misses.set(self.misses.get() + 1);
+            return fetch().await;
+        };
+
+        // The cache only saves requests: an entry that can't be read, e.g. from a locked database,
+        // or that was written by another version and doesn't parse, is simply looked up again
+        let cached = self.cached(conn, workspace_id, kind, &version, expires).ok().flatten();
+        if let Some(value) = cached.and_then(|value| serde_json::from_str(&value).ok()) {
+            self.hits.set(self.hits.get() + 1);
+            return Ok(value);
+        }
+
+        self.misses.set(self.misses.get() + 1);
+        let value = fetch().await?;
+        // Nor does failing to store the entry fail the lookup
+        if let Ok(serialized) = serde_json::to_string(&value) {
+            let _ = conn.execute(
+                "INSERT OR REPLACE INTO lookups (workspace_id, kind, updated_at, value, cached_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![workspace_id, kind, version, serialized, Utc::now().to_rfc3339()],
+            );
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::cell::Cell;
+
+    fn workspace(updated_at: &str) -> Value {
+        json!({ "id": "ws-cached", "attributes": { "updated-at": updated_at } })
+    }
+
+    #[tokio::test]
+    async fn test_lookups_are_cached_until_the_workspace_changes() {
+        let cache = LookupCache::open_in_memory(Duration::hours(1)).unwrap();
+        let fetches = Cell::new(0);
+        let fetch = |owner: &str| {
+            fetches.set(fetches.get() + 1);
+            let owners = vec![owner.to_string()];
+            async move { Ok::<_, Box<dyn Error + Send + Sync>>(owners) }
+        };
+
+        let first: Vec<String> = cache.get_or_fetch(&workspace("2024-05-01T00:00:00Z"), OWNERS, || fetch("ops")).await.unwrap();
+        let second: Vec<String> = cache.get_or_fetch(&workspace("2024-05-01T00:00:00Z"), OWNERS, || fetch("sre")).await.unwrap();
+        assert_eq!((first, second, fetches.get()), (vec!["ops".to_string()], vec!["ops".to_string()], 1));
+
+        let changed: Vec<String> = cache.get_or_fetch(&workspace("2024-05-02T00:00:00Z"), OWNERS, || fetch("sre")).await.unwrap();
+        assert_eq!((changed, fetches.get()), (vec!["sre".to_string()], 2));
+
+        let unkeyed = json!({ "attributes": { "name": "hand-added" } });
+        let _: Vec<String> = cache.get_or_fetch(&unkeyed, OWNERS, || fetch("ops")).await.unwrap();
+        let _: Vec<String> = cache.get_or_fetch(&unkeyed, OWNERS, || fetch("ops")).await.unwrap();
+        assert_eq!(fetches.get(), 4);
+        assert_eq!(cache.stats(), CacheStats::new(1, 4));
+    }
+
+    #[tokio::test]
+    async fn test_state_derived_lookups_follow_state_and_run() {
+        // Entries of any age count, as long as the state version and run are the same
+        let cache = LookupCache::open_in_memory(Duration::zero()).unwrap();
+        let at = |state: &str, run: &str, updated_at: &str| json!({
+            "id": "ws-state-keyed",
+            "attributes": { "updated-at": updated_at },
+            "relationships": {
+                "current-state-version": { "data": { "id": state } },
+                "current-run": { "data": { "id": run } }
+            }
+        });
+        let fetches = Cell::new(0);
+        let fetch = |cost: f64| {
+            fetches.set(fetches.get() + 1);
+            async move { Ok::<_, Box<dyn Error + Send + Sync>>(Some(cost)) }
+        };
+
+        let first: Option<f64> = cache.get_or_fetch(&at("sv-1", "run-1", "2024-05-01T00:00:00Z"), COST, || fetch(12.5)).await.unwrap();
+        // Renaming or retagging changes updated-at but not the state
+        let renamed: Option<f64> = cache.get_or_fetch(&at("sv-1", "run-1", "2024-06-01T00:00:00Z"), COST, || fetch(99.0)).await.unwrap();
+        assert_eq!((first, renamed, fetches.get()), (Some(12.5), Some(12.5), 1));
+
+        let applied: Option<f64> = cache.get_or_fetch(&at("sv-2", "run-2", "2024-06-01T00:00:00Z"), COST, || fetch(30.0)).await.unwrap();
+        assert_eq!((applied, fetches.get()), (Some(30.0), 2));
+        assert_eq!(cache.stats(), CacheStats::new(1, 2));
+        assert_eq!(cache.stats().describe(), "1 of 3 lookups reused from the cache (33.3%)");
+    }
+
+    #[tokio::test]
+    async fn test_unusable_cache_is_a_miss() {
+        let cache = LookupCache::open_in_memory(Duration::hours(1)).unwrap();
+        cache.conn.as_ref().unwrap().execute_batch("DROP TABLE lookups").unwrap();
+
+        for _ in 0..2 {
+            let owners: Vec<String> = cache.get_or_fetch(&workspace("2024-05-01T00:00:00Z"), OWNERS, || async {
+                Ok(vec!["platform".to_string()])
+            }).await.unwrap();
+            assert_eq!(owners, vec!["platform".to_string()]);
+        }
+        assert_eq!(cache.stats(), CacheStats::new(0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_disabled_caches_fetch() {
+        let expired = LookupCache::open_in_memory(Duration::zero()).unwrap();
+        let disabled = LookupCache::from_config(&Config { lookup_cache_minutes: 0, ..Config::default() }).unwrap();
+
+        for cache in [expired, disabled] {
+            let fetches = Cell::new(0);
+            for _ in 0..2 {
+                let _: Vec<String> = cache.get_or_fetch(&workspace("2024-05-01T00:00:00Z"), OWNERS, || {
+                    fetches.set(fetches.get() + 1);
+                    async { Ok(vec!["platform".to_string()]) }
+                }).await.unwrap();
+            }
+            assert_eq!(fetches.get(), 2);
+        }
+    }
+}
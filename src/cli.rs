@@ -0,0 +1,2057 @@
+//! The `tfe_cleanup` command line: parses the arguments and runs the subcommands on the library's
+//! modules.
+
+use crate::{
+    actions, ages, branches, cache, clusters, config, contacts, datadog, debug_bundle, default_project, dependencies,
+    destroy, doctor, entitlements, events, fixtures, hibernate, history, human_activity, i18n, limits,
+    manifest, migrate, inspect, kill_switch, no_vcs, notify, orgs, overrides, plan_exports, profile,
+    pull_requests, redact, registry, report, run_lock, run_summary, servicenow, scan, selftest,
+    script, sinks, staleness, storage, summary, team_access, tfe, timefmt, update, validate,
+    variables, window,
+};
+
+use chrono_tz::Tz;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use csv::Reader;
+use actions::{ActionContext, Category, PipelineResult, Pipelines};
+use ages::AgeStats;
+use cache::LookupCache;
+use config::Config;
+use contacts::Contacts;
+use datadog::Datadog;
+use debug_bundle::DebugLog;
+use destroy::DestroyOptions;
+use events::EventEmitter;
+use history::History;
+use i18n::Lang;
+use human_activity::HumanActivity;
+use kill_switch::KillSwitch;
+use staleness::{Policy, Verdict};
+use limits::{BlastRadius, CircuitBreaker};
+use migrate::MigrateOptions;
+use script::Shell;
+use orgs::OrgFilter;
+use plan_exports::PlanExportOptions;
+use profile::Phase;
+use registry::ProviderOptions;
+use storage::{PruneOptions, Pruned};
+use report::{Column, ReportContext};
+use run_lock::RunLock;
+use run_summary::{Counter, Counters};
+use scan::{PartialScan, Scan};
+use servicenow::ServiceNow;
+use team_access::TeamScope;
+use tfe::{OrgTotals, TfeClient};
+use window::DeletionWindow;
+
+#[derive(Parser)]
+#[command(name = "tfe_cleanup", about = "Cleanup TFE workspaces that have been unused for more than 90 days")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Config file (defaults to tfe_cleanup.toml in the working directory, if present)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Time zone for displayed timestamps, e.g. Europe/Berlin
+    #[arg(long, global = true, default_value = "UTC")]
+    timezone: Tz,
+
+    /// Write redacted API request metadata, timings, the effective config and decision traces
+    /// to this zip, e.g. to attach to a support ticket
+    #[arg(long, global = true, value_name = "FILE")]
+    debug_bundle: Option<PathBuf>,
+
+    /// Write per-phase timings (listing, enrichment, filtering, reporting) and API latency
+    /// percentiles to this JSON file, e.g. to tune concurrency for large organizations
+    #[arg(long, global = true, value_name = "FILE")]
+    profile: Option<PathBuf>,
+
+    /// Format of the summary (duration, API calls, workspaces scanned, flagged and deleted)
+    /// printed to stdout at the end of the run. Defaults to text, or to off for `scan --output json`
+    #[arg(long, global = true, value_enum)]
+    summary: Option<SummaryFormat>,
+
+    /// Language of prompts, report headers and notifications
+    #[arg(long, global = true, value_enum, default_value_t = Lang::En)]
+    lang: Lang,
+
+    /// Checked before every destructive action of any command: the run aborts unless the URL
+    /// answers with a success status and a body other than `stop`
+    #[arg(long, global = true, value_name = "URL")]
+    kill_switch_url: Option<String>,
+
+    #[command(flatten)]
+    cleanup: CleanupArgs,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SummaryFormat {
+    Text,
+    Json,
+    Off,
+}
+
+impl Cli {
+    /// The `--summary` format, or the default: off when stdout is already JSON.
+    fn summary_format(&self) -> SummaryFormat {
+        match (self.summary, &self.command) {
+            (Some(format), _) => format,
+            (None, Some(Commands::Scan(ScanArgs { output: OutputFormat::Json, .. }))) => SummaryFormat::Off,
+            (None, _) => SummaryFormat::Text,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    /// Columns of the CSV report, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = report::DEFAULT_COLUMNS.to_vec())]
+    columns: Vec<Column>,
+}
+
+#[derive(Args)]
+struct OrgArgs {
+    /// Only cover this organization; repeat for several (defaults to every organization visible
+    /// to the token)
+    #[arg(long = "org", value_name = "ORG")]
+    orgs: Vec<String>,
+    /// Only cover organizations whose name matches this regular expression
+    #[arg(long, value_name = "REGEX")]
+    org_regex: Option<Regex>,
+    /// Only cover the workspaces this team has admin access to, e.g. when a team runs its own
+    /// cleanup with a team token
+    #[arg(long, value_name = "NAME")]
+    team: Option<String>,
+}
+
+impl OrgArgs {
+    fn filter(&self, config: &Config) -> OrgFilter {
+        OrgFilter::new(self.orgs.clone(), self.org_regex.clone(), &config.organizations)
+    }
+}
+
+#[derive(Args)]
+struct ScanArgs {
+    /// Show every workspace with the rules evaluated to flag, keep or exclude it
+    #[arg(long)]
+    explain: bool,
+    /// Format of the results written to stdout; progress always goes to stderr
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    #[command(flatten)]
+    report: ReportArgs,
+    #[command(flatten)]
+    orgs: OrgArgs,
+    /// Development aid: write the scanned workspaces, secrets scrubbed, to DIR/workspaces.json
+    /// as fixtures for the report snapshot tests
+    #[arg(long, value_name = "DIR", hide = true)]
+    fixtures: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CleanupArgs {
+    #[command(flatten)]
+    report: ReportArgs,
+    #[command(flatten)]
+    orgs: OrgArgs,
+    /// Download and verify each workspace's current state into this directory before deleting it
+    #[arg(long)]
+    archive_state: Option<PathBuf>,
+    /// terraform binary for deleting workspaces the API can't, e.g. ones without an organization
+    /// in the CSV. Without it, the cleanup runs no external commands
+    #[arg(long, value_name = "PATH")]
+    terraform_bin: Option<PathBuf>,
+    /// On TFE releases without safe delete, delete workspaces reporting no resources through the
+    /// plain API. A run applying between the check and the deletion leaves its resources orphaned
+    #[arg(long)]
+    delete_if_empty: bool,
+    /// Outside the configured deletion windows, wait for the next window instead of exiting
+    #[arg(long)]
+    wait_for_window: bool,
+    /// Abort if more than this many workspaces of one organization would be deleted
+    #[arg(long)]
+    max_deletions: Option<usize>,
+    /// Abort if more than this percentage of an organization's workspaces would be deleted
+    #[arg(long)]
+    max_percent: Option<f64>,
+    /// Open a ServiceNow change request with the deletion plan and wait for its approval before deleting
+    #[arg(long)]
+    change_request: bool,
+    /// Give up on a change request not approved within this many minutes
+    #[arg(long, value_name = "MINUTES", default_value_t = servicenow::DEFAULT_MAX_WAIT_MINUTES, requires = "change_request")]
+    change_request_timeout: u64,
+    /// Only act on workspaces flagged by at least this many consecutive scans; scan and cleanup
+    /// runs both count
+    #[arg(long, value_name = "N")]
+    min_streak: Option<u32>,
+    /// Write the planned actions to this script instead of running them: a PowerShell script if
+    /// it ends in .ps1, a POSIX shell script otherwise
+    #[arg(long, value_name = "FILE")]
+    emit_script: Option<PathBuf>,
+    /// Stop acting on an organization after this many consecutive workspaces failed; other
+    /// organizations carry on. 0 never stops
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    max_failures_per_org: u32,
+    /// Clean up without asking; required when stdin is not a terminal
+    #[arg(long, conflicts_with = "no_cleanup")]
+    yes: bool,
+    /// Only scan and report, without asking to clean up
+    #[arg(long)]
+    no_cleanup: bool,
+}
+
+#[derive(Args)]
+struct DestroyArgs {
+    /// Print the schedule without queuing any run
+    #[arg(long)]
+    dry_run: bool,
+    /// Run slots of each organization kept free for regular deploys
+    #[arg(long, default_value_t = 1)]
+    reserve_slots: usize,
+    /// Expected duration of one destroy run in minutes, used to space out the waves
+    #[arg(long, default_value_t = 10)]
+    run_minutes: u64,
+    /// Outside the configured deletion windows, wait for the next window instead of exiting
+    #[arg(long)]
+    wait_for_window: bool,
+    /// Abort if more than this many workspaces of one organization would be destroyed
+    #[arg(long)]
+    max_deletions: Option<usize>,
+    /// Abort if more than this percentage of an organization's workspaces would be destroyed
+    #[arg(long)]
+    max_percent: Option<f64>,
+    /// Open a ServiceNow change request with the plan and wait for its approval before queuing
+    #[arg(long)]
+    change_request: bool,
+    /// Give up on a change request not approved within this many minutes
+    #[arg(long, value_name = "MINUTES", default_value_t = servicenow::DEFAULT_MAX_WAIT_MINUTES, requires = "change_request")]
+    change_request_timeout: u64,
+    /// Queue without asking; required when stdin is not a terminal
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List stale workspaces and write the CSV without changing anything
+    Scan(ScanArgs),
+    /// List stale workspaces, write the CSV and interactively clean them up (the default)
+    Cleanup(CleanupArgs),
+    /// Delete plan exports belonging to runs older than a number of days
+    PlanExports {
+        /// Organization to process (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Only runs created more than this many days ago are considered
+        #[arg(long, default_value_t = 30)]
+        older_than_days: i64,
+        /// Print what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Maximum number of plan exports to delete per workspace
+        #[arg(long)]
+        per_workspace_limit: Option<usize>,
+    },
+    /// Soft-delete the stored data of old state and configuration versions (TFE only)
+    PruneVersions {
+        /// Organization to process (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Newest state and configuration versions kept in every workspace, whatever their age
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..))]
+        keep: u16,
+        /// Only versions made more than this many days ago are pruned
+        #[arg(long, default_value_t = 90)]
+        older_than_days: i64,
+        /// Print what would be pruned without pruning anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Queue destroy runs for the workspaces in the CSV, staggered to leave room for regular runs
+    Destroy(DestroyArgs),
+    /// Delete old private registry provider versions that no workspace lock file references
+    Providers {
+        /// Organization to process (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Only versions published more than this many days ago are considered
+        #[arg(long, default_value_t = 180)]
+        older_than_days: i64,
+        /// Print what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also delete GPG signing keys that no remaining provider version uses
+        #[arg(long)]
+        delete_unused_keys: bool,
+    },
+    /// Rename a workspace or move it to another project instead of deleting it
+    Migrate {
+        /// Workspace to migrate, as <org>/<workspace>
+        workspace: String,
+        /// New name for the workspace
+        #[arg(long)]
+        new_name: Option<String>,
+        /// Name of the project to move the workspace into
+        #[arg(long)]
+        project: Option<String>,
+        /// Grant teams that had access through the old project direct access to the workspace
+        #[arg(long)]
+        transfer_team_access: bool,
+    },
+    /// Report sensitive variables that haven't been rotated recently
+    StaleSecrets {
+        /// Organization to scan (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Sensitive variables unchanged for more than this many days are reported
+        #[arg(long, default_value_t = 180)]
+        rotation_days: i64,
+    },
+    /// Report workspaces without a VCS connection that no pipeline has run recently
+    NoVcs {
+        /// Organization to scan (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Workspaces with an API-driven run within this many days are left out
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+    },
+    /// Report VCS-backed workspaces tracking branches that no longer exist; reads GITHUB_TOKEN
+    /// and GITLAB_TOKEN
+    DeletedBranches {
+        /// Organization to scan (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+    },
+    /// Report workspace admin grants to teams that no longer exist or are unused, optionally revoking them
+    TeamAccess {
+        /// Organization to scan (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Teams without members whose token hasn't been used for this many days count as unused
+        #[arg(long, default_value_t = 90)]
+        inactive_days: i64,
+        /// Revoke the reported grants after confirmation
+        #[arg(long)]
+        revoke: bool,
+        /// Revoke without asking; required when stdin is not a terminal
+        #[arg(long, requires = "revoke")]
+        yes: bool,
+    },
+    /// Report workspaces left in their organization's default project, grouped by owner
+    DefaultProject {
+        /// Organization to scan (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+        /// Move the reported workspaces into this project after confirmation, granting teams
+        /// with access through the default project direct access
+        #[arg(long, value_name = "PROJECT")]
+        move_to_project: Option<String>,
+        /// Move without asking; required when stdin is not a terminal
+        #[arg(long, requires = "move_to_project")]
+        yes: bool,
+    },
+    /// Re-apply a workspace hibernated by the `hibernate` action and remove its hibernated tag
+    Wake {
+        /// Workspace to wake, as <org>/<workspace>
+        workspace: String,
+    },
+    /// List workspaces that aren't in a manifest of expected workspaces, and expected ones that don't exist
+    Manifest {
+        /// YAML file mapping each organization to the names of its expected workspaces
+        manifest: PathBuf,
+        /// Organization to compare (defaults to every organization in the manifest)
+        #[arg(long)]
+        org: Option<String>,
+    },
+    /// Organization-level reports
+    Org {
+        #[command(subcommand)]
+        command: OrgCommand,
+    },
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Check connectivity, the token, rate-limit headroom, entitlements and output paths
+    Doctor {
+        /// Organization whose entitlements to check (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+    },
+    /// Group workspaces by naming pattern, e.g. `app-pr-<n>`, and suggest exclude or include
+    /// rules for each group
+    Clusters {
+        /// Smallest number of workspaces reported as a group
+        #[arg(long, default_value_t = 3)]
+        min_size: usize,
+        #[command(flatten)]
+        orgs: OrgArgs,
+    },
+    /// Create throwaway workspaces in a sandbox organization and run them through the scan,
+    /// quarantine, delete and restore lifecycle, verifying each step
+    Selftest {
+        /// Dedicated sandbox organization; refused if it has workspaces not created by selftest
+        #[arg(long)]
+        org: String,
+        /// Number of throwaway workspaces
+        #[arg(long, default_value_t = 2)]
+        workspaces: usize,
+    },
+    /// Replace this binary with the latest release after verifying its checksum
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+    /// Show everything known about one workspace and why it is or isn't flagged as stale
+    Inspect {
+        /// Workspace to inspect, as <org>/<workspace>
+        workspace: String,
+    },
+    /// Write per-organization metrics (workspaces, stale %, age, resources, spend) as JSON
+    Export {
+        /// Path of the JSON file to write, e.g. summary.json
+        path: PathBuf,
+        /// Organization to summarize (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum OrgCommand {
+    /// Show entitlement usage (workspaces, users, concurrency) against each organization's
+    /// limits, and how much the stale workspaces take up
+    Report {
+        /// Organization to report on (defaults to every organization visible to the token)
+        #[arg(long)]
+        org: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Check the config file and print the effective configuration, defaults filled in
+    Validate {
+        /// Also check that notification channels and report sinks can be reached
+        #[arg(long)]
+        probe: bool,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum CleanupChoice {
+    Delete,
+    Migrate,
+    Skip,
+}
+
+/// Runs the command line given to the process and returns its exit code.
+pub async fn main() -> ExitCode {
+    redact::install_panic_hook();
+
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is::<PartialScan>() => {
+            eprintln!("Error: {}; the results above cover the other organizations only.", e);
+            ExitCode::from(PartialScan::EXIT_CODE)
+        }
+        Err(e) => {
+            // Errors may quote URLs and API responses, so they are scrubbed like panics
+            eprintln!("Error: {}", redact::scrub(&e.to_string()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the command and ends with the summary of what the run did, whichever command it was.
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let started = Instant::now();
+    i18n::set(cli.lang);
+    let summary_format = cli.summary_format();
+    let counters = Arc::new(Counters::new());
+
+    let result = match cli.command {
+        Some(Commands::SelfUpdate { check }) => run_self_update(check).await,
+        _ => {
+            update::notify_if_outdated(update::GITHUB_API).await;
+            match cli.command {
+                Some(Commands::Config { command: ConfigCommand::Validate { probe } }) => run_config_validate(cli.config.as_deref(), probe).await,
+                _ => run_against_tfe(cli, counters.clone(), started).await,
+            }
+        }
+    };
+
+    let summary = counters.summary(started);
+    match summary_format {
+        SummaryFormat::Text => print!("{}", summary.render()),
+        SummaryFormat::Json => println!("{}", summary.to_json()),
+        SummaryFormat::Off => {}
+    }
+    result
+}
+
+/// Loads the config and runs a command against TFE, counting into `counters`, then writes the
+/// profile and debug bundle if asked for.
+async fn run_against_tfe(cli: Cli, counters: Arc<Counters>, started: Instant) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = Config::load(cli.config.as_deref())?;
+    // Doctor diagnoses broken setups, so an unreadable sheet mustn't keep it from running
+    if !matches!(cli.command, Some(Commands::Doctor { .. })) {
+        overrides::apply(&mut config).await?;
+    }
+    let policy = Policy::from_config(&config)?;
+
+    // Obtain TFE token (and optional TFE_ADDRESS) from environment
+    let debug_log = cli.debug_bundle.as_ref().map(|_| Arc::new(DebugLog::default()));
+    let client = TfeClient::from_env()?.with_debug_log(debug_log.clone()).with_counters(counters);
+    let debug_bundle = cli.debug_bundle.clone();
+    let profile_path = cli.profile.clone();
+    if profile_path.is_some() {
+        profile::enable();
+    }
+
+    let result = run_command(cli, &config, &policy, &client).await;
+    if let Some(path) = profile_path {
+        let report = serde_json::to_string_pretty(&profile::report(started))?;
+        match std::fs::write(&path, report + "\n") {
+            Ok(()) => eprintln!("Profile written to {}", path.display()),
+            Err(e) => eprintln!("Warning: could not write the profile {}: {}", path.display(), e),
+        }
+    }
+    if let (Some(path), Some(log)) = (debug_bundle, debug_log) {
+        match debug_bundle::write(&path, &log, &config, &result) {
+            Ok(()) => eprintln!("Debug bundle written to {}", path.display()),
+            Err(e) => eprintln!("Warning: could not write the debug bundle {}: {}", path.display(), e),
+        }
+    }
+    result
+}
+
+async fn run_command(cli: Cli, config: &Config, policy: &Policy, client: &TfeClient) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Subcommands working on one organization, or all of them, still honor the config's lists
+    let single_org = |org: Option<String>| OrgFilter::new(org.into_iter().collect(), None, &config.organizations);
+    let kill_switch = cli.kill_switch_url.as_deref().map(KillSwitch::new).transpose()?;
+    let kill_switch = kill_switch.as_ref();
+
+    match cli.command {
+        Some(Commands::PlanExports { org, older_than_days, dry_run, per_workspace_limit }) => {
+            let options = PlanExportOptions { older_than_days, dry_run, per_workspace_limit };
+            run_plan_exports(client, config, kill_switch, &single_org(org), &options).await
+        }
+        Some(Commands::PruneVersions { org, keep, older_than_days, dry_run }) => {
+            let options = PruneOptions { keep: keep.into(), older_than_days, dry_run };
+            run_prune_versions(client, config, kill_switch, &single_org(org), &options).await
+        }
+        Some(Commands::Destroy(args)) => {
+            run_destroy(client, config, kill_switch, &args).await
+        }
+        Some(Commands::Providers { org, older_than_days, dry_run, delete_unused_keys }) => {
+            let options = ProviderOptions { older_than_days, dry_run, delete_unused_keys };
+            run_providers(client, config, kill_switch, &single_org(org), &options).await
+        }
+        Some(Commands::Migrate { workspace, new_name, project, transfer_team_access }) => {
+            let (org, name) = tfe::parse_workspace_ref(&workspace)?;
+            let options = MigrateOptions { new_name, project, transfer_team_access };
+            migrate::migrate_workspace(client, &org, &name, &options).await
+        }
+        Some(Commands::StaleSecrets { org, rotation_days }) => run_stale_secrets(client, &single_org(org), rotation_days).await,
+        Some(Commands::NoVcs { org, days }) => run_no_vcs(client, &single_org(org), days).await,
+        Some(Commands::DeletedBranches { org }) => run_deleted_branches(client, &single_org(org)).await,
+        Some(Commands::Manifest { manifest, org }) => run_manifest(client, config, &manifest, org).await,
+        Some(Commands::TeamAccess { org, inactive_days, revoke, yes }) => {
+            run_team_access(client, config, kill_switch, &single_org(org), inactive_days, revoke, yes).await
+        }
+        Some(Commands::DefaultProject { org, move_to_project, yes }) => {
+            run_default_project(client, config, kill_switch, &single_org(org), move_to_project, yes).await
+        }
+        Some(Commands::Wake { workspace }) => {
+            let (org, name) = tfe::parse_workspace_ref(&workspace)?;
+            let history = History::open(&config.history_db)?;
+            let (run_id, configuration_version) = hibernate::wake(client, &history, &org, &name).await?;
+            println!("Queued apply run {} for {}/{} ({})", run_id, org, name,
+                configuration_version.map_or("latest configuration".to_string(), |cv| format!("configuration version {}", cv)));
+            Ok(())
+        }
+        Some(Commands::Doctor { org }) => {
+            // Listing organizations needs a working token; without one, only check the rest
+            let orgs = orgs::discover(client, &single_org(org)).await.unwrap_or_default();
+            let checks = doctor::run_checks(client, config, &orgs).await?;
+            print!("{}", doctor::render(&checks));
+            let failed = checks.iter().filter(|check| !check.passed()).count();
+            if failed > 0 {
+                return Err(format!("{} of {} checks failed", failed, checks.len()).into());
+            }
+            Ok(())
+        }
+        Some(Commands::Clusters { min_size, orgs }) => {
+            let mut names = Vec::new();
+            let scan = scan_workspaces(client, config, &orgs, policy, Utc::now(), |workspace, verdict| {
+                names.push((workspace["attributes"]["name"].as_str().unwrap_or("").to_string(), verdict.is_stale()));
+            }).await?;
+            print!("{}", clusters::render(&clusters::cluster(&names, min_size)));
+            PartialScan::check(&scan)
+        }
+        Some(Commands::Selftest { org, workspaces }) => {
+            if let Some(reason) = single_org(None).skip_reason(&org) {
+                return Err(format!("organization {} is {}", org, reason).into());
+            }
+            let checks = selftest::run(client, &org, &selftest::workspace_names(workspaces), &config.actions.tag).await?;
+            print!("{}", doctor::render(&checks));
+            if let Some(failed) = checks.iter().find(|check| !check.passed()) {
+                return Err(format!("selftest failed at {}", failed.name).into());
+            }
+            Ok(())
+        }
+        // Run by `run` before connecting to TFE, and need no connection if they get here
+        Some(Commands::SelfUpdate { check }) => run_self_update(check).await,
+        Some(Commands::Config { command: ConfigCommand::Validate { probe } }) => run_config_validate(cli.config.as_deref(), probe).await,
+        Some(Commands::Inspect { workspace }) => {
+            let (org, name) = tfe::parse_workspace_ref(&workspace)?;
+            let inspection = inspect::inspect(client, config, policy, &org, &name).await?;
+            print!("{}", inspect::render(&inspection, cli.timezone));
+            Ok(())
+        }
+        Some(Commands::Org { command: OrgCommand::Report { org } }) => {
+            let mut report = Vec::new();
+            for org in &orgs::discover(client, &single_org(org)).await? {
+                report.push(entitlements::org_usage(client, org, policy).await?);
+            }
+            print!("{}", entitlements::render(&report));
+            Ok(())
+        }
+        Some(Commands::Export { path, org }) => {
+            let orgs = orgs::discover(client, &single_org(org)).await?;
+            let summary = summary::build_summary(client, &LookupCache::from_config(config)?, &orgs, policy).await?;
+            summary::write_summary(&summary, &path)?;
+            eprintln!("Summary of {} organizations written to {}", summary.organizations.len(), path.display());
+            Ok(())
+        }
+        Some(Commands::Scan(ScanArgs { fixtures: Some(dir), orgs, .. })) => {
+            let mut workspaces = Vec::new();
+            scan_workspaces(client, config, &orgs, policy, Utc::now(), |workspace, _| workspaces.push(workspace.clone())).await?;
+            scan::sort_by_org_and_name(&mut workspaces);
+            fixtures::write(&dir, &workspaces)?;
+            eprintln!("{} workspaces written to {}", workspaces.len(), dir.join("workspaces.json").display());
+            Ok(())
+        }
+        Some(Commands::Scan(args)) => run_scan(client, config, policy, &args, cli.timezone).await,
+        Some(Commands::Cleanup(args)) => run_interactive_cleanup(client, config, policy, kill_switch, &args, cli.timezone).await,
+        None => run_interactive_cleanup(client, config, policy, kill_switch, &cli.cleanup, cli.timezone).await,
+    }
+}
+
+async fn run_self_update(check: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let release = update::latest_release(update::GITHUB_API, std::time::Duration::from_secs(30)).await?;
+    if !update::is_newer(&release.tag) {
+        println!("tfe_cleanup {} is up to date (latest release {}).", env!("CARGO_PKG_VERSION"), release.tag);
+        return Ok(());
+    }
+    if check {
+        println!("tfe_cleanup {} is available (this is {}).", release.tag, env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    let public_key = update::RELEASE_PUBLIC_KEY
+        .ok_or("this build has no release signing key to verify releases with; install the new release by hand")?;
+    eprintln!("Downloading tfe_cleanup {}...", release.tag);
+    let binary = update::download_verified(&release, public_key).await?;
+    update::replace_executable(&std::env::current_exe()?, &binary)?;
+    println!("Updated tfe_cleanup from {} to {}.", env!("CARGO_PKG_VERSION"), release.tag);
+    Ok(())
+}
+
+async fn run_config_validate(path: Option<&Path>, probe: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::load(path)?;
+    match Config::resolve_path(path) {
+        Some(path) => eprintln!("Checking {}", path.display()),
+        None => eprintln!("No config file; checking the defaults"),
+    }
+
+    let mut problems = validate::problems(&config);
+    if probe {
+        for (target, outcome) in validate::probe(&config).await {
+            match outcome {
+                Ok(()) => eprintln!("Reachable: {}", target),
+                Err(e) => problems.push(format!("{} unreachable: {}", target, e)),
+            }
+        }
+    }
+    for problem in &problems {
+        eprintln!("Problem: {}", problem);
+    }
+
+    // Secrets serialize redacted
+    print!("{}", toml::to_string_pretty(&config)?);
+    if !problems.is_empty() {
+        let plural = if problems.len() == 1 { "" } else { "s" };
+        return Err(format!("{} problem{} in config", problems.len(), plural).into());
+    }
+    eprintln!("Config is valid.");
+    Ok(())
+}
+
+async fn run_plan_exports(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    orgs: &OrgFilter,
+    options: &PlanExportOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let orgs = orgs::discover(client, orgs).await?;
+    let cleanup = async {
+        let mut total = 0;
+        for org in &orgs {
+            total += plan_exports::cleanup_plan_exports(client, kill_switch, org, options).await?;
+        }
+        Ok(total)
+    };
+    let total = match options.dry_run {
+        true => cleanup.await?,
+        false => with_run_lock(client, config, kill_switch, &orgs, cleanup).await?,
+    };
+
+    if options.dry_run {
+        println!("{}", i18n::format("plan-exports-would-delete", [("count", total.into())]));
+    } else {
+        println!("{}", i18n::format("plan-exports-deleted", [("count", total.into())]));
+    }
+
+    Ok(())
+}
+
+async fn run_destroy(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    args: &DestroyArgs,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = DestroyOptions { dry_run: args.dry_run, reserve_slots: args.reserve_slots, run_minutes: args.run_minutes };
+    let mut by_org: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (org, name) in read_queued_workspaces("old_inactive_accounts.csv")? {
+        if org.is_empty() {
+            return Err("old_inactive_accounts.csv has no Organization column; include 'org' in --columns".into());
+        }
+        by_org.entry(org).or_default().push(name);
+    }
+
+    let windows = window::parse_windows(&config.deletion_windows)?;
+    let destroy = async {
+        for (org, names) in &by_org {
+            let schedule = destroy::schedule(client, org, names, &options).await?;
+            destroy::run_schedule(client, kill_switch, &windows, org, &schedule, &options).await?;
+        }
+        Ok(())
+    };
+    if options.dry_run {
+        return destroy.await;
+    }
+
+    // Gated like the cleanup's deletions: destroyed resources are as gone as deleted workspaces
+    let ask = confirmation_needed(args.yes, io::stdin().is_terminal(), "destroy runs")?;
+    if !wait_for_deletion_window(&windows, args.wait_for_window).await {
+        eprintln!("{}", i18n::text("window-closed-nothing-queued"));
+        eprintln!("{}", i18n::format("window-next-open", [("at", window::next_open(&windows, Utc::now()).to_rfc3339().into())]));
+        return Ok(());
+    }
+
+    let planned: Vec<Value> = by_org.iter()
+        .flat_map(|(org, names)| names.iter().map(move |name| json!({
+            "attributes": { "name": name },
+            "relationships": { "organization": { "data": { "id": org } } }
+        })))
+        .collect();
+    let mut totals = OrgTotals::new();
+    for org in by_org.keys() {
+        totals.insert(org.clone(), tfe::count_workspaces(client, org).await?);
+    }
+    let limits = BlastRadius { max_deletions: args.max_deletions, max_percent: args.max_percent };
+    limits::check(&limits, &planned, &totals)?;
+
+    if ask {
+        let stdin = io::stdin();
+        let answer = prompt_line(&mut stdin.lock(), &format!("{} ", i18n::format("prompt-queue-destroy", [("count", planned.len().into())])))?;
+        if answer.as_deref() != Some("y") {
+            eprintln!("{}", i18n::text("nothing-queued"));
+            return Ok(());
+        }
+    }
+    if args.change_request {
+        let servicenow = ServiceNow::from_env()?;
+        let change = servicenow.create_change_request(&planned).await?;
+        eprintln!("{}", i18n::format("change-request-created", [("number", change.number.as_str().into())]));
+        servicenow.wait_for_approval(&change, servicenow::POLL_INTERVAL, Duration::from_secs(args.change_request_timeout * 60)).await?;
+        eprintln!("{}", i18n::format("change-request-approved", [("number", change.number.as_str().into())]));
+    }
+
+    with_run_lock(client, config, kill_switch, &by_org.keys().cloned().collect::<Vec<_>>(), destroy).await
+}
+
+async fn run_prune_versions(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    orgs: &OrgFilter,
+    options: &PruneOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let orgs = orgs::discover(client, orgs).await?;
+    let history = History::open(&config.history_db)?;
+    let prune = async {
+        let mut total = Pruned::default();
+        for org in &orgs {
+            let pruned = storage::prune_versions(client, &history, kill_switch, org, options).await?;
+            total.state_versions += pruned.state_versions;
+            total.state_bytes += pruned.state_bytes;
+            total.configuration_versions += pruned.configuration_versions;
+        }
+        Ok(total)
+    };
+    let total = match options.dry_run {
+        true => prune.await?,
+        false => with_run_lock(client, config, kill_switch, &orgs, prune).await?,
+    };
+
+    let message = if options.dry_run { "versions-would-prune" } else { "versions-pruned" };
+    println!("{}", i18n::format(message, [
+        ("states", total.state_versions.into()),
+        ("size", storage::format_bytes(total.state_bytes).into()),
+        ("configurations", total.configuration_versions.into()),
+    ]));
+    Ok(())
+}
+
+async fn run_providers(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    orgs: &OrgFilter,
+    options: &ProviderOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let orgs = orgs::discover(client, orgs).await?;
+    let cleanup = async {
+        let (mut versions, mut keys) = (0, 0);
+        for org in &orgs {
+            let cleanup = registry::cleanup_providers(client, kill_switch, org, options).await?;
+            versions += cleanup.versions;
+            keys += cleanup.keys;
+        }
+        Ok((versions, keys))
+    };
+    let (versions, keys) = match options.dry_run {
+        true => cleanup.await?,
+        false => with_run_lock(client, config, kill_switch, &orgs, cleanup).await?,
+    };
+
+    if options.dry_run {
+        println!("{}", i18n::format("providers-would-delete", [("versions", versions.into()), ("keys", keys.into())]));
+    } else {
+        println!("{}", i18n::format("providers-deleted", [("versions", versions.into()), ("keys", keys.into())]));
+    }
+
+    Ok(())
+}
+
+async fn run_stale_secrets(
+    client: &TfeClient,
+    orgs: &OrgFilter,
+    rotation_days: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut findings = Vec::new();
+    for org in &orgs::discover(client, orgs).await? {
+        findings.extend(variables::find_stale_secrets(client, org, rotation_days).await?);
+    }
+
+    eprintln!("{}", i18n::format("stale-secrets", [("days", rotation_days.into())]));
+    for finding in &findings {
+        println!("{}", i18n::format("stale-secret", [
+            ("workspace", format!("{}/{}", finding.org, finding.workspace).into()),
+            ("key", finding.key.as_str().into()),
+            ("category", finding.category.as_str().into()),
+            ("changed", finding.last_changed.as_str().into()),
+        ]));
+    }
+
+    variables::create_stale_secrets_csv(&findings, "stale_sensitive_variables.csv")?;
+    eprintln!("{}", i18n::format("csv-created", [("file", "stale_sensitive_variables.csv".into())]));
+
+    Ok(())
+}
+
+/// A finding's last activity for the listings, "never" when it has none.
+fn last_activity_or_never(last_activity: &str) -> String {
+    match last_activity {
+        "" => i18n::text("last-activity-never"),
+        _ => last_activity.to_string(),
+    }
+}
+
+async fn run_no_vcs(
+    client: &TfeClient,
+    orgs: &OrgFilter,
+    days: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut findings = Vec::new();
+    for org in &orgs::discover(client, orgs).await? {
+        findings.extend(no_vcs::find_no_vcs_workspaces(client, org, days).await?);
+    }
+
+    eprintln!("{}", i18n::format("no-vcs", [("days", days.into())]));
+    for finding in &findings {
+        println!("{}", i18n::format("workspace-last-activity", [
+            ("workspace", format!("{}/{}", finding.org, finding.workspace).into()),
+            ("when", last_activity_or_never(&finding.last_activity).into()),
+        ]));
+    }
+
+    no_vcs::create_no_vcs_csv(&findings, "no_vcs_workspaces.csv")?;
+    eprintln!("{}", i18n::format("csv-created", [("file", "no_vcs_workspaces.csv".into())]));
+
+    Ok(())
+}
+
+async fn run_deleted_branches(client: &TfeClient, orgs: &OrgFilter) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let providers = branches::Providers::from_env();
+    if providers.github.is_none() && providers.gitlab.is_none() {
+        return Err("set GITHUB_TOKEN and/or GITLAB_TOKEN to check branches".into());
+    }
+
+    let (mut findings, mut skipped) = (Vec::new(), 0);
+    for org in &orgs::discover(client, orgs).await? {
+        let (found, unsupported) = branches::find_deleted_branches(client, &providers, org).await?;
+        findings.extend(found);
+        skipped += unsupported;
+    }
+
+    eprintln!("{}", i18n::text("deleted-branches"));
+    for finding in &findings {
+        println!("{}/{}: {}@{} ({})", finding.org, finding.workspace, finding.repository, finding.branch, finding.missing.label());
+    }
+    if skipped > 0 {
+        eprintln!("{}", i18n::format("deleted-branches-skipped", [("count", skipped.into())]));
+    }
+
+    branches::create_deleted_branches_csv(&findings, "deleted_branches.csv")?;
+    eprintln!("{}", i18n::format("csv-created", [("file", "deleted_branches.csv".into())]));
+
+    Ok(())
+}
+
+async fn run_manifest(
+    client: &TfeClient,
+    config: &Config,
+    path: &Path,
+    org: Option<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let manifest = manifest::load(path)?;
+    let selected = match org {
+        Some(org) if !manifest.contains_key(&org) => return Err(format!("organization {} is not in the manifest", org).into()),
+        Some(org) => vec![org],
+        None => manifest.keys().cloned().collect(),
+    };
+
+    let mut findings = Vec::new();
+    for org in &orgs::discover(client, &OrgFilter::new(selected, None, &config.organizations)).await? {
+        let workspaces = tfe::list_workspaces(client, org).await?;
+        findings.extend(manifest::compare(org, &workspaces, &manifest[org]));
+    }
+
+    eprintln!("{}", i18n::format("manifest-differences", [("path", path.display().to_string().into())]));
+    for finding in &findings {
+        match finding.difference {
+            manifest::Difference::Unlisted => println!("{}", i18n::format("manifest-unlisted", [
+                ("workspace", format!("{}/{}", finding.org, finding.workspace).into()),
+                ("difference", finding.difference.label().into()),
+                ("when", last_activity_or_never(&finding.last_activity).into()),
+            ])),
+            manifest::Difference::Missing => println!("{}/{}: {}", finding.org, finding.workspace, finding.difference.label()),
+        }
+    }
+
+    manifest::create_manifest_csv(&findings, "manifest_differences.csv")?;
+    eprintln!("{}", i18n::format("csv-created", [("file", "manifest_differences.csv".into())]));
+
+    Ok(())
+}
+
+async fn run_team_access(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    orgs: &OrgFilter,
+    inactive_days: i64,
+    revoke: bool,
+    yes: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Decided before the listing, so a run that can't ask fails straight away
+    let ask = revoke && confirmation_needed(yes, io::stdin().is_terminal(), "revocation")?;
+    let orgs = orgs::discover(client, orgs).await?;
+    let mut findings = Vec::new();
+    for org in &orgs {
+        findings.extend(team_access::find_stale_admin_grants(client, org, inactive_days).await?);
+    }
+
+    eprintln!("{}", i18n::format("stale-grants", [("days", inactive_days.into())]));
+    for finding in &findings {
+        println!("{}", i18n::format("stale-grant", [
+            ("workspace", format!("{}/{}", finding.org, finding.workspace).into()),
+            ("id", finding.team_id.as_str().into()),
+            ("team", finding.team_name.as_str().into()),
+            ("reason", finding.reason.as_str().into()),
+        ]));
+    }
+
+    if !revoke || findings.is_empty() {
+        return Ok(());
+    }
+
+    if ask {
+        let stdin = io::stdin();
+        let answer = prompt_line(&mut stdin.lock(), &format!("{} ", i18n::format("prompt-revoke-grants", [("count", findings.len().into())])))?;
+        if answer.as_deref() != Some("y") {
+            eprintln!("{}", i18n::text("nothing-revoked"));
+            return Ok(());
+        }
+    }
+
+    with_run_lock(client, config, kill_switch, &orgs, async {
+        for finding in &findings {
+            kill_switch::check(kill_switch).await?;
+            team_access::revoke(client, finding).await?;
+            println!("{}", i18n::format("grant-revoked", [
+                ("id", finding.team_id.as_str().into()),
+                ("workspace", format!("{}/{}", finding.org, finding.workspace).into()),
+            ]));
+        }
+        Ok(())
+    }).await
+}
+
+async fn run_default_project(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    orgs: &OrgFilter,
+    move_to_project: Option<String>,
+    yes: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Decided before the listing, so a run that can't ask fails straight away
+    let ask = match move_to_project {
+        Some(_) => confirmation_needed(yes, io::stdin().is_terminal(), "move")?,
+        None => false,
+    };
+    let cache = LookupCache::from_config(config)?;
+    let orgs = orgs::discover(client, orgs).await?;
+    let mut findings = Vec::new();
+    for org in &orgs {
+        findings.extend(default_project::find_default_project_workspaces(client, &cache, config, org).await?);
+    }
+
+    eprintln!("{}", i18n::text("default-project-by-owner"));
+    for (owner, workspaces) in default_project::by_owner(&findings) {
+        println!("{}:", owner);
+        for finding in workspaces {
+            println!("  {}", i18n::format("workspace-last-activity", [
+                ("workspace", format!("{}/{}", finding.org, finding.workspace).into()),
+                ("when", last_activity_or_never(&finding.last_activity).into()),
+            ]));
+        }
+    }
+
+    default_project::create_default_project_csv(&findings, "default_project_workspaces.csv")?;
+    eprintln!("{}", i18n::format("csv-created", [("file", "default_project_workspaces.csv".into())]));
+
+    let Some(project) = move_to_project else { return Ok(()) };
+    if findings.is_empty() {
+        return Ok(());
+    }
+    if ask {
+        let stdin = io::stdin();
+        let answer = prompt_line(&mut stdin.lock(), &format!("{} ", i18n::format("prompt-move-workspaces", [("count", findings.len().into()), ("project", project.as_str().into())])))?;
+        if answer.as_deref() != Some("y") {
+            eprintln!("{}", i18n::text("nothing-moved"));
+            return Ok(());
+        }
+    }
+
+    let options = MigrateOptions { new_name: None, project: Some(project), transfer_team_access: true };
+    // One workspace failing to move, e.g. for a name taken in the target, doesn't stop the rest
+    let failed = with_run_lock(client, config, kill_switch, &orgs, async {
+        let mut failed = 0;
+        for finding in &findings {
+            kill_switch::check(kill_switch).await?;
+            if let Err(e) = migrate::migrate_workspace(client, &finding.org, &finding.workspace, &options).await {
+                eprintln!("Warning: could not move {}/{}: {}", finding.org, finding.workspace, redact::scrub(&e.to_string()));
+                failed += 1;
+            }
+        }
+        Ok(failed)
+    }).await?;
+
+    eprintln!("{}", i18n::format("moved-workspaces", [("moved", (findings.len() - failed).into()), ("failed", failed.into())]));
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} workspaces could not be moved", failed, findings.len()).into()),
+    }
+}
+
+/// Streams and evaluates the workspaces of every TFE organization the run covers.
+async fn scan_workspaces(
+    client: &TfeClient,
+    config: &Config,
+    args: &OrgArgs,
+    policy: &Policy,
+    now: DateTime<Utc>,
+    inspect: impl FnMut(&Value, &Verdict),
+) -> Result<Scan, Box<dyn std::error::Error + Send + Sync>> {
+    let mut orgs = orgs::discover(client, &args.filter(config)).await?;
+    let team = match &args.team {
+        Some(team) => Some(TeamScope::resolve(client, team, &mut orgs).await?),
+        None => None,
+    };
+    let human = match config.human_activity {
+        true => Some(profile::timed(Phase::Enrichment, HumanActivity::prepare(client, &config.audit_trail, &orgs)).await),
+        false => None,
+    };
+    let mut scan = scan::scan(client, &orgs, team.as_ref(), human.as_ref(), policy, now, inspect).await?;
+    scan.team = team;
+    Ok(scan)
+}
+
+/// Flags the scanned pull request workspaces whose pull request is merged or closed, returning
+/// the rule that flagged each by workspace id.
+async fn reap_pull_requests(client: &TfeClient, config: &Config, policy: &Policy, scan: &mut Scan) -> HashMap<String, String> {
+    match (&config.pull_requests, &policy.pull_request) {
+        (Some(pull_requests), Some(pattern)) => {
+            pull_requests::reap(scan, pull_requests, pattern, &branches::Providers::from_env(), client.counters()).await
+        }
+        _ => HashMap::new(),
+    }
+}
+
+/// Writes the stale workspaces to the CSV the cleanup works from and to every configured
+/// report sink.
+#[allow(clippy::too_many_arguments)]
+async fn report_stale_workspaces(
+    client: &TfeClient,
+    config: &Config,
+    contacts: &mut Contacts,
+    command: &str,
+    scan: &Scan,
+    policy: &Policy,
+    report: &ReportArgs,
+    timezone: Tz,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let context = write_stale_csv(client, config, contacts, &scan.stale, report, timezone).await?;
+
+    let results = notify::ScanResults::new(command, &scan.totals, &scan.stale, policy)
+        .with_opted_out(&scan.opted_out)
+        .with_plaintext_secrets(&scan.stale, &context.plaintext_secrets)
+        .with_contacts(&context.contacts)
+        .with_ages(scan.age_stats.clone())
+        .with_errors(&scan.errors);
+    let report = sinks::Report { stale: &scan.stale, columns: &report.columns, context: &context, results: &results };
+    for sink in sinks::from_config(&config.sinks, &config.notifications) {
+        profile::timed(Phase::Reporting, sink.write(&report)).await
+            .map_err(|e| format!("cannot write report to {}: {}", sink.describe(), e))?;
+    }
+
+    Ok(())
+}
+
+async fn write_stale_csv(
+    client: &TfeClient,
+    config: &Config,
+    contacts: &mut Contacts,
+    old_inactive_accounts: &[Value],
+    report: &ReportArgs,
+    timezone: Tz,
+) -> Result<ReportContext, Box<dyn std::error::Error + Send + Sync>> {
+    let cache = LookupCache::from_config(config)?;
+    let context = profile::timed(Phase::Enrichment,
+        report::build_context(client, &cache, config, contacts, old_inactive_accounts, &report.columns, timezone)).await?;
+    eprintln!("{}.", cache.stats().describe());
+    let started = Instant::now();
+    create_csv(old_inactive_accounts, &report.columns, &context, "old_inactive_accounts.csv")?;
+    profile::record(Phase::Reporting, started.elapsed());
+    eprintln!("{}", i18n::format("csv-created", [("file", "old_inactive_accounts.csv".into())]));
+    Ok(context)
+}
+
+async fn run_scan(
+    client: &TfeClient,
+    config: &Config,
+    policy: &Policy,
+    args: &ScanArgs,
+    timezone: Tz,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (explain, report) = (args.explain, &args.report);
+    let now = Utc::now();
+
+    let mut contacts = Contacts::from_config(&config.contacts)?;
+    // Explanations cover every workspace, so they are printed as the workspaces stream in,
+    // except for pull request workspaces, whose verdict waits for their pull request
+    let mut explained = 0;
+    let mut awaiting = Vec::new();
+    let scan = scan_workspaces(client, config, &args.orgs, policy, now, |workspace, verdict| {
+        if !explain {
+            return;
+        }
+        if scan::awaits_pull_request(workspace, verdict, policy) {
+            awaiting.push((workspace.clone(), verdict.clone()));
+            return;
+        }
+        print_explanation(args.output, workspace, verdict, now, explained == 0);
+        explained += 1;
+    }).await;
+    let (scan, reaped) = match scan {
+        Ok(mut scan) => {
+            let reaped = reap_pull_requests(client, config, policy, &mut scan).await;
+            (Ok(scan), reaped)
+        }
+        Err(e) => (Err(e), HashMap::new()),
+    };
+    for (workspace, verdict) in awaiting {
+        let verdict = match reaped.get(workspace["id"].as_str().unwrap_or("")) {
+            Some(rule) => verdict.flag(rule.clone()),
+            None => verdict,
+        };
+        print_explanation(args.output, &workspace, &verdict, now, explained == 0);
+        explained += 1;
+    }
+    // Closed even when the scan fails part way, so the explanations printed stay valid JSON
+    if explain && matches!(args.output, OutputFormat::Json) {
+        println!("{}", json_array_end(explained == 0));
+    }
+    let mut scan = scan?;
+    record_scan(config, &mut scan)?;
+    profile::timed(Phase::Reporting, publish_results(client, config, &mut contacts, policy, "scan", &scan)).await;
+
+    match args.output {
+        // The explanations are the JSON on stdout, so only the CSV is written besides them
+        OutputFormat::Json if explain => {
+            write_stale_csv(client, config, &mut contacts, &scan.stale, report, timezone).await?;
+        }
+        OutputFormat::Json => {
+            let results: Vec<Value> = scan.stale.iter()
+                .map(|workspace| scan::result(workspace, &staleness::evaluate(workspace, policy, now), false, now))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&results)?);
+            write_stale_csv(client, config, &mut contacts, &scan.stale, report, timezone).await?;
+        }
+        OutputFormat::Text => {
+            report_stale_workspaces(client, config, &mut contacts, "scan", &scan, policy, report, timezone).await?;
+        }
+    }
+
+    PartialScan::check(&scan)
+}
+
+/// Prints a workspace's verdict with every rule evaluated for it, as an item of a JSON array
+/// or as text.
+fn print_explanation(output: OutputFormat, workspace: &Value, verdict: &Verdict, now: DateTime<Utc>, first: bool) {
+    match output {
+        OutputFormat::Json => print!("{}", json_array_item(&scan::result(workspace, verdict, true, now), first)),
+        OutputFormat::Text => {
+            println!("{}/{}: {}", tfe::workspace_org(workspace), workspace["attributes"]["name"].as_str().unwrap_or(""), verdict.status.label());
+            for step in &verdict.trace {
+                println!("  - {}", step);
+            }
+        }
+    }
+}
+
+/// `value` as the first or a further item of a JSON array, formatted so that the items and
+/// `json_array_end` together read like `serde_json::to_string_pretty` of the whole array.
+fn json_array_item(value: &Value, first: bool) -> String {
+    let item = serde_json::to_string_pretty(value).unwrap_or_default().replace('\n', "\n  ");
+    format!("{}\n  {}", if first { "[" } else { "," }, item)
+}
+
+fn json_array_end(empty: bool) -> &'static str {
+    if empty { "[]" } else { "\n]" }
+}
+
+async fn run_interactive_cleanup(
+    client: &TfeClient,
+    config: &Config,
+    policy: &Policy,
+    kill_switch: Option<&KillSwitch>,
+    args: &CleanupArgs,
+    timezone: Tz,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let windows = window::parse_windows(&config.deletion_windows)?;
+
+    // Shared by the report and the notifications, so each identity is resolved once a run
+    let mut contacts = Contacts::from_config(&config.contacts)?;
+    let mut scan = scan_workspaces(client, config, &args.orgs, policy, Utc::now(), |_, _| {}).await?;
+    reap_pull_requests(client, config, policy, &mut scan).await;
+    let history = record_scan(config, &mut scan)?;
+    report_stale_workspaces(client, config, &mut contacts, "cleanup", &scan, policy, &args.report, timezone).await?;
+    profile::timed(Phase::Reporting, publish_results(client, config, &mut contacts, policy, "cleanup", &scan)).await;
+
+    clean_up(client, config, kill_switch, args, &scan, &history, &windows).await?;
+    PartialScan::check(&scan)
+}
+
+/// Acts on the stale workspaces of a scan: writes the cleanup script, or cleans them up once
+/// the deletion window opens and the cleanup is confirmed. Scans that missed organizations are
+/// refused.
+async fn clean_up(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    args: &CleanupArgs,
+    scan: &Scan,
+    history: &History,
+    windows: &[DeletionWindow],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // The blast radius and streaks of a partial scan would only cover some organizations
+    if let Err(e) = PartialScan::check(scan) {
+        eprintln!("{}", i18n::text("cleanup-partial-scan"));
+        return Err(e);
+    }
+    let old_inactive_accounts = &scan.stale;
+    let limits = BlastRadius { max_deletions: args.max_deletions, max_percent: args.max_percent };
+    let pipelines = Pipelines::new(&config.actions, &config.notifications, args.archive_state.as_deref(), args.terraform_bin.as_deref(), args.delete_if_empty);
+    if let Some(path) = &args.emit_script {
+        // Whoever runs the script picks the time, so deletion windows don't apply
+        limits::check(&limits, old_inactive_accounts, &scan.totals)?;
+        let plan = plan_cleanup(client, history, old_inactive_accounts, scan.team.as_ref(), args.min_streak).await?;
+        write_cleanup_script(path, client.base_url(), &pipelines, &plan.workspaces)?;
+        eprintln!("{}", i18n::format("cleanup-script-written", [
+            ("count", plan.workspaces.len().into()),
+            ("path", path.display().to_string().into()),
+        ]));
+        return Ok(());
+    }
+
+    // Decided before waiting for a window, so a run that can't ask fails straight away
+    let decided = cleanup_decision(args, io::stdin().is_terminal())?;
+    if decided == Some(CleanupChoice::Skip) {
+        eprintln!("{}", i18n::text("cleanup-skipped-by-flag"));
+        return Ok(());
+    }
+
+    if !wait_for_deletion_window(windows, args.wait_for_window).await {
+        eprintln!("{}", i18n::format("window-closed-queued", [
+            ("count", old_inactive_accounts.len().into()),
+            ("file", "old_inactive_accounts.csv".into()),
+        ]));
+        eprintln!("{}", i18n::format("window-next-open", [("at", window::next_open(windows, Utc::now()).to_rfc3339().into())]));
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let choice = match decided {
+        Some(choice) => choice,
+        None => {
+            // Ask user if they want to perform cleanup
+            eprint!("{} ", i18n::text("prompt-cleanup"));
+            io::stderr().flush()?;
+            read_cleanup_choice(&mut input)?
+        }
+    };
+
+    match choice {
+        CleanupChoice::Delete => {
+            limits::check(&limits, old_inactive_accounts, &scan.totals)?;
+            if args.change_request {
+                let servicenow = ServiceNow::from_env()?;
+                let change = servicenow.create_change_request(old_inactive_accounts).await?;
+                eprintln!("{}", i18n::format("change-request-created", [("number", change.number.as_str().into())]));
+                servicenow.wait_for_approval(&change, servicenow::POLL_INTERVAL, Duration::from_secs(args.change_request_timeout * 60)).await?;
+                eprintln!("{}", i18n::format("change-request-approved", [("number", change.number.as_str().into())]));
+            }
+
+            eprintln!("{}", i18n::text("cleanup-proceeding"));
+            let mut breaker = CircuitBreaker::new(args.max_failures_per_org);
+            let events = EventEmitter::from_config(config.events.as_ref())?;
+            let context = ActionContext { client, history, events: events.as_ref(), kill_switch, windows };
+            let cleanup = perform_terraform_cleanup(&context, &pipelines, &mut breaker, old_inactive_accounts, scan.team.as_ref(), args.min_streak);
+            with_run_lock(client, config, kill_switch, &stale_orgs(old_inactive_accounts), cleanup).await?;
+        }
+        // Only answered at the prompt, so stdin is a terminal
+        CleanupChoice::Migrate => {
+            let migrations = prompt_migrations(client, kill_switch, old_inactive_accounts, &mut input);
+            with_run_lock(client, config, kill_switch, &stale_orgs(old_inactive_accounts), migrations).await?;
+        }
+        CleanupChoice::Skip => {
+            eprintln!("{}", i18n::text("cleanup-skipped"));
+        }
+    }
+
+    Ok(())
+}
+
+/// The organizations of the workspaces, leaving out workspaces queued by hand without one.
+fn stale_orgs(workspaces: &[Value]) -> Vec<String> {
+    tfe::count_by_org(workspaces).into_keys().filter(|org| !org.is_empty()).collect()
+}
+
+/// Guards a destructive command like the cleanup's deletions: checks the kill switch, then holds
+/// the run lock of `orgs` while `work` runs, releasing it however `work` ends.
+async fn with_run_lock<T>(
+    client: &TfeClient,
+    config: &Config,
+    kill_switch: Option<&KillSwitch>,
+    orgs: &[String],
+    work: impl Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    kill_switch::check(kill_switch).await?;
+    let lock = RunLock::acquire(client, &config.run_lock, orgs).await?;
+    let result = work.await;
+    lock.release().await;
+    result
+}
+
+/// Sends the run's results to Datadog, the notification channels and the event endpoint, where
+/// configured. Monitoring and notification problems are reported but never fail the run.
+async fn publish_results(client: &TfeClient, config: &Config, contacts: &mut Contacts, policy: &Policy, command: &str, scan: &Scan) {
+    match Datadog::from_env(config.report_url.clone()) {
+        Ok(Some(datadog)) => {
+            if let Err(e) = datadog.publish(command, &scan.totals, &scan.stale).await {
+                eprintln!("Warning: could not send metrics to Datadog: {}", redact::scrub(&e.to_string()));
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: invalid Datadog configuration: {}", redact::scrub(&e.to_string())),
+    }
+
+    let results = notify::ScanResults::new(command, &scan.totals, &scan.stale, policy)
+        .with_opted_out(&scan.opted_out)
+        .with_contacts(&owner_contacts(client, config, contacts, &scan.stale).await)
+        .with_ages(scan.age_stats.clone())
+        .with_errors(&scan.errors);
+    if let Err(e) = notify::notify(&config.notifications, &results).await {
+        eprintln!("Warning: could not send notifications: {}", redact::scrub(&e.to_string()));
+    }
+
+    match EventEmitter::from_config(config.events.as_ref()) {
+        Ok(Some(events)) => events.scanned(command, &scan.totals, &scan.stale).await,
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: could not send events: {}", redact::scrub(&e.to_string())),
+    }
+}
+
+/// How to reach the owners of the workspaces, by workspace id, if contact sources are
+/// configured. Workspaces whose owners can't be looked up are left out with a warning.
+/// Identities the report already resolved aren't looked up again.
+async fn owner_contacts(client: &TfeClient, config: &Config, contacts: &mut Contacts, workspaces: &[Value]) -> HashMap<String, Vec<contacts::Contact>> {
+    if config.contacts.is_empty() || (config.notifications.slack_webhook.is_none() && config.notifications.teams_webhook.is_none()) {
+        return HashMap::new();
+    }
+    let cache = match LookupCache::from_config(config) {
+        Ok(cache) => cache,
+        Err(e) => {
+            eprintln!("Warning: cannot look up owner contacts: {}", redact::scrub(&e.to_string()));
+            return HashMap::new();
+        }
+    };
+    let mut owners = HashMap::new();
+    for workspace in workspaces {
+        match report::owners(client, &cache, config, workspace).await {
+            Ok(found) => {
+                owners.insert(workspace["id"].as_str().unwrap_or("").to_string(), found);
+            }
+            Err(e) => eprintln!("Warning: cannot look up the owners of {}: {}",
+                workspace["attributes"]["name"].as_str().unwrap_or(""), redact::scrub(&e.to_string())),
+        }
+    }
+    contacts.of_workspaces(&owners).await
+}
+
+/// Returns whether destructive actions may proceed now, sleeping until the next deletion
+/// window first when `wait` is set.
+async fn wait_for_deletion_window(windows: &[DeletionWindow], wait: bool) -> bool {
+    let now = Utc::now();
+    if window::is_open(windows, now) {
+        return true;
+    }
+    if !wait {
+        return false;
+    }
+
+    let opens_at = window::next_open(windows, now);
+    eprintln!("{}", i18n::format("window-waiting", [("at", opens_at.to_rfc3339().into())]));
+    tokio::time::sleep((opens_at - now).to_std().unwrap_or_default()).await;
+    true
+}
+
+/// Asks, per workspace, for a new name and/or target project and migrates the workspace.
+async fn prompt_migrations<R: BufRead>(
+    client: &TfeClient,
+    kill_switch: Option<&KillSwitch>,
+    workspaces: &[Value],
+    input: &mut R,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for workspace in workspaces {
+        let org = tfe::workspace_org(workspace);
+        let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+
+        let inactive_for = workspace["attributes"]["last-activity-at"].as_str()
+            .and_then(|raw| timefmt::age(raw, Utc::now()))
+            .unwrap_or_else(|| i18n::text("age-unknown"));
+        eprintln!("{}", i18n::format("migrate-workspace", [("workspace", format!("{}/{}", org, name).into()), ("age", inactive_for.into())]));
+        let new_name = prompt_line(input, &format!("  {} ", i18n::text("prompt-new-name")))?;
+        let project = prompt_line(input, &format!("  {} ", i18n::text("prompt-target-project")))?;
+
+        if new_name.is_none() && project.is_none() {
+            eprintln!("  {}", i18n::format("migrate-skipped", [("workspace", format!("{}/{}", org, name).into())]));
+            continue;
+        }
+
+        kill_switch::check(kill_switch).await?;
+        let options = MigrateOptions { new_name, project, transfer_team_access: true };
+        if let Err(e) = migrate::migrate_workspace(client, org, name, &options).await {
+            eprintln!("  Failed to migrate {}/{}: {}", org, name, redact::scrub(&e.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+fn prompt_line<R: BufRead>(input: &mut R, prompt: &str) -> Result<Option<String>, std::io::Error> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let line = line.trim();
+    Ok(if line.is_empty() { None } else { Some(line.to_string()) })
+}
+
+fn create_csv(
+    accounts: &[Value],
+    columns: &[Column],
+    context: &ReportContext,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    report::write_csv(std::fs::File::create(path)?, accounts, columns, context)
+}
+
+/// The choice made on the command line, or `None` if the user is to be asked. Without a
+/// terminal to ask on, one of `--yes` and `--no-cleanup` is required: a prompt would block or
+/// take whatever happens to be piped in as the answer.
+fn cleanup_decision(args: &CleanupArgs, interactive: bool) -> Result<Option<CleanupChoice>, String> {
+    if args.yes {
+        Ok(Some(CleanupChoice::Delete))
+    } else if args.no_cleanup {
+        Ok(Some(CleanupChoice::Skip))
+    } else if interactive {
+        Ok(None)
+    } else {
+        Err("stdin is not a terminal, so the cleanup can't be confirmed; pass --yes to clean up or --no-cleanup to only report".to_string())
+    }
+}
+
+/// Whether the user is to be asked before `what` goes ahead: not with `--yes`, which is
+/// required without a terminal to ask on, as for the cleanup.
+fn confirmation_needed(yes: bool, interactive: bool, what: &str) -> Result<bool, String> {
+    match (yes, interactive) {
+        (true, _) => Ok(false),
+        (false, true) => Ok(true),
+        (false, false) => Err(format!("stdin is not a terminal, so the {} can't be confirmed; pass --yes", what)),
+    }
+}
+
+fn read_cleanup_choice<R: BufRead>(mut input: R) -> Result<CleanupChoice, std::io::Error> {
+    let mut user_input = String::new();
+    input.read_line(&mut user_input)?;
+    Ok(match user_input.trim().to_lowercase().as_str() {
+        "y" => CleanupChoice::Delete,
+        "m" => CleanupChoice::Migrate,
+        _ => CleanupChoice::Skip,
+    })
+}
+
+/// Records the workspaces flagged by this run in the history, where the stale streaks that
+/// `--min-streak` requires are counted, and sets the scan's age statistics, compared to the
+/// previous run's.
+fn record_scan(config: &Config, scan: &mut Scan) -> Result<History, Box<dyn std::error::Error + Send + Sync>> {
+    let mut history = History::open(&config.history_db)?;
+    let flagged: Vec<(&str, &str)> = scan.stale.iter()
+        .map(|ws| (tfe::workspace_org(ws), ws["attributes"]["name"].as_str().unwrap_or("")))
+        .collect();
+    // Organizations that failed to list flagged only part of their stale workspaces
+    let orgs: Vec<&str> = scan.totals.keys()
+        .filter(|org| !scan.errors.contains_key(*org))
+        .map(String::as_str)
+        .collect();
+    let previous = history.last_ages()?;
+    history.record_scan(&orgs, &flagged)?;
+
+    let stats = AgeStats::from_counts(&scan.ages).compared_to(previous.as_ref());
+    history.record_ages(&stats)?;
+    scan.age_stats = Some(stats);
+    Ok(history)
+}
+
+/// Returns why a workspace needs no deletion if an earlier run already handled it, either
+/// according to the history or because the API no longer knows it. `listed` is the workspace
+/// as the scan listed it: its history only counts if it was created before the recorded
+/// action, as a workspace re-created under the same name is a new one to handle.
+async fn already_handled(
+    client: &TfeClient,
+    history: &History,
+    org: &str,
+    name: &str,
+    listed: Option<&Value>,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(action) = history.handled_action(org, name)? {
+        if listed.is_none_or(|workspace| !recreated_since(workspace, &action.at)) {
+            return Ok(Some(i18n::format("handled-action", [("action", action.action.as_str().into()), ("at", action.at.as_str().into())])));
+        }
+    }
+    if !org.is_empty() && !tfe::workspace_exists(client, org, name).await? {
+        return Ok(Some(i18n::text("handled-gone")));
+    }
+    Ok(None)
+}
+
+/// Whether the workspace was created after `at`, an RFC 3339 timestamp of the history.
+fn recreated_since(workspace: &Value, at: &str) -> bool {
+    let parse = |value: &str| DateTime::parse_from_rfc3339(value).ok();
+    match (workspace["attributes"]["created-at"].as_str().and_then(parse), parse(at)) {
+        (Some(created), Some(at)) => created > at,
+        _ => false,
+    }
+}
+
+/// Reads the `(organization, name)` of every workspace in a stale workspace CSV. The
+/// organization is empty when the CSV was written without the Organization column.
+fn read_queued_workspaces(path: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut rdr = Reader::from_path(path)?;
+    let headers = rdr.headers()?.clone();
+    let name_index = report::column_index(&headers, Column::Name)
+        .ok_or_else(|| format!("{} has no Name column; include 'name' in --columns", path))?;
+    let org_index = report::column_index(&headers, Column::Org);
+
+    let mut workspaces = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        let org = org_index.and_then(|i| record.get(i)).unwrap_or("");
+        workspaces.push((org.to_string(), record[name_index].to_string()));
+    }
+    Ok(workspaces)
+}
+
+/// Puts the queued workspaces in an order that deletes downstream workspaces (triggered by runs
+/// of another workspace, or reading its state) before their upstream ones. Dependencies are
+/// only looked up for workspaces listed by the API, never from the cache: they change with
+/// other workspaces, which leaves this one's `updated-at` alone. Any that can't be read are
+/// reported and ignored.
+async fn order_for_deletion(
+    client: &TfeClient,
+    stale: &[Value],
+    queued: Vec<(String, String)>,
+) -> Vec<(String, String)> {
+    let listed: Vec<Option<&Value>> = queued.iter()
+        .map(|(org, name)| stale.iter()
+            .find(|ws| tfe::workspace_org(ws) == org && ws["attributes"]["name"].as_str() == Some(name)))
+        .collect();
+    let ids: Vec<String> = listed.iter()
+        .map(|workspace| workspace.and_then(|ws| ws["id"].as_str()).unwrap_or("").to_string())
+        .collect();
+
+    let mut downstream = HashMap::new();
+    for id in ids.iter().filter(|id| !id.is_empty()) {
+        match dependencies::downstream(client, id).await {
+            Ok(dependents) => {
+                downstream.insert(id.clone(), dependents);
+            }
+            Err(e) => eprintln!("Warning: could not read the dependents of {}: {}", id, redact::scrub(&e.to_string())),
+        }
+    }
+
+    let (order, cyclic) = dependencies::deletion_order(&ids, &downstream);
+    if !cyclic.is_empty() {
+        let names: Vec<&str> = cyclic.iter().map(|&i| queued[i].1.as_str()).collect();
+        eprintln!("Warning: {} depend on each other; they are cleaned up in CSV order.", names.join(", "));
+    }
+
+    let mut queued: Vec<Option<(String, String)>> = queued.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| queued[i].take()).collect()
+}
+
+/// Runs the action pipeline of its category on every workspace in the CSV, downstream workspaces
+/// first. `stale` holds the workspaces as listed by the API; workspaces added to the CSV by hand
+/// are looked up, and with a team scope left alone unless the team administers them. With
+/// `min_streak`, workspaces flagged by fewer consecutive scans are held back.
+async fn perform_terraform_cleanup(
+    context: &ActionContext<'_>,
+    pipelines: &Pipelines,
+    breaker: &mut CircuitBreaker,
+    stale: &[Value],
+    team: Option<&TeamScope>,
+    min_streak: Option<u32>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (client, history) = (context.client, context.history);
+    let (mut completed, mut deleted, mut stopped, mut failed, mut reclaimed) = (0, 0, 0, 0, 0);
+
+    let plan = plan_cleanup(client, history, stale, team, min_streak).await?;
+    for workspace in &plan.workspaces {
+        let (org, name) = (tfe::workspace_org(workspace), workspace["attributes"]["name"].as_str().unwrap_or(""));
+        let pipeline = pipelines.for_category(cleanup_category(workspace));
+        if !breaker.allow(org) {
+            continue;
+        }
+
+        // Measured up front, as deleting the workspace deletes its state versions
+        let stored = if actions::deletes(pipeline) && !org.is_empty() {
+            storage::state_bytes(client, org, name).await.unwrap_or_else(|e| {
+                eprintln!("Warning: could not measure the state of {}: {}", name, redact::scrub(&e.to_string()));
+                0
+            })
+        } else {
+            0
+        };
+
+        eprintln!("{}", i18n::format("cleanup-workspace", [("workspace", name.into())]));
+        let result = actions::run_pipeline(pipeline, context, workspace).await?;
+        match &result {
+            PipelineResult::Completed(actions) => {
+                completed += 1;
+                if actions.contains(&"deleted") {
+                    deleted += 1;
+                    client.counters().increment(Counter::Deleted);
+                    client.counters().add(Counter::ReclaimedBytes, stored);
+                    reclaimed += stored;
+                }
+            }
+            PipelineResult::Stopped => stopped += 1,
+            PipelineResult::Failed => {
+                failed += 1;
+                client.counters().increment(Counter::Failed);
+            }
+        }
+        if breaker.record(org, result == PipelineResult::Failed) {
+            eprintln!("{}", i18n::format("org-failures", [("threshold", breaker.threshold().into()), ("org", org.into())]));
+        }
+    }
+
+    println!("{}", i18n::format("cleanup-outcome", [
+        ("completed", completed.into()),
+        ("deleted", deleted.into()),
+        ("reclaimed", storage::format_bytes(reclaimed).into()),
+        ("handled", plan.handled.into()),
+        ("stopped", stopped.into()),
+        ("failed", failed.into()),
+    ]));
+    if plan.out_of_scope > 0 {
+        println!("{}", i18n::format("cleanup-out-of-scope", [
+            ("count", plan.out_of_scope.into()),
+            ("team", team.map_or("", |team| team.name.as_str()).into()),
+        ]));
+    }
+    if plan.held > 0 {
+        println!("{}", i18n::format("cleanup-held", [("count", plan.held.into()), ("streak", min_streak.unwrap_or_default().into())]));
+    }
+    for (org, skipped) in breaker.tripped() {
+        println!("{}", i18n::format("circuit-breaker-tripped", [
+            ("org", org.into()),
+            ("threshold", breaker.threshold().into()),
+            ("skipped", skipped.into()),
+        ]));
+    }
+
+    Ok(())
+}
+
+/// The workspaces a cleanup acts on, in deletion order, and how many it leaves alone.
+struct CleanupPlan {
+    workspaces: Vec<Value>,
+    /// Already deleted or otherwise handled by an earlier run.
+    handled: usize,
+    /// Not yet flagged by `--min-streak` consecutive scans.
+    held: usize,
+    /// Queued by hand, but outside the `--team` scope.
+    out_of_scope: usize,
+}
+
+async fn plan_cleanup(
+    client: &TfeClient,
+    history: &History,
+    stale: &[Value],
+    team: Option<&TeamScope>,
+    min_streak: Option<u32>,
+) -> Result<CleanupPlan, Box<dyn std::error::Error + Send + Sync>> {
+    let mut plan = CleanupPlan { workspaces: Vec::new(), handled: 0, held: 0, out_of_scope: 0 };
+
+    let queued = read_queued_workspaces("old_inactive_accounts.csv")?;
+    for (org, account_name) in &order_for_deletion(client, stale, queued).await {
+        let (org, account_name) = (org.as_str(), account_name.as_str());
+
+        let listed = stale.iter()
+            .find(|ws| tfe::workspace_org(ws) == org && ws["attributes"]["name"].as_str() == Some(account_name));
+        if let Some(reason) = already_handled(client, history, org, account_name, listed).await? {
+            println!("{}", i18n::format("already-handled", [("workspace", account_name.into()), ("reason", reason.into())]));
+            plan.handled += 1;
+            continue;
+        }
+
+        if let Some(min_streak) = min_streak {
+            let streak = history.stale_streak(org, account_name)?;
+            if streak < min_streak {
+                println!("{}", i18n::format("holding-back", [
+                    ("workspace", account_name.into()),
+                    ("streak", streak.into()),
+                    ("required", min_streak.into()),
+                ]));
+                plan.held += 1;
+                continue;
+            }
+        }
+
+        let workspace = match listed {
+            Some(workspace) => workspace.clone(),
+            None if !org.is_empty() => tfe::get_workspace(client, org, account_name).await?,
+            None => json!({ "attributes": { "name": account_name } }),
+        };
+        // Listed workspaces were checked by the scan; those queued by hand haven't been
+        if let (None, Some(team)) = (listed, team) {
+            if !team.has_admin(client, &workspace).await? {
+                println!("{}", i18n::format("leaving-alone", [("workspace", account_name.into()), ("team", team.name.as_str().into())]));
+                plan.out_of_scope += 1;
+                continue;
+            }
+        }
+        plan.workspaces.push(workspace);
+    }
+
+    Ok(plan)
+}
+
+/// Workspaces queued by hand without an organization can only be deleted, through the CLI.
+fn cleanup_category(workspace: &Value) -> Category {
+    if tfe::workspace_org(workspace).is_empty() { Category::Stale } else { Category::of(workspace) }
+}
+
+/// Writes each workspace's pipeline as shell commands to an executable script, for
+/// environments where the binary can't run.
+fn write_cleanup_script(path: &Path, address: &str, pipelines: &Pipelines, workspaces: &[Value]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let shell = Shell::for_path(path);
+    let commands: Vec<(String, Vec<String>)> = workspaces.iter()
+        .map(|workspace| {
+            let (org, name) = (tfe::workspace_org(workspace), workspace["attributes"]["name"].as_str().unwrap_or(""));
+            let title = if org.is_empty() { name.to_string() } else { format!("{}/{}", org, name) };
+            (title, actions::script_pipeline(pipelines.for_category(cleanup_category(workspace)), shell, workspace))
+        })
+        .collect();
+
+    std::fs::write(path, shell.render(address, &commands))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use mockito::{mock, server_url};
+    use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_fetch_accounts() {
+        let mock_server = mock("GET", "/api/v2/organizations")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({
+                "data": [
+                    {
+                        "attributes": {
+                            "name": "old-account",
+                            "last-activity-at": "2020-01-01T00:00:00Z"
+                        }
+                    },
+                    {
+                        "attributes": {
+                            "name": "new-account",
+                            "last-activity-at": (Utc::now() - Duration::days(1)).to_rfc3339()
+                        }
+                    }
+                ]
+            }).to_string())
+            .create();
+
+        std::env::set_var("TFE_TOKEN", "test-token");
+        let client = reqwest::Client::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str("Bearer test-token").unwrap());
+
+        let accounts_response = client.get(format!("{}/api/v2/organizations", server_url()))
+            .headers(headers)
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+
+        let old_inactive_accounts = staleness::filter_old_inactive_accounts(accounts_response["data"].as_array().unwrap(), &Policy::default());
+
+        assert_eq!(old_inactive_accounts.len(), 1);
+        assert_eq!(old_inactive_accounts[0]["attributes"]["name"], "old-account");
+
+        mock_server.assert();
+    }
+
+    #[test]
+    fn test_json_array_items_read_like_a_pretty_array() {
+        let values = vec![json!({ "name": "a", "trace": ["x", "y"] }), json!({ "name": "b\nc" })];
+        let streamed: String = values.iter().enumerate().map(|(i, value)| json_array_item(value, i == 0)).collect();
+
+        assert_eq!(streamed + json_array_end(false), serde_json::to_string_pretty(&values).unwrap());
+        assert_eq!(json_array_end(true), serde_json::to_string_pretty(&Vec::<Value>::new()).unwrap());
+    }
+
+    #[test]
+    fn test_scan_result_json() {
+        let workspace = json!({
+            "id": "ws-1",
+            "attributes": { "name": "app", "last-activity-at": "2020-01-01T00:00:00Z" },
+            "relationships": { "organization": { "data": { "id": "acme" } } }
+        });
+        let verdict = staleness::evaluate(&workspace, &Policy::default(), Utc::now());
+
+        let result = scan::result(&workspace, &verdict, false, Utc::now());
+
+        assert_eq!(result["org"], "acme");
+        assert_eq!(result["status"], "FLAGGED");
+        assert_eq!(result["no_activity_data"], false);
+        assert!(result.get("trace").is_none());
+        assert!(scan::result(&workspace, &verdict, true, Utc::now())["trace"].is_array());
+    }
+
+    #[test]
+    fn test_csv_creation() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let old_inactive_accounts = vec![
+            json!({
+                "attributes": {
+                    "name": "old-account",
+                    "last-activity-at": "2020-01-01T00:00:00Z"
+                }
+            })
+        ];
+
+        create_csv(&old_inactive_accounts, report::DEFAULT_COLUMNS, &ReportContext::default(), path).unwrap();
+
+        let mut rdr = csv::Reader::from_path(path).unwrap();
+        let records: Vec<csv::StringRecord> = rdr.records().map(|r| r.unwrap()).collect();
+
+        assert_eq!(records.len(), 1); // Header is consumed by the reader
+        assert_eq!(&records[0][0], "old-account");
+        assert_eq!(&records[0][1], "2020-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_csv_custom_columns_and_escaping() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let accounts = vec![json!({
+            "id": "ws-1",
+            "attributes": { "name": "app, \"legacy\"", "resource-count": 3 },
+            "relationships": { "organization": { "data": { "id": "acme" } } }
+        })];
+
+        create_csv(&accounts, &[Column::Org, Column::Name, Column::Resources], &ReportContext::default(), path).unwrap();
+
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(contents, "Organization,Name,Resources\nacme,\"app, \"\"legacy\"\"\",3\n");
+
+        let mut rdr = csv::Reader::from_path(path).unwrap();
+        let headers = rdr.headers().unwrap().clone();
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(&record[report::column_index(&headers, Column::Name).unwrap()], "app, \"legacy\"");
+    }
+
+    #[test]
+    fn test_user_input_yes() {
+        let input = b"y\n";
+        assert_eq!(read_cleanup_choice(&input[..]).unwrap(), CleanupChoice::Delete);
+    }
+
+    #[test]
+    fn test_user_input_no() {
+        let input = b"n\n";
+        assert_eq!(read_cleanup_choice(&input[..]).unwrap(), CleanupChoice::Skip);
+    }
+
+    #[test]
+    fn test_cleanup_decision_without_terminal() {
+        let args = |flags: &[&str]| {
+            let cli = Cli::parse_from(std::iter::once("tfe_cleanup").chain(flags.iter().copied()));
+            match cli.command {
+                Some(Commands::Cleanup(args)) => args,
+                _ => cli.cleanup,
+            }
+        };
+
+        assert_eq!(cleanup_decision(&args(&["cleanup"]), true), Ok(None));
+        assert!(cleanup_decision(&args(&["cleanup"]), false).unwrap_err().contains("pass --yes"));
+        assert!(cleanup_decision(&args(&[]), false).is_err());
+        assert_eq!(cleanup_decision(&args(&["cleanup", "--yes"]), false), Ok(Some(CleanupChoice::Delete)));
+        assert_eq!(cleanup_decision(&args(&["--no-cleanup"]), false), Ok(Some(CleanupChoice::Skip)));
+        assert!(Cli::try_parse_from(["tfe_cleanup", "cleanup", "--yes", "--no-cleanup"]).is_err());
+    }
+
+    #[test]
+    fn test_confirmation_needed_without_terminal() {
+        assert_eq!(confirmation_needed(false, true, "destroy runs"), Ok(true));
+        assert_eq!(confirmation_needed(true, false, "destroy runs"), Ok(false));
+        assert!(confirmation_needed(false, false, "destroy runs").unwrap_err().contains("pass --yes"));
+        assert!(Cli::try_parse_from(["tfe_cleanup", "team-access", "--revoke", "--yes"]).is_ok());
+        assert!(Cli::try_parse_from(["tfe_cleanup", "team-access", "--yes"]).is_err());
+        assert!(Cli::try_parse_from(["tfe_cleanup", "default-project", "--yes"]).is_err());
+    }
+
+    #[test]
+    fn test_recreated_since() {
+        let workspace = json!({ "attributes": { "created-at": "2024-05-02T00:00:00Z" } });
+        assert!(recreated_since(&workspace, "2024-05-01T12:00:00+00:00"));
+        assert!(!recreated_since(&workspace, "2024-05-03T12:00:00+00:00"));
+        assert!(!recreated_since(&json!({ "attributes": {} }), "2024-05-01T12:00:00+00:00"));
+    }
+
+    #[test]
+    fn test_change_request_timeout_needs_a_change_request() {
+        assert!(Cli::try_parse_from(["tfe_cleanup", "cleanup"]).is_ok());
+        assert!(Cli::try_parse_from(["tfe_cleanup", "cleanup", "--change-request", "--change-request-timeout", "30"]).is_ok());
+        assert!(Cli::try_parse_from(["tfe_cleanup", "cleanup", "--change-request-timeout", "30"]).is_err());
+    }
+
+    #[test]
+    fn test_user_input_migrate() {
+        let input = b"M\n";
+        assert_eq!(read_cleanup_choice(&input[..]).unwrap(), CleanupChoice::Migrate);
+    }
+}
\ No newline at end of file
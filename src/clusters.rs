@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+/// Share of stale workspaces above which a cluster is suggested for cleanup as a whole.
+const EPHEMERAL_STALE_SHARE: f64 = 0.5;
+
+/// How a cluster's names are alike.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Equal but for numbers or hashes, e.g. `app-pr-1234`.
+    Shape,
+    /// Equal but for the last part, e.g. `billing-dev` and `billing-prod`.
+    Prefix,
+    /// Equal in the last part, e.g. `alice-sandbox` and `bob-sandbox`.
+    Suffix,
+}
+
+/// Workspaces whose names follow one pattern.
+#[derive(Debug, PartialEq)]
+pub struct Cluster {
+    pub kind: Kind,
+    /// The pattern for people, e.g. `app-pr-<n>`.
+    pub label: String,
+    /// The pattern as a regular expression on the whole name, for `exclude` or `include`.
+    pub pattern: String,
+    pub workspaces: usize,
+    pub stale: usize,
+}
+
+/// What to do about a cluster, with the config snippet that does it.
+#[derive(Debug, PartialEq)]
+pub enum Suggestion {
+    /// Mostly stale: clean it up in bulk with a run restricted to the cluster.
+    CleanUp(String),
+    /// Nothing stale: keep it out of cleanups.
+    Exclude(String),
+    Review,
+}
+
+impl Cluster {
+    pub fn suggestion(&self) -> Suggestion {
+        let literal = format!("'{}'", self.pattern);
+        if self.stale == 0 {
+            Suggestion::Exclude(format!("exclude = [{}]", literal))
+        } else if self.stale as f64 / self.workspaces as f64 > EPHEMERAL_STALE_SHARE {
+            Suggestion::CleanUp(format!("include = [{}]", literal))
+        } else {
+            Suggestion::Review
+        }
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    matches!(c, '-' | '_' | '.')
+}
+
+/// Splits a name into parts and the separators after each, e.g. `app-pr-1` into
+/// `[("app", "-"), ("pr", "-"), ("1", "")]`.
+fn parts(name: &str) -> Vec<(&str, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = name;
+    while !rest.is_empty() {
+        let end = rest.find(is_separator).unwrap_or(rest.len());
+        let separators = rest[end..].find(|c| !is_separator(c)).map_or(rest.len(), |i| end + i);
+        parts.push((&rest[..end], &rest[end..separators]));
+        rest = &rest[separators..];
+    }
+    parts
+}
+
+/// The label and pattern of a part that varies between workspaces of a kind, if it does.
+fn variable(part: &str) -> Option<(&'static str, &'static str)> {
+    if !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()) {
+        Some(("<n>", r"\d+"))
+    } else if part.len() >= 7 && part.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) && part.chars().any(|c| c.is_ascii_digit()) {
+        // Lowercase only, like the pattern, so every name grouped under it matches the rule
+        Some(("<hash>", "[0-9a-f]+"))
+    } else {
+        None
+    }
+}
+
+/// `(label, pattern)` of the names of the kind the name belongs to, or `None` if the name has
+/// nothing to group by, e.g. no variable part for `Shape`.
+fn key(kind: Kind, name: &str) -> Option<(String, String)> {
+    let parts = parts(name);
+    match kind {
+        Kind::Shape => {
+            if !parts.iter().any(|(part, _)| variable(part).is_some()) {
+                return None;
+            }
+            let (mut label, mut pattern) = (String::new(), String::from("^"));
+            for (part, separator) in &parts {
+                match variable(part) {
+                    Some((part_label, part_pattern)) => {
+                        label.push_str(part_label);
+                        pattern.push_str(part_pattern);
+                    }
+                    None => {
+                        label.push_str(part);
+                        pattern.push_str(&regex::escape(part));
+                    }
+                }
+                label.push_str(separator);
+                pattern.push_str(&regex::escape(separator));
+            }
+            Some((label, pattern + "$"))
+        }
+        Kind::Prefix if parts.len() > 1 => {
+            let (last, _) = parts[parts.len() - 1];
+            let prefix = &name[..name.len() - last.len()];
+            Some((format!("{}*", prefix), format!("^{}", regex::escape(prefix))))
+        }
+        Kind::Suffix if parts.len() > 1 => {
+            let (first, _) = parts[0];
+            let suffix = &name[first.len()..];
+            Some((format!("*{}", suffix), format!("{}$", regex::escape(suffix))))
+        }
+        Kind::Prefix | Kind::Suffix => None,
+    }
+}
+
+/// Groups workspace names, each with whether it is stale, into clusters of at least
+/// `min_size`. Names are grouped by shape first; those left over by prefix, then by suffix.
+/// Largest clusters come first.
+pub fn cluster(names: &[(String, bool)], min_size: usize) -> Vec<Cluster> {
+    let mut left: Vec<&(String, bool)> = names.iter().collect();
+    let mut clusters = Vec::new();
+
+    for kind in [Kind::Shape, Kind::Prefix, Kind::Suffix] {
+        let mut groups: BTreeMap<(String, String), Vec<&(String, bool)>> = BTreeMap::new();
+        for entry in &left {
+            if let Some(key) = key(kind, &entry.0) {
+                groups.entry(key).or_default().push(entry);
+            }
+        }
+        for ((label, pattern), members) in groups {
+            if members.len() < min_size {
+                continue;
+            }
+            left.retain(|entry| !members.iter().any(|member| std::ptr::eq(*member, *entry)));
+            clusters.push(Cluster {
+                kind,
+                label,
+                pattern,
+                workspaces: members.len(),
+                stale: members.iter().filter(|(_, stale)| *stale).count(),
+            });
+        }
+    }
+
+    clusters.sort_by(|a, b| b.workspaces.cmp(&a.workspaces).then(a.label.cmp(&b.label)));
+    clusters
+}
+
+/// One paragraph per cluster with its suggestion and config snippet.
+pub fn render(clusters: &[Cluster]) -> String {
+    if clusters.is_empty() {
+        return "No naming patterns shared by enough workspaces.\n".to_string();
+    }
+    let mut out = String::new();
+    for cluster in clusters {
+        out.push_str(&format!("{} ({} workspaces, {} stale)\n", cluster.label, cluster.workspaces, cluster.stale));
+        match cluster.suggestion() {
+            Suggestion::CleanUp(snippet) => out.push_str(&format!(
+                "  Mostly stale, likely ephemeral. Clean them up together with a config restricted to them:\n    {}\n", snippet)),
+            Suggestion::Exclude(snippet) => out.push_str(&format!(
+                "  None stale, likely long-lived. Keep them out of cleanups:\n    {}\n", snippet)),
+            Suggestion::Review => out.push_str("  Partly stale; review them one by one.\n"),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn names(names: &[(&str, bool)]) -> Vec<(String, bool)> {
+        names.iter().map(|(name, stale)| (name.to_string(), *stale)).collect()
+    }
+
+    #[test]
+    fn test_parts() {
+        assert_eq!(parts("app-pr-1234"), vec![("app", "-"), ("pr", "-"), ("1234", "")]);
+        assert_eq!(parts("a__b."), vec![("a", "__"), ("b", ".")]);
+    }
+
+    #[test]
+    fn test_variable() {
+        assert_eq!(variable("1234"), Some(("<n>", r"\d+")));
+        assert_eq!(variable("3f9a2c1d"), Some(("<hash>", "[0-9a-f]+")));
+        // Uppercase hex wouldn't match the suggested pattern
+        assert_eq!(variable("3F9A2C1D"), None);
+        assert_eq!(variable("abcdefab"), None);
+    }
+
+    #[test]
+    fn test_cluster() {
+        let clusters = cluster(&names(&[
+            ("app-pr-1234", true), ("app-pr-1240", true), ("app-pr-1301", false),
+            ("billing-dev", false), ("billing-prod", false), ("billing-staging", false),
+            ("alice-sandbox", true), ("bob-sandbox", false), ("carol-sandbox", false),
+            ("build-3f9a2c1d", true), ("legacy", true),
+        ]), 3);
+
+        assert_eq!(clusters.iter().map(|c| (c.label.as_str(), c.stale)).collect::<Vec<_>>(), vec![
+            ("*-sandbox", 1), ("app-pr-<n>", 2), ("billing-*", 0),
+        ]);
+        assert_eq!(clusters[1].kind, Kind::Shape);
+        assert_eq!(clusters[1].suggestion(), Suggestion::CleanUp(r"include = ['^app\-pr\-\d+$']".to_string()));
+        assert_eq!(clusters[2].suggestion(), Suggestion::Exclude(r"exclude = ['^billing\-']".to_string()));
+        assert_eq!(clusters[0].suggestion(), Suggestion::Review);
+
+        let pattern = Regex::new(&clusters[1].pattern).unwrap();
+        assert!(pattern.is_match("app-pr-99") && !pattern.is_match("app-pr-99-old"));
+    }
+
+    #[test]
+    fn test_render() {
+        let rendered = render(&cluster(&names(&[("pr-1", true), ("pr-2", true), ("pr-3", true)]), 3));
+        assert_eq!(rendered, "pr-<n> (3 workspaces, 3 stale)\n  Mostly stale, likely ephemeral. Clean them up together with a config restricted to them:\n    include = ['^pr\\-\\d+$']\n");
+    }
+}
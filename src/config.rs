@@ -0,0 +1,496 @@
+pub use crate::redact::Secret;
+use crate::staleness::{DEFAULT_OPT_OUT_MARKERS, DEFAULT_THRESHOLD_DAYS};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file read when `--config` isn't given, if it exists in the working directory.
+pub const DEFAULT_CONFIG_PATH: &str = "tfe_cleanup.toml";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Workspaces without activity for longer than this many days are flagged.
+    pub stale_after_days: i64,
+    /// Stricter threshold for workspaces without a VCS connection, which are often experiments.
+    pub no_vcs_stale_after_days: Option<i64>,
+    /// Measures staleness from the last human activity, i.e. runs started by people, run
+    /// comments, variable changes and team access changes, instead of TFE's last activity, which
+    /// also counts runs of machine accounts. Costs up to eight requests, more with many variables
+    /// or teams, per workspace that isn't excluded, opted out or created within the threshold.
+    pub human_activity: bool,
+    /// Regular expressions; workspaces whose name matches any of them are never flagged.
+    pub exclude: Vec<String>,
+    /// Workspaces never flagged, as `<org>/<name>`; remote overrides add theirs here.
+    pub exclude_workspaces: Vec<String>,
+    /// Regular expressions; if any are given, only workspaces whose name matches one are flagged.
+    pub include: Vec<String>,
+    /// Workspaces created per pull request, flagged as soon as their pull request is closed.
+    pub pull_requests: Option<PullRequestsConfig>,
+    /// Workspaces whose description or tags contain any of these are never flagged and are
+    /// reported as opted out, so owners can keep a workspace without editing this file.
+    pub opt_out_markers: Vec<String>,
+    /// Times at which destructive actions are allowed. Empty means any time.
+    pub deletion_windows: Vec<WindowConfig>,
+    /// SQLite database recording the actions of previous runs.
+    pub history_db: PathBuf,
+    /// How long per-workspace lookups such as costs and owners are reused from the history
+    /// database while the workspace is unchanged. 0 turns the cache off.
+    pub lookup_cache_minutes: i64,
+    /// Where the published report can be found; linked from Datadog events.
+    pub report_url: Option<String>,
+    /// Owners of workspaces by `<org>/<name>`, or by name in every organization, shown in the
+    /// report instead of the teams with admin access.
+    pub owners: BTreeMap<String, Vec<String>>,
+    /// CSV of further exclusions and owners downloaded at the start of each run.
+    pub remote_overrides: Option<RemoteOverridesConfig>,
+    /// Where the `last_changed_by` column looks up who last changed a workspace, and human
+    /// activity looks up team access changes.
+    pub audit_trail: AuditTrailConfig,
+    /// Directories mapping TFE usernames and team names to email addresses and Slack handles,
+    /// asked in order.
+    pub contacts: Vec<ContactSourceConfig>,
+    pub notifications: NotificationConfig,
+    /// Automation endpoint sent a signed event for every workspace flagged and action taken.
+    pub events: Option<EventsConfig>,
+    /// Where scan and cleanup reports go. Empty means stdout only.
+    pub sinks: Vec<SinkConfig>,
+    pub actions: ActionsConfig,
+    pub organizations: OrganizationsConfig,
+    pub run_lock: RunLockConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            stale_after_days: DEFAULT_THRESHOLD_DAYS,
+            no_vcs_stale_after_days: None,
+            human_activity: false,
+            exclude: Vec::new(),
+            exclude_workspaces: Vec::new(),
+            include: Vec::new(),
+            pull_requests: None,
+            opt_out_markers: DEFAULT_OPT_OUT_MARKERS.map(String::from).to_vec(),
+            deletion_windows: Vec::new(),
+            history_db: PathBuf::from("tfe_cleanup_history.db"),
+            lookup_cache_minutes: 12 * 60,
+            report_url: None,
+            owners: BTreeMap::new(),
+            remote_overrides: None,
+            audit_trail: AuditTrailConfig::default(),
+            contacts: Vec::new(),
+            notifications: NotificationConfig::default(),
+            events: None,
+            sinks: Vec::new(),
+            actions: ActionsConfig::default(),
+            organizations: OrganizationsConfig::default(),
+            run_lock: RunLockConfig::default(),
+        }
+    }
+}
+
+/// Keeps two cleanups from deleting at the same time.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunLockConfig {
+    /// Lockfile held while a cleanup deletes; only guards runs sharing this file.
+    pub path: PathBuf,
+    /// Also mark each organization being cleaned up with a workspace, which guards runs on
+    /// other machines.
+    pub org_marker: bool,
+    /// Locks held for longer than this are taken to be left behind by a run that died.
+    pub stale_after_minutes: i64,
+}
+
+impl Default for RunLockConfig {
+    fn default() -> RunLockConfig {
+        RunLockConfig { path: PathBuf::from("tfe_cleanup.lock"), org_marker: false, stale_after_minutes: 24 * 60 }
+    }
+}
+
+/// Organizations the token may see but runs must leave alone, or the only ones they may cover.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OrganizationsConfig {
+    /// If not empty, only these organizations are covered.
+    pub allow: Vec<String>,
+    /// Never covered, even when selected with `--org`.
+    pub deny: Vec<String>,
+}
+
+/// Where teams keep their exclusions and owners, e.g. a Google Sheet published as CSV. The CSV
+/// needs a `Workspace` column and may have `Exclude` and `Owner` columns.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteOverridesConfig {
+    pub url: Secret,
+    /// Value of the `Authorization` header, e.g. `Bearer <token>`.
+    pub authorization: Option<Secret>,
+}
+
+/// How to find the pull request of a workspace, e.g. `{ pattern = '-pr-(\d+)$' }` for
+/// `app-pr-1234`. The repository is the workspace's VCS repository; CLI-driven workspaces use
+/// `repository`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PullRequestsConfig {
+    /// Regular expression on workspace names whose first group is the pull request number.
+    pub pattern: String,
+    /// `owner/repo` on GitHub, or the project's full path on GitLab.
+    pub repository: Option<String>,
+    /// `github` or `gitlab`: where `repository` is hosted.
+    #[serde(default = "default_pull_request_provider")]
+    pub provider: String,
+}
+
+fn default_pull_request_provider() -> String {
+    "github".to_string()
+}
+
+/// Audit trails can only be read with an organization token, so organizations other than the
+/// one `TFE_TOKEN` belongs to need theirs here.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditTrailConfig {
+    /// How far back the audit trail is read.
+    pub lookback_days: i64,
+    /// Organization tokens by organization name.
+    pub tokens: BTreeMap<String, Secret>,
+}
+
+impl Default for AuditTrailConfig {
+    fn default() -> AuditTrailConfig {
+        AuditTrailConfig { lookback_days: 90, tokens: BTreeMap::new() }
+    }
+}
+
+/// Where the people behind TFE usernames and teams are looked up, e.g.
+/// `{ type = "csv", path = "contacts.csv" }`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ContactSourceConfig {
+    /// `Identity`, `Email` and `Slack` columns; a team has one row per member.
+    Csv { path: PathBuf },
+    /// SCIM 2.0 API of the identity provider, e.g. `https://idp.example.com/scim/v2`.
+    Scim { url: String, token: Secret },
+    /// `ldap://` or `ldaps://` directory, searched under `base_dn` after a simple bind.
+    Ldap {
+        url: String,
+        bind_dn: String,
+        password: Secret,
+        base_dn: String,
+        #[serde(default = "default_ldap_user_attribute")]
+        user_attribute: String,
+        #[serde(default = "default_ldap_email_attribute")]
+        email_attribute: String,
+        slack_attribute: Option<String>,
+        #[serde(default = "default_ldap_group_attribute")]
+        group_attribute: String,
+        /// Holds uids (`memberUid`) or DNs (`member`) of the group's members.
+        #[serde(default = "default_ldap_member_attribute")]
+        member_attribute: String,
+    },
+}
+
+fn default_ldap_user_attribute() -> String {
+    "uid".to_string()
+}
+
+fn default_ldap_email_attribute() -> String {
+    "mail".to_string()
+}
+
+fn default_ldap_group_attribute() -> String {
+    "cn".to_string()
+}
+
+fn default_ldap_member_attribute() -> String {
+    "memberUid".to_string()
+}
+
+/// A recurring window, in UTC, e.g. `{ days = ["mon", "tue"], start = "02:00", end = "05:00" }`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowConfig {
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+/// Chat channels told about each run. Templates are Handlebars files rendered with the run's
+/// results; channels without their own template use `template`, or the built-in one.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub slack_webhook: Option<Secret>,
+    pub teams_webhook: Option<Secret>,
+    pub template: Option<PathBuf>,
+    pub slack_template: Option<PathBuf>,
+    pub teams_template: Option<PathBuf>,
+}
+
+/// Endpoint of a SOAR or automation pipeline. Every event is a JSON POST signed with `secret`
+/// (HMAC-SHA256 of the body in the `X-TFE-Cleanup-Signature` header).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventsConfig {
+    pub url: Secret,
+    pub secret: Secret,
+}
+
+/// A report destination, e.g. `{ type = "html", path = "stale.html" }`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    Stdout,
+    Csv { path: PathBuf },
+    Json { path: PathBuf },
+    Html { path: PathBuf },
+    S3 {
+        bucket: String,
+        key: String,
+        region: String,
+        endpoint: Option<String>,
+        #[serde(default)]
+        format: SinkFormat,
+    },
+    Webhook { url: Secret },
+    /// Mails the report through an SMTP relay; credentials come from `SMTP_USERNAME` and
+    /// `SMTP_PASSWORD` if set.
+    Email {
+        to: Vec<String>,
+        from: String,
+        smtp_host: String,
+        /// Defaults to the usual port of the TLS mode: 587, 465 or 25.
+        smtp_port: Option<u16>,
+        #[serde(default)]
+        tls: SmtpTls,
+        /// Handlebars template rendered with the run's results, like notification templates.
+        subject: Option<String>,
+        /// Handlebars file for the body; without one, the notifications' shared template or the
+        /// built-in one is used, as for chat channels.
+        template: Option<PathBuf>,
+        #[serde(default = "default_attachments")]
+        attach: Vec<SinkFormat>,
+    },
+}
+
+fn default_attachments() -> Vec<SinkFormat> {
+    vec![SinkFormat::Csv]
+}
+
+/// Format of reports uploaded to object storage or attached to emails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkFormat {
+    #[default]
+    Csv,
+    Json,
+    Html,
+}
+
+/// How the SMTP connection is secured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS.
+    #[default]
+    Starttls,
+    /// TLS from the start (SMTPS).
+    Tls,
+    /// Unencrypted, e.g. a relay on localhost.
+    None,
+}
+
+/// Something the cleanup does to a stale workspace.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Delete,
+    Lock,
+    Tag,
+    Notify,
+    QueueDestroy,
+    Archive,
+    Hibernate,
+}
+
+/// The actions applied, in order, to the stale workspaces of each category.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActionsConfig {
+    pub stale: Vec<ActionKind>,
+    pub no_activity_data: Vec<ActionKind>,
+    pub no_vcs: Vec<ActionKind>,
+    /// Tag added by the `tag` action.
+    pub tag: String,
+    /// Directory the `archive` action writes state to.
+    pub archive_dir: PathBuf,
+}
+
+impl Default for ActionsConfig {
+    fn default() -> ActionsConfig {
+        ActionsConfig {
+            stale: vec![ActionKind::Delete],
+            no_activity_data: vec![ActionKind::Delete],
+            no_vcs: vec![ActionKind::Delete],
+            tag: "tfe-cleanup-stale".to_string(),
+            archive_dir: PathBuf::from("state_archive"),
+        }
+    }
+}
+
+impl Config {
+    /// The file `load` reads: `path` if given, otherwise the default config file if present.
+    pub fn resolve_path(path: Option<&Path>) -> Option<&Path> {
+        match path {
+            Some(path) => Some(path),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Some(Path::new(DEFAULT_CONFIG_PATH)),
+            None => None,
+        }
+    }
+
+    /// Loads `path`, or the default config file if present, or falls back to defaults.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error + Send + Sync>> {
+        let path = match Config::resolve_path(path) {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read config file {}: {}", path.display(), e))?;
+        Config::parse(&contents).map_err(|e| format!("invalid config file {}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_deletion_windows() {
+        let config = Config::parse(r#"
+            [[deletion_windows]]
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            start = "02:00"
+            end = "05:00"
+        "#).unwrap();
+
+        assert_eq!(config.deletion_windows.len(), 1);
+        assert_eq!(config.deletion_windows[0].days.len(), 5);
+        assert_eq!(config.deletion_windows[0].start, "02:00");
+    }
+
+    #[test]
+    fn test_parse_notifications() {
+        let config = Config::parse(r#"
+            [notifications]
+            slack_webhook = "https://hooks.slack.com/services/T/B/X"
+            template = "templates/cleanup.hbs"
+        "#).unwrap();
+
+        assert!(config.notifications.slack_webhook.is_some());
+        assert_eq!(config.notifications.template, Some(PathBuf::from("templates/cleanup.hbs")));
+        assert!(config.notifications.teams_webhook.is_none());
+    }
+
+    #[test]
+    fn test_parse_sinks() {
+        let config = Config::parse(r#"
+            [[sinks]]
+            type = "stdout"
+
+            [[sinks]]
+            type = "html"
+            path = "stale.html"
+
+            [[sinks]]
+            type = "s3"
+            bucket = "reports"
+            key = "tfe/stale.json"
+            region = "eu-west-1"
+            format = "json"
+        "#).unwrap();
+
+        assert_eq!(config.sinks.len(), 3);
+        assert!(matches!(&config.sinks[1], SinkConfig::Html { path } if path == Path::new("stale.html")));
+        assert!(matches!(&config.sinks[2], SinkConfig::S3 { format: SinkFormat::Json, endpoint: None, .. }));
+    }
+
+    #[test]
+    fn test_parse_contacts() {
+        let config = Config::parse(r#"
+            [[contacts]]
+            type = "csv"
+            path = "contacts.csv"
+
+            [[contacts]]
+            type = "ldap"
+            url = "ldaps://ldap.example.com"
+            bind_dn = "cn=tfe-cleanup,ou=services,dc=example,dc=com"
+            password = "bind-password"
+            base_dn = "dc=example,dc=com"
+            slack_attribute = "slackHandle"
+        "#).unwrap();
+
+        assert_eq!(config.contacts.len(), 2);
+        assert!(matches!(&config.contacts[1], ContactSourceConfig::Ldap { user_attribute, member_attribute, .. }
+            if user_attribute == "uid" && member_attribute == "memberUid"));
+    }
+
+    #[test]
+    fn test_parse_actions() {
+        let config = Config::parse(r#"
+            [actions]
+            no_vcs = ["notify", "archive", "delete"]
+            no_activity_data = ["tag", "lock"]
+        "#).unwrap();
+
+        assert_eq!(config.actions.stale, vec![ActionKind::Delete]);
+        assert_eq!(config.actions.no_vcs, vec![ActionKind::Notify, ActionKind::Archive, ActionKind::Delete]);
+        assert_eq!(config.actions.no_activity_data, vec![ActionKind::Tag, ActionKind::Lock]);
+        assert!(Config::parse("[actions]\nstale = [\"explode\"]").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_fields() {
+        assert!(Config::parse("delete_everything = true").is_err());
+    }
+
+    #[test]
+    fn test_effective_config_round_trips_without_secrets() {
+        let config = Config::parse(r#"
+            exclude = ["^prod-"]
+
+            [notifications]
+            slack_webhook = "https://hooks.slack.com/services/T9/B9/effective"
+
+            [[sinks]]
+            type = "email"
+            to = ["platform@example.com"]
+            from = "tfe-cleanup@example.com"
+            smtp_host = "smtp.example.com"
+        "#).unwrap();
+
+        let effective = toml::to_string_pretty(&config).unwrap();
+        assert!(!effective.contains("effective\""));
+        assert!(effective.contains("stale_after_days = 90"));
+
+        let reparsed = Config::parse(&effective).unwrap();
+        assert_eq!(reparsed.exclude, config.exclude);
+        assert!(matches!(&reparsed.sinks[0], SinkConfig::Email { attach, tls: SmtpTls::Starttls, .. } if attach == &[SinkFormat::Csv]));
+    }
+
+    #[test]
+    fn test_load_without_file_uses_defaults() {
+        let config = Config::load(None).unwrap();
+        assert!(config.deletion_windows.is_empty());
+        assert_eq!(config.history_db, PathBuf::from("tfe_cleanup_history.db"));
+    }
+}
@@ -0,0 +1,549 @@
+use crate::config::ContactSourceConfig;
+use crate::redact::{self, Secret};
+use async_trait::async_trait;
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, SearchOptions};
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Time allowed for a request to a SCIM API, and to connect to an LDAP directory and for each
+/// of its replies.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Users looked up by one request, e.g. the members of a team.
+const USERS_PER_REQUEST: usize = 50;
+
+/// How to reach a person.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Contact {
+    pub email: Option<String>,
+    pub slack: Option<String>,
+}
+
+impl Contact {
+    /// e.g. `alice@example.com (@alice)`.
+    pub fn describe(&self) -> String {
+        match (&self.email, &self.slack) {
+            (Some(email), Some(slack)) => format!("{} ({})", email, slack),
+            (Some(email), None) => email.clone(),
+            (None, Some(slack)) => slack.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.email.is_none() && self.slack.is_none()
+    }
+}
+
+/// Somewhere TFE usernames and team names can be looked up as the people behind them.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    fn describe(&self) -> String;
+
+    /// The person a username stands for, or the members of a team; empty if unknown.
+    async fn resolve(&self, identity: &str) -> Result<Vec<Contact>, Box<dyn Error + Send + Sync>>;
+}
+
+/// The resolver for each configured source, in order.
+pub fn from_config(sources: &[ContactSourceConfig]) -> Result<Vec<Box<dyn Resolver>>, Box<dyn Error + Send + Sync>> {
+    sources.iter()
+        .map(|source| -> Result<Box<dyn Resolver>, Box<dyn Error + Send + Sync>> {
+            Ok(match source {
+                ContactSourceConfig::Csv { path } => Box::new(CsvResolver::new(path.clone())),
+                ContactSourceConfig::Scim { url, token } => Box::new(ScimResolver::new(url.clone(), token.clone())?),
+                ContactSourceConfig::Ldap {
+                    url, bind_dn, password, base_dn, user_attribute, email_attribute, slack_attribute, group_attribute, member_attribute,
+                } => Box::new(LdapResolver {
+                    url: url.clone(),
+                    bind_dn: bind_dn.clone(),
+                    password: password.clone(),
+                    base_dn: base_dn.clone(),
+                    user_attribute: user_attribute.clone(),
+                    email_attribute: email_attribute.clone(),
+                    slack_attribute: slack_attribute.clone(),
+                    group_attribute: group_attribute.clone(),
+                    member_attribute: member_attribute.clone(),
+                    connection: Mutex::new(None),
+                }),
+            })
+        })
+        .collect()
+}
+
+/// A CSV with `Identity`, `Email` and `Slack` columns. A team has one row per member. The file
+/// is read on the first lookup.
+pub struct CsvResolver {
+    pub path: PathBuf,
+    contacts: OnceLock<Result<HashMap<String, Vec<Contact>>, String>>,
+}
+
+impl CsvResolver {
+    pub fn new(path: PathBuf) -> CsvResolver {
+        CsvResolver { path, contacts: OnceLock::new() }
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|value| !value.is_empty()).map(str::to_string)
+}
+
+fn read_csv(path: &Path) -> Result<HashMap<String, Vec<Contact>>, Box<dyn Error + Send + Sync>> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header.eq_ignore_ascii_case(name));
+    let identity = column("Identity").ok_or_else(|| format!("{} has no Identity column", path.display()))?;
+    let (email, slack) = (column("Email"), column("Slack"));
+
+    let mut contacts: HashMap<String, Vec<Contact>> = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let contact = Contact {
+            email: non_empty(email.and_then(|i| record.get(i))),
+            slack: non_empty(slack.and_then(|i| record.get(i))),
+        };
+        if let (Some(identity), false) = (non_empty(record.get(identity)), contact.is_empty()) {
+            contacts.entry(identity).or_default().push(contact);
+        }
+    }
+    Ok(contacts)
+}
+
+#[async_trait]
+impl Resolver for CsvResolver {
+    fn describe(&self) -> String {
+        format!("CSV {}", self.path.display())
+    }
+
+    async fn resolve(&self, identity: &str) -> Result<Vec<Contact>, Box<dyn Error + Send + Sync>> {
+        let contacts = self.contacts.get_or_init(|| read_csv(&self.path).map_err(|e| e.to_string()));
+        Ok(contacts.as_ref().map_err(Clone::clone)?.get(identity).cloned().unwrap_or_default())
+    }
+}
+
+/// A SCIM 2.0 API (RFC 7644), e.g. of the identity provider. Usernames are looked up by
+/// `userName`, teams by the `displayName` of a group.
+pub struct ScimResolver {
+    /// Base URL, e.g. `https://idp.example.com/scim/v2`.
+    pub url: String,
+    pub token: Secret,
+    client: reqwest::Client,
+}
+
+impl ScimResolver {
+    pub fn new(url: String, token: Secret) -> Result<ScimResolver, Box<dyn Error + Send + Sync>> {
+        Ok(ScimResolver { url, token, client: reqwest::Client::builder().timeout(TIMEOUT).build()? })
+    }
+
+    async fn get(&self, path: &str, filter: Option<String>) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}{}", self.url.trim_end_matches('/'), path);
+        let mut request = self.client.get(&url).bearer_auth(self.token.expose());
+        if let Some(filter) = filter {
+            request = request.query(&[("filter", filter), ("count", USERS_PER_REQUEST.to_string())]);
+        }
+        let response = request.send().await.map_err(|e| e.without_url())?;
+        match response.status() {
+            status if status.is_success() => Ok(response.json().await?),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(format!("SCIM {} refused the token", path).into()),
+            status => Err(format!("SCIM {} returned {}", path, status).into()),
+        }
+    }
+
+    /// Every SCIM filter value is a JSON string.
+    fn filter(attribute: &str, value: &str) -> String {
+        format!("{} eq {}", attribute, Value::from(value))
+    }
+
+    /// The primary email of a SCIM user, or the first one.
+    fn contact(user: &Value) -> Contact {
+        let emails = user["emails"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let email = emails.iter().find(|email| email["primary"] == true).or(emails.first());
+        Contact { email: email.and_then(|email| email["value"].as_str()).map(str::to_string), slack: None }
+    }
+}
+
+#[async_trait]
+impl Resolver for ScimResolver {
+    fn describe(&self) -> String {
+        format!("SCIM {}", redact::url(&self.url))
+    }
+
+    async fn resolve(&self, identity: &str) -> Result<Vec<Contact>, Box<dyn Error + Send + Sync>> {
+        let users = self.get("/Users", Some(Self::filter("userName", identity))).await?;
+        if let Some(users) = users["Resources"].as_array().filter(|users| !users.is_empty()) {
+            return Ok(users.iter().map(Self::contact).filter(|contact| !contact.is_empty()).collect());
+        }
+
+        let groups = self.get("/Groups", Some(Self::filter("displayName", identity))).await?;
+        let members: Vec<&str> = groups["Resources"].as_array().into_iter().flatten()
+            .flat_map(|group| group["members"].as_array().into_iter().flatten())
+            .filter_map(|member| member["value"].as_str())
+            .collect();
+        let mut contacts = Vec::new();
+        for ids in members.chunks(USERS_PER_REQUEST) {
+            let filter = ids.iter().map(|id| Self::filter("id", id)).collect::<Vec<_>>().join(" or ");
+            let users = self.get("/Users", Some(filter)).await?;
+            contacts.extend(users["Resources"].as_array().into_iter().flatten().map(Self::contact).filter(|contact| !contact.is_empty()));
+        }
+        Ok(contacts)
+    }
+}
+
+/// An LDAP directory. Usernames are looked up by `user_attribute`; teams as groups by
+/// `group_attribute`, whose members (`member_attribute`, uids or DNs) are then looked up as users.
+/// The connection is bound on the first lookup and kept for the rest of the run.
+pub struct LdapResolver {
+    pub url: String,
+    pub bind_dn: String,
+    pub password: Secret,
+    pub base_dn: String,
+    pub user_attribute: String,
+    pub email_attribute: String,
+    pub slack_attribute: Option<String>,
+    pub group_attribute: String,
+    pub member_attribute: String,
+    connection: Mutex<Option<Ldap>>,
+}
+
+impl LdapResolver {
+    fn values<'a>(entry: &'a SearchEntry, attribute: &str) -> &'a [String] {
+        entry.attrs.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+            .map_or(&[], |(_, values)| values)
+    }
+
+    /// The bound connection, connecting and binding on first use.
+    async fn connection(&self) -> Result<Ldap, Box<dyn Error + Send + Sync>> {
+        let mut connection = self.connection.lock().await;
+        if let Some(ldap) = connection.as_ref() {
+            return Ok(ldap.clone());
+        }
+        let settings = LdapConnSettings::new().set_conn_timeout(TIMEOUT);
+        let (driver, mut ldap) = LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(driver);
+        ldap.with_timeout(TIMEOUT).simple_bind(&self.bind_dn, self.password.expose()).await?.success()
+            .map_err(|e| format!("LDAP bind as {} failed: {}", self.bind_dn, e))?;
+        *connection = Some(ldap.clone());
+        Ok(ldap)
+    }
+
+    /// The requested attributes of every entry under `base_dn` whose `attribute` equals one of
+    /// `values`. A failed search drops the connection, so the next lookup connects anew.
+    async fn search(&self, attribute: &str, values: &[&str], attributes: &[&str]) -> Result<Vec<SearchEntry>, Box<dyn Error + Send + Sync>> {
+        let filter = values.iter()
+            .map(|value| format!("({}={})", attribute, ldap3::ldap_escape(*value)))
+            .collect::<String>();
+        let filter = if values.len() == 1 { filter } else { format!("(|{})", filter) };
+        let mut ldap = self.connection().await?;
+        let result = ldap.with_search_options(SearchOptions::new().sizelimit(USERS_PER_REQUEST as i32))
+            .with_timeout(TIMEOUT)
+            .search(&self.base_dn, Scope::Subtree, &filter, attributes.to_vec())
+            .await
+            .and_then(|result| result.success());
+        match result {
+            Ok((entries, _)) => Ok(entries.into_iter().map(SearchEntry::construct).collect()),
+            Err(e) => {
+                *self.connection.lock().await = None;
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn users(&self, uids: &[&str]) -> Result<Vec<Contact>, Box<dyn Error + Send + Sync>> {
+        let mut attributes = vec![self.email_attribute.as_str()];
+        attributes.extend(self.slack_attribute.as_deref());
+        let mut contacts = Vec::new();
+        for uids in uids.chunks(USERS_PER_REQUEST) {
+            let entries = self.search(&self.user_attribute, uids, &attributes).await?;
+            contacts.extend(entries.iter()
+                .map(|entry| Contact {
+                    email: Self::values(entry, &self.email_attribute).first().cloned(),
+                    slack: self.slack_attribute.as_ref().and_then(|attribute| Self::values(entry, attribute).first()).cloned(),
+                })
+                .filter(|contact| !contact.is_empty()));
+        }
+        Ok(contacts)
+    }
+}
+
+/// The uid of a group member given as a uid or as a DN such as `uid=alice,ou=people,dc=example`.
+fn member_uid(member: &str) -> &str {
+    match member.split(',').next().and_then(|rdn| rdn.split_once('=')) {
+        Some((_, value)) if member.contains(',') => value,
+        _ => member,
+    }
+}
+
+#[async_trait]
+impl Resolver for LdapResolver {
+    fn describe(&self) -> String {
+        format!("LDAP {}", self.url)
+    }
+
+    async fn resolve(&self, identity: &str) -> Result<Vec<Contact>, Box<dyn Error + Send + Sync>> {
+        let contacts = self.users(&[identity]).await?;
+        if !contacts.is_empty() {
+            return Ok(contacts);
+        }
+        let groups = self.search(&self.group_attribute, &[identity], &[&self.member_attribute]).await?;
+        let members: Vec<&str> = groups.iter()
+            .flat_map(|group| Self::values(group, &self.member_attribute))
+            .map(|member| member_uid(member))
+            .collect();
+        self.users(&members).await
+    }
+}
+
+/// The configured resolvers, asked in order until one knows an identity. Answers are kept for
+/// the rest of the run.
+pub struct Contacts {
+    resolvers: Vec<Box<dyn Resolver>>,
+    resolved: HashMap<String, Vec<Contact>>,
+}
+
+impl Contacts {
+    pub fn new(resolvers: Vec<Box<dyn Resolver>>) -> Contacts {
+        Contacts { resolvers, resolved: HashMap::new() }
+    }
+
+    pub fn from_config(sources: &[ContactSourceConfig]) -> Result<Contacts, Box<dyn Error + Send + Sync>> {
+        Ok(Contacts::new(from_config(sources)?))
+    }
+
+    /// The contacts of an identity, empty if no resolver knows it. Resolvers that fail are
+    /// skipped with a warning.
+    pub async fn resolve(&mut self, identity: &str) -> Vec<Contact> {
+        if let Some(contacts) = self.resolved.get(identity) {
+            return contacts.clone();
+        }
+        let mut contacts = Vec::new();
+        for resolver in &self.resolvers {
+            match resolver.resolve(identity).await {
+                Ok(found) if !found.is_empty() => {
+                    contacts = found;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: cannot look up {} in {}: {}", identity, resolver.describe(), redact::scrub(&e.to_string())),
+            }
+        }
+        self.resolved.insert(identity.to_string(), contacts.clone());
+        contacts
+    }
+
+    /// The contacts of each workspace's identities (owners, people), by workspace id, without
+    /// duplicates. Workspaces none of whose identities resolve are left out.
+    pub async fn of_workspaces(&mut self, identities: &HashMap<String, Vec<String>>) -> HashMap<String, Vec<Contact>> {
+        let mut contacts = HashMap::new();
+        for (id, identities) in identities {
+            let mut found: Vec<Contact> = Vec::new();
+            for identity in identities {
+                for contact in self.resolve(identity).await {
+                    if !found.contains(&contact) {
+                        found.push(contact);
+                    }
+                }
+            }
+            if !found.is_empty() {
+                contacts.insert(id.clone(), found);
+            }
+        }
+        contacts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+    use std::io::Write;
+
+    fn contact(email: &str) -> Contact {
+        Contact { email: Some(email.to_string()), slack: None }
+    }
+
+    #[tokio::test]
+    async fn test_csv_resolver() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "Identity,Email,Slack\nalice,alice@example.com,@alice\nplatform,bob@example.com,\nplatform,,@carol\nnobody,,").unwrap();
+        let resolver = CsvResolver::new(file.path().to_path_buf());
+
+        assert_eq!(resolver.resolve("alice").await.unwrap()[0].describe(), "alice@example.com (@alice)");
+        let team: Vec<String> = resolver.resolve("platform").await.unwrap().iter().map(Contact::describe).collect();
+        assert_eq!(team, vec!["bob@example.com", "@carol"]);
+        assert!(resolver.resolve("nobody").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scim_resolver() {
+        let _user = mock("GET", "/scim-contacts/Users")
+            .match_query(Matcher::UrlEncoded("filter".into(), r#"userName eq "alice""#.into()))
+            .match_header("authorization", "Bearer scim-token")
+            .with_status(200)
+            .with_body(json!({ "Resources": [{ "emails": [
+                { "value": "alice@home.example", "primary": false },
+                { "value": "alice@example.com", "primary": true }
+            ] }] }).to_string())
+            .create();
+        let _no_user = mock("GET", "/scim-contacts/Users")
+            .match_query(Matcher::UrlEncoded("filter".into(), r#"userName eq "platform""#.into()))
+            .with_status(200)
+            .with_body(json!({ "Resources": [] }).to_string())
+            .create();
+        let group = json!({ "Resources": [{ "members": [{ "value": "u-bob" }, { "value": "u-carol" }] }] });
+        let _group = mock("GET", "/scim-contacts/Groups")
+            .match_query(Matcher::UrlEncoded("filter".into(), r#"displayName eq "platform""#.into()))
+            .with_status(200)
+            .with_body(group.to_string())
+            .create();
+        let members = mock("GET", "/scim-contacts/Users")
+            .match_query(Matcher::UrlEncoded("filter".into(), r#"id eq "u-bob" or id eq "u-carol""#.into()))
+            .with_status(200)
+            .with_body(json!({ "Resources": [
+                { "emails": [{ "value": "bob@example.com" }] },
+                { "emails": [{ "value": "carol@example.com" }] }
+            ] }).to_string())
+            .expect(1)
+            .create();
+
+        let resolver = ScimResolver::new(format!("{}/scim-contacts/", server_url()), Secret::new("scim-token")).unwrap();
+        assert_eq!(resolver.resolve("alice").await.unwrap(), vec![contact("alice@example.com")]);
+        assert_eq!(resolver.resolve("platform").await.unwrap(), vec![contact("bob@example.com"), contact("carol@example.com")]);
+        members.assert();
+    }
+
+    fn ber(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len if len < 0x80 => out.push(len as u8),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn ber_string(value: &str) -> Vec<u8> {
+        ber(0x04, value.as_bytes())
+    }
+
+    fn ldap_entry(dn: &str, attribute: &str, values: &[&str]) -> Vec<u8> {
+        let values: Vec<u8> = values.iter().flat_map(|value| ber_string(value)).collect();
+        let attribute = ber(0x30, &[ber_string(attribute), ber(0x31, &values)].concat());
+        ber(0x64, &[ber_string(dn), ber(0x30, &attribute)].concat())
+    }
+
+    /// A directory with alice and the team platform of bob and carol, counting binds.
+    async fn serve_ldap(listener: tokio::net::TcpListener, binds: std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        loop {
+            let mut header = [0u8; 2];
+            if stream.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            let len = match header[1] {
+                len if len < 0x80 => len as usize,
+                count => {
+                    let mut bytes = vec![0u8; (count & 0x7f) as usize];
+                    stream.read_exact(&mut bytes).await.unwrap();
+                    bytes.iter().fold(0, |len, byte| len << 8 | *byte as usize)
+                }
+            };
+            let mut request = vec![0u8; len];
+            stream.read_exact(&mut request).await.unwrap();
+            // messageID, then the operation
+            let id = &request[..2 + request[1] as usize];
+            let reply = |operation: Vec<u8>| ber(0x30, &[id, &operation].concat());
+            let success = [ber(0x0a, &[0]), ber_string(""), ber_string("")].concat();
+            let contains = |text: &str| request.windows(text.len()).any(|window| window == text.as_bytes());
+
+            let mut replies = Vec::new();
+            match request[id.len()] {
+                0x60 => {
+                    binds.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    replies.push(reply(ber(0x61, &success)));
+                }
+                0x63 => {
+                    if contains("memberUid") {
+                        replies.push(reply(ldap_entry("cn=platform,dc=example", "memberUid", &["uid=bob,ou=people,dc=example", "carol"])));
+                    } else if contains("bob") && contains("carol") {
+                        replies.push(reply(ldap_entry("uid=bob,dc=example", "mail", &["bob@example.com"])));
+                        replies.push(reply(ldap_entry("uid=carol,dc=example", "mail", &["carol@example.com"])));
+                    } else if contains("alice") {
+                        replies.push(reply(ldap_entry("uid=alice,dc=example", "mail", &["alice@example.com"])));
+                    }
+                    replies.push(reply(ber(0x65, &success)));
+                }
+                _ => return,
+            }
+            for reply in replies {
+                stream.write_all(&reply).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ldap_resolver_binds_once() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let binds = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        tokio::spawn(serve_ldap(listener, binds.clone()));
+
+        let resolver = LdapResolver {
+            url,
+            bind_dn: "cn=admin".to_string(),
+            password: Secret::new("ldap-bind-password"),
+            base_dn: "dc=example".to_string(),
+            user_attribute: "uid".to_string(),
+            email_attribute: "mail".to_string(),
+            slack_attribute: None,
+            group_attribute: "cn".to_string(),
+            member_attribute: "memberUid".to_string(),
+            connection: Mutex::new(None),
+        };
+        assert_eq!(resolver.resolve("alice").await.unwrap(), vec![contact("alice@example.com")]);
+        assert_eq!(resolver.resolve("platform").await.unwrap(), vec![contact("bob@example.com"), contact("carol@example.com")]);
+        assert_eq!(binds.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    struct Fixed(Result<Vec<Contact>, String>);
+
+    #[async_trait]
+    impl Resolver for Fixed {
+        fn describe(&self) -> String {
+            "fixed".to_string()
+        }
+
+        async fn resolve(&self, _identity: &str) -> Result<Vec<Contact>, Box<dyn Error + Send + Sync>> {
+            self.0.clone().map_err(Into::into)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_contacts_fall_through_resolvers() {
+        let mut contacts = Contacts::new(vec![
+            Box::new(Fixed(Err("unreachable".to_string()))),
+            Box::new(Fixed(Ok(Vec::new()))),
+            Box::new(Fixed(Ok(vec![contact("ops@example.com")]))),
+        ]);
+        let identities = HashMap::from([
+            ("ws-1".to_string(), vec!["ops".to_string(), "sre".to_string()]),
+        ]);
+
+        let resolved = contacts.of_workspaces(&identities).await;
+        assert_eq!(resolved["ws-1"], vec![contact("ops@example.com")]);
+    }
+
+    #[test]
+    fn test_member_uid() {
+        assert_eq!(member_uid("uid=alice,ou=people,dc=example,dc=com"), "alice");
+        assert_eq!(member_uid("alice"), "alice");
+    }
+}
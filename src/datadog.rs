@@ -0,0 +1,160 @@
+use crate::redact;
+use crate::tfe::{self, OrgTotals};
+use chrono::Utc;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+
+const DEFAULT_SITE: &str = "datadoghq.com";
+
+/// Sends run events and hygiene metrics to Datadog.
+pub struct Datadog {
+    client: reqwest::Client,
+    base_url: String,
+    headers: HeaderMap,
+    report_url: Option<String>,
+}
+
+impl Datadog {
+    pub fn new(base_url: &str, api_key: &str, report_url: Option<String>) -> Result<Datadog, Box<dyn Error + Send + Sync>> {
+        redact::register(api_key);
+        let mut api_key = HeaderValue::from_str(api_key)?;
+        api_key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("DD-API-KEY", api_key);
+
+        Ok(Datadog {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers,
+            report_url,
+        })
+    }
+
+    /// Builds a client from `DD_API_KEY` and the optional `DD_SITE` (e.g. `datadoghq.eu`).
+    /// Returns `None` when no API key is set, which disables the integration.
+    pub fn from_env(report_url: Option<String>) -> Result<Option<Datadog>, Box<dyn Error + Send + Sync>> {
+        let api_key = match env::var("DD_API_KEY") {
+            Ok(api_key) if !api_key.is_empty() => api_key,
+            _ => return Ok(None),
+        };
+        let site = env::var("DD_SITE").unwrap_or_else(|_| DEFAULT_SITE.to_string());
+        Ok(Some(Datadog::new(&format!("https://api.{}", site), &api_key, report_url)?))
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = self.client.post(format!("{}{}", self.base_url, path))
+            .headers(self.headers.clone())
+            .json(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(format!("Datadog API returned {} for {}: {}", status, path, response.text().await.unwrap_or_default()).into());
+        }
+        Ok(())
+    }
+
+    /// Posts one event summarizing the run and a `tfe_cleanup.stale_workspaces` and
+    /// `tfe_cleanup.workspaces` gauge per organization.
+    pub async fn publish(&self, command: &str, totals: &OrgTotals, stale: &[Value]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let counts = counts_by_org(totals, stale);
+        self.post("/api/v1/events", &event(command, &counts, self.report_url.as_deref())).await?;
+        self.post("/api/v1/series", &series(&counts, Utc::now().timestamp())).await
+    }
+}
+
+/// Total and stale workspace counts per organization.
+fn counts_by_org(totals: &OrgTotals, stale: &[Value]) -> BTreeMap<String, (usize, usize)> {
+    let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for (org, total) in totals {
+        counts.entry(org.clone()).or_default().0 += total;
+    }
+    for workspace in stale {
+        counts.entry(tfe::workspace_org(workspace).to_string()).or_default().1 += 1;
+    }
+    counts
+}
+
+fn event(command: &str, counts: &BTreeMap<String, (usize, usize)>, report_url: Option<&str>) -> Value {
+    let stale: usize = counts.values().map(|(_, stale)| stale).sum();
+    let mut text: Vec<String> = counts.iter()
+        .map(|(org, (total, stale))| format!("{}: {} of {} workspaces stale", org, stale, total))
+        .collect();
+    if let Some(url) = report_url {
+        text.push(format!("Report: {}", url));
+    }
+
+    json!({
+        "title": format!("tfe_cleanup {}: {} stale workspaces in {} organizations", command, stale, counts.len()),
+        "text": text.join("\n"),
+        "alert_type": "info",
+        "source_type_name": "tfe_cleanup",
+        "tags": [format!("command:{}", command)],
+    })
+}
+
+fn series(counts: &BTreeMap<String, (usize, usize)>, timestamp: i64) -> Value {
+    let series: Vec<Value> = counts.iter()
+        .flat_map(|(org, (total, stale))| {
+            [("tfe_cleanup.workspaces", total), ("tfe_cleanup.stale_workspaces", stale)].map(|(metric, value)| json!({
+                "metric": metric,
+                "type": "gauge",
+                "points": [[timestamp, value]],
+                "tags": [format!("org:{}", org)],
+            }))
+        })
+        .collect();
+
+    json!({ "series": series })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    fn workspace(org: &str) -> Value {
+        json!({ "relationships": { "organization": { "data": { "id": org } } } })
+    }
+
+    #[test]
+    fn test_event_and_series() {
+        let workspaces = vec![workspace("acme"), workspace("acme"), workspace("initech")];
+        let counts = counts_by_org(&tfe::count_by_org(&workspaces), &[workspace("acme")]);
+
+        let event = event("scan", &counts, Some("https://reports/tfe"));
+        assert_eq!(event["title"], "tfe_cleanup scan: 1 stale workspaces in 2 organizations");
+        assert_eq!(event["text"], "acme: 1 of 2 workspaces stale\ninitech: 0 of 1 workspaces stale\nReport: https://reports/tfe");
+
+        let series = series(&counts, 1_700_000_000);
+        assert_eq!(series["series"].as_array().unwrap().len(), 4);
+        assert_eq!(series["series"][1], json!({
+            "metric": "tfe_cleanup.stale_workspaces",
+            "type": "gauge",
+            "points": [[1_700_000_000, 1]],
+            "tags": ["org:acme"],
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_publish_sends_api_key() {
+        let events = mock("POST", "/api/v1/events")
+            .match_header("DD-API-KEY", "dd-key")
+            .match_body(Matcher::Regex("tfe_cleanup cleanup".into()))
+            .with_status(202)
+            .create();
+        let metrics = mock("POST", "/api/v1/series")
+            .match_header("DD-API-KEY", "dd-key")
+            .with_status(202)
+            .create();
+
+        let datadog = Datadog::new(&server_url(), "dd-key", None).unwrap();
+        datadog.publish("cleanup", &tfe::count_by_org(&[workspace("acme")]), &[]).await.unwrap();
+
+        events.assert();
+        metrics.assert();
+    }
+}
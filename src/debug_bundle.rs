@@ -0,0 +1,141 @@
+use crate::config::Config;
+use crate::redact;
+use crate::staleness::Verdict;
+use crate::tfe;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// What a run did, collected for `--debug-bundle`: every API request with its response
+/// metadata and timing, and the verdict on every workspace scanned. Nothing from request or
+/// response bodies is kept, and everything is scrubbed of secrets.
+#[derive(Debug)]
+pub struct DebugLog {
+    started: Instant,
+    requests: Mutex<Vec<Value>>,
+    decisions: Mutex<Vec<Value>>,
+}
+
+impl Default for DebugLog {
+    fn default() -> DebugLog {
+        DebugLog { started: Instant::now(), requests: Mutex::default(), decisions: Mutex::default() }
+    }
+}
+
+impl DebugLog {
+    pub fn record_request(&self, method: &str, url: &reqwest::Url, outcome: Result<&reqwest::Response, &reqwest::Error>, took: Duration) {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let mut record = json!({
+            "at_ms": (self.started.elapsed() - took).as_millis() as u64,
+            "method": method,
+            "path": redact::scrub(&path),
+            "duration_ms": took.as_millis() as u64,
+        });
+        match outcome {
+            Ok(response) => {
+                let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(redact::scrub);
+                record["status"] = json!(response.status().as_u16());
+                record["request_id"] = json!(header("x-request-id"));
+                record["ratelimit_remaining"] = json!(header("x-ratelimit-remaining"));
+            }
+            Err(e) => record["error"] = json!(redact::scrub(&e.to_string())),
+        }
+        self.requests.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(record);
+    }
+
+    pub fn record_decision(&self, workspace: &Value, verdict: &Verdict) {
+        let record = json!({
+            "org": tfe::workspace_org(workspace),
+            "name": workspace["attributes"]["name"],
+            "status": verdict.status.label(),
+            "trace": verdict.trace,
+        });
+        self.decisions.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(record);
+    }
+}
+
+/// One JSON document per line.
+fn json_lines(records: &Mutex<Vec<Value>>) -> String {
+    records.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter()
+        .map(|record| format!("{}\n", record))
+        .collect()
+}
+
+/// Writes the zip to attach to support tickets: `run.json` with the version, command line and
+/// outcome, `config.toml` with the effective configuration (secrets redacted), and
+/// `requests.jsonl` and `decisions.jsonl` from the log.
+pub fn write(path: &Path, log: &DebugLog, config: &Config, outcome: &Result<(), Box<dyn Error + Send + Sync>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let run = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "args": std::env::args().map(|arg| redact::scrub(&arg)).collect::<Vec<_>>(),
+        "address": redact::scrub(&std::env::var("TFE_ADDRESS").unwrap_or_default()),
+        "duration_ms": log.started.elapsed().as_millis() as u64,
+        "error": outcome.as_ref().err().map(|e| redact::scrub(&e.to_string())),
+    });
+
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let files = [
+        ("run.json", serde_json::to_string_pretty(&run)?),
+        ("config.toml", toml::to_string_pretty(config)?),
+        ("requests.jsonl", json_lines(&log.requests)),
+        ("decisions.jsonl", json_lines(&log.decisions)),
+    ];
+    for (name, contents) in files {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(contents.as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::staleness::{self, Policy};
+    use crate::tfe::TfeClient;
+    use mockito::{mock, server_url};
+    use std::io::Read;
+    use std::sync::Arc;
+    use zip::ZipArchive;
+
+    #[tokio::test]
+    async fn test_bundle_records_requests_and_decisions() {
+        let _workspace = mock("GET", "/api/v2/organizations/bundle-org/workspaces/bundle-ws")
+            .with_status(200)
+            .with_header("x-request-id", "req-123")
+            .with_body(json!({ "data": { "attributes": { "name": "bundle-ws" } } }).to_string())
+            .create();
+        let log = Arc::new(DebugLog::default());
+        let client = TfeClient::new(&server_url(), "bundle-secret-token").unwrap().with_debug_log(Some(log.clone()));
+
+        let workspace = tfe::get_workspace(&client, "bundle-org", "bundle-ws").await.unwrap();
+        log.record_decision(&workspace, &staleness::evaluate(&workspace, &Policy::default(), chrono::Utc::now()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.zip");
+        write(&path, &log, &Config::default(), &Err("boom with bundle-secret-token".into())).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive.by_name(name).unwrap().read_to_string(&mut contents).unwrap();
+            contents
+        };
+        let requests = read("requests.jsonl");
+        assert!(requests.contains("\"path\":\"/api/v2/organizations/bundle-org/workspaces/bundle-ws\""));
+        assert!(requests.contains("\"request_id\":\"req-123\"") && requests.contains("\"status\":200"));
+        assert!(read("decisions.jsonl").contains("\"status\":\"KEPT\""));
+        let run = read("run.json");
+        assert!(run.contains("boom with [redacted]") && !run.contains("bundle-secret-token"));
+        assert!(read("config.toml").contains("stale_after_days = 90"));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::cache::LookupCache;
+use crate::config::Config;
+use crate::redact;
+use crate::report;
+use crate::tfe::{self, TfeClient};
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Name TFE gives the project workspaces land in when created without one.
+const DEFAULT_PROJECT: &str = "Default Project";
+
+/// Owner lookups in flight at once.
+const CONCURRENT_LOOKUPS: usize = 8;
+
+/// Owner under which workspaces without owners are grouped.
+pub const NO_OWNER: &str = "(no owner)";
+
+/// A workspace in its organization's default project.
+#[derive(Debug, PartialEq)]
+pub struct DefaultProjectWorkspace {
+    pub org: String,
+    pub workspace: String,
+    pub last_activity: String,
+    pub owners: Vec<String>,
+}
+
+/// The id of the organization's default project: the one the organization points to, or the
+/// one named "Default Project" on TFE versions without that relationship.
+async fn default_project_id(client: &TfeClient, org: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let organization = client.get(&format!("/organizations/{}", org)).await?;
+    if let Some(id) = organization["data"]["relationships"]["default-project"]["data"]["id"].as_str() {
+        return Ok(Some(id.to_string()));
+    }
+    let projects = client.get_all_with_query(&format!("/organizations/{}/projects", org), &[("filter[names]", DEFAULT_PROJECT)]).await?;
+    Ok(projects.iter()
+        .find(|project| project["attributes"]["name"] == DEFAULT_PROJECT)
+        .and_then(|project| project["id"].as_str())
+        .map(str::to_string))
+}
+
+/// Lists the workspaces of `org` still in its default project, which usually means nobody
+/// decided where they belong, with their owners.
+pub async fn find_default_project_workspaces(
+    client: &TfeClient,
+    cache: &LookupCache,
+    config: &Config,
+    org: &str,
+) -> Result<Vec<DefaultProjectWorkspace>, Box<dyn Error + Send + Sync>> {
+    let Some(project_id) = default_project_id(client, org).await? else {
+        eprintln!("Warning: organization {} has no default project", org);
+        return Ok(Vec::new());
+    };
+
+    let in_default_project: Vec<Value> = tfe::list_workspaces(client, org).await?.into_iter()
+        .filter(|workspace| workspace["relationships"]["project"]["data"]["id"] == project_id.as_str())
+        .collect();
+    let findings = stream::iter(&in_default_project)
+        .map(|workspace| async move {
+            let name = workspace["attributes"]["name"].as_str().unwrap_or("").to_string();
+            let owners = report::owners(client, cache, config, workspace).await.unwrap_or_else(|e| {
+                eprintln!("Warning: could not look up the owners of {}/{}: {}", org, name, redact::scrub(&e.to_string()));
+                Vec::new()
+            });
+            DefaultProjectWorkspace {
+                org: org.to_string(),
+                workspace: name,
+                last_activity: workspace["attributes"]["last-activity-at"].as_str().unwrap_or("").to_string(),
+                owners,
+            }
+        })
+        .buffered(CONCURRENT_LOOKUPS)
+        .collect()
+        .await;
+
+    Ok(findings)
+}
+
+/// Groups the findings by owner, so each owner sees the workspaces to find a project for. A
+/// workspace with several owners is listed under each.
+pub fn by_owner(findings: &[DefaultProjectWorkspace]) -> BTreeMap<&str, Vec<&DefaultProjectWorkspace>> {
+    let mut groups: BTreeMap<&str, Vec<&DefaultProjectWorkspace>> = BTreeMap::new();
+    for finding in findings {
+        if finding.owners.is_empty() {
+            groups.entry(NO_OWNER).or_default().push(finding);
+        }
+        for owner in &finding.owners {
+            groups.entry(owner.as_str()).or_default().push(finding);
+        }
+    }
+    groups
+}
+
+pub fn create_default_project_csv(findings: &[DefaultProjectWorkspace], path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["Owner", "Organization", "Workspace", "Last Activity"])?;
+
+    for (owner, workspaces) in by_owner(findings) {
+        for finding in workspaces {
+            wtr.write_record([owner, &finding.org, &finding.workspace, &finding.last_activity])?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn workspace(id: &str, name: &str, project: &str) -> Value {
+        json!({
+            "id": id,
+            "attributes": { "name": name, "last-activity-at": "2024-01-01T00:00:00Z" },
+            "relationships": { "project": { "data": { "id": project, "type": "projects" } } }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_find_default_project_workspaces() {
+        let _org = mock("GET", "/api/v2/organizations/default-project-org")
+            .with_status(200)
+            .with_body(json!({ "data": { "relationships": { "default-project": { "data": { "id": "prj-default" } } } } }).to_string())
+            .create();
+        let _workspaces = mock("GET", "/api/v2/organizations/default-project-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                workspace("ws-sprawl", "sprawl", "prj-default"),
+                workspace("ws-placed", "placed", "prj-platform"),
+                workspace("ws-orphan", "orphan", "prj-default"),
+            ] }).to_string())
+            .create();
+        let _grants = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-orphan".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let config = Config {
+            owners: BTreeMap::from([("sprawl".to_string(), vec!["alice".to_string(), "platform".to_string()])]),
+            lookup_cache_minutes: 0,
+            ..Config::default()
+        };
+        let cache = LookupCache::from_config(&config).unwrap();
+        let findings = find_default_project_workspaces(&client, &cache, &config, "default-project-org").await.unwrap();
+
+        let names: Vec<&str> = findings.iter().map(|finding| finding.workspace.as_str()).collect();
+        assert_eq!(names, vec!["sprawl", "orphan"]);
+        let groups: Vec<(&str, usize)> = by_owner(&findings).into_iter().map(|(owner, workspaces)| (owner, workspaces.len())).collect();
+        assert_eq!(groups, vec![("(no owner)", 1), ("alice", 1), ("platform", 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_default_project_by_name() {
+        let _org = mock("GET", "/api/v2/organizations/legacy-project-org")
+            .with_status(200)
+            .with_body(json!({ "data": { "relationships": {} } }).to_string())
+            .create();
+        let _projects = mock("GET", "/api/v2/organizations/legacy-project-org/projects")
+            .match_query(Matcher::UrlEncoded("filter[names]".into(), "Default Project".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "prj-legacy", "attributes": { "name": "Default Project" } }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        assert_eq!(default_project_id(&client, "legacy-project-org").await.unwrap().as_deref(), Some("prj-legacy"));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::tfe::{self, ApiError, TfeClient};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::error::Error;
+
+#[derive(Debug, PartialEq)]
+pub enum DeleteOutcome {
+    Deleted,
+    /// TFE refused the deletion, typically because the workspace still manages resources.
+    Refused(String),
+    /// The TFE instance has no safe-delete endpoint (older self-hosted releases).
+    Unsupported,
+}
+
+/// The error details of a JSON:API error body, or the raw body if it isn't one.
+fn error_detail(body: &str) -> String {
+    let details: Vec<String> = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|body| body["errors"].as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|error| error["detail"].as_str().or(error["title"].as_str()).map(str::to_string))
+        .collect();
+
+    if details.is_empty() { body.to_string() } else { details.join("; ") }
+}
+
+/// Deletes a workspace through the safe-delete endpoint, which refuses workspaces that still
+/// manage resources instead of orphaning them.
+pub async fn safe_delete(client: &TfeClient, org: &str, name: &str) -> Result<DeleteOutcome, Box<dyn Error + Send + Sync>> {
+    let path = format!("/organizations/{}/workspaces/{}/actions/safe-delete", org, name);
+
+    match client.post(&path, &json!({})).await {
+        Ok(_) => Ok(DeleteOutcome::Deleted),
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::CONFLICT => {
+                Ok(DeleteOutcome::Refused(error_detail(&api_err.body)))
+            }
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND
+                || api_err.status == StatusCode::METHOD_NOT_ALLOWED => Ok(DeleteOutcome::Unsupported),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Deletes a workspace on TFE releases without safe delete, refusing as safe delete would when
+/// the workspace reports managing resources. Needs nothing but the API.
+pub async fn delete_if_empty(client: &TfeClient, org: &str, name: &str) -> Result<DeleteOutcome, Box<dyn Error + Send + Sync>> {
+    let workspace = tfe::get_workspace(client, org, name).await?;
+    match workspace["attributes"]["resource-count"].as_u64() {
+        Some(0) => {
+            client.delete(&format!("/organizations/{}/workspaces/{}", org, name)).await?;
+            Ok(DeleteOutcome::Deleted)
+        }
+        Some(count) => Ok(DeleteOutcome::Refused(format!("workspace still manages {} resources", count))),
+        None => Ok(DeleteOutcome::Refused("TFE doesn't report how many resources the workspace manages".to_string())),
+    }
+}
+
+/// What to do about a workspace whose safe deletion was refused.
+pub fn destroy_then_delete_hint(org: &str, name: &str) -> String {
+    format!("queue a destroy run for {}/{} (tfe_cleanup destroy, or Settings > Destruction and Deletion), \
+        then re-run the cleanup to delete the empty workspace", org, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_error_detail() {
+        let body = json!({ "errors": [{ "status": "409", "title": "conflict", "detail": "Workspace still manages 3 resources" }] });
+        assert_eq!(error_detail(&body.to_string()), "Workspace still manages 3 resources");
+        assert_eq!(error_detail("not json"), "not json");
+    }
+
+    #[tokio::test]
+    async fn test_safe_delete_outcomes() {
+        let _deleted = mock("POST", "/api/v2/organizations/safe-org/workspaces/empty/actions/safe-delete")
+            .with_status(204)
+            .create();
+        let _refused = mock("POST", "/api/v2/organizations/safe-org/workspaces/busy/actions/safe-delete")
+            .with_status(409)
+            .with_body(json!({ "errors": [{ "detail": "Workspace still manages resources" }] }).to_string())
+            .create();
+        let _unsupported = mock("POST", "/api/v2/organizations/safe-org/workspaces/legacy/actions/safe-delete")
+            .with_status(404)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        assert_eq!(safe_delete(&client, "safe-org", "empty").await.unwrap(), DeleteOutcome::Deleted);
+        assert_eq!(safe_delete(&client, "safe-org", "busy").await.unwrap(),
+            DeleteOutcome::Refused("Workspace still manages resources".to_string()));
+        assert_eq!(safe_delete(&client, "safe-org", "legacy").await.unwrap(), DeleteOutcome::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn test_delete_if_empty() {
+        let _empty = mock("GET", "/api/v2/organizations/legacy-org/workspaces/empty")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "name": "empty", "resource-count": 0 } } }).to_string())
+            .create();
+        let deleted = mock("DELETE", "/api/v2/organizations/legacy-org/workspaces/empty")
+            .with_status(204)
+            .expect(1)
+            .create();
+        let _busy = mock("GET", "/api/v2/organizations/legacy-org/workspaces/busy")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "name": "busy", "resource-count": 4 } } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        assert_eq!(delete_if_empty(&client, "legacy-org", "empty").await.unwrap(), DeleteOutcome::Deleted);
+        assert_eq!(delete_if_empty(&client, "legacy-org", "busy").await.unwrap(),
+            DeleteOutcome::Refused("workspace still manages 4 resources".to_string()));
+        deleted.assert();
+    }
+}
@@ -0,0 +1,108 @@
+use crate::tfe::TfeClient;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+/// Ids of the workspaces that depend on a workspace: those its runs trigger and those allowed
+/// to read its state.
+pub async fn downstream(client: &TfeClient, workspace_id: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let triggers = client.get_all(&format!("/workspaces/{}/run-triggers?filter[run-trigger][type]=outbound", workspace_id)).await?;
+    let consumers = client.get_all(&format!("/workspaces/{}/relationships/remote-state-consumers", workspace_id)).await?;
+
+    let mut ids: Vec<String> = triggers.iter()
+        .filter_map(|trigger| trigger["relationships"]["workspace"]["data"]["id"].as_str())
+        .chain(consumers.iter().filter_map(|consumer| consumer["id"].as_str()))
+        .map(str::to_string)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Orders workspaces for deletion so that every workspace comes after the workspaces in the
+/// list that depend on it, keeping the given order otherwise. Returns indexes into `ids` and
+/// the indexes caught in dependency cycles, which are appended in their original order.
+pub fn deletion_order(ids: &[String], downstream: &HashMap<String, Vec<String>>) -> (Vec<usize>, Vec<usize>) {
+    let index: HashMap<&str, usize> = ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+    // Dependents within the list that have to be deleted before each workspace
+    let pending: Vec<HashSet<usize>> = ids.iter()
+        .enumerate()
+        .map(|(i, id)| downstream.get(id).into_iter().flatten()
+            .filter_map(|dependent| index.get(dependent.as_str()).copied())
+            .filter(|&dependent| dependent != i)
+            .collect())
+        .collect();
+
+    let mut order = Vec::with_capacity(ids.len());
+    let mut done = vec![false; ids.len()];
+    loop {
+        // Lowest index first, so independent workspaces keep their order
+        let next = (0..ids.len()).find(|&i| !done[i] && pending[i].iter().all(|&dependent| done[dependent]));
+        match next {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => break,
+        }
+    }
+
+    let cyclic: Vec<usize> = (0..ids.len()).filter(|&i| !done[i]).collect();
+    order.extend(&cyclic);
+    (order, cyclic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    fn ids(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn graph(edges: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        edges.iter().map(|(upstream, downstream)| (upstream.to_string(), ids(downstream))).collect()
+    }
+
+    #[test]
+    fn test_deletion_order_removes_consumers_first() {
+        // network feeds compute, which feeds app; logs is independent
+        let workspaces = ids(&["network", "logs", "compute", "app"]);
+        let downstream = graph(&[("network", &["compute", "outside-the-list"]), ("compute", &["app"])]);
+
+        let (order, cyclic) = deletion_order(&workspaces, &downstream);
+
+        let names: Vec<&str> = order.iter().map(|&i| workspaces[i].as_str()).collect();
+        assert_eq!(names, vec!["logs", "app", "compute", "network"]);
+        assert!(cyclic.is_empty());
+    }
+
+    #[test]
+    fn test_deletion_order_appends_cycles() {
+        let workspaces = ids(&["a", "b", "c"]);
+        let downstream = graph(&[("a", &["b"]), ("b", &["a"])]);
+
+        assert_eq!(deletion_order(&workspaces, &downstream), (vec![2, 0, 1], vec![0, 1]));
+    }
+
+    #[tokio::test]
+    async fn test_downstream() {
+        let _triggers = mock("GET", "/api/v2/workspaces/ws-upstream/run-triggers")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "id": "rt-1", "relationships": { "workspace": { "data": { "id": "ws-triggered" } } } }
+            ] }).to_string())
+            .create();
+        let _consumers = mock("GET", "/api/v2/workspaces/ws-upstream/relationships/remote-state-consumers")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "ws-reader" }, { "id": "ws-triggered" }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        assert_eq!(downstream(&client, "ws-upstream").await.unwrap(), vec!["ws-reader", "ws-triggered"]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::kill_switch::{self, KillSwitch};
+use crate::tfe::{self, ApiError, TfeClient};
+use crate::window::{self, DeletionWindow};
+use chrono::Utc;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+
+/// Concurrent runs assumed when the organization's subscription can't be read (e.g. on TFE).
+const DEFAULT_RUNS_CEILING: usize = 1;
+
+pub struct DestroyOptions {
+    pub dry_run: bool,
+    /// Run slots left free for regular deploys while destroy runs are queued.
+    pub reserve_slots: usize,
+    /// Expected duration of one destroy run, used to space out the waves.
+    pub run_minutes: u64,
+}
+
+/// When each destroy run of an organization is queued, relative to the start.
+#[derive(Debug, PartialEq)]
+pub struct Schedule {
+    pub runs_ceiling: usize,
+    pub busy: usize,
+    pub wave_size: usize,
+    /// `(minutes after start, workspace name)` in queueing order.
+    pub slots: Vec<(u64, String)>,
+    pub estimated_minutes: u64,
+}
+
+/// Spreads the destroy runs over waves that use only the run slots not taken by runs already
+/// in the queue and not reserved for regular deploys. At least one destroy runs per wave.
+pub fn plan_schedule(workspaces: &[String], runs_ceiling: usize, busy: usize, options: &DestroyOptions) -> Schedule {
+    let wave_size = runs_ceiling.saturating_sub(busy).saturating_sub(options.reserve_slots).max(1);
+    let slots: Vec<(u64, String)> = workspaces.iter()
+        .enumerate()
+        .map(|(i, name)| ((i / wave_size) as u64 * options.run_minutes, name.clone()))
+        .collect();
+    let waves = workspaces.len().div_ceil(wave_size) as u64;
+
+    Schedule { runs_ceiling, busy, wave_size, slots, estimated_minutes: waves * options.run_minutes }
+}
+
+/// The number of runs the organization may execute concurrently.
+async fn runs_ceiling(client: &TfeClient, org: &str) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    match client.get(&format!("/organizations/{}/subscription", org)).await {
+        Ok(subscription) => Ok(subscription["data"]["attributes"]["runs-ceiling"].as_u64()
+            .map_or(DEFAULT_RUNS_CEILING, |ceiling| ceiling as usize)),
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND => Ok(DEFAULT_RUNS_CEILING),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Looks up the organization's concurrency and current run queue and plans the destroy waves.
+pub async fn schedule(
+    client: &TfeClient,
+    org: &str,
+    workspaces: &[String],
+    options: &DestroyOptions,
+) -> Result<Schedule, Box<dyn Error + Send + Sync>> {
+    let ceiling = runs_ceiling(client, org).await?;
+    let busy = client.get_all(&format!("/organizations/{}/runs/queue", org)).await?.len();
+    Ok(plan_schedule(workspaces, ceiling, busy, options))
+}
+
+/// Queues a destroy run for a workspace and returns the run's id.
+pub async fn queue_destroy(client: &TfeClient, org: &str, name: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let workspace = tfe::get_workspace(client, org, name).await?;
+    let workspace_id = workspace["id"].as_str().ok_or("workspace has no id")?;
+    let run = client.post("/runs", &destroy_run(workspace_id)).await?;
+    Ok(run["data"]["id"].as_str().unwrap_or("").to_string())
+}
+
+/// Body of the request queuing a destroy run of the workspace.
+pub fn destroy_run(workspace_id: &str) -> Value {
+    json!({
+        "data": {
+            "type": "runs",
+            "attributes": { "is-destroy": true, "message": "Destroy before deletion by tfe_cleanup" },
+            "relationships": { "workspace": { "data": { "type": "workspaces", "id": workspace_id } } }
+        }
+    })
+}
+
+/// Prints the schedule and, unless in dry-run mode, queues the destroy runs wave by wave. Each
+/// run is only queued while one of `windows` is open, as a wave may start after they closed.
+pub async fn run_schedule(
+    client: &TfeClient,
+    kill_switch: Option<&KillSwitch>,
+    windows: &[DeletionWindow],
+    org: &str,
+    schedule: &Schedule,
+    options: &DestroyOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    eprintln!("{}: {} concurrent runs, {} busy, {} destroy runs per wave; estimated {} minutes",
+        org, schedule.runs_ceiling, schedule.busy, schedule.wave_size, schedule.estimated_minutes);
+
+    let mut elapsed = 0;
+    for (offset, name) in &schedule.slots {
+        if options.dry_run {
+            println!("[dry-run] t+{}m: destroy {}/{}", offset, org, name);
+            continue;
+        }
+
+        if *offset > elapsed {
+            eprintln!("Waiting {} minutes before the next wave...", offset - elapsed);
+            tokio::time::sleep(Duration::from_secs((offset - elapsed) * 60)).await;
+            elapsed = *offset;
+        }
+        window::check(windows, Utc::now())?;
+        kill_switch::check(kill_switch).await?;
+        let run_id = queue_destroy(client, org, name).await?;
+        println!("Queued destroy run {} for {}/{}", run_id, org, name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    fn names(count: usize) -> Vec<String> {
+        (1..=count).map(|i| format!("ws-{}", i)).collect()
+    }
+
+    #[test]
+    fn test_plan_schedule() {
+        let options = DestroyOptions { dry_run: true, reserve_slots: 1, run_minutes: 10 };
+
+        let schedule = plan_schedule(&names(5), 5, 2, &options);
+
+        assert_eq!(schedule.wave_size, 2);
+        assert_eq!(schedule.estimated_minutes, 30);
+        let offsets: Vec<u64> = schedule.slots.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, vec![0, 0, 10, 10, 20]);
+    }
+
+    #[test]
+    fn test_plan_schedule_with_full_queue_runs_one_at_a_time() {
+        let options = DestroyOptions { dry_run: true, reserve_slots: 1, run_minutes: 5 };
+        let schedule = plan_schedule(&names(3), 2, 4, &options);
+
+        assert_eq!(schedule.wave_size, 1);
+        assert_eq!(schedule.estimated_minutes, 15);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_reads_ceiling_and_queue() {
+        let _subscription = mock("GET", "/api/v2/organizations/destroy-org/subscription")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "runs-ceiling": 4 } } }).to_string())
+            .create();
+        let _queue = mock("GET", "/api/v2/organizations/destroy-org/runs/queue")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "run-busy" }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let options = DestroyOptions { dry_run: true, reserve_slots: 1, run_minutes: 10 };
+        let schedule = schedule(&client, "destroy-org", &names(4), &options).await.unwrap();
+
+        assert_eq!((schedule.runs_ceiling, schedule.busy, schedule.wave_size), (4, 1, 2));
+        assert_eq!(schedule.estimated_minutes, 20);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::config::{Config, SinkConfig};
+use crate::tfe::TfeClient;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Remaining API requests below this share of the limit fail the rate-limit check.
+const MIN_RATE_LIMIT_HEADROOM: f64 = 0.1;
+
+/// Entitlements runs depend on: state storage for archiving and inventories, cost estimation
+/// for the cost column and summaries.
+const REQUIRED_ENTITLEMENTS: &[&str] = &["state-storage", "cost-estimation"];
+
+/// One line of the checklist: what was checked and what was found, or why it failed.
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Result<String, String>,
+}
+
+impl Check {
+    pub fn new(name: impl Into<String>, outcome: Result<String, String>) -> Check {
+        Check { name: name.into(), outcome }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+/// The checklist, one `[PASS]` or `[FAIL]` line per check.
+pub fn render(checks: &[Check]) -> String {
+    checks.iter()
+        .map(|check| match &check.outcome {
+            Ok(detail) => format!("[PASS] {}: {}\n", check.name, detail),
+            Err(reason) => format!("[FAIL] {}: {}\n", check.name, reason),
+        })
+        .collect()
+}
+
+/// `(host, port)` of a base URL such as `https://tfe.example.com`.
+fn host_and_port(base_url: &str) -> (String, u16) {
+    let (scheme, rest) = base_url.split_once("://").unwrap_or(("https", base_url));
+    let authority = rest.split('/').next().unwrap_or(rest);
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host.to_string(), port.parse().unwrap()),
+        _ => (authority.to_string(), if scheme == "http" { 80 } else { 443 }),
+    }
+}
+
+async fn dns(client: &TfeClient) -> Check {
+    let (host, port) = host_and_port(client.base_url());
+    let outcome = match tokio::net::lookup_host((host.as_str(), port)).await {
+        Ok(mut addresses) => match addresses.next() {
+            Some(address) => Ok(format!("{} resolves to {}", host, address.ip())),
+            None => Err(format!("{} has no addresses", host)),
+        },
+        Err(e) => Err(format!("cannot resolve {}: {}", host, e)),
+    };
+    Check::new("DNS", outcome)
+}
+
+/// Connecting to the unauthenticated ping endpoint exercises TCP and, for https, TLS.
+async fn connection(client: &TfeClient) -> Check {
+    let outcome = match reqwest::get(client.url("/ping")).await {
+        Ok(response) => Ok(format!("{} answered {}", client.base_url(), response.status())),
+        Err(e) => Err(format!("cannot connect to {}: {}", client.base_url(), e.without_url())),
+    };
+    Check::new("Connection", outcome)
+}
+
+/// Checks the token by reading its account, and the rate-limit headers of that response.
+async fn token_and_rate_limit(client: &TfeClient) -> Vec<Check> {
+    let (account, headers) = match client.get_with_headers("/account/details").await {
+        Ok(response) => response,
+        Err(e) => return vec![Check::new("Token", Err(e.to_string()))],
+    };
+    let username = account["data"]["attributes"]["username"].as_str().unwrap_or("unknown user");
+
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).and_then(|value| value.parse::<f64>().ok());
+    let rate_limit = match (header("x-ratelimit-limit"), header("x-ratelimit-remaining")) {
+        (Some(limit), Some(remaining)) if remaining < limit * MIN_RATE_LIMIT_HEADROOM => {
+            Err(format!("only {} of {} requests left; wait for the limit to reset", remaining, limit))
+        }
+        (Some(limit), Some(remaining)) => Ok(format!("{} of {} requests left", remaining, limit)),
+        _ => Ok("not reported by this TFE instance".to_string()),
+    };
+
+    vec![
+        Check::new("Token", Ok(format!("valid, authenticated as {}", username))),
+        Check::new("Rate limit", rate_limit),
+    ]
+}
+
+async fn entitlements(client: &TfeClient, org: &str) -> Check {
+    let name = format!("Entitlements of {}", org);
+    let set = match client.get(&format!("/organizations/{}/entitlement-set", org)).await {
+        Ok(set) => set,
+        Err(e) => return Check::new(name, Err(e.to_string())),
+    };
+
+    let missing: Vec<&str> = REQUIRED_ENTITLEMENTS.iter()
+        .copied()
+        .filter(|entitlement| set["data"]["attributes"][entitlement].as_bool() != Some(true))
+        .collect();
+    let outcome = if missing.is_empty() {
+        Ok(REQUIRED_ENTITLEMENTS.join(", "))
+    } else {
+        Err(format!("missing {}", missing.join(", ")))
+    };
+    Check::new(name, outcome)
+}
+
+/// Whether files can be created in `dir`, by creating and removing one.
+fn writable(dir: &Path) -> Result<String, String> {
+    let probe = dir.join(".tfe_cleanup_doctor");
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map(|_| "writable".to_string())
+        .map_err(|e| format!("cannot write to {}: {}", dir.display(), e))
+}
+
+/// Directories runs write to: the working directory for the CSV reports, the history
+/// database's directory, and those of file sinks and state archives.
+fn output_dirs(config: &Config) -> Vec<PathBuf> {
+    let parent = |path: &Path| match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut dirs = vec![PathBuf::from("."), parent(&config.history_db)];
+    for sink in &config.sinks {
+        if let SinkConfig::Csv { path } | SinkConfig::Json { path } | SinkConfig::Html { path } = sink {
+            dirs.push(parent(path));
+        }
+    }
+    // The archive directory is created on first use, so its parent must be writable
+    let archive_dir = &config.actions.archive_dir;
+    dirs.push(if archive_dir.is_dir() { archive_dir.clone() } else { parent(archive_dir) });
+
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Runs every check. Checks that need the API are skipped when TFE can't be reached or the
+/// token is rejected.
+pub async fn run_checks(client: &TfeClient, config: &Config, orgs: &[String]) -> Result<Vec<Check>, Box<dyn Error + Send + Sync>> {
+    let mut checks = vec![dns(client).await, connection(client).await];
+    if checks.iter().all(Check::passed) {
+        checks.extend(token_and_rate_limit(client).await);
+    }
+    if checks.iter().any(|check| check.name == "Token" && check.passed()) {
+        for org in orgs {
+            checks.push(entitlements(client, org).await);
+        }
+    }
+    for dir in output_dirs(config) {
+        checks.push(Check::new(format!("Output directory {}", dir.display()), writable(&dir)));
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use serde_json::json;
+
+    #[test]
+    fn test_host_and_port() {
+        assert_eq!(host_and_port("https://app.terraform.io"), ("app.terraform.io".to_string(), 443));
+        assert_eq!(host_and_port("http://127.0.0.1:1234"), ("127.0.0.1".to_string(), 1234));
+    }
+
+    #[test]
+    fn test_output_dirs_and_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            history_db: dir.path().join("history.db"),
+            sinks: vec![SinkConfig::Html { path: dir.path().join("reports/stale.html") }],
+            ..Config::default()
+        };
+
+        let dirs = output_dirs(&config);
+        assert!(dirs.contains(&dir.path().to_path_buf()));
+        assert!(dirs.contains(&dir.path().join("reports")));
+
+        let checks = vec![
+            Check::new("History", writable(dir.path())),
+            Check::new("Reports", writable(&dir.path().join("reports"))),
+        ];
+        let rendered = render(&checks);
+        assert!(rendered.starts_with("[PASS] History: writable\n[FAIL] Reports: cannot write to "));
+    }
+
+    #[tokio::test]
+    async fn test_run_checks_against_tfe() {
+        let _ping = mock("GET", "/api/v2/ping").with_status(204).create();
+        let _account = mock("GET", "/api/v2/account/details")
+            .with_status(200)
+            .with_header("x-ratelimit-limit", "30")
+            .with_header("x-ratelimit-remaining", "2")
+            .with_body(json!({ "data": { "attributes": { "username": "cleanup-bot" } } }).to_string())
+            .create();
+        let _entitlements = mock("GET", "/api/v2/organizations/doctor-org/entitlement-set")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "state-storage": true, "cost-estimation": false } } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let checks = run_checks(&client, &Config::default(), &["doctor-org".to_string()]).await.unwrap();
+        let outcome = |name: &str| &checks.iter().find(|check| check.name == name).unwrap().outcome;
+
+        assert!(outcome("DNS").is_ok());
+        assert!(outcome("Connection").is_ok());
+        assert_eq!(outcome("Token"), &Ok("valid, authenticated as cleanup-bot".to_string()));
+        assert_eq!(outcome("Rate limit"), &Err("only 2 of 30 requests left; wait for the limit to reset".to_string()));
+        assert_eq!(outcome("Entitlements of doctor-org"), &Err("missing cost-estimation".to_string()));
+    }
+}
@@ -0,0 +1,375 @@
+//! Scanning and cleanup as streams of typed events, for tools embedding tfe_cleanup instead of
+//! running the binary and parsing its CSV. A portal can render each workspace as it is
+//! evaluated and each cleanup as it finishes:
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use tfe_cleanup::config::Config;
+//! use tfe_cleanup::engine::{CleanupEngine, Event};
+//! use tfe_cleanup::tfe::TfeClient;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! let engine = CleanupEngine::new(TfeClient::from_env()?, Config::load(None)?)?;
+//! let orgs = vec!["my-org".to_string()];
+//! let mut findings = Box::pin(engine.scan(&orgs));
+//! while let Some(event) = findings.next().await {
+//!     if let Event::Finding(finding) = event {
+//!         println!("{}/{}: {}", finding.org, finding.name, finding.rule);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Both streams are `Send`, so a server can drive them from spawned tasks.
+
+pub use crate::actions::PipelineResult;
+pub use crate::limits::BlastRadius;
+
+use crate::actions::{self, ActionContext, Category, Pipelines};
+use crate::config::Config;
+use crate::history::History;
+use crate::human_activity::HumanActivity;
+use crate::kill_switch::KillSwitch;
+use crate::limits;
+use crate::redact;
+use crate::run_lock::RunLock;
+use crate::scan::{self, Scan};
+use crate::staleness::{Policy, Status, Verdict};
+use crate::tfe::{self, TfeClient};
+use chrono::Utc;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::future::{self, Future, FutureExt};
+use futures::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// The verdict on one scanned workspace.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub org: String,
+    pub name: String,
+    pub id: String,
+    pub status: Status,
+    /// The rule that decided the status.
+    pub rule: String,
+    /// Every rule evaluated on the way.
+    pub trace: Vec<String>,
+    /// The workspace as TFE listed it.
+    pub workspace: Value,
+}
+
+impl Finding {
+    fn new(workspace: &Value, verdict: &Verdict) -> Finding {
+        Finding {
+            org: tfe::workspace_org(workspace).to_string(),
+            name: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+            id: workspace["id"].as_str().unwrap_or("").to_string(),
+            status: verdict.status,
+            rule: verdict.rule.clone(),
+            trace: verdict.trace.clone(),
+            workspace: workspace.clone(),
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.status == Status::Flagged
+    }
+}
+
+/// How the cleanup pipeline of one workspace ended.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionResult {
+    pub org: String,
+    pub name: String,
+    pub id: String,
+    pub result: PipelineResult,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Finding(Finding),
+    Action(ActionResult),
+    /// A workspace passed to a cleanup that is no longer stale when re-checked, e.g. because it
+    /// was used since it was found stale.
+    Skipped {
+        org: String,
+        name: String,
+        id: String,
+        reason: String,
+    },
+    /// An organization couldn't be scanned, or the cleanup was aborted, e.g. by the kill switch.
+    /// Findings and results before it stand.
+    Error {
+        org: Option<String>,
+        error: String,
+    },
+}
+
+/// Blast radius of an engine unless set with [`CleanupEngine::with_blast_radius`]: at most a
+/// tenth of an organization per cleanup.
+const DEFAULT_MAX_PERCENT: f64 = 10.0;
+
+/// Scans and cleans up with the policy, actions and run lock of a config, as the `scan` and
+/// `cleanup` commands do but without their output files, prompts and deletion windows, which
+/// are left to the embedding tool.
+pub struct CleanupEngine {
+    client: TfeClient,
+    config: Config,
+    policy: Policy,
+    history: History,
+    kill_switch: Option<KillSwitch>,
+    limits: BlastRadius,
+}
+
+impl CleanupEngine {
+    pub fn new(client: TfeClient, config: Config) -> Result<CleanupEngine, Box<dyn Error + Send + Sync>> {
+        let policy = Policy::from_config(&config)?;
+        let history = History::open(&config.history_db)?;
+        let limits = BlastRadius { max_deletions: None, max_percent: Some(DEFAULT_MAX_PERCENT) };
+        Ok(CleanupEngine { client, config, policy, history, kill_switch: None, limits })
+    }
+
+    /// Checked before every destructive action, as with `--kill-switch-url`.
+    pub fn with_kill_switch(mut self, kill_switch: KillSwitch) -> CleanupEngine {
+        self.kill_switch = Some(kill_switch);
+        self
+    }
+
+    /// Limits on each cleanup, as with `--max-deletions` and `--max-percent`.
+    pub fn with_blast_radius(mut self, limits: BlastRadius) -> CleanupEngine {
+        self.limits = limits;
+        self
+    }
+
+    /// A finding for every workspace of `orgs` as it is evaluated, and an error for every
+    /// organization that couldn't be listed. Human activity is looked up if configured.
+    pub fn scan<'a>(&'a self, orgs: &'a [String]) -> impl Stream<Item = Event> + Send + 'a {
+        let (sender, receiver) = mpsc::unbounded();
+        let scanning = async move {
+            let inspect = |workspace: &Value, verdict: &Verdict| send(&sender, Event::Finding(Finding::new(workspace, verdict)));
+            match self.evaluate(orgs, inspect).await {
+                Ok(scan) => {
+                    for (org, error) in scan.errors {
+                        send(&sender, Event::Error { org: Some(org), error });
+                    }
+                }
+                Err(e) => send(&sender, Event::Error { org: None, error: redact::scrub(&e.to_string()) }),
+            }
+        };
+        driven_by(scanning, receiver)
+    }
+
+    /// Runs the configured pipeline of each stale workspace, e.g. the workspaces of stale
+    /// findings, with a result for each as it finishes. Once the run lock is held, the
+    /// organizations are scanned again: workspaces no longer stale are skipped, and the stream
+    /// ends with an error, before any action, if the rest exceeds the blast radius. It also ends
+    /// with an error if the lock can't be acquired or the kill switch is engaged. Dropping the
+    /// stream midway releases the lock in the background.
+    pub fn clean_up<'a>(&'a self, stale: &'a [Value]) -> impl Stream<Item = Event> + Send + 'a {
+        let (sender, receiver) = mpsc::unbounded();
+        let cleaning = async move {
+            if let Err(e) = self.run_pipelines(stale, &sender).await {
+                send(&sender, Event::Error { org: None, error: redact::scrub(&e.to_string()) });
+            }
+        };
+        driven_by(cleaning, receiver)
+    }
+
+    async fn evaluate(&self, orgs: &[String], inspect: impl FnMut(&Value, &Verdict) + Send) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        let human = match self.config.human_activity {
+            true => Some(HumanActivity::prepare(&self.client, &self.config.audit_trail, orgs).await),
+            false => None,
+        };
+        scan::scan(&self.client, orgs, None, human.as_ref(), &self.policy, Utc::now(), inspect).await
+    }
+
+    async fn run_pipelines(&self, stale: &[Value], sender: &UnboundedSender<Event>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(kill_switch) = &self.kill_switch {
+            kill_switch.check().await?;
+        }
+        let mut orgs: Vec<String> = stale.iter().map(|workspace| tfe::workspace_org(workspace).to_string()).collect();
+        orgs.sort_unstable();
+        orgs.dedup();
+        let lock = RunLock::acquire(&self.client, &self.config.run_lock, &orgs).await?;
+        let outcome = self.run_verified_pipelines(stale, &orgs, sender).await;
+        lock.release().await;
+        outcome
+    }
+
+    async fn run_verified_pipelines(&self, stale: &[Value], orgs: &[String], sender: &UnboundedSender<Event>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let scan = self.evaluate(orgs, |_, _| {}).await?;
+        if let Some((org, error)) = scan.errors.iter().next() {
+            return Err(format!("cannot re-check the workspaces of {}: {}", org, error).into());
+        }
+        let requested: HashSet<&str> = stale.iter().filter_map(|workspace| workspace["id"].as_str()).collect();
+        let verified: Vec<Value> = scan.stale.into_iter()
+            .filter(|workspace| workspace["id"].as_str().is_some_and(|id| requested.contains(id)))
+            .collect();
+        let still_stale: HashSet<&str> = verified.iter().filter_map(|workspace| workspace["id"].as_str()).collect();
+        for workspace in stale {
+            let id = workspace["id"].as_str().unwrap_or("");
+            if !still_stale.contains(id) {
+                send(sender, Event::Skipped {
+                    org: tfe::workspace_org(workspace).to_string(),
+                    name: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+                    id: id.to_string(),
+                    reason: "no longer stale".to_string(),
+                });
+            }
+        }
+        limits::check(&self.limits, &verified, &scan.totals)?;
+
+        let pipelines = Pipelines::new(&self.config.actions, &self.config.notifications, None, None, false);
+        let context = ActionContext { client: &self.client, history: &self.history, events: None, kill_switch: self.kill_switch.as_ref(), windows: &[] };
+        for workspace in &verified {
+            let result = actions::run_pipeline(pipelines.for_category(Category::of(workspace)), &context, workspace).await?;
+            send(sender, Event::Action(ActionResult {
+                org: tfe::workspace_org(workspace).to_string(),
+                name: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+                id: workspace["id"].as_str().unwrap_or("").to_string(),
+                result,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Receivers outliving the stream only drop events nobody waits for.
+fn send(sender: &UnboundedSender<Event>, event: Event) {
+    let _ = sender.unbounded_send(event);
+}
+
+/// The events `work` sends as it goes. The stream drives `work` while it is polled and ends
+/// once `work` is done, dropping its sender, and every event was taken.
+fn driven_by<'a>(work: impl Future<Output = ()> + Send + 'a, events: mpsc::UnboundedReceiver<Event>) -> impl Stream<Item = Event> + Send + 'a {
+    let work = work.into_stream().filter_map(|()| future::ready(None));
+    stream::select(events, work)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RunLockConfig;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    fn engine(dir: &std::path::Path) -> CleanupEngine {
+        let config = Config {
+            history_db: dir.join("history.db"),
+            run_lock: RunLockConfig { path: dir.join("tfe_cleanup.lock"), ..RunLockConfig::default() },
+            ..Config::default()
+        };
+        CleanupEngine::new(TfeClient::new(&server_url(), "test-token").unwrap(), config).unwrap()
+    }
+
+    fn workspace(org: &str, id: &str, name: &str, last_activity: &str) -> Value {
+        json!({
+            "id": id,
+            "attributes": { "name": name, "last-activity-at": last_activity, "vcs-repo": { "identifier": "acme/infra" } },
+            "relationships": { "organization": { "data": { "id": org } } }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_scan_streams_findings_and_errors() {
+        let recent = Utc::now().to_rfc3339();
+        let _workspaces = mock("GET", "/api/v2/organizations/engine-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                workspace("engine-org", "ws-old", "old", "2020-01-01T00:00:00Z"),
+                workspace("engine-org", "ws-busy", "busy", &recent),
+            ] }).to_string())
+            .create();
+        let _forbidden = mock("GET", "/api/v2/organizations/engine-forbidden-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(403)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = engine(dir.path());
+        let orgs = vec!["engine-org".to_string(), "engine-forbidden-org".to_string()];
+        let events = engine.scan(&orgs);
+        assert_send(&events);
+        let events: Vec<Event> = events.collect().await;
+
+        let findings: Vec<(&str, bool)> = events.iter()
+            .filter_map(|event| match event {
+                Event::Finding(finding) => Some((finding.name.as_str(), finding.is_stale())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(findings, vec![("old", true), ("busy", false)]);
+        assert!(matches!(events.last(), Some(Event::Error { org: Some(org), .. }) if org == "engine-forbidden-org"));
+    }
+
+    #[tokio::test]
+    async fn test_clean_up_streams_action_results() {
+        let recent = Utc::now().to_rfc3339();
+        let _workspaces = mock("GET", "/api/v2/organizations/engine-clean-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                workspace("engine-clean-org", "ws-engine-old", "old", "2020-01-01T00:00:00Z"),
+                workspace("engine-clean-org", "ws-engine-used", "used", &recent),
+            ] }).to_string())
+            .create();
+        let deleted = mock("POST", "/api/v2/organizations/engine-clean-org/workspaces/old/actions/safe-delete")
+            .with_status(204)
+            .create();
+        let not_deleted = mock("POST", "/api/v2/organizations/engine-clean-org/workspaces/used/actions/safe-delete")
+            .with_status(204)
+            .expect(0)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = engine(dir.path()).with_blast_radius(BlastRadius::default());
+        // Used since it was found stale
+        let stale = vec![
+            workspace("engine-clean-org", "ws-engine-old", "old", "2020-01-01T00:00:00Z"),
+            workspace("engine-clean-org", "ws-engine-used", "used", "2020-01-01T00:00:00Z"),
+        ];
+        let events = engine.clean_up(&stale);
+        assert_send(&events);
+        let events: Vec<Event> = events.collect().await;
+
+        deleted.assert();
+        not_deleted.assert();
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(matches!(&events[0], Event::Skipped { name, .. } if name == "used"), "{:?}", events);
+        let Event::Action(action) = &events[1] else { panic!("{:?}", events) };
+        assert_eq!((action.name.as_str(), &action.result), ("old", &PipelineResult::Completed(vec!["deleted"])));
+        assert!(!dir.path().join("tfe_cleanup.lock").exists());
+    }
+
+    #[tokio::test]
+    async fn test_clean_up_refuses_beyond_blast_radius() {
+        let _workspaces = mock("GET", "/api/v2/organizations/engine-radius-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                workspace("engine-radius-org", "ws-radius-1", "old-1", "2020-01-01T00:00:00Z"),
+                workspace("engine-radius-org", "ws-radius-2", "old-2", "2020-01-01T00:00:00Z"),
+            ] }).to_string())
+            .create();
+        let not_deleted = mock("POST", Matcher::Regex("^/api/v2/organizations/engine-radius-org/workspaces/.*/actions/safe-delete$".to_string()))
+            .expect(0)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = engine(dir.path());
+        let stale = vec![workspace("engine-radius-org", "ws-radius-1", "old-1", "2020-01-01T00:00:00Z")];
+        let events: Vec<Event> = engine.clean_up(&stale).collect().await;
+
+        not_deleted.assert();
+        assert_eq!(events.len(), 1, "{:?}", events);
+        assert!(matches!(&events[0], Event::Error { error, .. } if error.starts_with("refusing to delete")), "{:?}", events);
+        assert!(!dir.path().join("tfe_cleanup.lock").exists());
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+}
@@ -0,0 +1,169 @@
+use crate::staleness::{self, Policy};
+use crate::tfe::{self, ApiError, TfeClient};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::error::Error;
+
+/// How much of a quota is in use. `limit` is `None` when TFE reports no limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub used: u64,
+    pub limit: Option<u64>,
+}
+
+impl Usage {
+    /// e.g. "42/50 (84%)", or "42/unlimited".
+    pub fn describe(&self) -> String {
+        match self.limit {
+            Some(0) | None => format!("{}/unlimited", self.used),
+            Some(limit) => format!("{}/{} ({}%)", self.used, limit, self.used * 100 / limit),
+        }
+    }
+}
+
+/// Quota usage of one organization, with how many workspaces the cleanup would free.
+#[derive(Debug, PartialEq)]
+pub struct OrgUsage {
+    pub name: String,
+    pub workspaces: Usage,
+    pub stale_workspaces: u64,
+    pub users: Usage,
+    /// Runs in progress against the concurrency ceiling.
+    pub concurrency: Usage,
+}
+
+/// An attribute of the entitlement set or subscription read as a limit.
+fn limit(attributes: &Value, name: &str) -> Option<u64> {
+    attributes[name].as_u64()
+}
+
+/// The organization's subscription attributes, or null where there is none (TFE installations
+/// have no subscriptions).
+async fn subscription(client: &TfeClient, org: &str) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    match client.get(&format!("/organizations/{}/subscription", org)).await {
+        Ok(mut subscription) => Ok(subscription["data"]["attributes"].take()),
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND => Ok(Value::Null),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Reads the limits of `org` from its entitlement set and subscription and counts what is in
+/// use. Stale workspaces are counted with `policy`, as a scan would.
+pub async fn org_usage(client: &TfeClient, org: &str, policy: &Policy) -> Result<OrgUsage, Box<dyn Error + Send + Sync>> {
+    let entitlements = client.get(&format!("/organizations/{}/entitlement-set", org)).await?["data"]["attributes"].take();
+    let subscription = subscription(client, org).await?;
+
+    let workspaces = tfe::list_workspaces(client, org).await?;
+    let stale = staleness::filter_old_inactive_accounts(&workspaces, policy).len();
+    let members = client.get_all(&format!("/organizations/{}/organization-memberships", org)).await?;
+    let active_runs = client.get_all(&format!("/organizations/{}/runs/queue", org)).await?;
+
+    Ok(OrgUsage {
+        name: org.to_string(),
+        workspaces: Usage { used: workspaces.len() as u64, limit: limit(&entitlements, "workspace-limit") },
+        stale_workspaces: stale as u64,
+        users: Usage {
+            used: members.iter().filter(|member| member["attributes"]["status"] == "active").count() as u64,
+            limit: limit(&entitlements, "user-limit").or_else(|| limit(&subscription, "contract-user-limit")),
+        },
+        concurrency: Usage { used: active_runs.len() as u64, limit: limit(&subscription, "runs-ceiling") },
+    })
+}
+
+/// The report as a table, one organization per row, followed by what the cleanup would recover.
+pub fn render(report: &[OrgUsage]) -> String {
+    let mut rows = vec![[
+        "Organization".to_string(), "Workspaces".to_string(), "Stale".to_string(),
+        "Users".to_string(), "Concurrency".to_string(),
+    ]];
+    for org in report {
+        rows.push([
+            org.name.clone(),
+            org.workspaces.describe(),
+            org.stale_workspaces.to_string(),
+            org.users.describe(),
+            org.concurrency.describe(),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..5).map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0)).collect();
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+
+    let (stale, workspaces): (u64, u64) = report.iter().fold((0, 0), |(stale, total), org| (stale + org.stale_workspaces, total + org.workspaces.used));
+    out.push_str(&format!("\nCleaning up the {} stale workspaces would free {}% of the {} workspaces in use.\n",
+        stale, (stale * 100).checked_div(workspaces).unwrap_or(0), workspaces));
+    for org in report.iter().filter(|org| org.stale_workspaces > 0) {
+        if let Some(limit) = org.workspaces.limit.filter(|limit| *limit > 0) {
+            out.push_str(&format!("{}: {} of {} workspaces in use after cleanup\n",
+                org.name, org.workspaces.used.saturating_sub(org.stale_workspaces), limit));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_usage_describe() {
+        assert_eq!(Usage { used: 42, limit: Some(50) }.describe(), "42/50 (84%)");
+        assert_eq!(Usage { used: 3, limit: None }.describe(), "3/unlimited");
+    }
+
+    #[tokio::test]
+    async fn test_org_usage() {
+        let _entitlements = mock("GET", "/api/v2/organizations/seats-org/entitlement-set")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "user-limit": 25, "workspace-limit": 10 } } }).to_string())
+            .create();
+        let _subscription = mock("GET", "/api/v2/organizations/seats-org/subscription")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "runs-ceiling": 5 } } }).to_string())
+            .create();
+        let _workspaces = mock("GET", "/api/v2/organizations/seats-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "name": "old", "last-activity-at": "2020-01-01T00:00:00Z" } },
+                { "attributes": { "name": "busy", "last-activity-at": chrono::Utc::now().to_rfc3339() } }
+            ] }).to_string())
+            .create();
+        let _members = mock("GET", "/api/v2/organizations/seats-org/organization-memberships")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "status": "active" } },
+                { "attributes": { "status": "invited" } }
+            ] }).to_string())
+            .create();
+        let _queue = mock("GET", "/api/v2/organizations/seats-org/runs/queue")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "run-1" }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let usage = org_usage(&client, "seats-org", &Policy::default()).await.unwrap();
+
+        assert_eq!(usage, OrgUsage {
+            name: "seats-org".to_string(),
+            workspaces: Usage { used: 2, limit: Some(10) },
+            stale_workspaces: 1,
+            users: Usage { used: 1, limit: Some(25) },
+            concurrency: Usage { used: 1, limit: Some(5) },
+        });
+        let rendered = render(&[usage]);
+        assert!(rendered.contains("seats-org     2/10 (20%)  1      1/25 (4%)  1/5 (20%)"));
+        assert!(rendered.contains("seats-org: 1 of 10 workspaces in use after cleanup"));
+    }
+}
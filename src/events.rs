@@ -0,0 +1,128 @@
+use crate::config::EventsConfig;
+use crate::redact::{self, Secret};
+use crate::tfe::{self, OrgTotals};
+use crate::sinks;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+
+/// How long an event may take to be accepted before it is given up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header carrying `sha256=` followed by the hex HMAC-SHA256 of the body.
+pub const SIGNATURE_HEADER: &str = "X-TFE-Cleanup-Signature";
+
+/// The HMAC-SHA256 of `body` under `secret`, as sent in the signature header.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    format!("sha256={}", sinks::hex(&sinks::hmac(secret.as_bytes(), body)))
+}
+
+/// Posts one signed JSON event per thing a run does to an automation endpoint. Events carry
+/// `sent_at` so receivers can reject replays.
+pub struct EventEmitter {
+    url: Secret,
+    secret: Secret,
+    http: reqwest::Client,
+}
+
+impl EventEmitter {
+    pub fn from_config(config: Option<&EventsConfig>) -> Result<Option<EventEmitter>, Box<dyn Error + Send + Sync>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+        let http = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Some(EventEmitter { url: config.url.clone(), secret: config.secret.clone(), http }))
+    }
+
+    async fn post(&self, mut event: Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+        event["sent_at"] = json!(Utc::now().to_rfc3339());
+        let body = serde_json::to_vec(&event)?;
+        let url = self.url.expose();
+        let response = self.http.post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature(self.secret.expose(), &body))
+            .body(body)
+            .send().await
+            .map_err(|e| format!("event endpoint {}: {}", redact::url(url), e.without_url()))?;
+        if !response.status().is_success() {
+            return Err(format!("event endpoint {} returned {}", redact::url(url), response.status()).into());
+        }
+        Ok(())
+    }
+
+    /// Sends the event, reporting failures on stderr: automation must never stop a run.
+    async fn send(&self, event: Value) {
+        if let Err(e) = self.post(event).await {
+            eprintln!("Warning: could not send event: {}", redact::scrub(&e.to_string()));
+        }
+    }
+
+    /// One `scanned` event per organization, rather than one per workspace, and a `flagged`
+    /// event per stale workspace.
+    pub async fn scanned(&self, command: &str, totals: &OrgTotals, stale: &[Value]) {
+        for (org, total) in totals {
+            let flagged = stale.iter().filter(|workspace| tfe::workspace_org(workspace) == org).count();
+            self.send(json!({ "event": "scanned", "command": command, "org": org, "workspaces": total, "flagged": flagged })).await;
+        }
+        for workspace in stale {
+            self.send(workspace_event("flagged", workspace)).await;
+        }
+    }
+
+    /// An action of a cleanup pipeline, e.g. `deleted` or `locked`, with how it ended.
+    pub async fn action(&self, action: &str, workspace: &Value, outcome: &str, message: &str) {
+        let mut event = workspace_event(action, workspace);
+        event["outcome"] = json!(outcome);
+        event["message"] = json!(redact::scrub(message));
+        self.send(event).await;
+    }
+}
+
+fn workspace_event(event: &str, workspace: &Value) -> Value {
+    json!({
+        "event": event,
+        "org": tfe::workspace_org(workspace),
+        "workspace": workspace["attributes"]["name"],
+        "workspace_id": workspace["id"],
+        "last_activity": workspace["attributes"]["last-activity-at"],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[test]
+    fn test_signature() {
+        // Test case 2 of RFC 4231
+        assert_eq!(signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[tokio::test]
+    async fn test_action_event_is_signed() {
+        let endpoint = mock("POST", "/events/signed")
+            .match_header(SIGNATURE_HEADER, Matcher::Regex("^sha256=[0-9a-f]{64}$".to_string()))
+            .match_body(Matcher::PartialJson(json!({
+                "event": "deleted", "org": "events-org", "workspace": "legacy", "outcome": "succeeded"
+            })))
+            .with_status(204)
+            .expect(1)
+            .create();
+        let emitter = EventEmitter::from_config(Some(&EventsConfig {
+            url: Secret::new(format!("{}/events/signed", server_url())),
+            secret: Secret::new("events-signing-secret"),
+        })).unwrap().unwrap();
+
+        let workspace = json!({
+            "id": "ws-1",
+            "attributes": { "name": "legacy" },
+            "relationships": { "organization": { "data": { "id": "events-org" } } }
+        });
+        emitter.action("deleted", &workspace, "succeeded", "Successfully deleted workspace for legacy").await;
+
+        endpoint.assert();
+    }
+}
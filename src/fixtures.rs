@@ -0,0 +1,66 @@
+use crate::redact::REDACTED;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Parts of attribute names whose values must never end up in a committed fixture.
+const SENSITIVE: &[&str] = &["token", "secret", "password", "key", "url", "email", "webhook"];
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SENSITIVE.iter().any(|part| name.contains(part))
+}
+
+/// Strips an API payload of everything that could leak from a live organization: string values
+/// of sensitive-looking attributes are redacted and `links`, which carry hostnames, are dropped.
+pub fn scrub(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter()
+            .filter(|(name, _)| name != "links")
+            .map(|(name, value)| match value {
+                Value::String(_) if is_sensitive(&name) => (name, Value::String(REDACTED.to_string())),
+                value => (name, scrub(value)),
+            })
+            .collect()),
+        Value::Array(values) => Value::Array(values.into_iter().map(scrub).collect()),
+        value => value,
+    }
+}
+
+/// Writes the scrubbed workspaces to `dir/workspaces.json`, the fixture the golden report tests
+/// are rendered from.
+pub fn write(dir: &Path, workspaces: &[Value]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    fs::create_dir_all(dir)?;
+    let scrubbed = scrub(Value::Array(workspaces.to_vec()));
+    fs::write(dir.join("workspaces.json"), serde_json::to_string_pretty(&scrubbed)? + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_scrub() {
+        let workspace = json!({
+            "id": "ws-1",
+            "attributes": {
+                "name": "app",
+                "vcs-repo": { "identifier": "acme/app", "oauth-token-id": "ot-123", "repository-http-url": "https://git.internal/acme/app" },
+                "webhook-url": "https://tfe.internal/webhooks/vcs/abc"
+            },
+            "links": { "self": "/api/v2/workspaces/ws-1" }
+        });
+
+        assert_eq!(scrub(workspace), json!({
+            "id": "ws-1",
+            "attributes": {
+                "name": "app",
+                "vcs-repo": { "identifier": "acme/app", "oauth-token-id": REDACTED, "repository-http-url": REDACTED },
+                "webhook-url": REDACTED
+            }
+        }));
+    }
+}
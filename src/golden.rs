@@ -0,0 +1,104 @@
+//! Snapshot tests of every report format over the workspaces in `fixtures/`. A change to a
+//! format shows up as a snapshot diff; review it with `cargo insta review`.
+
+use crate::ages::{self, AgeStats};
+use crate::notify::{self, ScanResults};
+use crate::report::{self, Column, ReportContext};
+use crate::sinks::{self, Report};
+use crate::staleness::{self, Policy};
+use crate::tfe;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde_json::Value;
+
+const WORKSPACES: &str = include_str!("../fixtures/workspaces.json");
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap()
+}
+
+fn workspaces() -> Vec<Value> {
+    serde_json::from_str(WORKSPACES).unwrap()
+}
+
+fn stale(workspaces: &[Value]) -> Vec<Value> {
+    workspaces.iter()
+        .filter(|workspace| staleness::evaluate(workspace, &Policy::default(), now()).is_stale())
+        .cloned()
+        .collect()
+}
+
+fn context() -> ReportContext {
+    ReportContext { timezone: Tz::Europe__Berlin, now: now(), ..ReportContext::default() }
+}
+
+fn results(workspaces: &[Value], stale: &[Value]) -> ScanResults {
+    let ages: Vec<i64> = workspaces.iter().filter_map(|workspace| ages::days_inactive(workspace, now())).collect();
+    ScanResults::at("scan", &tfe::count_by_org(workspaces), stale, &Policy::default(), now())
+        .with_ages(Some(AgeStats::new(&ages)))
+}
+
+#[test]
+fn csv_default_columns() {
+    let workspaces = workspaces();
+    let mut csv = Vec::new();
+    report::write_csv(&mut csv, &stale(&workspaces), report::DEFAULT_COLUMNS, &context()).unwrap();
+
+    insta::assert_snapshot!(String::from_utf8(csv).unwrap());
+}
+
+#[test]
+fn csv_every_column() {
+    let workspaces = workspaces();
+    let mut csv = Vec::new();
+    report::write_csv(&mut csv, &stale(&workspaces), Column::value_variants(), &context()).unwrap();
+
+    insta::assert_snapshot!(String::from_utf8(csv).unwrap());
+}
+
+#[test]
+fn json_results() {
+    let workspaces = workspaces();
+    let stale = stale(&workspaces);
+
+    insta::assert_snapshot!(serde_json::to_string_pretty(&results(&workspaces, &stale)).unwrap());
+}
+
+#[test]
+fn scan_output_json() {
+    let explained: Vec<Value> = workspaces().iter()
+        .map(|workspace| crate::scan::result(workspace, &staleness::evaluate(workspace, &Policy::default(), now()), true, now()))
+        .collect();
+
+    insta::assert_snapshot!(serde_json::to_string_pretty(&explained).unwrap());
+}
+
+#[test]
+fn stdout_text() {
+    let workspaces = workspaces();
+    let stale = stale(&workspaces);
+    let (context, results) = (context(), results(&workspaces, &stale));
+    let report = Report { stale: &stale, columns: report::DEFAULT_COLUMNS, context: &context, results: &results };
+
+    insta::assert_snapshot!(sinks::render_text(&report));
+}
+
+#[test]
+fn html_page() {
+    let workspaces = workspaces();
+    let stale = stale(&workspaces);
+    let columns = [Column::Name, Column::Org, Column::Description, Column::InactiveFor, Column::StalenessBasis];
+    let (context, results) = (context(), results(&workspaces, &stale));
+    let report = Report { stale: &stale, columns: &columns, context: &context, results: &results };
+
+    insta::assert_snapshot!(sinks::render_html(&report));
+}
+
+#[test]
+fn notification_default_template() {
+    let workspaces = workspaces();
+    let stale = stale(&workspaces);
+
+    insta::assert_snapshot!(notify::render(notify::DEFAULT_TEMPLATE, &results(&workspaces, &stale)).unwrap());
+}
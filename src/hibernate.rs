@@ -0,0 +1,133 @@
+use crate::destroy;
+use crate::history::{self, History};
+use crate::tfe::{self, TfeClient};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Tag marking workspaces whose resources were destroyed but that are kept to be woken later.
+pub const HIBERNATED_TAG: &str = "hibernated";
+
+/// The configuration version of the workspace's current run, which a later wake re-applies.
+async fn current_configuration_version(client: &TfeClient, workspace: &Value) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let run_id = match workspace["relationships"]["current-run"]["data"]["id"].as_str() {
+        Some(run_id) => run_id,
+        None => return Ok(None),
+    };
+    let run = client.get(&format!("/runs/{}", run_id)).await?;
+    Ok(run["data"]["relationships"]["configuration-version"]["data"]["id"].as_str().map(str::to_string))
+}
+
+pub fn tag_body() -> Value {
+    json!({ "data": [{ "type": "tags", "attributes": { "name": HIBERNATED_TAG } }] })
+}
+
+/// Queues a destroy run but keeps the workspace, tagged `hibernated`, with its configuration
+/// version recorded in the history for `wake`. Returns the destroy run's id.
+pub async fn hibernate(client: &TfeClient, history: &History, workspace: &Value) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let org = tfe::workspace_org(workspace);
+    let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+    let workspace_id = workspace["id"].as_str().ok_or("workspace id unknown; include 'org' in the CSV")?;
+
+    let configuration_version = current_configuration_version(client, workspace).await?;
+    let run_id = destroy::queue_destroy(client, org, name).await?;
+    client.post(&format!("/workspaces/{}/relationships/tags", workspace_id), &tag_body()).await?;
+    history.record_hibernation(org, name, configuration_version.as_deref())?;
+    Ok(run_id)
+}
+
+/// Queues an apply of the configuration version recorded when the workspace was hibernated, or
+/// of its latest configuration if none was recorded, and removes the `hibernated` tag. Returns
+/// the run's id and the configuration version applied.
+pub async fn wake(client: &TfeClient, history: &History, org: &str, name: &str) -> Result<(String, Option<String>), Box<dyn Error + Send + Sync>> {
+    let hibernation = history.hibernation(org, name)?
+        .ok_or_else(|| format!("{}/{} was not hibernated by tfe_cleanup", org, name))?;
+    let workspace = tfe::get_workspace(client, org, name).await?;
+    let workspace_id = workspace["id"].as_str().ok_or("workspace has no id")?;
+
+    let mut relationships = json!({ "workspace": { "data": { "type": "workspaces", "id": workspace_id } } });
+    if let Some(configuration_version) = &hibernation.configuration_version {
+        relationships["configuration-version"] = json!({ "data": { "type": "configuration-versions", "id": configuration_version } });
+    }
+    let run = client.post("/runs", &json!({
+        "data": {
+            "type": "runs",
+            "attributes": { "message": "Wake from hibernation by tfe_cleanup" },
+            "relationships": relationships
+        }
+    })).await?;
+
+    client.delete_with_body(&format!("/workspaces/{}/relationships/tags", workspace_id), &tag_body()).await?;
+    // Lets later cleanups handle the workspace again
+    history.record_action(org, name, "woken", history::SUCCEEDED)?;
+    let run_id = run["data"]["id"].as_str().unwrap_or("").to_string();
+    Ok((run_id, hibernation.configuration_version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[tokio::test]
+    async fn test_hibernate_then_wake() {
+        let workspace = json!({
+            "id": "ws-sleepy",
+            "attributes": { "name": "sleepy" },
+            "relationships": {
+                "organization": { "data": { "id": "sandbox-org" } },
+                "current-run": { "data": { "id": "run-last-apply" } }
+            }
+        });
+        let _run = mock("GET", "/api/v2/runs/run-last-apply")
+            .with_status(200)
+            .with_body(json!({ "data": { "relationships": { "configuration-version": { "data": { "id": "cv-kept" } } } } }).to_string())
+            .create();
+        let _get = mock("GET", "/api/v2/organizations/sandbox-org/workspaces/sleepy")
+            .with_status(200)
+            .with_body(json!({ "data": workspace }).to_string())
+            .create();
+        let destroy = mock("POST", "/api/v2/runs")
+            .match_body(Matcher::PartialJson(json!({ "data": { "attributes": { "is-destroy": true } } })))
+            .with_status(201)
+            .with_body(json!({ "data": { "id": "run-destroy" } }).to_string())
+            .expect(1)
+            .create();
+        let tag = mock("POST", "/api/v2/workspaces/ws-sleepy/relationships/tags")
+            .match_body(Matcher::Regex(HIBERNATED_TAG.into()))
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+
+        assert_eq!(hibernate(&client, &history, &workspace).await.unwrap(), "run-destroy");
+        destroy.assert();
+        tag.assert();
+        // As the pipeline records it
+        history.record_action("sandbox-org", "sleepy", "hibernated", history::SUCCEEDED).unwrap();
+        assert!(history.handled_action("sandbox-org", "sleepy").unwrap().is_some());
+
+        let apply = mock("POST", "/api/v2/runs")
+            .match_body(Matcher::PartialJson(json!({ "data": { "relationships": {
+                "configuration-version": { "data": { "id": "cv-kept" } }
+            } } })))
+            .with_status(201)
+            .with_body(json!({ "data": { "id": "run-wake" } }).to_string())
+            .expect(1)
+            .create();
+        let untag = mock("DELETE", "/api/v2/workspaces/ws-sleepy/relationships/tags")
+            .match_body(Matcher::Regex(HIBERNATED_TAG.into()))
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let (run_id, configuration_version) = wake(&client, &history, "sandbox-org", "sleepy").await.unwrap();
+        assert_eq!((run_id.as_str(), configuration_version.as_deref()), ("run-wake", Some("cv-kept")));
+        apply.assert();
+        untag.assert();
+        assert_eq!(history.handled_action("sandbox-org", "sleepy").unwrap(), None);
+
+        assert!(wake(&client, &history, "sandbox-org", "never-hibernated").await.is_err());
+    }
+}
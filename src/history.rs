@@ -0,0 +1,271 @@
+use crate::ages::AgeStats;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// Outcome recorded for an action that completed.
+pub const SUCCEEDED: &str = "succeeded";
+/// Outcome recorded for an action that was attempted and failed.
+pub const FAILED: &str = "failed";
+/// Outcome recorded when TFE refused the action, e.g. a safe delete of a workspace with resources.
+pub const REFUSED: &str = "refused";
+
+/// Actions after which a workspace needs no further handling by later runs: it is gone, or
+/// quarantined, or its owners were told.
+const HANDLED_ACTIONS: &[&str] = &["deleted", "hibernated", "destroy-queued", "locked", "tagged", "notified"];
+
+/// What `wake` needs to know about a hibernated workspace.
+#[derive(Debug, PartialEq)]
+pub struct Hibernation {
+    /// Configuration version applied before the workspace was hibernated, if it had a run.
+    pub configuration_version: Option<String>,
+    pub at: String,
+}
+
+/// A previously recorded action on a workspace.
+#[derive(Debug, PartialEq)]
+pub struct Action {
+    pub action: String,
+    pub outcome: String,
+    pub at: String,
+}
+
+/// SQLite store of what earlier runs did, so scheduled re-runs can tell what's already handled.
+/// The connection is behind a mutex so a history can be shared across threads, e.g. by cleanups
+/// an embedding server spawns.
+pub struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<History, Box<dyn Error + Send + Sync>> {
+        History::init(Connection::open(path)?)
+    }
+
+    /// A history kept for one run only, e.g. for `selftest`, which mustn't touch the real one.
+    pub fn open_in_memory() -> Result<History, Box<dyn Error + Send + Sync>> {
+        History::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<History, Box<dyn Error + Send + Sync>> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS actions (
+                id INTEGER PRIMARY KEY,
+                org TEXT NOT NULL,
+                workspace TEXT NOT NULL,
+                action TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS actions_workspace ON actions (org, workspace);
+            CREATE TABLE IF NOT EXISTS scans (
+                id INTEGER PRIMARY KEY,
+                at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS flagged (
+                scan_id INTEGER NOT NULL REFERENCES scans (id),
+                org TEXT NOT NULL,
+                workspace TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS flagged_workspace ON flagged (org, workspace);
+            CREATE TABLE IF NOT EXISTS scanned_orgs (
+                scan_id INTEGER NOT NULL REFERENCES scans (id),
+                org TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS scanned_orgs_org ON scanned_orgs (org);
+            CREATE TABLE IF NOT EXISTS scan_ages (
+                scan_id INTEGER PRIMARY KEY REFERENCES scans (id),
+                stats TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS hibernations (
+                id INTEGER PRIMARY KEY,
+                org TEXT NOT NULL,
+                workspace TEXT NOT NULL,
+                configuration_version TEXT,
+                at TEXT NOT NULL
+            );",
+        )?;
+        Ok(History { conn: Mutex::new(conn) })
+    }
+
+    /// A panic while the connection was held leaves no transaction open, so a poisoned lock is
+    /// still usable.
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn record_action(&self, org: &str, workspace: &str, action: &str, outcome: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn().execute(
+            "INSERT INTO actions (org, workspace, action, outcome, at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![org, workspace, action, outcome, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Records a scan, the organizations it listed completely and the workspaces it flagged as
+    /// stale.
+    pub fn record_scan(&mut self, orgs: &[&str], flagged: &[(&str, &str)]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conn = self.conn.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let tx = conn.transaction()?;
+        tx.execute("INSERT INTO scans (at) VALUES (?1)", params![Utc::now().to_rfc3339()])?;
+        let scan_id = tx.last_insert_rowid();
+        for org in orgs {
+            tx.execute("INSERT INTO scanned_orgs (scan_id, org) VALUES (?1, ?2)", params![scan_id, org])?;
+        }
+        for (org, workspace) in flagged {
+            tx.execute("INSERT INTO flagged (scan_id, org, workspace) VALUES (?1, ?2, ?3)",
+                params![scan_id, org, workspace])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Attaches the age statistics of the workspaces scanned to the most recent scan.
+    pub fn record_ages(&self, stats: &AgeStats) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO scan_ages (scan_id, stats) SELECT MAX(id), ?1 FROM scans",
+            params![serde_json::to_string(stats)?],
+        )?;
+        Ok(())
+    }
+
+    /// The age statistics of the most recent scan that has them.
+    pub fn last_ages(&self) -> Result<Option<AgeStats>, Box<dyn Error + Send + Sync>> {
+        let stats: Option<String> = self.conn().query_row(
+            "SELECT stats FROM scan_ages ORDER BY scan_id DESC LIMIT 1", [], |row| row.get(0),
+        ).optional()?;
+        // Statistics written by another version may not parse; the trend is then left out
+        Ok(stats.and_then(|stats| serde_json::from_str(&stats).ok()))
+    }
+
+    /// The number of consecutive scans of the workspace's organization, up to the most recent
+    /// one, that flagged the workspace. Scans of other organizations, or that failed to list
+    /// this one, neither count nor break the streak; nor do scans recorded by versions that
+    /// didn't record their organizations.
+    pub fn stale_streak(&self, org: &str, workspace: &str) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        Ok(self.conn().query_row(
+            "SELECT COUNT(*) FROM scanned_orgs WHERE org = ?1 AND scan_id > (
+                SELECT COALESCE(MAX(scan_id), 0) FROM scanned_orgs WHERE org = ?1 AND scan_id NOT IN (
+                    SELECT scan_id FROM flagged WHERE org = ?1 AND workspace = ?2))",
+            params![org, workspace],
+            |row| row.get(0),
+        )?)
+    }
+
+    pub fn record_hibernation(&self, org: &str, workspace: &str, configuration_version: Option<&str>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.conn().execute(
+            "INSERT INTO hibernations (org, workspace, configuration_version, at) VALUES (?1, ?2, ?3, ?4)",
+            params![org, workspace, configuration_version, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// The most recent hibernation of the workspace.
+    pub fn hibernation(&self, org: &str, workspace: &str) -> Result<Option<Hibernation>, Box<dyn Error + Send + Sync>> {
+        Ok(self.conn().query_row(
+            "SELECT configuration_version, at FROM hibernations WHERE org = ?1 AND workspace = ?2 ORDER BY id DESC LIMIT 1",
+            params![org, workspace],
+            |row| Ok(Hibernation { configuration_version: row.get(0)?, at: row.get(1)? }),
+        ).optional()?)
+    }
+
+    /// The workspace's most recent action, if it succeeded and means the workspace is already
+    /// taken care of. A pipeline stops at its first failure, so a workspace whose last action
+    /// failed or was refused still needs handling, whatever succeeded before.
+    pub fn handled_action(&self, org: &str, workspace: &str) -> Result<Option<Action>, Box<dyn Error + Send + Sync>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT action, outcome, at FROM actions
+             WHERE org = ?1 AND workspace = ?2
+             ORDER BY id DESC LIMIT 1",
+        )?;
+        let action = stmt
+            .query_row(params![org, workspace], |row| {
+                Ok(Action { action: row.get(0)?, outcome: row.get(1)?, at: row.get(2)? })
+            })
+            .optional()?;
+
+        Ok(action.filter(|action| action.outcome == SUCCEEDED && HANDLED_ACTIONS.contains(&action.action.as_str())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handled_action() {
+        let history = History::open_in_memory().unwrap();
+        history.record_action("acme", "app", "deleted", FAILED).unwrap();
+        assert_eq!(history.handled_action("acme", "app").unwrap(), None);
+
+        history.record_action("acme", "app", "deleted", SUCCEEDED).unwrap();
+        let action = history.handled_action("acme", "app").unwrap().unwrap();
+        assert_eq!(action.action, "deleted");
+        assert_eq!(history.handled_action("other-org", "app").unwrap(), None);
+
+        history.record_action("acme", "quarantined", "locked", SUCCEEDED).unwrap();
+        history.record_action("acme", "quarantined", "tagged", SUCCEEDED).unwrap();
+        assert_eq!(history.handled_action("acme", "quarantined").unwrap().unwrap().action, "tagged");
+
+        history.record_action("acme", "half-done", "locked", SUCCEEDED).unwrap();
+        history.record_action("acme", "half-done", "deleted", FAILED).unwrap();
+        assert_eq!(history.handled_action("acme", "half-done").unwrap(), None);
+        history.record_action("acme", "half-done", "archived", SUCCEEDED).unwrap();
+        assert_eq!(history.handled_action("acme", "half-done").unwrap(), None);
+    }
+
+    #[test]
+    fn test_last_ages() {
+        let mut history = History::open_in_memory().unwrap();
+        assert_eq!(history.last_ages().unwrap(), None);
+
+        history.record_scan(&[], &[]).unwrap();
+        history.record_ages(&AgeStats::new(&[10, 200])).unwrap();
+        history.record_scan(&[], &[]).unwrap();
+        history.record_ages(&AgeStats::new(&[10, 20, 300])).unwrap();
+
+        assert_eq!(history.last_ages().unwrap().map(|stats| stats.workspaces), Some(3));
+    }
+
+    #[test]
+    fn test_stale_streak() {
+        let mut history = History::open_in_memory().unwrap();
+        assert_eq!(history.stale_streak("acme", "app").unwrap(), 0);
+
+        history.record_scan(&["acme"], &[("acme", "app"), ("acme", "glitch")]).unwrap();
+        history.record_scan(&["acme"], &[("acme", "app")]).unwrap();
+        history.record_scan(&["acme"], &[("acme", "app"), ("acme", "glitch")]).unwrap();
+
+        assert_eq!(history.stale_streak("acme", "app").unwrap(), 3);
+        assert_eq!(history.stale_streak("acme", "glitch").unwrap(), 1);
+
+        history.record_scan(&["acme"], &[("acme", "glitch")]).unwrap();
+        assert_eq!(history.stale_streak("acme", "app").unwrap(), 0);
+        assert_eq!(history.stale_streak("acme", "glitch").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_stale_streak_skips_scans_of_other_orgs() {
+        let mut history = History::open_in_memory().unwrap();
+        history.record_scan(&["acme"], &[("acme", "app")]).unwrap();
+        // Scoped to another organization, or acme failed to list
+        history.record_scan(&["other"], &[("other", "app")]).unwrap();
+        history.record_scan(&["acme", "other"], &[("acme", "app")]).unwrap();
+
+        assert_eq!(history.stale_streak("acme", "app").unwrap(), 2);
+        assert_eq!(history.stale_streak("other", "app").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_history_persists_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+
+        History::open(&path).unwrap().record_action("acme", "app", "deleted", SUCCEEDED).unwrap();
+
+        assert!(History::open(&path).unwrap().handled_action("acme", "app").unwrap().is_some());
+    }
+}
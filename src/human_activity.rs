@@ -0,0 +1,149 @@
+use crate::audit_trail;
+use crate::config::AuditTrailConfig;
+use crate::redact;
+use crate::tfe::TfeClient;
+use crate::variables;
+use chrono::{DateTime, FixedOffset};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Attribute the latest human activity is recorded in, for staleness to measure from.
+pub const ATTRIBUTE: &str = "last-human-activity-at";
+
+/// Runs per workspace looked at for runs started by people and for comments.
+const RECENT_RUNS: usize = 5;
+
+fn parse(timestamp: Option<&str>) -> Option<DateTime<FixedOffset>> {
+    timestamp.and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+}
+
+/// The users of an `include=created_by` response who are machine accounts.
+fn service_accounts(included: &Value) -> Vec<&str> {
+    included.as_array().into_iter().flatten()
+        .filter(|resource| resource["type"] == "users" && resource["attributes"]["is-service-account"] == true)
+        .filter_map(|user| user["id"].as_str())
+        .collect()
+}
+
+/// What people, as opposed to machine accounts, last did to workspaces: started a run, commented
+/// on one, changed a variable or changed a team's access. TFE's `last-activity-at` counts every
+/// run, so workspaces only applied by a nightly drift check never look stale.
+pub struct HumanActivity {
+    /// Latest team access changes from the audit trail, by team access id.
+    team_access: HashMap<String, String>,
+}
+
+impl HumanActivity {
+    /// Reads the team access changes of `orgs` from their audit trail. Organizations whose trail
+    /// can't be read are warned about, and their team access changes go unnoticed.
+    pub async fn prepare(client: &TfeClient, config: &AuditTrailConfig, orgs: &[String]) -> HumanActivity {
+        HumanActivity { team_access: audit_trail::team_access_changes_of(client, config, orgs).await }
+    }
+
+    /// The latest human activity in the workspace, or `None` if there was none to be seen.
+    pub async fn last(&self, client: &TfeClient, id: &str) -> Result<Option<DateTime<FixedOffset>>, Box<dyn Error + Send + Sync>> {
+        let mut latest = Vec::new();
+
+        let runs = client.get(&format!("/workspaces/{}/runs?page[size]={}&include=created_by", id, RECENT_RUNS)).await?;
+        let machines = service_accounts(&runs["included"]);
+        for run in runs["data"].as_array().into_iter().flatten() {
+            let creator = run["relationships"]["created-by"]["data"]["id"].as_str();
+            if creator.is_some_and(|creator| !machines.contains(&creator)) {
+                latest.extend(parse(run["attributes"]["created-at"].as_str()));
+            }
+            let Some(run_id) = run["id"].as_str() else { continue };
+            let events = client.get(&format!("/runs/{}/run-events", run_id)).await?;
+            latest.extend(events["data"].as_array().into_iter().flatten()
+                .filter(|event| event["attributes"]["action"] == "commented")
+                .filter_map(|event| parse(event["attributes"]["created-at"].as_str())));
+        }
+
+        for variable in client.get_all(&format!("/workspaces/{}/vars", id)).await? {
+            latest.extend(parse(variables::last_changed(&variable)));
+        }
+
+        for access in client.get_all(&format!("/team-workspaces?filter[workspace][id]={}", id)).await? {
+            let changed = access["id"].as_str().and_then(|access_id| self.team_access.get(access_id));
+            latest.extend(parse(changed.map(String::as_str)));
+        }
+
+        Ok(latest.into_iter().max())
+    }
+
+    /// Records the latest human activity in the workspace's `last-human-activity-at`, null if
+    /// there was none. If it can't be looked up the attribute is left out with a warning, and
+    /// staleness is measured from `last-activity-at` as usual.
+    pub async fn enrich(&self, client: &TfeClient, workspace: &mut Value) {
+        let id = workspace["id"].as_str().unwrap_or("").to_string();
+        match self.last(client, &id).await {
+            Ok(last) => workspace["attributes"][ATTRIBUTE] = last.map(|date| date.to_rfc3339()).into(),
+            Err(e) => eprintln!("Warning: could not look up the human activity of workspace {}: {}", id, redact::scrub(&e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_enrich_takes_the_latest_human_activity() {
+        let _runs = mock("GET", "/api/v2/workspaces/ws-human/runs")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({
+                "data": [
+                    { "id": "run-bot", "attributes": { "created-at": "2024-05-20T00:00:00Z" },
+                      "relationships": { "created-by": { "data": { "id": "user-bot", "type": "users" } } } },
+                    { "id": "run-person", "attributes": { "created-at": "2024-01-10T00:00:00Z" },
+                      "relationships": { "created-by": { "data": { "id": "user-person", "type": "users" } } } },
+                ],
+                "included": [
+                    { "id": "user-bot", "type": "users", "attributes": { "is-service-account": true } },
+                    { "id": "user-person", "type": "users", "attributes": { "is-service-account": false } },
+                ]
+            }).to_string())
+            .create();
+        let _bot_events = mock("GET", "/api/v2/runs/run-bot/run-events")
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "action": "queued", "created-at": "2024-05-20T00:00:00Z" } },
+                { "attributes": { "action": "commented", "created-at": "2024-02-01T00:00:00Z" } },
+            ] }).to_string())
+            .create();
+        let _person_events = mock("GET", "/api/v2/runs/run-person/run-events")
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .create();
+        let _vars = mock("GET", "/api/v2/workspaces/ws-human/vars")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "attributes": { "created-at": "2023-06-01T00:00:00Z", "updated-at": "2024-01-20T00:00:00Z" } }] }).to_string())
+            .create();
+        let _access = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-human".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "tws-human" }, { "id": "tws-untouched" }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let activity = HumanActivity { team_access: HashMap::from([("tws-human".to_string(), "2024-03-05T00:00:00Z".to_string())]) };
+        let mut workspace = json!({ "id": "ws-human", "attributes": { "last-activity-at": "2024-05-20T00:00:00Z" } });
+        activity.enrich(&client, &mut workspace).await;
+        assert_eq!(workspace["attributes"][ATTRIBUTE], "2024-03-05T00:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn test_enrich_leaves_the_workspace_alone_on_errors() {
+        let _runs = mock("GET", "/api/v2/workspaces/ws-human-error/runs").match_query(Matcher::Any).with_status(500).create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let activity = HumanActivity { team_access: HashMap::new() };
+        let mut workspace = json!({ "id": "ws-human-error", "attributes": {} });
+        activity.enrich(&client, &mut workspace).await;
+        assert!(workspace["attributes"].get(ATTRIBUTE).is_none());
+    }
+}
@@ -0,0 +1,131 @@
+use clap::ValueEnum;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use std::sync::OnceLock;
+use unic_langid::{langid, LanguageIdentifier};
+
+/// Languages of prompts, report headers and notifications. Messages live in `locales/<lang>.ftl`
+/// and the default notification template in `locales/<lang>.notification.hbs`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+}
+
+impl Lang {
+    const ALL: [Lang; 2] = [Lang::En, Lang::De];
+
+    fn id(&self) -> LanguageIdentifier {
+        match self {
+            Lang::En => langid!("en"),
+            Lang::De => langid!("de"),
+        }
+    }
+
+    fn messages(&self) -> &'static str {
+        match self {
+            Lang::En => include_str!("../locales/en.ftl"),
+            Lang::De => include_str!("../locales/de.ftl"),
+        }
+    }
+
+    pub fn notification_template(&self) -> &'static str {
+        match self {
+            Lang::En => include_str!("../locales/en.notification.hbs"),
+            Lang::De => include_str!("../locales/de.notification.hbs"),
+        }
+    }
+}
+
+fn bundle(lang: Lang) -> FluentBundle<FluentResource> {
+    let resource = FluentResource::try_new(lang.messages().to_string())
+        .unwrap_or_else(|(_, errors)| panic!("invalid messages for {:?}: {:?}", lang, errors));
+    let mut bundle = FluentBundle::new_concurrent(vec![lang.id()]);
+    // Isolation marks would end up in CSV cells and terminals
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).unwrap_or_else(|errors| panic!("duplicate messages for {:?}: {:?}", lang, errors));
+    bundle
+}
+
+/// The bundles of every language, indexed like `Lang::ALL`, parsed on first use.
+fn bundles() -> &'static [FluentBundle<FluentResource>] {
+    static BUNDLES: OnceLock<Vec<FluentBundle<FluentResource>>> = OnceLock::new();
+    BUNDLES.get_or_init(|| Lang::ALL.into_iter().map(bundle).collect())
+}
+
+/// The language of this run, English until set.
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Sets the language of this run. Only the first call has an effect.
+pub fn set(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+pub fn current() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+/// The message in `lang`, falling back to English and then to the id for messages missing from
+/// a translation.
+fn message(lang: Lang, id: &str, args: Option<&FluentArgs>) -> String {
+    let bundles = bundles();
+    [lang, Lang::En].into_iter()
+        .map(|lang| &bundles[lang as usize])
+        .find_map(|bundle| {
+            let pattern = bundle.get_message(id)?.value()?;
+            let mut errors = Vec::new();
+            Some(bundle.format_pattern(pattern, args, &mut errors).into_owned())
+        })
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// The message `id` in the language of this run.
+pub fn text(id: &str) -> String {
+    message(current(), id, None)
+}
+
+/// The message `id` in the language of this run, with its variables filled in.
+pub fn format<'a>(id: &str, args: impl IntoIterator<Item = (&'a str, FluentValue<'a>)>) -> String {
+    let args: FluentArgs = args.into_iter().collect();
+    message(current(), id, Some(&args))
+}
+
+/// The message `id` in every language, e.g. to read back a report written in another language.
+pub fn in_every_language(id: &str) -> impl Iterator<Item = String> + '_ {
+    Lang::ALL.into_iter().map(move |lang| message(lang, id, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(lang: Lang) -> Vec<&'static str> {
+        lang.messages().lines()
+            .filter_map(|line| line.split_once(" = "))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn test_every_language_has_every_message() {
+        let english = ids(Lang::En);
+        for lang in Lang::ALL {
+            assert_eq!(ids(lang), english, "messages of {:?}", lang);
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(message(Lang::De, "column-project", None), "Projekt");
+        let args: FluentArgs = [("count", FluentValue::from(3))].into_iter().collect();
+        assert_eq!(message(Lang::En, "prompt-revoke-grants", Some(&args)), "Revoke these 3 grants? (y/n):");
+        assert_eq!(message(Lang::De, "no-such-message", None), "no-such-message");
+        assert_eq!(in_every_language("column-org").collect::<Vec<_>>(), vec!["Organization", "Organisation"]);
+        let args: FluentArgs = [("count", FluentValue::from(1))].into_iter().collect();
+        assert_eq!(message(Lang::De, "age-days", Some(&args)), "1 Tag");
+        let args: FluentArgs = [("count", FluentValue::from(4))].into_iter().collect();
+        assert_eq!(message(Lang::De, "age-years", Some(&args)), "4 Jahre");
+        assert_eq!(message(Lang::De, "ago-years", Some(&args)), "vor 4 Jahren");
+    }
+}
@@ -0,0 +1,236 @@
+use crate::config::Config;
+use crate::human_activity::HumanActivity;
+use crate::scan;
+use crate::staleness::{Policy, Status, Verdict};
+use crate::tfe::{self, TfeClient};
+use crate::timefmt;
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::Write;
+
+const RECENT_RUNS: usize = 5;
+
+/// Everything the cleanup logic knows about a single workspace.
+#[derive(Debug)]
+pub struct Inspection {
+    pub org: String,
+    pub workspace: Value,
+    pub recent_runs: Vec<Value>,
+    pub consumers: Vec<String>,
+    pub inbound_triggers: Vec<String>,
+    pub outbound_triggers: Vec<String>,
+    pub owners: Vec<String>,
+    pub verdict: Verdict,
+}
+
+fn names(items: &[Value], attribute: &str) -> Vec<String> {
+    items.iter()
+        .filter_map(|item| item["attributes"][attribute].as_str().map(str::to_string))
+        .collect()
+}
+
+/// Teams with admin access to the workspace are treated as its owners.
+pub async fn owners(client: &TfeClient, workspace_id: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut owners = Vec::new();
+    for access in client.get_all(&format!("/team-workspaces?filter[workspace][id]={}", workspace_id)).await? {
+        if access["attributes"]["access"] != "admin" {
+            continue;
+        }
+        if let Some(team_id) = access["relationships"]["team"]["data"]["id"].as_str() {
+            let team = client.get(&format!("/teams/{}", team_id)).await?;
+            owners.push(team["data"]["attributes"]["name"].as_str().unwrap_or(team_id).to_string());
+        }
+    }
+    Ok(owners)
+}
+
+/// Looks the workspace up and evaluates it as a scan would, with its human activity and pull
+/// request when the config turns those on.
+pub async fn inspect(
+    client: &TfeClient,
+    config: &Config,
+    policy: &Policy,
+    org: &str,
+    name: &str,
+) -> Result<Inspection, Box<dyn Error + Send + Sync>> {
+    let mut workspace = tfe::get_workspace(client, org, name).await?;
+    let id = workspace["id"].as_str().ok_or("workspace has no id")?.to_string();
+
+    let runs = client.get(&format!("/workspaces/{}/runs?page[size]={}", id, RECENT_RUNS)).await?;
+    let consumers = client.get_all(&format!("/workspaces/{}/relationships/remote-state-consumers", id)).await?;
+    let inbound = client.get_all(&format!("/workspaces/{}/run-triggers?filter[run-trigger][type]=inbound", id)).await?;
+    let outbound = client.get_all(&format!("/workspaces/{}/run-triggers?filter[run-trigger][type]=outbound", id)).await?;
+    let human = match config.human_activity {
+        true => Some(HumanActivity::prepare(client, &config.audit_trail, &[org.to_string()]).await),
+        false => None,
+    };
+    let verdict = scan::evaluate_one(client, human.as_ref(), config.pull_requests.as_ref(), policy, Utc::now(), &mut workspace).await;
+
+    Ok(Inspection {
+        org: org.to_string(),
+        recent_runs: runs["data"].as_array().cloned().unwrap_or_default(),
+        consumers: names(&consumers, "name"),
+        inbound_triggers: names(&inbound, "sourceable-name"),
+        outbound_triggers: names(&outbound, "workspace-name"),
+        owners: owners(client, &id).await?,
+        verdict,
+        workspace,
+    })
+}
+
+fn list(items: &[String]) -> String {
+    if items.is_empty() { "(none)".to_string() } else { items.join(", ") }
+}
+
+pub fn render(inspection: &Inspection, timezone: Tz) -> String {
+    let attributes = &inspection.workspace["attributes"];
+    let tags: Vec<String> = attributes["tag-names"].as_array()
+        .map(|tags| tags.iter().filter_map(|t| t.as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+    let mut out = String::new();
+
+    let _ = writeln!(out, "Workspace:        {}/{} ({})", inspection.org,
+        attributes["name"].as_str().unwrap_or(""), inspection.workspace["id"].as_str().unwrap_or(""));
+    let _ = writeln!(out, "Last activity:    {}", match attributes["last-activity-at"].as_str() {
+        Some(raw) => timefmt::describe(raw, timezone, Utc::now()),
+        None => "(no activity data)".to_string(),
+    });
+    let _ = writeln!(out, "Resources:        {}", attributes["resource-count"].as_u64().unwrap_or(0));
+    let _ = writeln!(out, "Tags:             {}", list(&tags));
+    let _ = writeln!(out, "Owners:           {}", list(&inspection.owners));
+    let _ = writeln!(out, "Consumers:        {}", list(&inspection.consumers));
+    let _ = writeln!(out, "Triggered by:     {}", list(&inspection.inbound_triggers));
+    let _ = writeln!(out, "Triggers:         {}", list(&inspection.outbound_triggers));
+    let _ = writeln!(out, "Verdict:          {}", match inspection.verdict.status {
+        Status::Flagged => "stale",
+        Status::Kept => "not stale",
+        Status::Excluded => "excluded",
+        Status::OptedOut => "opted out",
+    });
+    let _ = writeln!(out, "Rule:             {}", inspection.verdict.rule);
+    let _ = writeln!(out, "Recent runs:");
+    if inspection.recent_runs.is_empty() {
+        let _ = writeln!(out, "  (none)");
+    }
+    for run in &inspection.recent_runs {
+        let _ = writeln!(out, "  {}  {:<20} {}",
+            run["id"].as_str().unwrap_or(""),
+            run["attributes"]["status"].as_str().unwrap_or(""),
+            timefmt::describe(run["attributes"]["created-at"].as_str().unwrap_or(""), timezone, Utc::now()));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_inspect() {
+        let _workspace = mock("GET", "/api/v2/organizations/inspect-org/workspaces/billing")
+            .with_status(200)
+            .with_body(json!({ "data": {
+                "id": "ws-inspect",
+                "attributes": {
+                    "name": "billing",
+                    "last-activity-at": "2020-01-01T00:00:00Z",
+                    "resource-count": 12,
+                    "tag-names": ["team:payments"]
+                }
+            } }).to_string())
+            .create();
+        let _runs = mock("GET", "/api/v2/workspaces/ws-inspect/runs")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "id": "run-1", "attributes": { "status": "applied", "created-at": "2019-12-31T00:00:00Z" } }
+            ] }).to_string())
+            .create();
+        let _consumers = mock("GET", "/api/v2/workspaces/ws-inspect/relationships/remote-state-consumers")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "attributes": { "name": "checkout" } }] }).to_string())
+            .create();
+        let _inbound = mock("GET", "/api/v2/workspaces/ws-inspect/run-triggers")
+            .match_query(Matcher::UrlEncoded("filter[run-trigger][type]".into(), "inbound".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "attributes": { "sourceable-name": "network" } }] }).to_string())
+            .create();
+        let _outbound = mock("GET", "/api/v2/workspaces/ws-inspect/run-triggers")
+            .match_query(Matcher::UrlEncoded("filter[run-trigger][type]".into(), "outbound".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .create();
+        let _access = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-inspect".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "access": "admin" }, "relationships": { "team": { "data": { "id": "team-inspect" } } } },
+                { "attributes": { "access": "read" }, "relationships": { "team": { "data": { "id": "team-readers" } } } }
+            ] }).to_string())
+            .create();
+        let _team = mock("GET", "/api/v2/teams/team-inspect")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "name": "payments" } } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let inspection = inspect(&client, &Config::default(), &Policy::default(), "inspect-org", "billing").await.unwrap();
+        let output = render(&inspection, Tz::UTC);
+
+        assert_eq!(inspection.owners, vec!["payments"]);
+        assert!(output.contains("Resources:        12"));
+        assert!(output.contains("Consumers:        checkout"));
+        assert!(output.contains("Triggered by:     network"));
+        assert!(output.contains("Triggers:         (none)"));
+        assert!(output.contains("Verdict:          stale"));
+        assert!(output.contains("run-1  applied"));
+        assert!(output.contains("Last activity:    2020-01-01T00:00:00Z (2020-01-01 00:00 UTC, "));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_measures_from_human_activity() {
+        let _workspace = mock("GET", "/api/v2/organizations/inspect-human-org/workspaces/batch")
+            .with_status(200)
+            .with_body(json!({ "data": {
+                "id": "ws-inspect-human",
+                "attributes": { "name": "batch", "last-activity-at": Utc::now().to_rfc3339(), "created-at": "2020-01-01T00:00:00Z" }
+            } }).to_string())
+            .create();
+        // Only a nightly drift check by a machine account has run lately
+        let _runs = mock("GET", "/api/v2/workspaces/ws-inspect-human/runs")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({
+                "data": [{ "id": "run-drift", "attributes": { "status": "planned", "created-at": Utc::now().to_rfc3339() },
+                           "relationships": { "created-by": { "data": { "id": "user-bot", "type": "users" } } } }],
+                "included": [{ "id": "user-bot", "type": "users", "attributes": { "is-service-account": true } }]
+            }).to_string())
+            .create();
+        let _events = mock("GET", "/api/v2/runs/run-drift/run-events")
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .create();
+        let empty = |path: &str| mock("GET", path).match_query(Matcher::Any).with_status(200).with_body(json!({ "data": [] }).to_string()).create();
+        let _consumers = empty("/api/v2/workspaces/ws-inspect-human/relationships/remote-state-consumers");
+        let _triggers = empty("/api/v2/workspaces/ws-inspect-human/run-triggers");
+        let _vars = empty("/api/v2/workspaces/ws-inspect-human/vars");
+        let _access = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-inspect-human".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let config = Config { human_activity: true, ..Config::default() };
+        let inspection = inspect(&client, &config, &Policy::default(), "inspect-human-org", "batch").await.unwrap();
+
+        assert_eq!(inspection.verdict.status, Status::Flagged);
+        assert!(inspection.verdict.rule.starts_with("created-at 2020-01-01T00:00:00Z"), "{}", inspection.verdict.rule);
+    }
+}
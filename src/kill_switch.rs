@@ -0,0 +1,60 @@
+use crate::redact;
+use std::error::Error;
+use std::time::Duration;
+
+/// A URL operators can flip to abort a run remotely, e.g. an object in a bucket. The run goes on
+/// only while the URL answers with a success status and a body other than `stop`; anything
+/// else, including no answer, aborts it.
+pub struct KillSwitch {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl KillSwitch {
+    pub fn new(url: &str) -> Result<KillSwitch, Box<dyn Error + Send + Sync>> {
+        let http = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(KillSwitch { url: url.to_string(), http })
+    }
+
+    /// Fails with the reason if the switch is engaged.
+    pub async fn check(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = redact::url(&self.url);
+        let response = self.http.get(&self.url).send().await
+            .map_err(|e| format!("kill switch {} unreachable, aborting: {}", url, e.without_url()))?;
+        if !response.status().is_success() {
+            return Err(format!("kill switch {} returned {}, aborting", url, response.status()).into());
+        }
+        let body = response.text().await?;
+        if body.trim().eq_ignore_ascii_case("stop") {
+            return Err(format!("kill switch {} engaged, aborting", url).into());
+        }
+        Ok(())
+    }
+}
+
+/// Checks the switch, if the run has one.
+pub async fn check(kill_switch: Option<&KillSwitch>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match kill_switch {
+        Some(kill_switch) => kill_switch.check().await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[tokio::test]
+    async fn test_check() {
+        let _go = mock("GET", "/kill-switch-go").with_status(200).with_body("go\n").create();
+        let _stop = mock("GET", "/kill-switch-stop").with_status(200).with_body("STOP\n").create();
+        let _gone = mock("GET", "/kill-switch-gone").with_status(404).create();
+
+        assert!(KillSwitch::new(&format!("{}/kill-switch-go", server_url())).unwrap().check().await.is_ok());
+        let engaged = KillSwitch::new(&format!("{}/kill-switch-stop", server_url())).unwrap().check().await.unwrap_err();
+        assert!(engaged.to_string().ends_with("engaged, aborting"));
+        let missing = KillSwitch::new(&format!("{}/kill-switch-gone", server_url())).unwrap().check().await.unwrap_err();
+        assert!(missing.to_string().ends_with("returned 404 Not Found, aborting"));
+    }
+}
@@ -0,0 +1,64 @@
+//! Finds stale Terraform Enterprise workspaces and cleans them up. The `tfe_cleanup` binary is
+//! the command line front end of these modules; tools embedding them use `engine`, and the
+//! benchmarks `staleness`. Everything else is internal.
+
+mod actions;
+mod ages;
+mod archive;
+mod audit_trail;
+mod branches;
+mod cache;
+#[doc(hidden)]
+pub mod cli;
+mod clusters;
+pub mod config;
+mod contacts;
+mod datadog;
+mod debug_bundle;
+mod default_project;
+mod delete;
+mod dependencies;
+mod destroy;
+mod doctor;
+pub mod engine;
+mod entitlements;
+mod events;
+mod fixtures;
+#[cfg(test)]
+mod golden;
+mod hibernate;
+mod history;
+mod human_activity;
+mod i18n;
+mod limits;
+mod manifest;
+mod migrate;
+mod inspect;
+pub mod kill_switch;
+mod no_vcs;
+mod notify;
+mod orgs;
+mod overrides;
+mod plan_exports;
+mod profile;
+mod pull_requests;
+mod redact;
+mod registry;
+mod report;
+mod run_lock;
+mod run_summary;
+mod servicenow;
+mod scan;
+mod selftest;
+mod script;
+mod sinks;
+pub mod staleness;
+mod storage;
+mod summary;
+mod team_access;
+pub mod tfe;
+mod timefmt;
+mod update;
+mod validate;
+mod variables;
+mod window;
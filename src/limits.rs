@@ -0,0 +1,155 @@
+use crate::tfe::{self, OrgTotals};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Upper bounds on how much of an organization a single run may delete.
+#[derive(Debug, Default)]
+pub struct BlastRadius {
+    pub max_deletions: Option<usize>,
+    pub max_percent: Option<f64>,
+}
+
+/// Checks the planned deletions against the limits, per organization. `totals` counts every
+/// workspace scanned, the denominator of the percentage.
+pub fn check(limits: &BlastRadius, planned: &[Value], totals: &OrgTotals) -> Result<(), String> {
+    for (org, count) in tfe::count_by_org(planned) {
+        if let Some(max) = limits.max_deletions {
+            if count > max {
+                return Err(format!(
+                    "refusing to delete {} workspaces in organization {}: --max-deletions is {}",
+                    count, org, max
+                ));
+            }
+        }
+
+        if let Some(max_percent) = limits.max_percent {
+            let total = totals.get(&org).copied().unwrap_or(count).max(count);
+            let percent = count as f64 * 100.0 / total as f64;
+            if percent > max_percent {
+                return Err(format!(
+                    "refusing to delete {} of {} workspaces ({:.1}%) in organization {}: --max-percent is {}",
+                    count, total, percent, org, max_percent
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Stops acting on an organization after a number of consecutive failed workspaces, e.g. when
+/// the token lost its permissions there, while other organizations carry on.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    consecutive: HashMap<String, u32>,
+    /// Organizations whose breaker tripped, with the workspaces left untouched since.
+    tripped: BTreeMap<String, usize>,
+}
+
+impl CircuitBreaker {
+    /// A breaker tripping after `threshold` consecutive failures; 0 never trips.
+    pub fn new(threshold: u32) -> CircuitBreaker {
+        CircuitBreaker { threshold, consecutive: HashMap::new(), tripped: BTreeMap::new() }
+    }
+
+    /// Whether the organization may still be acted on. A tripped organization's workspaces are
+    /// counted as skipped.
+    pub fn allow(&mut self, org: &str) -> bool {
+        match self.tripped.get_mut(org) {
+            Some(skipped) => {
+                *skipped += 1;
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Records how a workspace's actions went. Returns true when this failure trips the breaker.
+    pub fn record(&mut self, org: &str, failed: bool) -> bool {
+        let consecutive = self.consecutive.entry(org.to_string()).or_default();
+        *consecutive = if failed { *consecutive + 1 } else { 0 };
+        if failed && self.threshold > 0 && *consecutive >= self.threshold && !self.tripped.contains_key(org) {
+            self.tripped.insert(org.to_string(), 0);
+            return true;
+        }
+        false
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    /// The tripped organizations and how many of their workspaces were skipped.
+    pub fn tripped(&self) -> &BTreeMap<String, usize> {
+        &self.tripped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn workspaces(org: &str, count: usize) -> Vec<Value> {
+        (0..count)
+            .map(|i| json!({
+                "attributes": { "name": format!("ws-{}", i) },
+                "relationships": { "organization": { "data": { "id": org } } }
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn test_within_limits() {
+        let all = workspaces("acme", 10);
+        let limits = BlastRadius { max_deletions: Some(5), max_percent: Some(50.0) };
+        assert!(check(&limits, &all[..5], &tfe::count_by_org(&all)).is_ok());
+    }
+
+    #[test]
+    fn test_max_deletions_exceeded() {
+        let all = workspaces("acme", 10);
+        let limits = BlastRadius { max_deletions: Some(2), max_percent: None };
+        let err = check(&limits, &all[..3], &tfe::count_by_org(&all)).unwrap_err();
+        assert!(err.contains("--max-deletions is 2"));
+    }
+
+    #[test]
+    fn test_max_percent_is_per_organization() {
+        let mut all = workspaces("small", 2);
+        all.extend(workspaces("large", 100));
+        let limits = BlastRadius { max_deletions: None, max_percent: Some(25.0) };
+
+        // one of two workspaces in "small" is 50%, even though it's under 1% of the total
+        let err = check(&limits, &all[..1], &tfe::count_by_org(&all)).unwrap_err();
+        assert!(err.contains("organization small"));
+    }
+
+    #[test]
+    fn test_circuit_breaker_isolates_organizations() {
+        let mut breaker = CircuitBreaker::new(2);
+
+        assert!(!breaker.record("acme", true));
+        assert!(!breaker.record("acme", false));
+        assert!(!breaker.record("acme", true));
+        assert!(!breaker.record("globex", true));
+        assert!(breaker.record("acme", true));
+
+        assert!(!breaker.allow("acme"));
+        assert!(!breaker.allow("acme"));
+        assert!(breaker.allow("globex"));
+        assert_eq!(breaker.tripped().iter().collect::<Vec<_>>(), vec![(&"acme".to_string(), &2)]);
+    }
+
+    #[test]
+    fn test_circuit_breaker_without_threshold() {
+        let mut breaker = CircuitBreaker::new(0);
+
+        for _ in 0..5 {
+            assert!(!breaker.record("acme", true));
+        }
+        assert!(breaker.allow("acme"));
+        assert!(breaker.tripped().is_empty());
+    }
+}
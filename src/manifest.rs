@@ -0,0 +1,127 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The workspaces expected to exist, per organization, e.g. generated from a service catalog:
+///
+/// ```yaml
+/// acme:
+///   - networking-prod
+///   - payments-staging
+/// ```
+pub type Manifest = BTreeMap<String, Vec<String>>;
+
+pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error + Send + Sync>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read manifest {}: {}", path.display(), e))?;
+    serde_yaml::from_str(&contents).map_err(|e| format!("invalid manifest {}: {}", path.display(), e).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Difference {
+    /// In TFE but not in the manifest: nobody claims it, so it is likely abandoned.
+    Unlisted,
+    /// In the manifest but not in TFE.
+    Missing,
+}
+
+impl Difference {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Difference::Unlisted => "not in manifest",
+            Difference::Missing => "not in TFE",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ManifestFinding {
+    pub org: String,
+    pub workspace: String,
+    pub difference: Difference,
+    /// Empty for missing workspaces and those without activity data.
+    pub last_activity: String,
+}
+
+/// Compares the workspaces of `org` with those the manifest expects there.
+pub fn compare(org: &str, workspaces: &[Value], expected: &[String]) -> Vec<ManifestFinding> {
+    let expected: BTreeSet<&str> = expected.iter().map(String::as_str).collect();
+    let existing: BTreeSet<&str> = workspaces.iter().filter_map(|workspace| workspace["attributes"]["name"].as_str()).collect();
+
+    let unlisted = workspaces.iter()
+        .filter(|workspace| workspace["attributes"]["name"].as_str().is_some_and(|name| !expected.contains(name)))
+        .map(|workspace| ManifestFinding {
+            org: org.to_string(),
+            workspace: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+            difference: Difference::Unlisted,
+            last_activity: workspace["attributes"]["last-activity-at"].as_str().unwrap_or("").to_string(),
+        });
+    let missing = expected.difference(&existing).map(|name| ManifestFinding {
+        org: org.to_string(),
+        workspace: name.to_string(),
+        difference: Difference::Missing,
+        last_activity: String::new(),
+    });
+
+    unlisted.chain(missing).collect()
+}
+
+pub fn create_manifest_csv(findings: &[ManifestFinding], path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["Organization", "Workspace", "Difference", "Last Activity"])?;
+
+    for finding in findings {
+        wtr.write_record([&finding.org, &finding.workspace, finding.difference.label(), &finding.last_activity])?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.yaml");
+        fs::write(&path, "acme:\n  - networking-prod\n  - payments-staging\nsandbox: []\n").unwrap();
+
+        let manifest = load(&path).unwrap();
+        assert_eq!(manifest["acme"], vec!["networking-prod", "payments-staging"]);
+        assert!(manifest["sandbox"].is_empty());
+
+        fs::write(&path, "acme: networking-prod\n").unwrap();
+        assert!(load(&path).unwrap_err().to_string().starts_with("invalid manifest"));
+    }
+
+    #[test]
+    fn test_compare() {
+        let workspaces = vec![
+            json!({ "attributes": { "name": "networking-prod", "last-activity-at": "2024-05-01T00:00:00Z" } }),
+            json!({ "attributes": { "name": "jdoe-experiment", "last-activity-at": "2023-01-01T00:00:00Z" } }),
+        ];
+        let expected = vec!["networking-prod".to_string(), "payments-staging".to_string()];
+
+        let findings = compare("acme", &workspaces, &expected);
+
+        assert_eq!(findings, vec![
+            ManifestFinding {
+                org: "acme".to_string(),
+                workspace: "jdoe-experiment".to_string(),
+                difference: Difference::Unlisted,
+                last_activity: "2023-01-01T00:00:00Z".to_string(),
+            },
+            ManifestFinding {
+                org: "acme".to_string(),
+                workspace: "payments-staging".to_string(),
+                difference: Difference::Missing,
+                last_activity: String::new(),
+            },
+        ]);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::tfe::{self, TfeClient};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::error::Error;
+
+pub struct MigrateOptions {
+    pub new_name: Option<String>,
+    pub project: Option<String>,
+    pub transfer_team_access: bool,
+}
+
+/// Maps a project-level team access level to the closest workspace-level one.
+/// Custom project permissions have no direct equivalent and must be granted by hand.
+fn workspace_access_for(project_access: &str) -> Option<&'static str> {
+    match project_access {
+        "read" => Some("read"),
+        "write" => Some("write"),
+        "maintain" | "admin" => Some("admin"),
+        _ => None,
+    }
+}
+
+async fn find_project_id(client: &TfeClient, org: &str, project: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let projects = client.get_all_with_query(&format!("/organizations/{}/projects", org), &[("filter[names]", project)]).await?;
+    projects.iter()
+        .find(|p| p["attributes"]["name"].as_str() == Some(project))
+        .and_then(|p| p["id"].as_str())
+        .map(str::to_string)
+        .ok_or_else(|| format!("project '{}' not found in organization {}", project, org).into())
+}
+
+/// Grants workspace-level access to every team that currently reaches the workspace through
+/// its project, so moving the workspace to another project doesn't lock those teams out.
+async fn transfer_team_access(
+    client: &TfeClient,
+    workspace_id: &str,
+    old_project_id: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let project_access = client.get_all(&format!("/team-projects?filter[project][id]={}", old_project_id)).await?;
+    let existing: HashSet<String> = client.get_all(&format!("/team-workspaces?filter[workspace][id]={}", workspace_id)).await?
+        .iter()
+        .filter_map(|access| access["relationships"]["team"]["data"]["id"].as_str().map(str::to_string))
+        .collect();
+
+    for access in &project_access {
+        let team_id = access["relationships"]["team"]["data"]["id"].as_str().unwrap_or("");
+        let level = access["attributes"]["access"].as_str().unwrap_or("");
+
+        if team_id.is_empty() || existing.contains(team_id) {
+            continue;
+        }
+
+        match workspace_access_for(level) {
+            Some(workspace_level) => {
+                client.post("/team-workspaces", &json!({
+                    "data": {
+                        "type": "team-workspaces",
+                        "attributes": { "access": workspace_level },
+                        "relationships": {
+                            "workspace": { "data": { "type": "workspaces", "id": workspace_id } },
+                            "team": { "data": { "type": "teams", "id": team_id } }
+                        }
+                    }
+                })).await?;
+                eprintln!("Granted {} access to team {}", workspace_level, team_id);
+            }
+            None => eprintln!("Team {} has '{}' project access; grant workspace access manually", team_id, level),
+        }
+    }
+
+    Ok(())
+}
+
+/// Renames a workspace and/or moves it to another project instead of deleting it.
+pub async fn migrate_workspace(
+    client: &TfeClient,
+    org: &str,
+    name: &str,
+    options: &MigrateOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if options.new_name.is_none() && options.project.is_none() {
+        return Err("nothing to migrate: pass a new name and/or a target project".into());
+    }
+
+    let workspace = tfe::get_workspace(client, org, name).await?;
+    let workspace_id = workspace["id"].as_str().ok_or("workspace has no id")?.to_string();
+
+    let mut attributes = json!({});
+    if let Some(new_name) = &options.new_name {
+        attributes["name"] = Value::from(new_name.as_str());
+    }
+    let mut body = json!({ "data": { "type": "workspaces", "attributes": attributes } });
+
+    if let Some(project) = &options.project {
+        let project_id = find_project_id(client, org, project).await?;
+        let old_project_id = workspace["relationships"]["project"]["data"]["id"].as_str().unwrap_or("");
+
+        if options.transfer_team_access && !old_project_id.is_empty() && old_project_id != project_id {
+            transfer_team_access(client, &workspace_id, old_project_id).await?;
+        }
+
+        body["data"]["relationships"] = json!({
+            "project": { "data": { "type": "projects", "id": project_id } }
+        });
+    }
+
+    client.patch(&format!("/workspaces/{}", workspace_id), &body).await?;
+    println!("Migrated workspace {}/{}", org, name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[test]
+    fn test_workspace_access_for() {
+        assert_eq!(workspace_access_for("read"), Some("read"));
+        assert_eq!(workspace_access_for("maintain"), Some("admin"));
+        assert_eq!(workspace_access_for("custom"), None);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_moves_project_and_transfers_access() {
+        let _workspace = mock("GET", "/api/v2/organizations/migrate-org/workspaces/legacy")
+            .with_status(200)
+            .with_body(json!({
+                "data": {
+                    "id": "ws-migrate",
+                    "relationships": { "project": { "data": { "id": "prj-old" } } }
+                }
+            }).to_string())
+            .create();
+        let _projects = mock("GET", "/api/v2/organizations/migrate-org/projects")
+            .match_query(Matcher::UrlEncoded("filter[names]".into(), "platform & tools".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [{ "id": "prj-new", "attributes": { "name": "platform & tools" } }]
+            }).to_string())
+            .create();
+        let _team_projects = mock("GET", "/api/v2/team-projects")
+            .match_query(Matcher::UrlEncoded("filter[project][id]".into(), "prj-old".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [
+                    { "attributes": { "access": "write" }, "relationships": { "team": { "data": { "id": "team-a" } } } },
+                    { "attributes": { "access": "read" }, "relationships": { "team": { "data": { "id": "team-b" } } } }
+                ]
+            }).to_string())
+            .create();
+        let _team_workspaces = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-migrate".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [{ "relationships": { "team": { "data": { "id": "team-b" } } } }]
+            }).to_string())
+            .create();
+        let grant = mock("POST", "/api/v2/team-workspaces")
+            .match_body(Matcher::Regex("team-a".into()))
+            .with_status(201)
+            .expect(1)
+            .create();
+        let patch = mock("PATCH", "/api/v2/workspaces/ws-migrate")
+            .match_body(Matcher::Regex("prj-new".into()))
+            .with_status(200)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let options = MigrateOptions {
+            new_name: None,
+            project: Some("platform & tools".to_string()),
+            transfer_team_access: true,
+        };
+        migrate_workspace(&client, "migrate-org", "legacy", &options).await.unwrap();
+
+        grant.assert();
+        patch.assert();
+    }
+}
@@ -0,0 +1,127 @@
+use crate::staleness;
+use crate::tfe::{self, TfeClient};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use std::error::Error;
+
+/// Run source of runs queued through the API, e.g. by a pipeline.
+const API_RUN_SOURCE: &str = "tfe-api";
+
+/// A workspace without a VCS connection that no pipeline has run recently.
+#[derive(Debug, PartialEq)]
+pub struct NoVcsWorkspace {
+    pub org: String,
+    pub workspace: String,
+    pub last_activity: String,
+    pub last_api_run: String,
+}
+
+/// Creation time of the most recent API-driven run among `runs`.
+fn last_api_run(runs: &[Value]) -> Option<DateTime<Utc>> {
+    runs.iter()
+        .filter(|run| run["attributes"]["source"] == API_RUN_SOURCE)
+        .filter_map(|run| run["attributes"]["created-at"].as_str())
+        .filter_map(|created| DateTime::parse_from_rfc3339(created).ok())
+        .map(|created| created.with_timezone(&Utc))
+        .max()
+}
+
+/// Lists the workspaces of `org` with no VCS connection and no API-driven run in `days` days.
+pub async fn find_no_vcs_workspaces(
+    client: &TfeClient,
+    org: &str,
+    days: i64,
+) -> Result<Vec<NoVcsWorkspace>, Box<dyn Error + Send + Sync>> {
+    let cutoff = Utc::now() - Duration::days(days);
+    let mut findings = Vec::new();
+
+    for workspace in tfe::list_workspaces(client, org).await? {
+        if staleness::is_vcs_backed(&workspace) {
+            continue;
+        }
+
+        let workspace_id = workspace["id"].as_str().unwrap_or("");
+        // Runs are listed newest first, so the first page covers any recent API run
+        let response = client.get(&format!("/workspaces/{}/runs?page[size]=100", workspace_id)).await?;
+        let runs = response["data"].as_array().cloned().unwrap_or_default();
+
+        let last_run = last_api_run(&runs);
+        if last_run.is_some_and(|last_run| last_run >= cutoff) {
+            continue;
+        }
+
+        findings.push(NoVcsWorkspace {
+            org: org.to_string(),
+            workspace: workspace["attributes"]["name"].as_str().unwrap_or(workspace_id).to_string(),
+            last_activity: workspace["attributes"]["last-activity-at"].as_str().unwrap_or("").to_string(),
+            last_api_run: last_run.map(|last_run| last_run.to_rfc3339()).unwrap_or_default(),
+        });
+    }
+
+    Ok(findings)
+}
+
+pub fn create_no_vcs_csv(findings: &[NoVcsWorkspace], path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(["Organization", "Workspace", "Last Activity", "Last API Run"])?;
+
+    for finding in findings {
+        wtr.write_record([&finding.org, &finding.workspace, &finding.last_activity, &finding.last_api_run])?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_last_api_run() {
+        let runs = vec![
+            json!({ "attributes": { "source": "tfe-configuration-version", "created-at": "2024-05-01T00:00:00Z" } }),
+            json!({ "attributes": { "source": "tfe-api", "created-at": "2024-02-01T00:00:00Z" } }),
+            json!({ "attributes": { "source": "tfe-api", "created-at": "2024-01-01T00:00:00Z" } }),
+        ];
+
+        assert_eq!(last_api_run(&runs).unwrap().to_rfc3339(), "2024-02-01T00:00:00+00:00");
+        assert_eq!(last_api_run(&runs[..1]), None);
+    }
+
+    #[tokio::test]
+    async fn test_find_no_vcs_workspaces() {
+        let _workspaces = mock("GET", "/api/v2/organizations/novcs-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "id": "ws-novcs-cli", "attributes": { "name": "experiment", "vcs-repo": null } },
+                { "id": "ws-novcs-pipeline", "attributes": { "name": "pipeline", "vcs-repo": null } },
+                { "id": "ws-novcs-vcs", "attributes": { "name": "app", "vcs-repo": { "identifier": "acme/app" } } }
+            ] }).to_string())
+            .create();
+        let _cli_runs = mock("GET", "/api/v2/workspaces/ws-novcs-cli/runs")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "source": "terraform+cloud", "created-at": Utc::now().to_rfc3339() } }
+            ] }).to_string())
+            .create();
+        let _pipeline_runs = mock("GET", "/api/v2/workspaces/ws-novcs-pipeline/runs")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "source": "tfe-api", "created-at": Utc::now().to_rfc3339() } }
+            ] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let findings = find_no_vcs_workspaces(&client, "novcs-org", 30).await.unwrap();
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].workspace, "experiment");
+        assert_eq!(findings[0].last_api_run, "");
+    }
+}
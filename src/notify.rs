@@ -0,0 +1,302 @@
+use crate::ages::AgeStats;
+use crate::config::NotificationConfig;
+use crate::contacts::Contact;
+use crate::i18n;
+use crate::redact::{self, Secret};
+use crate::staleness::{self, Policy};
+use crate::tfe::{self, OrgTotals};
+use crate::timefmt;
+use crate::variables::PlaintextSecret;
+use chrono::{DateTime, Utc};
+use handlebars::Handlebars;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+
+/// Used in English for every channel without a template of its own; other languages have theirs
+/// in `locales`.
+#[cfg(test)]
+pub const DEFAULT_TEMPLATE: &str = include_str!("../locales/en.notification.hbs");
+
+/// The results of a run as seen by notification templates.
+#[derive(Debug, Serialize)]
+pub struct ScanResults {
+    pub command: String,
+    pub generated_at: String,
+    pub threshold_days: i64,
+    pub total_workspaces: usize,
+    pub stale_count: usize,
+    pub organizations: Vec<OrgResults>,
+    pub stale: Vec<StaleWorkspace>,
+    /// `org/name` of the workspaces whose owners opted them out of cleanup.
+    pub opted_out: Vec<String>,
+    /// Non-sensitive variables of the stale workspaces whose values look like credentials.
+    pub plaintext_secrets: Vec<PlaintextSecretFinding>,
+    /// How long the scanned workspaces have been inactive.
+    pub ages: Option<AgeStats>,
+    /// Organizations that couldn't be scanned; the rest of the results leave them out.
+    pub errors: Vec<OrgError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrgError {
+    pub org: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaintextSecretFinding {
+    pub org: String,
+    pub workspace: String,
+    pub key: String,
+    pub category: String,
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrgResults {
+    pub name: String,
+    pub total: usize,
+    pub stale: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StaleWorkspace {
+    pub org: String,
+    pub name: String,
+    pub id: String,
+    pub last_activity: Option<String>,
+    pub inactive_for: Option<String>,
+    pub no_activity_data: bool,
+    /// How to reach the workspace's owners, e.g. `alice@example.com (@alice)`.
+    pub contacts: Vec<String>,
+}
+
+impl ScanResults {
+    /// `totals` counts the workspaces scanned per organization.
+    pub fn new(command: &str, totals: &OrgTotals, stale: &[Value], policy: &Policy) -> ScanResults {
+        ScanResults::at(command, totals, stale, policy, Utc::now())
+    }
+
+    /// The results as of `now`, which ages and the generation time are based on.
+    pub fn at(command: &str, totals: &OrgTotals, stale: &[Value], policy: &Policy, now: DateTime<Utc>) -> ScanResults {
+        let mut organizations: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for (org, total) in totals {
+            organizations.entry(org).or_default().0 += total;
+        }
+        for workspace in stale {
+            organizations.entry(tfe::workspace_org(workspace)).or_default().1 += 1;
+        }
+
+        ScanResults {
+            command: command.to_string(),
+            generated_at: now.to_rfc3339(),
+            threshold_days: policy.threshold_days,
+            total_workspaces: totals.values().sum(),
+            stale_count: stale.len(),
+            organizations: organizations.into_iter()
+                .map(|(name, (total, stale))| OrgResults { name: name.to_string(), total, stale })
+                .collect(),
+            stale: stale.iter()
+                .map(|workspace| {
+                    let last_activity = workspace["attributes"]["last-activity-at"].as_str();
+                    StaleWorkspace {
+                        org: tfe::workspace_org(workspace).to_string(),
+                        name: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+                        id: workspace["id"].as_str().unwrap_or("").to_string(),
+                        last_activity: last_activity.map(str::to_string),
+                        inactive_for: last_activity.and_then(|raw| timefmt::age(raw, now)),
+                        no_activity_data: staleness::lacks_activity_data(workspace),
+                        contacts: Vec::new(),
+                    }
+                })
+                .collect(),
+            opted_out: Vec::new(),
+            plaintext_secrets: Vec::new(),
+            ages: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_opted_out(mut self, opted_out: &[Value]) -> ScanResults {
+        self.opted_out = opted_out.iter()
+            .map(|workspace| format!("{}/{}", tfe::workspace_org(workspace), workspace["attributes"]["name"].as_str().unwrap_or("")))
+            .collect();
+        self
+    }
+
+    pub fn with_errors(mut self, errors: &BTreeMap<String, String>) -> ScanResults {
+        self.errors = errors.iter().map(|(org, error)| OrgError { org: org.clone(), error: error.clone() }).collect();
+        self
+    }
+
+    pub fn with_ages(mut self, ages: Option<AgeStats>) -> ScanResults {
+        self.ages = ages;
+        self
+    }
+
+    /// `contacts` maps workspace IDs to the contacts of their owners, as in the report context.
+    pub fn with_contacts(mut self, contacts: &HashMap<String, Vec<Contact>>) -> ScanResults {
+        for workspace in &mut self.stale {
+            workspace.contacts = contacts.get(&workspace.id).into_iter().flatten().map(Contact::describe).collect();
+        }
+        self
+    }
+
+    /// `secrets` maps workspace IDs to their findings, as in the report context.
+    pub fn with_plaintext_secrets(mut self, stale: &[Value], secrets: &HashMap<String, Vec<PlaintextSecret>>) -> ScanResults {
+        self.plaintext_secrets = stale.iter()
+            .flat_map(|workspace| {
+                let findings = secrets.get(workspace["id"].as_str().unwrap_or("")).map(Vec::as_slice).unwrap_or_default();
+                findings.iter().map(move |secret| PlaintextSecretFinding {
+                    org: tfe::workspace_org(workspace).to_string(),
+                    workspace: workspace["attributes"]["name"].as_str().unwrap_or("").to_string(),
+                    key: secret.key.clone(),
+                    category: secret.category.clone(),
+                    kind: secret.kind.clone(),
+                })
+            })
+            .collect();
+        self
+    }
+}
+
+/// Renders a Handlebars template with the run's results. Templates are plain text, so
+/// nothing is HTML-escaped.
+pub fn render(template: &str, results: &ScanResults) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.set_strict_mode(true);
+    Ok(handlebars.render_template(template, results)?)
+}
+
+/// The template of a channel: its own file if configured, otherwise the shared one, otherwise
+/// the built-in default.
+pub fn template_for(config: &NotificationConfig, channel_template: &Option<std::path::PathBuf>) -> Result<String, Box<dyn Error + Send + Sync>> {
+    match channel_template.as_ref().or(config.template.as_ref()) {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| format!("cannot read notification template {}: {}", path.display(), e).into()),
+        None => Ok(i18n::current().notification_template().to_string()),
+    }
+}
+
+async fn post_webhook(url: &str, body: &Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // reqwest errors carry the URL, which is the webhook's credential
+    let response = reqwest::Client::new().post(url).json(body).send().await
+        .map_err(|e| format!("webhook {}: {}", redact::url(url), e.without_url()))?;
+    if !response.status().is_success() {
+        return Err(format!("webhook {} returned {}", redact::url(url), response.status()).into());
+    }
+    Ok(())
+}
+
+/// Whether a webhook's host answers at all, without posting to it. Any HTTP status counts;
+/// only connection failures are errors.
+pub async fn probe_url(url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let client = reqwest::Client::builder().timeout(std::time::Duration::from_secs(10)).build()?;
+    client.head(url).send().await
+        .map_err(|e| format!("webhook {}: {}", redact::url(url), e.without_url()))?;
+    Ok(())
+}
+
+/// Sends a plain message to every configured chat channel. Errors if there is none.
+pub async fn notify_text(config: &NotificationConfig, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let channels: Vec<&Secret> = config.slack_webhook.iter().chain(config.teams_webhook.iter()).collect();
+    if channels.is_empty() {
+        return Err("no notification channels configured".into());
+    }
+    for url in channels {
+        post_webhook(url.expose(), &json!({ "text": text })).await?;
+    }
+    Ok(())
+}
+
+/// Sends the run's results to every configured chat channel.
+pub async fn notify(config: &NotificationConfig, results: &ScanResults) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(url) = &config.slack_webhook {
+        let text = render(&template_for(config, &config.slack_template)?, results)?;
+        post_webhook(url.expose(), &json!({ "text": text })).await?;
+    }
+    if let Some(url) = &config.teams_webhook {
+        let text = render(&template_for(config, &config.teams_template)?, results)?;
+        post_webhook(url.expose(), &json!({ "text": text })).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    fn results() -> ScanResults {
+        let workspaces = vec![
+            json!({
+                "id": "ws-1",
+                "attributes": { "name": "old", "last-activity-at": "2020-01-01T00:00:00Z" },
+                "relationships": { "organization": { "data": { "id": "acme" } } }
+            }),
+            json!({
+                "id": "ws-2",
+                "attributes": { "name": "fresh", "last-activity-at": Utc::now().to_rfc3339() },
+                "relationships": { "organization": { "data": { "id": "acme" } } }
+            }),
+        ];
+        ScanResults::new("scan", &tfe::count_by_org(&workspaces), &workspaces[..1], &Policy::default())
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let text = render(DEFAULT_TEMPLATE, &results()).unwrap();
+
+        assert!(text.starts_with("TFE cleanup scan: 1 of 2 workspaces stale (no activity for 90 days)\n"));
+        assert!(text.contains("acme: 1 of 2 stale\n"));
+        assert!(text.contains("- acme/old, inactive for "));
+        assert!(!text.contains("owners:"));
+
+        let contacts = HashMap::from([("ws-1".to_string(), vec![
+            Contact { email: Some("alice@example.com".to_string()), slack: Some("@alice".to_string()) },
+            Contact { email: None, slack: Some("@bob".to_string()) },
+        ])]);
+        let text = render(DEFAULT_TEMPLATE, &results().with_contacts(&contacts)).unwrap();
+        assert!(text.contains("; owners: alice@example.com (@alice), @bob\n"));
+    }
+
+    #[test]
+    fn test_render_translated_template() {
+        let text = render(i18n::Lang::De.notification_template(), &results()).unwrap();
+
+        assert!(text.starts_with("TFE-Bereinigung scan: 1 von 2 Workspaces veraltet (seit 90 Tagen ohne Aktivität)\n"));
+        assert!(text.contains("acme: 1 von 2 veraltet\n"));
+        assert!(text.contains("- acme/old, inaktiv seit "));
+    }
+
+    #[test]
+    fn test_render_custom_template() {
+        let template = "{{#each stale}}<https://tfe.example.com/app/{{org}}/workspaces/{{name}}|{{name}}> {{/each}}";
+        assert_eq!(render(template, &results()).unwrap(), "<https://tfe.example.com/app/acme/workspaces/old|old> ");
+        assert!(render("{{no_such_field}}", &results()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_notify_posts_rendered_text() {
+        let slack = mock("POST", "/slack-hook")
+            .match_body(Matcher::PartialJson(json!({ "text": "1 stale" })))
+            .with_status(200)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("slack.hbs");
+        fs::write(&template, "{{stale_count}} stale").unwrap();
+        let config = NotificationConfig {
+            slack_webhook: Some(Secret::new(format!("{}/slack-hook", server_url()))),
+            slack_template: Some(template),
+            ..NotificationConfig::default()
+        };
+
+        notify(&config, &results()).await.unwrap();
+        slack.assert();
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::OrganizationsConfig;
+use crate::tfe::{self, TfeClient};
+use regex::Regex;
+use std::error::Error;
+
+/// Decides which organizations a run covers: those selected on the command line (all visible
+/// ones if none are), narrowed by `--org-regex` and the config's allow and deny lists.
+#[derive(Debug, Default)]
+pub struct OrgFilter {
+    pub selected: Vec<String>,
+    pub pattern: Option<Regex>,
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl OrgFilter {
+    pub fn new(selected: Vec<String>, pattern: Option<Regex>, config: &OrganizationsConfig) -> OrgFilter {
+        OrgFilter { selected, pattern, allow: config.allow.clone(), deny: config.deny.clone() }
+    }
+
+    /// Why an organization is left out of the run, or `None` if it is covered.
+    pub fn skip_reason(&self, org: &str) -> Option<String> {
+        if self.deny.iter().any(|denied| denied == org) {
+            return Some("in the config's deny list".to_string());
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|allowed| allowed == org) {
+            return Some("not in the config's allow list".to_string());
+        }
+        match &self.pattern {
+            Some(pattern) if !pattern.is_match(org) => Some(format!("does not match --org-regex {}", pattern)),
+            _ => None,
+        }
+    }
+}
+
+/// The organizations the run covers. Skipped organizations are reported on stderr with the reason.
+pub async fn discover(client: &TfeClient, filter: &OrgFilter) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let candidates = if filter.selected.is_empty() {
+        tfe::list_organizations(client).await?
+            .iter()
+            .filter_map(|org| org["attributes"]["name"].as_str().map(str::to_string))
+            .collect()
+    } else {
+        filter.selected.clone()
+    };
+
+    Ok(candidates.into_iter()
+        .filter(|org| match filter.skip_reason(org) {
+            Some(reason) => {
+                eprintln!("Skipping organization {}: {}", org, reason);
+                false
+            }
+            None => true,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::server_url;
+
+    #[test]
+    fn test_skip_reason() {
+        let filter = OrgFilter {
+            pattern: Some(Regex::new("^acme-").unwrap()),
+            allow: vec!["acme-prod".to_string(), "acme-dev".to_string(), "globex".to_string()],
+            deny: vec!["acme-dev".to_string()],
+            ..OrgFilter::default()
+        };
+
+        assert_eq!(filter.skip_reason("acme-prod"), None);
+        assert_eq!(filter.skip_reason("acme-dev").unwrap(), "in the config's deny list");
+        assert_eq!(filter.skip_reason("acme-test").unwrap(), "not in the config's allow list");
+        assert_eq!(filter.skip_reason("globex").unwrap(), "does not match --org-regex ^acme-");
+        assert_eq!(OrgFilter::default().skip_reason("anything"), None);
+    }
+
+    #[tokio::test]
+    async fn test_discover_filters_selected_organizations() {
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let filter = OrgFilter {
+            selected: vec!["discover-ours".to_string(), "discover-partner".to_string()],
+            deny: vec!["discover-partner".to_string()],
+            ..OrgFilter::default()
+        };
+
+        assert_eq!(discover(&client, &filter).await.unwrap(), vec!["discover-ours"]);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config::{Config, RemoteOverridesConfig};
+use crate::redact;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// How long downloading the overrides may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Exclusions and owners kept outside the config file, e.g. in a team's spreadsheet. Both are
+/// keyed by `<org>/<name>`, as names repeat across organizations.
+#[derive(Debug, Default, PartialEq)]
+pub struct Overrides {
+    /// Workspaces never to flag.
+    pub exclude: Vec<String>,
+    pub owners: BTreeMap<String, Vec<String>>,
+}
+
+/// Whether a cell of the `Exclude` column marks the workspace as excluded.
+fn is_yes(cell: &str) -> bool {
+    matches!(cell.trim().to_ascii_lowercase().as_str(), "yes" | "y" | "true" | "x" | "1")
+}
+
+/// Reads a CSV with a `Workspace` column and optional `Organization`, `Exclude` and `Owner`
+/// columns, matched case-insensitively. Without an organization, the workspace must be written
+/// as `<org>/<name>`. Several owners in one cell are separated by `;` or `,`.
+pub fn parse(csv: &str) -> Result<Overrides, Box<dyn Error + Send + Sync>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(csv.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header.trim().eq_ignore_ascii_case(name));
+    let workspace = column("workspace").ok_or("no Workspace column")?;
+    let (organization, exclude, owner) = (column("organization"), column("exclude"), column("owner"));
+
+    let mut overrides = Overrides::default();
+    for record in reader.records() {
+        let record = record?;
+        let workspace = record.get(workspace).unwrap_or("").trim();
+        if workspace.is_empty() {
+            continue;
+        }
+        let name = match organization.and_then(|index| record.get(index)).map(str::trim) {
+            Some(org) if !org.is_empty() => format!("{}/{}", org, workspace),
+            _ if workspace.contains('/') => workspace.to_string(),
+            _ => return Err(format!("line {}: no organization for workspace {}; add an Organization column or write it as <org>/{}",
+                record.position().map_or(0, |position| position.line()), workspace, workspace).into()),
+        };
+        if exclude.and_then(|index| record.get(index)).is_some_and(is_yes) {
+            overrides.exclude.push(name.clone());
+        }
+        let owners: Vec<String> = owner.and_then(|index| record.get(index)).unwrap_or("")
+            .split([';', ','])
+            .map(str::trim)
+            .filter(|owner| !owner.is_empty())
+            .map(str::to_string)
+            .collect();
+        if !owners.is_empty() {
+            overrides.owners.insert(name, owners);
+        }
+    }
+    Ok(overrides)
+}
+
+/// Downloads and parses the overrides CSV.
+pub async fn fetch(remote: &RemoteOverridesConfig) -> Result<Overrides, Box<dyn Error + Send + Sync>> {
+    let url = remote.url.expose();
+    let mut request = reqwest::Client::builder().timeout(TIMEOUT).build()?.get(url);
+    if let Some(authorization) = &remote.authorization {
+        request = request.header(reqwest::header::AUTHORIZATION, authorization.expose());
+    }
+    let response = request.send().await.map_err(|e| format!("overrides {}: {}", redact::url(url), e.without_url()))?;
+    if !response.status().is_success() {
+        return Err(format!("overrides {} returned {}", redact::url(url), response.status()).into());
+    }
+    parse(&response.text().await?).map_err(|e| format!("invalid overrides CSV {}: {}", redact::url(url), e).into())
+}
+
+impl Overrides {
+    /// Adds the overrides to the config's: exclusions to the excluded workspaces, owners
+    /// replacing those configured locally for the same workspace.
+    pub fn merge_into(self, config: &mut Config) {
+        config.exclude_workspaces.extend(self.exclude);
+        config.owners.extend(self.owners);
+    }
+}
+
+/// Fetches the configured remote overrides, if any, and merges them into `config`. A sheet
+/// that can't be read fails the run rather than dropping exclusions someone relies on.
+pub async fn apply(config: &mut Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(remote) = config.remote_overrides.clone() else {
+        return Ok(());
+    };
+    let overrides = fetch(&remote).await?;
+    eprintln!("Loaded {} exclusions and {} owners from {}", overrides.exclude.len(), overrides.owners.len(), redact::url(remote.url.expose()));
+    overrides.merge_into(config);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redact::Secret;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_parse() {
+        let overrides = parse("Team,Organization,workspace,Exclude,Owner\n\
+            payments,acme,billing-prod,yes,payments; sre\n\
+            payments,acme-eu,billing-prod,,payments-eu\n\
+            payments,,acme/billing-dev,,payments\n\
+            data,acme,etl (old),x,\n\
+            data,acme,,yes,nobody\n").unwrap();
+
+        assert_eq!(overrides.exclude, vec!["acme/billing-prod", "acme/etl (old)"]);
+        assert_eq!(overrides.owners["acme/billing-prod"], vec!["payments", "sre"]);
+        assert_eq!(overrides.owners["acme-eu/billing-prod"], vec!["payments-eu"]);
+        assert_eq!(overrides.owners["acme/billing-dev"], vec!["payments"]);
+        assert!(parse("Name\nbilling-prod\n").is_err());
+        let error = parse("Workspace,Exclude\nbilling-prod,yes\n").unwrap_err();
+        assert!(error.to_string().starts_with("line 2: no organization for workspace billing-prod"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_apply_merges_remote_overrides() {
+        let _sheet = mock("GET", "/sheets/keep.csv")
+            .match_header("authorization", "Bearer sheet-token")
+            .with_status(200)
+            .with_body("Workspace,Exclude,Owner\nacme/legacy.app,yes,platform\n")
+            .create();
+        let mut config = Config {
+            exclude: vec!["^prod-".to_string()],
+            remote_overrides: Some(RemoteOverridesConfig {
+                url: Secret::new(format!("{}/sheets/keep.csv", server_url())),
+                authorization: Some(Secret::new("Bearer sheet-token")),
+            }),
+            ..Config::default()
+        };
+
+        apply(&mut config).await.unwrap();
+
+        assert_eq!(config.exclude, vec!["^prod-"]);
+        assert_eq!(config.exclude_workspaces, vec!["acme/legacy.app"]);
+        assert_eq!(config.owners["acme/legacy.app"], vec!["platform"]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::kill_switch::{self, KillSwitch};
+use crate::tfe::{self, TfeClient};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use std::error::Error;
+
+pub struct PlanExportOptions {
+    pub older_than_days: i64,
+    pub dry_run: bool,
+    pub per_workspace_limit: Option<usize>,
+}
+
+/// Returns the runs created before `cutoff`. Runs without a parseable `created-at` are kept out.
+fn runs_older_than(runs: &[Value], cutoff: DateTime<Utc>) -> Vec<&Value> {
+    runs.iter()
+        .filter(|run| {
+            let created_at = run["attributes"]["created-at"].as_str().unwrap_or("");
+            match DateTime::parse_from_rfc3339(created_at) {
+                Ok(created_at) => created_at < cutoff,
+                Err(_) => false,
+            }
+        })
+        .collect()
+}
+
+fn export_ids(plan: &Value) -> Vec<String> {
+    plan["data"]["relationships"]["exports"]["data"]
+        .as_array()
+        .map(|exports| {
+            exports.iter()
+                .filter_map(|export| export["id"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Deletes the plan exports of runs older than the configured age in every workspace of `org`.
+/// Returns the number of exports deleted (or that would be deleted in dry-run mode).
+pub async fn cleanup_plan_exports(
+    client: &TfeClient,
+    kill_switch: Option<&KillSwitch>,
+    org: &str,
+    options: &PlanExportOptions,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let cutoff = Utc::now() - Duration::days(options.older_than_days);
+    let mut total = 0;
+
+    for workspace in tfe::list_workspaces(client, org).await? {
+        let workspace_id = workspace["id"].as_str().unwrap_or("");
+        let workspace_name = workspace["attributes"]["name"].as_str().unwrap_or(workspace_id);
+        let runs = client.get_all(&format!("/workspaces/{}/runs", workspace_id)).await?;
+        let mut deleted_in_workspace = 0;
+
+        'runs: for run in runs_older_than(&runs, cutoff) {
+            let plan_id = match run["relationships"]["plan"]["data"]["id"].as_str() {
+                Some(plan_id) => plan_id,
+                None => continue,
+            };
+            let plan = client.get(&format!("/plans/{}", plan_id)).await?;
+
+            for export_id in export_ids(&plan) {
+                if let Some(limit) = options.per_workspace_limit {
+                    if deleted_in_workspace >= limit {
+                        eprintln!("Reached limit of {} plan exports for workspace {}", limit, workspace_name);
+                        break 'runs;
+                    }
+                }
+
+                if options.dry_run {
+                    println!("[dry-run] Would delete plan export {} of run {} in {}/{}",
+                        export_id, run["id"].as_str().unwrap_or(""), org, workspace_name);
+                } else {
+                    kill_switch::check(kill_switch).await?;
+                    client.delete(&format!("/plan-exports/{}", export_id)).await?;
+                    println!("Deleted plan export {} of run {} in {}/{}",
+                        export_id, run["id"].as_str().unwrap_or(""), org, workspace_name);
+                }
+                deleted_in_workspace += 1;
+            }
+        }
+
+        total += deleted_in_workspace;
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_runs_older_than() {
+        let runs = vec![
+            json!({ "id": "run-old", "attributes": { "created-at": "2020-01-01T00:00:00Z" } }),
+            json!({ "id": "run-new", "attributes": { "created-at": Utc::now().to_rfc3339() } }),
+            json!({ "id": "run-missing", "attributes": {} }),
+        ];
+
+        let old = runs_older_than(&runs, Utc::now() - Duration::days(30));
+
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0]["id"], "run-old");
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_plan_exports_respects_limit() {
+        let _workspaces = mock("GET", "/api/v2/organizations/export-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({
+                "data": [{ "id": "ws-export", "attributes": { "name": "app" } }]
+            }).to_string())
+            .create();
+        let _runs = mock("GET", "/api/v2/workspaces/ws-export/runs")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({
+                "data": [{
+                    "id": "run-export",
+                    "attributes": { "created-at": "2020-01-01T00:00:00Z" },
+                    "relationships": { "plan": { "data": { "id": "plan-export" } } }
+                }]
+            }).to_string())
+            .create();
+        let _plan = mock("GET", "/api/v2/plans/plan-export")
+            .with_status(200)
+            .with_body(json!({
+                "data": {
+                    "id": "plan-export",
+                    "relationships": { "exports": { "data": [
+                        { "id": "pe-1", "type": "plan-exports" },
+                        { "id": "pe-2", "type": "plan-exports" }
+                    ] } }
+                }
+            }).to_string())
+            .create();
+        let delete = mock("DELETE", "/api/v2/plan-exports/pe-1")
+            .with_status(204)
+            .expect(1)
+            .create();
+        let skipped = mock("DELETE", "/api/v2/plan-exports/pe-2")
+            .with_status(204)
+            .expect(0)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let options = PlanExportOptions { older_than_days: 30, dry_run: false, per_workspace_limit: Some(1) };
+        let deleted = cleanup_plan_exports(&client, None, "export-org", &options).await.unwrap();
+
+        assert_eq!(deleted, 1);
+        delete.assert();
+        skipped.assert();
+    }
+}
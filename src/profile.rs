@@ -0,0 +1,168 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The parts of a run `--profile` times. Phases overlap with the API calls made in them, and
+/// listing and filtering alternate page by page as workspaces stream in.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Waiting for pages of workspaces, and for the enrichment of those listed so far.
+    Listing,
+    /// Looking up more about workspaces: team scope, human activity and report columns.
+    Enrichment,
+    /// Evaluating workspaces against the policy.
+    Filtering,
+    /// Writing the CSV, report sinks and notifications.
+    Reporting,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [Phase::Listing, Phase::Enrichment, Phase::Filtering, Phase::Reporting];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::Listing => "listing",
+            Phase::Enrichment => "enrichment",
+            Phase::Filtering => "filtering",
+            Phase::Reporting => "reporting",
+        }
+    }
+}
+
+/// Where the time of a run went, collected as it happens like the run summary's counters.
+#[derive(Debug)]
+pub struct Profile {
+    /// Microseconds spent in each phase, indexed like `Phase::ALL`.
+    phases: [AtomicU64; 4],
+    /// Microseconds each API request took, retries of rate-limited requests counted separately.
+    latencies: Mutex<Vec<u64>>,
+}
+
+impl Profile {
+    pub const fn new() -> Profile {
+        Profile {
+            phases: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+            latencies: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, phase: Phase, took: Duration) {
+        self.phases[phase as usize].fetch_add(took.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_request(&self, took: Duration) {
+        self.latencies.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(took.as_micros() as u64);
+    }
+
+    pub fn report(&self, started: Instant) -> ProfileReport {
+        let ms = |micros: u64| (micros as f64 / 100.0).round() / 10.0;
+        let phases = Phase::ALL.iter()
+            .map(|phase| (phase.name(), ms(self.phases[*phase as usize].load(Ordering::Relaxed))))
+            .collect();
+
+        let mut latencies = self.latencies.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        latencies.sort_unstable();
+        // Nearest rank, so every percentile is a latency that was measured
+        let percentile = |p: usize| match latencies.len() {
+            0 => 0.0,
+            n => ms(latencies[((p * n).div_ceil(100)).clamp(1, n) - 1]),
+        };
+        let api = ApiLatency {
+            requests: latencies.len(),
+            p50_ms: percentile(50),
+            p90_ms: percentile(90),
+            p99_ms: percentile(99),
+            max_ms: latencies.last().copied().map(ms).unwrap_or(0.0),
+        };
+
+        ProfileReport { duration_ms: ms(started.elapsed().as_micros() as u64), phases, api }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile::new()
+    }
+}
+
+/// Written by `--profile`. Times are in milliseconds.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ProfileReport {
+    pub duration_ms: f64,
+    pub phases: BTreeMap<&'static str, f64>,
+    pub api: ApiLatency,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ApiLatency {
+    pub requests: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// The profile of this run.
+static PROFILE: Profile = Profile::new();
+
+/// Whether this run is profiled. Until enabled nothing is recorded, so runs without `--profile`
+/// don't hold a latency for every request.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn record(phase: Phase, took: Duration) {
+    if enabled() {
+        PROFILE.record(phase, took);
+    }
+}
+
+pub fn record_request(took: Duration) {
+    if enabled() {
+        PROFILE.record_request(took);
+    }
+}
+
+/// Awaits `future`, counting the time it takes towards `phase`.
+pub async fn timed<F: Future>(phase: Phase, future: F) -> F::Output {
+    let started = Instant::now();
+    let output = future.await;
+    record(phase, started.elapsed());
+    output
+}
+
+/// The profile of this run so far.
+pub fn report(started: Instant) -> ProfileReport {
+    PROFILE.report(started)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let profile = Profile::new();
+        profile.record(Phase::Listing, Duration::from_millis(1500));
+        profile.record(Phase::Listing, Duration::from_millis(500));
+        profile.record(Phase::Filtering, Duration::from_micros(2340));
+        for millis in (1..=100).rev() {
+            profile.record_request(Duration::from_millis(millis));
+        }
+
+        let report = profile.report(Instant::now());
+        assert_eq!(report.phases, BTreeMap::from([("listing", 2000.0), ("enrichment", 0.0), ("filtering", 2.3), ("reporting", 0.0)]));
+        assert_eq!(report.api, ApiLatency { requests: 100, p50_ms: 50.0, p90_ms: 90.0, p99_ms: 99.0, max_ms: 100.0 });
+
+        assert_eq!(Profile::new().report(Instant::now()).api.p99_ms, 0.0);
+    }
+}
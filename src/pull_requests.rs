@@ -0,0 +1,204 @@
+use crate::branches::{self, Kind, Providers};
+use crate::config::PullRequestsConfig;
+use crate::redact;
+use crate::run_summary::{Counter, Counters};
+use crate::scan::{self, Scan};
+use crate::tfe;
+use regex::Regex;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// How long a pull request lookup may take before it is given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What became of a workspace's pull request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Open,
+    Merged,
+    Closed,
+}
+
+impl State {
+    pub fn label(&self) -> &'static str {
+        match self {
+            State::Open => "open",
+            State::Merged => "merged",
+            State::Closed => "closed",
+        }
+    }
+}
+
+/// The pull request number in a workspace name, from the pattern's first group.
+pub fn number(pattern: &Regex, name: &str) -> Option<u64> {
+    pattern.captures(name)?.get(1)?.as_str().parse().ok()
+}
+
+/// Reads the state of pull request (or GitLab merge request) `number` of `repository`.
+pub async fn state(
+    http: &reqwest::Client,
+    kind: Kind,
+    provider: &branches::Provider,
+    repository: &str,
+    number: u64,
+) -> Result<State, Box<dyn Error + Send + Sync>> {
+    let number = number.to_string();
+    let url = match kind {
+        Kind::GitHub => {
+            let segments: Vec<&str> = std::iter::once("repos").chain(repository.split('/')).chain(["pulls", &number]).collect();
+            branches::endpoint(&provider.api, &segments)?
+        }
+        Kind::GitLab => branches::endpoint(&provider.api, &["projects", repository, "merge_requests", &number])?,
+    };
+    let response = branches::get(http, kind, provider, url.clone()).send().await.map_err(|e| e.without_url())?;
+    match response.status() {
+        status if status.is_success() => {}
+        StatusCode::NOT_FOUND => return Err(format!("pull request {} of {} not found", number, repository).into()),
+        status => return Err(format!("{} returned {}", url.path(), status).into()),
+    }
+
+    let body: Value = response.json().await?;
+    Ok(match kind {
+        Kind::GitHub if body["state"] == "open" => State::Open,
+        Kind::GitHub if body["merged"] == true || !body["merged_at"].is_null() => State::Merged,
+        Kind::GitHub => State::Closed,
+        Kind::GitLab => match body["state"].as_str() {
+            Some("merged") => State::Merged,
+            Some("closed") => State::Closed,
+            _ => State::Open,
+        },
+    })
+}
+
+/// Moves the scan's pull request workspaces whose pull request is merged or closed to its stale
+/// workspaces, however recently they were active, and returns the rule that flagged each, by
+/// workspace id, counting them in `counters`. Workspaces whose pull request can't be checked
+/// are kept, with a warning.
+pub async fn reap(
+    scan: &mut Scan,
+    config: &PullRequestsConfig,
+    pattern: &Regex,
+    providers: &Providers,
+    counters: &Counters,
+) -> HashMap<String, String> {
+    let mut reaped = HashMap::new();
+    let http = match reqwest::Client::builder().timeout(TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("Warning: cannot check pull requests: {}", e);
+            return reaped;
+        }
+    };
+    let mut checked: HashMap<(String, u64), Option<State>> = HashMap::new();
+
+    for workspace in std::mem::take(&mut scan.pull_requests) {
+        let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+        let Some(number) = number(pattern, name) else { continue };
+        let vcs = &workspace["attributes"]["vcs-repo"];
+        let (service, repository) = match vcs["identifier"].as_str() {
+            Some(identifier) => (vcs["service-provider"].as_str().unwrap_or(""), identifier),
+            None => match &config.repository {
+                Some(repository) => (config.provider.as_str(), repository.as_str()),
+                None => {
+                    eprintln!("Warning: {} has no VCS repository and pull_requests.repository isn't set", name);
+                    continue;
+                }
+            },
+        };
+        let Some((kind, provider)) = providers.for_service(service) else {
+            eprintln!("Warning: cannot check the pull request of {}: no token for {}", name, service);
+            continue;
+        };
+
+        let key = (repository.to_string(), number);
+        let state = match checked.get(&key) {
+            Some(state) => *state,
+            None => {
+                let state = match self::state(&http, kind, provider, repository, number).await {
+                    Ok(state) => Some(state),
+                    Err(e) => {
+                        eprintln!("Warning: cannot check the pull request of {}: {}", name, redact::scrub(&e.to_string()));
+                        None
+                    }
+                };
+                checked.insert(key, state);
+                state
+            }
+        };
+
+        if let Some(state @ (State::Merged | State::Closed)) = state {
+            let rule = format!("pull request #{} of {} is {}", number, repository, state.label());
+            eprintln!("Flagging {}/{}: {}", tfe::workspace_org(&workspace), name, rule);
+            counters.increment(Counter::Flagged);
+            reaped.insert(workspace["id"].as_str().unwrap_or("").to_string(), rule);
+            scan.stale.push(workspace);
+        }
+    }
+    scan::sort_by_org_and_name(&mut scan.stale);
+    reaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use branches::Provider;
+    use mockito::{mock, server_url};
+    use serde_json::json;
+
+    #[test]
+    fn test_number() {
+        let pattern = Regex::new(r"-pr-(\d+)$").unwrap();
+        assert_eq!(number(&pattern, "app-pr-1234"), Some(1234));
+        assert_eq!(number(&pattern, "app-prod"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reap() {
+        let workspace = |name: &str, vcs: Value| json!({ "id": format!("ws-{}", name), "attributes": { "name": name, "vcs-repo": vcs } });
+        let github = json!({ "identifier": "acme/reaper-app", "service-provider": "github" });
+        let _merged = mock("GET", "/reaper-github/repos/acme/reaper-app/pulls/1")
+            .with_status(200)
+            .with_body(json!({ "state": "closed", "merged": true }).to_string())
+            .create();
+        let _open = mock("GET", "/reaper-github/repos/acme/reaper-app/pulls/2")
+            .with_status(200)
+            .with_body(json!({ "state": "open", "merged": false }).to_string())
+            .create();
+        let _closed = mock("GET", "/reaper-gitlab/projects/acme%2Fcli-app/merge_requests/3")
+            .match_header("private-token", "gl-token")
+            .with_status(200)
+            .with_body(json!({ "state": "closed" }).to_string())
+            .create();
+
+        let mut scan = Scan {
+            pull_requests: vec![
+                workspace("app-pr-1", github.clone()),
+                workspace("app-pr-2", github),
+                workspace("cli-pr-3", Value::Null),
+            ],
+            ..Scan::default()
+        };
+        let config = PullRequestsConfig {
+            pattern: r"-pr-(\d+)$".to_string(),
+            repository: Some("acme/cli-app".to_string()),
+            provider: "gitlab".to_string(),
+        };
+        let providers = Providers {
+            github: Some(Provider { api: format!("{}/reaper-github", server_url()), token: "gh-token".to_string() }),
+            gitlab: Some(Provider { api: format!("{}/reaper-gitlab", server_url()), token: "gl-token".to_string() }),
+        };
+        let counters = Counters::new();
+        let reaped = reap(&mut scan, &config, &Regex::new(&config.pattern).unwrap(), &providers, &counters).await;
+
+        let stale: Vec<&str> = scan.stale.iter().map(|ws| ws["attributes"]["name"].as_str().unwrap()).collect();
+        assert_eq!(stale, vec!["app-pr-1", "cli-pr-3"]);
+        assert_eq!(reaped.len(), 2);
+        assert_eq!(counters.summary(std::time::Instant::now()).workspaces.flagged, 2);
+        assert_eq!(reaped["ws-app-pr-1"], "pull request #1 of acme/reaper-app is merged");
+        assert_eq!(reaped["ws-cli-pr-3"], "pull request #3 of acme/cli-app is closed");
+        assert!(scan.pull_requests.is_empty());
+    }
+}
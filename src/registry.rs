@@ -0,0 +1,290 @@
+use crate::kill_switch::{self, KillSwitch};
+use crate::tfe::{self, TfeClient};
+use chrono::{DateTime, Duration, Utc};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::Read;
+
+const LOCKFILE_NAME: &str = ".terraform.lock.hcl";
+
+pub struct ProviderOptions {
+    pub older_than_days: i64,
+    pub dry_run: bool,
+    pub delete_unused_keys: bool,
+}
+
+/// What a provider cleanup deleted (or would delete in dry-run mode).
+#[derive(Debug, Default, PartialEq)]
+pub struct ProviderCleanup {
+    pub versions: usize,
+    pub keys: usize,
+}
+
+/// The `(namespace/name, version)` of every provider pinned in a dependency lock file.
+/// The registry hostname is ignored since TFE can be reached under several names.
+fn lockfile_references(contents: &str) -> Vec<(String, String)> {
+    let block = Regex::new(r#"provider\s+"([^"]+)"\s*\{\s*version\s*=\s*"([^"]+)""#).unwrap();
+
+    block.captures_iter(contents)
+        .filter_map(|captures| {
+            let source: Vec<&str> = captures[1].rsplitn(3, '/').collect();
+            match source.as_slice() {
+                [name, namespace, ..] => Some((format!("{}/{}", namespace, name).to_lowercase(), captures[2].to_string())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Contents of every lock file in a gzipped configuration version tarball.
+fn lockfiles_in_archive(bytes: &[u8]) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+    let mut lockfiles = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.file_name().and_then(|name| name.to_str()) == Some(LOCKFILE_NAME) {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            lockfiles.push(contents);
+        }
+    }
+
+    Ok(lockfiles)
+}
+
+/// Provider versions pinned by the lock file in the configuration of each workspace's current run.
+/// Best effort: workspaces whose configuration can't be read are skipped with a warning.
+async fn referenced_versions(client: &TfeClient, org: &str) -> Result<HashSet<(String, String)>, Box<dyn Error + Send + Sync>> {
+    let mut referenced = HashSet::new();
+
+    for workspace in tfe::list_workspaces(client, org).await? {
+        let run_id = match workspace["relationships"]["current-run"]["data"]["id"].as_str() {
+            Some(run_id) => run_id,
+            None => continue,
+        };
+        let lockfiles = async {
+            let run = client.get(&format!("/runs/{}", run_id)).await?;
+            let config_id = run["data"]["relationships"]["configuration-version"]["data"]["id"]
+                .as_str()
+                .ok_or("run has no configuration version")?;
+            let bytes = client.download(&client.url(&format!("/configuration-versions/{}/download", config_id))).await?;
+            lockfiles_in_archive(&bytes)
+        }.await;
+
+        match lockfiles {
+            Ok(lockfiles) => referenced.extend(lockfiles.iter().flat_map(|lockfile| lockfile_references(lockfile))),
+            Err(e) => eprintln!("Warning: cannot read the lock file of {}/{}: {}",
+                org, workspace["attributes"]["name"].as_str().unwrap_or(""), e),
+        }
+    }
+
+    Ok(referenced)
+}
+
+/// Versions created before `cutoff` that no lock file references. The newest version of a
+/// provider is always kept.
+fn stale_versions<'a>(
+    versions: &'a [Value],
+    cutoff: DateTime<Utc>,
+    provider: &str,
+    referenced: &HashSet<(String, String)>,
+) -> Vec<&'a Value> {
+    let created_at = |version: &Value| {
+        version["attributes"]["created-at"].as_str().and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+    };
+    let newest = versions.iter().filter_map(created_at).max();
+
+    versions.iter()
+        .filter(|version| match created_at(version) {
+            Some(created) => created < cutoff && Some(created) != newest,
+            None => false,
+        })
+        .filter(|version| {
+            let number = version["attributes"]["version"].as_str().unwrap_or("").to_string();
+            !referenced.contains(&(provider.to_lowercase(), number))
+        })
+        .collect()
+}
+
+/// Deletes old, unreferenced versions of the private providers of `org` and, optionally,
+/// the GPG keys that no remaining version is signed with.
+pub async fn cleanup_providers(
+    client: &TfeClient,
+    kill_switch: Option<&KillSwitch>,
+    org: &str,
+    options: &ProviderOptions,
+) -> Result<ProviderCleanup, Box<dyn Error + Send + Sync>> {
+    let cutoff = Utc::now() - Duration::days(options.older_than_days);
+    let referenced = referenced_versions(client, org).await?;
+    let mut cleanup = ProviderCleanup::default();
+    let mut keys_in_use = HashSet::new();
+
+    let providers = client.get_all(&format!("/organizations/{}/registry-providers?filter[registry_name]=private", org)).await?;
+    for provider in &providers {
+        let namespace = provider["attributes"]["namespace"].as_str().unwrap_or(org);
+        let name = provider["attributes"]["name"].as_str().unwrap_or("");
+        let path = format!("/organizations/{}/registry-providers/private/{}/{}/versions", org, namespace, name);
+        let versions = client.get_all(&path).await?;
+
+        let stale = stale_versions(&versions, cutoff, &format!("{}/{}", namespace, name), &referenced);
+        for version in &versions {
+            if !stale.iter().any(|stale| std::ptr::eq(*stale, version)) {
+                keys_in_use.extend(version["attributes"]["key-id"].as_str().map(str::to_string));
+            }
+        }
+
+        for version in stale {
+            let number = version["attributes"]["version"].as_str().unwrap_or("");
+            let platforms = version["relationships"]["platforms"]["data"].as_array().map_or(0, Vec::len);
+            if options.dry_run {
+                println!("[dry-run] Would delete provider {}/{} {} ({} platforms)", namespace, name, number, platforms);
+            } else {
+                kill_switch::check(kill_switch).await?;
+                client.delete(&format!("{}/{}", path, number)).await?;
+                println!("Deleted provider {}/{} {} ({} platforms)", namespace, name, number, platforms);
+            }
+            cleanup.versions += 1;
+        }
+    }
+
+    if options.delete_unused_keys {
+        let keys = client.get_all(&format!("/api/registry/private/v2/gpg-keys?filter[namespace]={}", org)).await?;
+        for key in &keys {
+            let key_id = key["attributes"]["key-id"].as_str().unwrap_or("");
+            if key_id.is_empty() || keys_in_use.contains(key_id) {
+                continue;
+            }
+            if options.dry_run {
+                println!("[dry-run] Would delete unused GPG key {} of {}", key_id, org);
+            } else {
+                kill_switch::check(kill_switch).await?;
+                client.delete(&format!("/api/registry/private/v2/gpg-keys/{}/{}", org, key_id)).await?;
+                println!("Deleted unused GPG key {} of {}", key_id, org);
+            }
+            cleanup.keys += 1;
+        }
+    }
+
+    Ok(cleanup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    const LOCKFILE: &str = r#"
+provider "app.terraform.io/acme/internal" {
+  version     = "1.2.0"
+  constraints = "~> 1.2"
+  hashes = []
+}
+
+provider "registry.terraform.io/hashicorp/aws" {
+  version = "5.0.0"
+}
+"#;
+
+    fn archive_with_lockfile() -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(LOCKFILE.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, "infra/.terraform.lock.hcl", LOCKFILE.as_bytes()).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn version(number: &str, created_at: &str, key_id: &str) -> Value {
+        json!({ "attributes": { "version": number, "created-at": created_at, "key-id": key_id } })
+    }
+
+    #[test]
+    fn test_lockfile_references() {
+        assert_eq!(lockfile_references(LOCKFILE), vec![
+            ("acme/internal".to_string(), "1.2.0".to_string()),
+            ("hashicorp/aws".to_string(), "5.0.0".to_string()),
+        ]);
+        assert_eq!(lockfiles_in_archive(&archive_with_lockfile()).unwrap(), vec![LOCKFILE.to_string()]);
+    }
+
+    #[test]
+    fn test_stale_versions_keeps_referenced_and_newest() {
+        let versions = vec![
+            version("1.0.0", "2020-01-01T00:00:00Z", "K1"),
+            version("1.2.0", "2020-06-01T00:00:00Z", "K1"),
+            version("1.3.0", "2021-01-01T00:00:00Z", "K2"),
+        ];
+        let referenced = HashSet::from([("acme/internal".to_string(), "1.2.0".to_string())]);
+
+        let stale = stale_versions(&versions, Utc::now(), "acme/Internal", &referenced);
+
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0]["attributes"]["version"], "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_providers_deletes_versions_and_unused_keys() {
+        let _workspaces = mock("GET", "/api/v2/organizations/registry-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{
+                "id": "ws-registry",
+                "attributes": { "name": "infra" },
+                "relationships": { "current-run": { "data": { "id": "run-registry" } } }
+            }] }).to_string())
+            .create();
+        let _run = mock("GET", "/api/v2/runs/run-registry")
+            .with_status(200)
+            .with_body(json!({ "data": { "relationships": { "configuration-version": { "data": { "id": "cv-registry" } } } } }).to_string())
+            .create();
+        let _config = mock("GET", "/api/v2/configuration-versions/cv-registry/download")
+            .with_status(200)
+            .with_body(archive_with_lockfile())
+            .create();
+        let _providers = mock("GET", "/api/v2/organizations/registry-org/registry-providers")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "attributes": { "namespace": "acme", "name": "internal" } }] }).to_string())
+            .create();
+        let _versions = mock("GET", "/api/v2/organizations/registry-org/registry-providers/private/acme/internal/versions")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                version("1.0.0", "2020-01-01T00:00:00Z", "OLDKEY"),
+                version("1.2.0", "2020-06-01T00:00:00Z", "KEY"),
+                version("1.3.0", "2021-01-01T00:00:00Z", "KEY"),
+            ] }).to_string())
+            .create();
+        let delete_version = mock("DELETE", "/api/v2/organizations/registry-org/registry-providers/private/acme/internal/versions/1.0.0")
+            .with_status(204)
+            .expect(1)
+            .create();
+        let _keys = mock("GET", "/api/registry/private/v2/gpg-keys")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "key-id": "OLDKEY" } },
+                { "attributes": { "key-id": "KEY" } }
+            ] }).to_string())
+            .create();
+        let delete_key = mock("DELETE", "/api/registry/private/v2/gpg-keys/registry-org/OLDKEY")
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let options = ProviderOptions { older_than_days: 180, dry_run: false, delete_unused_keys: true };
+        let cleanup = cleanup_providers(&client, None, "registry-org", &options).await.unwrap();
+
+        assert_eq!(cleanup, ProviderCleanup { versions: 1, keys: 1 });
+        delete_version.assert();
+        delete_key.assert();
+    }
+}
@@ -0,0 +1,432 @@
+use crate::tfe::{self, ApiError, TfeClient};
+use crate::cache::{self, LookupCache};
+use crate::audit_trail::{self, LastChange};
+use crate::config::Config;
+use crate::contacts::{Contact, Contacts};
+use crate::{human_activity, i18n, inspect, redact, staleness, summary, timefmt, variables};
+use crate::variables::PlaintextSecret;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io;
+use std::sync::OnceLock;
+
+/// A column of the stale workspace CSV. Headers are part of the file's contract with
+/// whoever reads it (including the cleanup itself), so never rename one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum Column {
+    Name,
+    LastActivity,
+    Org,
+    Id,
+    Project,
+    LastRun,
+    Resources,
+    Cost,
+    Owner,
+    Created,
+    Updated,
+    TerraformVersion,
+    VcsRepo,
+    ExecutionMode,
+    Tags,
+    Locked,
+    Description,
+    InactiveFor,
+    LastActivityLocal,
+    StalenessBasis,
+    ResourceTypes,
+    LastChangedBy,
+    Contacts,
+}
+
+pub const DEFAULT_COLUMNS: &[Column] = &[Column::Name, Column::LastActivity, Column::Org];
+
+impl Column {
+    /// Id of the header in the `locales` messages.
+    fn message(&self) -> &'static str {
+        match self {
+            Column::Name => "column-name",
+            Column::LastActivity => "column-last-activity",
+            Column::Org => "column-org",
+            Column::Id => "column-id",
+            Column::Project => "column-project",
+            Column::LastRun => "column-last-run",
+            Column::Resources => "column-resources",
+            Column::Cost => "column-cost",
+            Column::Owner => "column-owner",
+            Column::Created => "column-created",
+            Column::Updated => "column-updated",
+            Column::TerraformVersion => "column-terraform-version",
+            Column::VcsRepo => "column-vcs-repo",
+            Column::ExecutionMode => "column-execution-mode",
+            Column::Tags => "column-tags",
+            Column::Locked => "column-locked",
+            Column::Description => "column-description",
+            Column::InactiveFor => "column-inactive-for",
+            Column::LastActivityLocal => "column-last-activity-local",
+            Column::StalenessBasis => "column-staleness-basis",
+            Column::ResourceTypes => "column-resource-types",
+            Column::LastChangedBy => "column-last-changed-by",
+            Column::Contacts => "column-contacts",
+        }
+    }
+
+    /// The header in the language of this run.
+    pub fn header(&self) -> String {
+        i18n::text(self.message())
+    }
+}
+
+/// Data for columns that need API lookups beyond the workspace listing, keyed by workspace id
+/// (or project id for project names). Only filled for the columns actually requested.
+#[derive(Debug)]
+pub struct ReportContext {
+    pub project_names: HashMap<String, String>,
+    pub costs: HashMap<String, f64>,
+    pub owners: HashMap<String, Vec<String>>,
+    /// When the current run of each workspace was created.
+    pub last_runs: HashMap<String, String>,
+    /// `(resource type, count)` in the current state, most frequent first.
+    pub resource_types: HashMap<String, Vec<(String, u64)>>,
+    /// Non-sensitive variables that look like credentials; looked up for every report.
+    pub plaintext_secrets: HashMap<String, Vec<PlaintextSecret>>,
+    /// The latest change to each workspace in its organization's audit trail.
+    pub last_changes: HashMap<String, LastChange>,
+    /// How to reach the owners and the last person to change each workspace.
+    pub contacts: HashMap<String, Vec<Contact>>,
+    pub timezone: Tz,
+    /// The moment ages are measured from.
+    pub now: DateTime<Utc>,
+}
+
+impl Default for ReportContext {
+    fn default() -> ReportContext {
+        ReportContext {
+            project_names: HashMap::new(),
+            costs: HashMap::new(),
+            owners: HashMap::new(),
+            last_runs: HashMap::new(),
+            resource_types: HashMap::new(),
+            plaintext_secrets: HashMap::new(),
+            last_changes: HashMap::new(),
+            contacts: HashMap::new(),
+            timezone: Tz::UTC,
+            now: Utc::now(),
+        }
+    }
+}
+
+/// The owners of a workspace: the ones configured for it in its organization or by name alone,
+/// otherwise the teams with admin access.
+pub async fn owners(client: &TfeClient, cache: &LookupCache, config: &Config, workspace: &Value) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+    let configured = config.owners.get(&format!("{}/{}", tfe::workspace_org(workspace), name)).or_else(|| config.owners.get(name));
+    match configured {
+        Some(owners) => Ok(owners.clone()),
+        None => {
+            let id = workspace["id"].as_str().unwrap_or("");
+            cache.get_or_fetch(workspace, cache::OWNERS, || inspect::owners(client, id)).await
+        }
+    }
+}
+
+/// Looks up what the columns need beyond the workspace itself.
+pub async fn build_context(
+    client: &TfeClient,
+    cache: &LookupCache,
+    config: &Config,
+    contacts: &mut Contacts,
+    workspaces: &[Value],
+    columns: &[Column],
+    timezone: Tz,
+) -> Result<ReportContext, Box<dyn Error + Send + Sync>> {
+    let mut context = ReportContext { timezone, ..ReportContext::default() };
+    let wants_contacts = columns.contains(&Column::Contacts) && !config.contacts.is_empty();
+
+    if columns.contains(&Column::Project) {
+        let orgs: HashSet<&str> = workspaces.iter().map(tfe::workspace_org).collect();
+        for org in orgs {
+            for project in client.get_all(&format!("/organizations/{}/projects", org)).await? {
+                if let (Some(id), Some(name)) = (project["id"].as_str(), project["attributes"]["name"].as_str()) {
+                    context.project_names.insert(id.to_string(), name.to_string());
+                }
+            }
+        }
+    }
+
+    if columns.contains(&Column::LastChangedBy) {
+        context.last_changes = audit_trail::last_changes_of(client, &config.audit_trail, workspaces).await;
+    }
+
+    for workspace in workspaces {
+        let id = workspace["id"].as_str().unwrap_or("").to_string();
+        if columns.contains(&Column::Cost) {
+            let cost = cache.get_or_fetch(workspace, cache::COST, || summary::estimated_monthly_cost(client, workspace)).await?;
+            if let Some(cost) = cost {
+                context.costs.insert(id.clone(), cost);
+            }
+        }
+        if columns.contains(&Column::Owner) || wants_contacts {
+            context.owners.insert(id.clone(), owners(client, cache, config, workspace).await?);
+        }
+        if columns.contains(&Column::LastRun) {
+            let created = cache.get_or_fetch(workspace, cache::LAST_RUN, || last_run(client, workspace)).await?;
+            if let Some(created) = created {
+                context.last_runs.insert(id.clone(), created);
+            }
+        }
+        if columns.contains(&Column::ResourceTypes) {
+            let counts = cache.get_or_fetch(workspace, cache::RESOURCE_TYPES, || resource_types(client, &id)).await?;
+            context.resource_types.insert(id.clone(), counts);
+        }
+        // Tokens without access to variables still get the rest of the report
+        match cache.get_or_fetch(workspace, cache::PLAINTEXT_SECRETS, || variables::find_plaintext_secrets(client, &id)).await {
+            Ok(secrets) if !secrets.is_empty() => {
+                context.plaintext_secrets.insert(id.clone(), secrets);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: could not check the variables of {} for secrets: {}", id, redact::scrub(&e.to_string())),
+        }
+    }
+
+    if wants_contacts {
+        let mut identities = context.owners.clone();
+        for (id, change) in &context.last_changes {
+            identities.entry(id.clone()).or_default().push(change.actor.clone());
+        }
+        context.contacts = contacts.of_workspaces(&identities).await;
+    }
+
+    Ok(context)
+}
+
+/// When the workspace's current run was created, `None` for workspaces that never ran.
+pub async fn last_run(client: &TfeClient, workspace: &Value) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let Some(run_id) = workspace["relationships"]["current-run"]["data"]["id"].as_str() else { return Ok(None) };
+    let run = client.get(&format!("/runs/{}", run_id)).await?;
+    Ok(run["data"]["attributes"]["created-at"].as_str().map(str::to_string))
+}
+
+/// Counts the resources of the workspace's current state version by type. Workspaces without
+/// state, or whose state hasn't been processed yet, have none.
+pub async fn resource_types(client: &TfeClient, workspace_id: &str) -> Result<Vec<(String, u64)>, Box<dyn Error + Send + Sync>> {
+    let state_version = match client.get(&format!("/workspaces/{}/current-state-version", workspace_id)).await {
+        Ok(response) => response,
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND => return Ok(Vec::new()),
+            _ => return Err(e),
+        },
+    };
+
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for resource in state_version["data"]["attributes"]["resources"].as_array().into_iter().flatten() {
+        if let Some(resource_type) = resource["type"].as_str() {
+            *counts.entry(resource_type.to_string()).or_default() += resource["count"].as_u64().unwrap_or(1);
+        }
+    }
+
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|(type_a, count_a), (type_b, count_b)| count_b.cmp(count_a).then(type_a.cmp(type_b)));
+    Ok(counts)
+}
+
+fn string_attribute(workspace: &Value, attribute: &str) -> String {
+    workspace["attributes"][attribute].as_str().unwrap_or("").to_string()
+}
+
+pub fn value(column: Column, workspace: &Value, context: &ReportContext) -> String {
+    let id = workspace["id"].as_str().unwrap_or("");
+
+    match column {
+        Column::Name => string_attribute(workspace, "name"),
+        Column::LastActivity => string_attribute(workspace, "last-activity-at"),
+        Column::Org => tfe::workspace_org(workspace).to_string(),
+        Column::Id => id.to_string(),
+        Column::Project => {
+            let project_id = workspace["relationships"]["project"]["data"]["id"].as_str().unwrap_or("");
+            context.project_names.get(project_id).cloned().unwrap_or_else(|| project_id.to_string())
+        }
+        Column::LastRun => context.last_runs.get(id).cloned().unwrap_or_default(),
+        Column::Resources => workspace["attributes"]["resource-count"].as_u64().unwrap_or(0).to_string(),
+        Column::Cost => context.costs.get(id).map(|cost| format!("{:.2}", cost)).unwrap_or_default(),
+        Column::Owner => context.owners.get(id).map(|owners| owners.join("; ")).unwrap_or_default(),
+        Column::Created => string_attribute(workspace, "created-at"),
+        Column::Updated => string_attribute(workspace, "updated-at"),
+        Column::TerraformVersion => string_attribute(workspace, "terraform-version"),
+        Column::VcsRepo => workspace["attributes"]["vcs-repo"]["identifier"].as_str().unwrap_or("").to_string(),
+        Column::ExecutionMode => string_attribute(workspace, "execution-mode"),
+        Column::Tags => workspace["attributes"]["tag-names"].as_array()
+            .map(|tags| tags.iter().filter_map(Value::as_str).collect::<Vec<_>>().join("; "))
+            .unwrap_or_default(),
+        Column::Locked => workspace["attributes"]["locked"].as_bool().unwrap_or(false).to_string(),
+        Column::Description => string_attribute(workspace, "description"),
+        Column::InactiveFor => timefmt::age(&string_attribute(workspace, "last-activity-at"), context.now)
+            .unwrap_or_default(),
+        Column::LastActivityLocal => timefmt::local(&string_attribute(workspace, "last-activity-at"), context.timezone)
+            .unwrap_or_default(),
+        Column::StalenessBasis => match staleness::activity_basis(workspace) {
+            Some(("last-activity-at", _, _)) => "last activity".to_string(),
+            Some((human_activity::ATTRIBUTE, _, _)) => "last human activity".to_string(),
+            Some(_) => "no activity data (created-at)".to_string(),
+            None => "no activity data".to_string(),
+        },
+        Column::ResourceTypes => context.resource_types.get(id)
+            .map(|counts| counts.iter()
+                .map(|(resource_type, count)| format!("{} {}", count, resource_type))
+                .collect::<Vec<_>>()
+                .join("; "))
+            .unwrap_or_default(),
+        Column::LastChangedBy => context.last_changes.get(id).map(LastChange::describe).unwrap_or_default(),
+        Column::Contacts => context.contacts.get(id)
+            .map(|contacts| contacts.iter().map(Contact::describe).collect::<Vec<_>>().join("; "))
+            .unwrap_or_default(),
+    }
+}
+
+/// Writes the workspaces as CSV with one column per selected column, headers first.
+pub fn write_csv<W: io::Write>(
+    writer: W,
+    workspaces: &[Value],
+    columns: &[Column],
+    context: &ReportContext,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(columns.iter().map(Column::header))?;
+
+    for workspace in workspaces {
+        wtr.write_record(columns.iter().map(|column| value(*column, workspace, context)))?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// The column of every header, in every language, built on first use.
+fn columns_by_header() -> &'static HashMap<String, Column> {
+    static COLUMNS: OnceLock<HashMap<String, Column>> = OnceLock::new();
+    COLUMNS.get_or_init(|| {
+        Column::value_variants().iter()
+            .flat_map(|&column| i18n::in_every_language(column.message()).map(move |header| (header, column)))
+            .collect()
+    })
+}
+
+/// Index of a column in a CSV header row written with these headers, in any language.
+pub fn column_index(headers: &csv::StringRecord, column: Column) -> Option<usize> {
+    let columns = columns_by_header();
+    headers.iter().position(|header| columns.get(header) == Some(&column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use serde_json::json;
+
+    #[test]
+    fn test_values() {
+        let workspace = json!({
+            "id": "ws-1",
+            "attributes": {
+                "name": "app",
+                "resource-count": 4,
+                "tag-names": ["team:a", "env:dev"],
+                "vcs-repo": { "identifier": "acme/infra" }
+            },
+            "relationships": {
+                "organization": { "data": { "id": "acme" } },
+                "project": { "data": { "id": "prj-1" } }
+            }
+        });
+        let mut context = ReportContext::default();
+        context.project_names.insert("prj-1".to_string(), "Platform".to_string());
+        context.costs.insert("ws-1".to_string(), 12.5);
+        context.owners.insert("ws-1".to_string(), vec!["ops".to_string(), "sre".to_string()]);
+
+        assert_eq!(value(Column::Org, &workspace, &context), "acme");
+        assert_eq!(value(Column::Project, &workspace, &context), "Platform");
+        assert_eq!(value(Column::Resources, &workspace, &context), "4");
+        assert_eq!(value(Column::Cost, &workspace, &context), "12.50");
+        assert_eq!(value(Column::Owner, &workspace, &context), "ops; sre");
+        assert_eq!(value(Column::Tags, &workspace, &context), "team:a; env:dev");
+        assert_eq!(value(Column::VcsRepo, &workspace, &context), "acme/infra");
+        assert_eq!(value(Column::Locked, &workspace, &context), "false");
+        assert_eq!(value(Column::LastRun, &workspace, &context), "");
+        context.last_runs.insert("ws-1".to_string(), "2024-04-30T08:00:00Z".to_string());
+        assert_eq!(value(Column::LastRun, &workspace, &context), "2024-04-30T08:00:00Z");
+        assert_eq!(value(Column::ResourceTypes, &workspace, &context), "");
+        context.resource_types.insert("ws-1".to_string(),
+            vec![("aws_iam_role".to_string(), 12), ("aws_instance".to_string(), 3)]);
+        assert_eq!(value(Column::ResourceTypes, &workspace, &context), "12 aws_iam_role; 3 aws_instance");
+        assert_eq!(value(Column::LastChangedBy, &workspace, &context), "");
+        context.last_changes.insert("ws-1".to_string(), LastChange {
+            actor: "alice".to_string(), action: "update".to_string(), at: "2024-05-01T10:00:00Z".to_string(),
+        });
+        assert_eq!(value(Column::LastChangedBy, &workspace, &context), "alice (update, 2024-05-01T10:00:00Z)");
+        context.contacts.insert("ws-1".to_string(), vec![
+            Contact { email: Some("alice@example.com".to_string()), slack: Some("@alice".to_string()) },
+            Contact { email: None, slack: Some("@bob".to_string()) },
+        ]);
+        assert_eq!(value(Column::Contacts, &workspace, &context), "alice@example.com (@alice); @bob");
+
+        let workspace = json!({ "attributes": { "last-activity-at": "2020-01-01T12:00:00Z" } });
+        context.timezone = "Asia/Tokyo".parse().unwrap();
+        assert_eq!(value(Column::LastActivityLocal, &workspace, &context), "2020-01-01 21:00 JST");
+        assert_eq!(value(Column::StalenessBasis, &workspace, &context), "last activity");
+
+        let workspace = json!({ "attributes": { "last-activity-at": null, "created-at": "2020-01-01T12:00:00Z" } });
+        assert_eq!(value(Column::StalenessBasis, &workspace, &context), "no activity data (created-at)");
+    }
+
+    #[tokio::test]
+    async fn test_resource_types() {
+        let _state = mock("GET", "/api/v2/workspaces/ws-inventory/current-state-version")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "resources": [
+                { "type": "aws_instance", "name": "web", "count": 3 },
+                { "type": "aws_iam_role", "name": "app", "count": 5 },
+                { "type": "aws_iam_role", "name": "ci", "count": 7 },
+                { "type": "aws_s3_bucket", "name": "logs", "count": 3 }
+            ] } } }).to_string())
+            .create();
+        let _no_state = mock("GET", "/api/v2/workspaces/ws-inventory-empty/current-state-version")
+            .with_status(404)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        assert_eq!(resource_types(&client, "ws-inventory").await.unwrap(), vec![
+            ("aws_iam_role".to_string(), 12),
+            ("aws_instance".to_string(), 3),
+            ("aws_s3_bucket".to_string(), 3),
+        ]);
+        assert!(resource_types(&client, "ws-inventory-empty").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_last_run() {
+        let _run = mock("GET", "/api/v2/runs/run-report-last")
+            .with_status(200)
+            .with_body(json!({ "data": { "id": "run-report-last", "attributes": { "created-at": "2024-04-30T08:00:00Z" } } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let ran = json!({ "relationships": { "current-run": { "data": { "id": "run-report-last" } } } });
+        let never_ran = json!({ "relationships": { "current-run": { "data": null } } });
+
+        assert_eq!(last_run(&client, &ran).await.unwrap().as_deref(), Some("2024-04-30T08:00:00Z"));
+        assert_eq!(last_run(&client, &never_ran).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_column_names_are_snake_case() {
+        assert_eq!(Column::from_str("last_run", false).unwrap(), Column::LastRun);
+        assert_eq!(Column::from_str("org", false).unwrap(), Column::Org);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::config::RunLockConfig;
+use crate::redact;
+use crate::tfe::{ApiError, TfeClient};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the workspace marking an organization as being cleaned up, with the run holding it
+/// in its description.
+pub const MARKER_WORKSPACE: &str = "tfe-cleanup-run-lock";
+
+/// The run holding a lock.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub host: String,
+    /// RFC 3339.
+    pub started_at: String,
+}
+
+impl Holder {
+    pub fn current() -> Holder {
+        let host = std::env::var("HOSTNAME").ok()
+            .or_else(|| fs::read_to_string("/etc/hostname").ok())
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| "unknown host".to_string());
+        Holder { pid: std::process::id(), host, started_at: Utc::now().to_rfc3339() }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("process {} on {} since {}", self.pid, self.host, self.started_at)
+    }
+
+    /// Whether the lock was left behind: held for longer than `stale_after`, or by a process
+    /// of this host that no longer runs.
+    fn is_stale(&self, stale_after: Duration, now: DateTime<Utc>) -> bool {
+        match DateTime::parse_from_rfc3339(&self.started_at) {
+            Ok(started_at) if now - started_at.with_timezone(&Utc) <= stale_after => {}
+            _ => return true,
+        }
+        let proc = Path::new("/proc");
+        self.host == Holder::current().host && proc.exists() && !proc.join(self.pid.to_string()).exists()
+    }
+}
+
+/// A lockfile, removed when dropped.
+#[derive(Debug)]
+pub struct LocalLock {
+    path: PathBuf,
+}
+
+impl LocalLock {
+    /// Creates the lockfile, failing if another run holds it. Stale lockfiles are taken over
+    /// with a warning.
+    pub fn acquire(path: &Path, holder: &Holder, stale_after: Duration) -> Result<LocalLock, Box<dyn Error + Send + Sync>> {
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(serde_json::to_string(holder)?.as_bytes())?;
+                    return Ok(LocalLock { path: path.to_path_buf() });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let current: Option<Holder> = fs::read_to_string(path).ok().and_then(|contents| serde_json::from_str(&contents).ok());
+                    match current {
+                        Some(current) if !current.is_stale(stale_after, Utc::now()) => {
+                            return Err(format!("another run holds {} ({}); remove it if that run is gone",
+                                path.display(), current.describe()).into());
+                        }
+                        Some(current) => eprintln!("Warning: taking over the stale lock {} of {}", path.display(), current.describe()),
+                        None => eprintln!("Warning: taking over the unreadable lock {}", path.display()),
+                    }
+                    fs::remove_file(path)?;
+                }
+                Err(e) => return Err(format!("cannot create lockfile {}: {}", path.display(), e).into()),
+            }
+        }
+        Err(format!("cannot acquire {}: another run took it over first", path.display()).into())
+    }
+}
+
+impl Drop for LocalLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!("Warning: could not remove lockfile {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn marker_path(org: &str) -> String {
+    format!("/organizations/{}/workspaces/{}", org, MARKER_WORKSPACE)
+}
+
+fn is_status(e: &(dyn Error + 'static), status: StatusCode) -> bool {
+    matches!(e.downcast_ref::<ApiError>(), Some(api_err) if api_err.status == status)
+}
+
+/// Marks the organization by creating the marker workspace, failing if another run's marker is
+/// there. Names are unique per organization, so only one run can create it.
+async fn mark(client: &TfeClient, org: &str, holder: &Holder, stale_after: Duration) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for _ in 0..2 {
+        let body = json!({ "data": { "type": "workspaces", "attributes": {
+            "name": MARKER_WORKSPACE,
+            "description": serde_json::to_string(holder)?,
+        } } });
+        let e = match client.post(&format!("/organizations/{}/workspaces", org), &body).await {
+            Ok(_) => return Ok(()),
+            Err(e) if is_status(e.as_ref(), StatusCode::UNPROCESSABLE_ENTITY) => e,
+            Err(e) => return Err(format!("cannot mark {} as being cleaned up: {}", org, e).into()),
+        };
+        let marker = client.get(&marker_path(org)).await.map_err(|_| e)?;
+        let current: Option<Holder> = marker["data"]["attributes"]["description"].as_str()
+            .and_then(|description| serde_json::from_str(description).ok());
+        match current {
+            Some(current) if !current.is_stale(stale_after, Utc::now()) => {
+                return Err(format!("another run is cleaning up {} ({}); delete its workspace {} if that run is gone",
+                    org, current.describe(), MARKER_WORKSPACE).into());
+            }
+            Some(current) => eprintln!("Warning: taking over the stale lock of {} held by {}", org, current.describe()),
+            None => eprintln!("Warning: taking over the unreadable lock of {}", org),
+        }
+        client.delete(&marker_path(org)).await?;
+    }
+    Err(format!("cannot lock {}: another run took it over first", org).into())
+}
+
+/// Held while a run deletes: the lockfile, and the marker of each organization if configured.
+/// Release it explicitly; a lock dropped unreleased, e.g. with a cancelled cleanup, removes its
+/// markers in the background, and a run that dies leaves them behind for the next run to take
+/// over once they are stale.
+#[derive(Debug)]
+pub struct RunLock {
+    _local: LocalLock,
+    client: TfeClient,
+    marked: Vec<String>,
+}
+
+impl RunLock {
+    pub async fn acquire(client: &TfeClient, config: &RunLockConfig, orgs: &[String]) -> Result<RunLock, Box<dyn Error + Send + Sync>> {
+        let holder = Holder::current();
+        let stale_after = Duration::minutes(config.stale_after_minutes);
+        let local = LocalLock::acquire(&config.path, &holder, stale_after)?;
+        let mut lock = RunLock { _local: local, client: client.clone(), marked: Vec::new() };
+        if config.org_marker {
+            for org in orgs {
+                if let Err(e) = mark(client, org, &holder, stale_after).await {
+                    lock.release().await;
+                    return Err(e);
+                }
+                lock.marked.push(org.clone());
+            }
+        }
+        Ok(lock)
+    }
+
+    pub async fn release(mut self) {
+        let marked = std::mem::take(&mut self.marked);
+        unmark(&self.client, &marked).await;
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if self.marked.is_empty() {
+            return;
+        }
+        let marked = std::mem::take(&mut self.marked);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let client = self.client.clone();
+                runtime.spawn(async move { unmark(&client, &marked).await });
+            }
+            Err(_) => eprintln!("Warning: could not remove the locks of {}: delete their workspace {} by hand",
+                marked.join(", "), MARKER_WORKSPACE),
+        }
+    }
+}
+
+async fn unmark(client: &TfeClient, orgs: &[String]) {
+    for org in orgs {
+        if let Err(e) = client.delete(&marker_path(org)).await {
+            if !is_status(e.as_ref(), StatusCode::NOT_FOUND) {
+                eprintln!("Warning: could not remove the lock of {}: delete its workspace {} by hand ({})",
+                    org, MARKER_WORKSPACE, redact::scrub(&e.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    fn config(dir: &Path, org_marker: bool) -> RunLockConfig {
+        RunLockConfig { path: dir.join("tfe_cleanup.lock"), org_marker, stale_after_minutes: 60 }
+    }
+
+    #[test]
+    fn test_local_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lock");
+        let holder = Holder::current();
+
+        let lock = LocalLock::acquire(&path, &holder, Duration::minutes(60)).unwrap();
+        let err = LocalLock::acquire(&path, &holder, Duration::minutes(60)).unwrap_err();
+        assert!(err.to_string().starts_with("another run holds"), "{}", err);
+        drop(lock);
+        assert!(!path.exists());
+
+        // Left behind by a run that started long ago
+        let old = Holder { started_at: (Utc::now() - Duration::hours(2)).to_rfc3339(), ..holder.clone() };
+        fs::write(&path, serde_json::to_string(&old).unwrap()).unwrap();
+        let _lock = LocalLock::acquire(&path, &holder, Duration::minutes(60)).unwrap();
+        assert_eq!(serde_json::from_str::<Holder>(&fs::read_to_string(&path).unwrap()).unwrap(), holder);
+    }
+
+    #[tokio::test]
+    async fn test_org_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        let created = mock("POST", "/api/v2/organizations/lock-free/workspaces").with_status(201).with_body("{}").create();
+        let released = mock("DELETE", "/api/v2/organizations/lock-free/workspaces/tfe-cleanup-run-lock").with_status(204).create();
+        let lock = RunLock::acquire(&client, &config(dir.path(), true), &["lock-free".to_string()]).await.unwrap();
+        created.assert();
+        lock.release().await;
+        released.assert();
+        assert!(!dir.path().join("tfe_cleanup.lock").exists());
+
+        // Dropped unreleased, e.g. by a cancelled cleanup
+        let _created = mock("POST", "/api/v2/organizations/lock-dropped/workspaces").with_status(201).with_body("{}").create();
+        let dropped = mock("DELETE", "/api/v2/organizations/lock-dropped/workspaces/tfe-cleanup-run-lock").with_status(204).create();
+        drop(RunLock::acquire(&client, &config(dir.path(), true), &["lock-dropped".to_string()]).await.unwrap());
+        for _ in 0..100 {
+            if dropped.matched() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        dropped.assert();
+
+        let holder = Holder { pid: 1, host: "other-host".to_string(), started_at: Utc::now().to_rfc3339() };
+        let _taken = mock("POST", "/api/v2/organizations/lock-held/workspaces").with_status(422).with_body("{}").create();
+        let _marker = mock("GET", "/api/v2/organizations/lock-held/workspaces/tfe-cleanup-run-lock")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "description": serde_json::to_string(&holder).unwrap() } } }).to_string())
+            .create();
+        let err = RunLock::acquire(&client, &config(dir.path(), true), &["lock-held".to_string()]).await.unwrap_err();
+        assert!(err.to_string().contains("another run is cleaning up lock-held (process 1 on other-host"), "{}", err);
+        assert!(!dir.path().join("tfe_cleanup.lock").exists());
+    }
+}
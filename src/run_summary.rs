@@ -0,0 +1,156 @@
+use crate::{i18n, storage};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// What a run did, counted where it happens so every command can end with the same summary.
+/// A run's counters travel with its `TfeClient`.
+#[derive(Debug, Default)]
+pub struct Counters {
+    api_calls: AtomicU64,
+    rate_limit_sleeps: AtomicU64,
+    scanned: AtomicU64,
+    flagged: AtomicU64,
+    excluded: AtomicU64,
+    deleted: AtomicU64,
+    failed: AtomicU64,
+    reclaimed_bytes: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    ApiCall,
+    RateLimitSleep,
+    Scanned,
+    Flagged,
+    /// Excluded by the config or opted out by the owners.
+    Excluded,
+    Deleted,
+    /// Workspaces whose cleanup pipeline failed.
+    Failed,
+    /// Bytes of state reported for deleted workspaces and pruned state versions.
+    ReclaimedBytes,
+}
+
+impl Counters {
+    pub const fn new() -> Counters {
+        Counters {
+            api_calls: AtomicU64::new(0),
+            rate_limit_sleeps: AtomicU64::new(0),
+            scanned: AtomicU64::new(0),
+            flagged: AtomicU64::new(0),
+            excluded: AtomicU64::new(0),
+            deleted: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            reclaimed_bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn counter(&self, counter: Counter) -> &AtomicU64 {
+        match counter {
+            Counter::ApiCall => &self.api_calls,
+            Counter::RateLimitSleep => &self.rate_limit_sleeps,
+            Counter::Scanned => &self.scanned,
+            Counter::Flagged => &self.flagged,
+            Counter::Excluded => &self.excluded,
+            Counter::Deleted => &self.deleted,
+            Counter::Failed => &self.failed,
+            Counter::ReclaimedBytes => &self.reclaimed_bytes,
+        }
+    }
+
+    pub fn increment(&self, counter: Counter) {
+        self.add(counter, 1);
+    }
+
+    pub fn add(&self, counter: Counter, amount: u64) {
+        self.counter(counter).fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self, started: Instant) -> RunSummary {
+        let get = |counter| self.counter(counter).load(Ordering::Relaxed);
+        RunSummary {
+            duration_secs: (started.elapsed().as_secs_f64() * 10.0).round() / 10.0,
+            api_calls: get(Counter::ApiCall),
+            rate_limit_sleeps: get(Counter::RateLimitSleep),
+            workspaces: WorkspaceCounts {
+                scanned: get(Counter::Scanned),
+                flagged: get(Counter::Flagged),
+                excluded: get(Counter::Excluded),
+                deleted: get(Counter::Deleted),
+                failed: get(Counter::Failed),
+            },
+            reclaimed_bytes: get(Counter::ReclaimedBytes),
+        }
+    }
+}
+
+/// Printed at the end of every command. Field names are part of the JSON output's contract.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub duration_secs: f64,
+    pub api_calls: u64,
+    pub rate_limit_sleeps: u64,
+    pub workspaces: WorkspaceCounts,
+    /// Reported state size of what the run deleted or pruned.
+    pub reclaimed_bytes: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WorkspaceCounts {
+    pub scanned: u64,
+    pub flagged: u64,
+    pub excluded: u64,
+    pub deleted: u64,
+    pub failed: u64,
+}
+
+impl RunSummary {
+    /// The summary in the language of this run, values aligned after the longest label.
+    pub fn render(&self) -> String {
+        let counts = &self.workspaces;
+        let lines = [
+            (i18n::text("summary-duration"), format!("{:.1}s", self.duration_secs)),
+            (i18n::text("summary-api-calls"), format!("{} ({})", self.api_calls,
+                i18n::format("summary-rate-limit-sleeps", [("count", self.rate_limit_sleeps.into())]))),
+            (i18n::text("summary-workspaces"), i18n::format("summary-workspace-counts", [
+                ("scanned", counts.scanned.into()),
+                ("flagged", counts.flagged.into()),
+                ("excluded", counts.excluded.into()),
+                ("deleted", counts.deleted.into()),
+                ("failed", counts.failed.into()),
+            ])),
+            (i18n::text("summary-reclaimed"), storage::format_bytes(self.reclaimed_bytes)),
+        ];
+        let width = lines.iter().map(|(label, _)| label.chars().count() + 1).max().unwrap_or_default() + 2;
+        let mut rendered = format!("\n{}\n", i18n::text("summary-title"));
+        for (label, value) in lines {
+            rendered.push_str(&format!("  {:<width$}{}\n", format!("{}:", label), value, width = width));
+        }
+        rendered
+    }
+
+    /// The summary as one line of JSON, e.g. for wrappers reading the last line of output.
+    pub fn to_json(&self) -> String {
+        format!("{{\"summary\":{}}}", serde_json::to_string(self).expect("summaries serialize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let counters = Counters::new();
+        for counter in [Counter::ApiCall, Counter::ApiCall, Counter::RateLimitSleep, Counter::Scanned, Counter::Scanned, Counter::Flagged, Counter::Deleted] {
+            counters.increment(counter);
+        }
+        counters.add(Counter::ReclaimedBytes, 1536);
+        let mut summary = counters.summary(Instant::now());
+        summary.duration_secs = 12.34;
+
+        assert_eq!(summary.render(), "\nRun summary\n  Duration:    12.3s\n  API calls:   2 (1 rate-limit sleeps)\n  Workspaces:  2 scanned, 1 flagged, 0 excluded, 1 deleted, 0 failed\n  Reclaimed:   1.5 KiB\n");
+        assert_eq!(summary.to_json(), r#"{"summary":{"duration_secs":12.34,"api_calls":2,"rate_limit_sleeps":1,"workspaces":{"scanned":2,"flagged":1,"excluded":0,"deleted":1,"failed":0},"reclaimed_bytes":1536}}"#);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::ages::{self, AgeCounts, AgeStats};
+use crate::branches::Providers;
+use crate::config::PullRequestsConfig;
+use crate::human_activity::HumanActivity;
+use crate::profile::{self, Phase};
+use crate::pull_requests;
+use crate::redact;
+use crate::run_summary::Counter;
+use crate::staleness::{self, Policy, Status, Verdict};
+use crate::team_access::TeamScope;
+use crate::tfe::{self, OrgTotals, TfeClient};
+use crate::timefmt;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+/// Organizations whose workspaces are listed concurrently.
+const CONCURRENT_ORGS: usize = 4;
+
+/// Workspaces looked up further (team scope, human activity) concurrently.
+const CONCURRENT_ENRICHMENTS: usize = 8;
+
+/// What a scan keeps of the workspaces streamed past it: how many each organization has, how
+/// long they have been inactive, the stale ones and those opted out by their owners. Memory
+/// grows with the number of stale workspaces, not with the size of the organizations.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub totals: OrgTotals,
+    /// Sorted by organization and name.
+    pub stale: Vec<Value>,
+    /// Sorted by organization and name.
+    pub opted_out: Vec<Value>,
+    /// Workspaces with an activity or creation date, by days since activity.
+    pub ages: AgeCounts,
+    /// Statistics of `ages`, with the trend since the previous run once recorded in the history.
+    pub age_stats: Option<AgeStats>,
+    /// Workspaces kept by the policy but named like pull request workspaces, to check whether
+    /// their pull request is still open.
+    pub pull_requests: Vec<Value>,
+    /// Organizations whose workspaces couldn't be listed, with the error. Workspaces listed
+    /// before the error are kept.
+    pub errors: BTreeMap<String, String>,
+    /// The team the scan was limited to, so a cleanup holds workspaces queued by hand to it too.
+    pub team: Option<TeamScope>,
+}
+
+impl Scan {
+    /// Counts a workspace, keeping it if it is stale or opted out.
+    pub fn add(&mut self, workspace: Value, verdict: &Verdict) {
+        *self.totals.entry(tfe::workspace_org(&workspace).to_string()).or_default() += 1;
+        match verdict.status {
+            Status::Flagged => self.stale.push(workspace),
+            Status::OptedOut => self.opted_out.push(workspace),
+            Status::Kept | Status::Excluded => {}
+        }
+    }
+
+    fn finish(mut self) -> Scan {
+        sort_by_org_and_name(&mut self.stale);
+        sort_by_org_and_name(&mut self.opted_out);
+        self
+    }
+}
+
+/// Returned by runs that completed for some organizations only, so they exit with
+/// `PartialScan::EXIT_CODE` instead of success.
+#[derive(Debug)]
+pub struct PartialScan {
+    pub failed: Vec<String>,
+}
+
+impl PartialScan {
+    pub const EXIT_CODE: u8 = 2;
+
+    pub fn check(scan: &Scan) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if scan.errors.is_empty() {
+            return Ok(());
+        }
+        Err(Box::new(PartialScan { failed: scan.errors.keys().cloned().collect() }))
+    }
+}
+
+impl fmt::Display for PartialScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not scan organizations {}", self.failed.join(", "))
+    }
+}
+
+impl Error for PartialScan {}
+
+/// Sorts workspaces so output doesn't depend on which request finished first.
+pub fn sort_by_org_and_name(workspaces: &mut [Value]) {
+    workspaces.sort_by(|a, b| {
+        (tfe::workspace_org(a), a["attributes"]["name"].as_str())
+            .cmp(&(tfe::workspace_org(b), b["attributes"]["name"].as_str()))
+    });
+}
+
+/// Streams the workspaces of `orgs`, listing several organizations at once, and evaluates each
+/// against the policy as its page arrives. With a team scope, workspaces the team doesn't
+/// administer are passed over as if they weren't listed. With human activity, workspaces the
+/// policy could flag are enriched with their last human activity before evaluation; several
+/// workspaces are looked up at once, in listing order. `inspect` sees every other workspace with
+/// its verdict, in the order they are fetched, e.g. to print explanations. An organization
+/// failing to list, e.g. with a 403 or a timeout, or whose team scope can't be checked, is
+/// recorded in `errors` and the others go on.
+pub async fn scan(
+    client: &TfeClient,
+    orgs: &[String],
+    team: Option<&TeamScope>,
+    human: Option<&HumanActivity>,
+    policy: &Policy,
+    now: DateTime<Utc>,
+    mut inspect: impl FnMut(&Value, &Verdict),
+) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+    // Built up front and boxed: closures in the stream, held across awaits, would keep the scan
+    // from being `Send`
+    let listings: Vec<_> = orgs.iter()
+        .map(|org| tfe::stream_workspaces(client, org).map_err(move |e| (org.clone(), e)).boxed())
+        .collect();
+    let mut workspaces = stream::iter(listings).flatten_unordered(CONCURRENT_ORGS)
+        .map(|result| async move {
+            let workspace = result?;
+            let org = tfe::workspace_org(&workspace).to_string();
+            enrich(client, team, human, policy, now, workspace).await.map_err(|e| (org, e))
+        })
+        .buffered(CONCURRENT_ENRICHMENTS)
+        .boxed();
+
+    let mut scan = Scan::default();
+    while let Some(result) = profile::timed(Phase::Listing, workspaces.next()).await {
+        let workspace = match result {
+            Ok(Some(workspace)) => workspace,
+            Ok(None) => continue,
+            // A failed listing ends that organization's stream; a failed lookup only skips the
+            // workspace, but leaves the organization's results incomplete all the same
+            Err((org, e)) => {
+                let error = redact::scrub(&e.to_string());
+                eprintln!("Warning: could not scan organization {}: {}", org, error);
+                scan.errors.entry(org).or_insert(error);
+                continue;
+            }
+        };
+
+        let filtering = Instant::now();
+        let verdict = staleness::evaluate(&workspace, policy, now);
+        if let Some(age) = ages::days_inactive(&workspace, now) {
+            *scan.ages.entry(age).or_default() += 1;
+        }
+        if let Some(log) = client.debug_log() {
+            log.record_decision(&workspace, &verdict);
+        }
+        inspect(&workspace, &verdict);
+        client.counters().increment(Counter::Scanned);
+        match verdict.status {
+            Status::Flagged => client.counters().increment(Counter::Flagged),
+            Status::Excluded | Status::OptedOut => client.counters().increment(Counter::Excluded),
+            Status::Kept => {}
+        }
+        if awaits_pull_request(&workspace, &verdict, policy) {
+            scan.pull_requests.push(workspace.clone());
+        }
+        scan.add(workspace, &verdict);
+        profile::record(Phase::Filtering, filtering.elapsed());
+    }
+    Ok(scan.finish())
+}
+
+/// Whether a kept workspace is named like a pull request workspace, so its verdict may still
+/// change once its pull request has been checked.
+pub fn awaits_pull_request(workspace: &Value, verdict: &Verdict, policy: &Policy) -> bool {
+    let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+    verdict.status == Status::Kept && policy.pull_request.as_ref().is_some_and(|pattern| pattern.is_match(name))
+}
+
+/// Looks up what the policy needs beyond the listing: `None` for workspaces outside the team
+/// scope, and the last human activity of those the policy could flag. Excluded, opted-out and
+/// recently created workspaces are evaluated without it, sparing their lookups.
+async fn enrich(
+    client: &TfeClient,
+    team: Option<&TeamScope>,
+    human: Option<&HumanActivity>,
+    policy: &Policy,
+    now: DateTime<Utc>,
+    mut workspace: Value,
+) -> Result<Option<Value>, Box<dyn Error + Send + Sync>> {
+    let enrichment = Instant::now();
+    if let Some(team) = team {
+        if !team.has_admin(client, &workspace).await? {
+            profile::record(Phase::Enrichment, enrichment.elapsed());
+            return Ok(None);
+        }
+    }
+    add_human_activity(client, human, policy, now, &mut workspace).await;
+    profile::record(Phase::Enrichment, enrichment.elapsed());
+    Ok(Some(workspace))
+}
+
+/// Records the last human activity of a workspace the policy could flag.
+async fn add_human_activity(client: &TfeClient, human: Option<&HumanActivity>, policy: &Policy, now: DateTime<Utc>, workspace: &mut Value) {
+    if let Some(human) = human {
+        if staleness::could_be_stale(workspace, policy, now) {
+            human.enrich(client, workspace).await;
+        }
+    }
+}
+
+/// Evaluates a single workspace the way a scan and its pull request check would: enriched with
+/// its last human activity, and flagged if it is a pull request workspace whose pull request is
+/// merged or closed.
+pub async fn evaluate_one(
+    client: &TfeClient,
+    human: Option<&HumanActivity>,
+    pull_requests: Option<&PullRequestsConfig>,
+    policy: &Policy,
+    now: DateTime<Utc>,
+    workspace: &mut Value,
+) -> Verdict {
+    add_human_activity(client, human, policy, now, workspace).await;
+    let verdict = staleness::evaluate(workspace, policy, now);
+    let (Some(config), Some(pattern)) = (pull_requests, &policy.pull_request) else { return verdict };
+    if !awaits_pull_request(workspace, &verdict, policy) {
+        return verdict;
+    }
+
+    let mut scan = Scan { pull_requests: vec![workspace.clone()], ..Scan::default() };
+    let mut reaped = pull_requests::reap(&mut scan, config, pattern, &Providers::from_env(), client.counters()).await;
+    match reaped.remove(workspace["id"].as_str().unwrap_or("")) {
+        Some(rule) => verdict.flag(rule),
+        None => verdict,
+    }
+}
+
+/// Machine-readable scan result for one workspace.
+pub fn result(workspace: &Value, verdict: &Verdict, explain: bool, now: DateTime<Utc>) -> Value {
+    let mut result = json!({
+        "org": tfe::workspace_org(workspace),
+        "name": workspace["attributes"]["name"],
+        "id": workspace["id"],
+        "last_activity_at": workspace["attributes"]["last-activity-at"],
+        "inactive_for": workspace["attributes"]["last-activity-at"].as_str()
+            .and_then(|raw| timefmt::age(raw, now)),
+        "no_activity_data": staleness::lacks_activity_data(workspace),
+        "status": verdict.status.label(),
+        "rule": verdict.rule,
+    });
+    if explain {
+        result["trace"] = json!(verdict.trace);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    fn workspace(org: &str, name: &str, last_activity: &str) -> Value {
+        json!({
+            "attributes": { "name": name, "last-activity-at": last_activity },
+            "relationships": { "organization": { "data": { "id": org } } }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_scan_streams_pages_and_keeps_only_stale() {
+        let recent = Utc::now().to_rfc3339();
+        let _first = mock("GET", "/api/v2/organizations/stream-org/workspaces")
+            .match_query(Matcher::UrlEncoded("page[number]".into(), "1".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [workspace("stream-org", "old-b", "2020-01-01T00:00:00Z"), workspace("stream-org", "busy", &recent)],
+                "meta": { "pagination": { "next-page": 2 } }
+            }).to_string())
+            .create();
+        let _second = mock("GET", "/api/v2/organizations/stream-org/workspaces")
+            .match_query(Matcher::UrlEncoded("page[number]".into(), "2".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [workspace("stream-org", "old-a", "2020-01-01T00:00:00Z")],
+                "meta": { "pagination": { "next-page": null } }
+            }).to_string())
+            .create();
+        let _other = mock("GET", "/api/v2/organizations/stream-other-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [workspace("stream-other-org", "old", "2020-01-01T00:00:00Z")] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let orgs = vec!["stream-org".to_string(), "stream-other-org".to_string()];
+        let mut inspected = 0;
+        let scan = scan(&client, &orgs, None, None, &Policy::default(), Utc::now(), |_, _| inspected += 1).await.unwrap();
+
+        assert_eq!(inspected, 4);
+        assert_eq!(scan.totals["stream-org"], 3);
+        assert_eq!(scan.totals["stream-other-org"], 1);
+        let stale: Vec<String> = scan.stale.iter()
+            .map(|ws| format!("{}/{}", tfe::workspace_org(ws), ws["attributes"]["name"].as_str().unwrap()))
+            .collect();
+        assert_eq!(stale, vec!["stream-org/old-a", "stream-org/old-b", "stream-other-org/old"]);
+        assert!(PartialScan::check(&scan).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_scan_goes_on_after_an_org_fails() {
+        let _forbidden = mock("GET", "/api/v2/organizations/partial-forbidden-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(403)
+            .with_body(r#"{"errors":[{"status":"403","title":"forbidden"}]}"#)
+            .create();
+        let _ok = mock("GET", "/api/v2/organizations/partial-ok-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [workspace("partial-ok-org", "old", "2020-01-01T00:00:00Z")] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let orgs = vec!["partial-forbidden-org".to_string(), "partial-ok-org".to_string()];
+        let scan = scan(&client, &orgs, None, None, &Policy::default(), Utc::now(), |_, _| {}).await.unwrap();
+
+        assert_eq!(scan.stale.len(), 1);
+        assert_eq!(scan.errors.keys().collect::<Vec<_>>(), vec!["partial-forbidden-org"]);
+        assert!(scan.errors["partial-forbidden-org"].contains("403"));
+        let error = PartialScan::check(&scan).unwrap_err();
+        assert_eq!(error.to_string(), "could not scan organizations partial-forbidden-org");
+    }
+
+    #[tokio::test]
+    async fn test_scan_records_team_scope_errors_per_org() {
+        let _teams = mock("GET", "/api/v2/organizations/scope-error-org/teams")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "team-ops", "attributes": { "name": "ops" } }] }).to_string())
+            .create();
+        let mut unreadable = workspace("scope-error-org", "unreadable", "2020-01-01T00:00:00Z");
+        unreadable["id"] = json!("ws-scope-unreadable");
+        let mut administered = workspace("scope-error-org", "administered", "2020-01-01T00:00:00Z");
+        administered["id"] = json!("ws-scope-administered");
+        let _workspaces = mock("GET", "/api/v2/organizations/scope-error-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [unreadable, administered] }).to_string())
+            .create();
+        let _forbidden = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-scope-unreadable".into()))
+            .with_status(403)
+            .with_body(r#"{"errors":[{"status":"403","title":"forbidden"}]}"#)
+            .create();
+        let _admin = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-scope-administered".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "attributes": { "access": "admin" },
+                "relationships": { "team": { "data": { "id": "team-ops" } } } }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let mut orgs = vec!["scope-error-org".to_string()];
+        let team = TeamScope::resolve(&client, "ops", &mut orgs).await.unwrap();
+        let scan = scan(&client, &orgs, Some(&team), None, &Policy::default(), Utc::now(), |_, _| {}).await.unwrap();
+
+        let stale: Vec<&str> = scan.stale.iter().map(|ws| ws["attributes"]["name"].as_str().unwrap()).collect();
+        assert_eq!(stale, vec!["administered"]);
+        assert!(scan.errors["scope-error-org"].contains("403"));
+    }
+}
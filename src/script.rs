@@ -0,0 +1,220 @@
+use serde_json::Value;
+use std::path::Path;
+
+/// The shell a cleanup script is written for: PowerShell for `.ps1` files, so the plan runs on
+/// Windows without a POSIX shell, curl or jq, and a POSIX shell otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Posix,
+    PowerShell,
+}
+
+impl Shell {
+    pub fn for_path(path: &Path) -> Shell {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("ps1") => Shell::PowerShell,
+            _ => Shell::Posix,
+        }
+    }
+
+    /// Quotes `value` as a literal string.
+    pub fn quote(self, value: &str) -> String {
+        match self {
+            Shell::Posix => format!("'{}'", value.replace('\'', r"'\''")),
+            Shell::PowerShell => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+
+    /// A call of the TFE API, authenticated with `$TFE_TOKEN` against `$TFE_ADDRESS`.
+    pub fn api(self, method: &str, path: &str, body: Option<&Value>) -> String {
+        let mut command = match self {
+            Shell::Posix => format!(
+                "curl -sSf -X {} -H \"Authorization: Bearer $TFE_TOKEN\" -H 'Content-Type: application/vnd.api+json' \"$TFE_ADDRESS/api/v2\"{}",
+                method, self.quote(path),
+            ),
+            Shell::PowerShell => format!(
+                "Invoke-RestMethod -Method {} -Headers $headers -ContentType 'application/vnd.api+json' -Uri (\"$env:TFE_ADDRESS/api/v2\" + {})",
+                method, self.quote(path),
+            ),
+        };
+        if let Some(body) = body {
+            let flag = match self {
+                Shell::Posix => "-d",
+                Shell::PowerShell => "-Body",
+            };
+            command.push_str(&format!(" {} {}", flag, self.quote(&body.to_string())));
+        }
+        command
+    }
+
+    /// A JSON post to the webhook held in the environment variable `var`, which the script
+    /// requires instead of containing the secret.
+    pub fn webhook(self, var: &str, body: &Value) -> String {
+        match self {
+            Shell::Posix => format!("curl -sSf -X POST -H 'Content-Type: application/json' \"${{{}:?set {} to the webhook URL}}\" -d {}",
+                var, var, self.quote(&body.to_string())),
+            Shell::PowerShell => format!("Invoke-RestMethod -Method POST -ContentType 'application/json' -Uri (Get-RequiredEnv {}) -Body {}",
+                self.quote(var), self.quote(&body.to_string())),
+        }
+    }
+
+    /// Creates `dir` and its parents unless they exist.
+    pub fn make_dir(self, dir: &str) -> String {
+        match self {
+            Shell::Posix => format!("mkdir -p {}", self.quote(dir)),
+            Shell::PowerShell => format!("New-Item -ItemType Directory -Force -Path {} | Out-Null", self.quote(dir)),
+        }
+    }
+
+    /// Downloads the state of the state version at `state_version_path` to `file`, failing if
+    /// there is none.
+    pub fn download_state(self, state_version_path: &str, file: &str) -> Vec<String> {
+        let state_version = self.api("GET", state_version_path, None);
+        match self {
+            Shell::Posix => vec![
+                format!("url=$({} | jq -er '.data.attributes[\"hosted-state-download-url\"]')", state_version),
+                format!("curl -sSfL -H \"Authorization: Bearer $TFE_TOKEN\" \"$url\" -o {}", self.quote(file)),
+            ],
+            Shell::PowerShell => vec![
+                format!("$url = ({}).data.attributes.'hosted-state-download-url'", state_version),
+                "if (-not $url) { throw 'no state to download' }".to_string(),
+                format!("Invoke-WebRequest -Headers $headers -Uri $url -OutFile {}", self.quote(file)),
+            ],
+        }
+    }
+
+    /// Runs `program` with `args`, failing if it does.
+    pub fn run(self, program: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| self.quote(arg)).collect();
+        match self {
+            Shell::Posix => format!("{} {}", self.quote(program), args.join(" ")),
+            Shell::PowerShell => format!("& {} {}; if ($LASTEXITCODE -ne 0) {{ throw {} }}",
+                self.quote(program), args.join(" "), self.quote(&format!("{} failed", program))),
+        }
+    }
+
+    fn header(self, address: &str) -> String {
+        match self {
+            // Without `set -e`: each workspace's subshell sets it, so a failure ends that
+            // workspace only
+            Shell::Posix => format!(
+                "#!/bin/sh\n\
+                 # Cleanup plan written by tfe_cleanup {}; review it before running.\n\
+                 set -u\n\
+                 : \"${{TFE_TOKEN:?set TFE_TOKEN to a TFE API token}}\"\n\
+                 TFE_ADDRESS=\"${{TFE_ADDRESS:-{}}}\"\n\
+                 failed=0\n",
+                env!("CARGO_PKG_VERSION"), address,
+            ),
+            Shell::PowerShell => format!(
+                "# Cleanup plan written by tfe_cleanup {}; review it before running.\n\
+                 $ErrorActionPreference = 'Stop'\n\
+                 if (-not $env:TFE_TOKEN) {{ throw 'set TFE_TOKEN to a TFE API token' }}\n\
+                 if (-not $env:TFE_ADDRESS) {{ $env:TFE_ADDRESS = {} }}\n\
+                 $headers = @{{ Authorization = \"Bearer $env:TFE_TOKEN\" }}\n\
+                 function Get-RequiredEnv($name) {{\n\
+                 \x20   $value = [Environment]::GetEnvironmentVariable($name)\n\
+                 \x20   if (-not $value) {{ throw \"set $name to the webhook URL\" }}\n\
+                 \x20   $value\n\
+                 }}\n\
+                 $failed = 0\n",
+                env!("CARGO_PKG_VERSION"), self.quote(address),
+            ),
+        }
+    }
+
+    /// A workspace's commands, run until the first one fails, after which the script reports
+    /// the workspace as failed and goes on with the next one.
+    fn workspace(self, title: &str, commands: &[String]) -> String {
+        let failure = self.quote(&format!("{} failed; going on with the next workspace", title));
+        let body: String = commands.iter().map(|command| format!("    {}\n", command)).collect();
+        match self {
+            // A subshell in a condition would ignore `set -e`, so its status is checked after
+            Shell::Posix => format!(
+                "\n# {}\n(\n    set -e\n{})\n[ $? -eq 0 ] || {{ echo {} >&2; failed=$((failed + 1)); }}\n",
+                title, body, failure,
+            ),
+            Shell::PowerShell => format!(
+                "\n# {}\ntry {{\n{}}} catch {{\n    Write-Warning {}\n    $failed++\n}}\n",
+                title, body, failure,
+            ),
+        }
+    }
+
+    fn footer(self) -> &'static str {
+        match self {
+            Shell::Posix => "\n[ \"$failed\" -eq 0 ] || { echo \"$failed workspaces failed\" >&2; exit 1; }\n",
+            Shell::PowerShell => "\nif ($failed) {\n    Write-Warning \"$failed workspaces failed\"\n    exit 1\n}\n",
+        }
+    }
+
+    /// The whole script: a header checking the environment, then each workspace's commands under
+    /// a comment naming it. A failing command ends its workspace's commands, as a failing action
+    /// stops a workspace's pipeline, and the script goes on with the next workspace; it exits
+    /// non-zero if any workspace failed.
+    pub fn render(self, address: &str, workspaces: &[(String, Vec<String>)]) -> String {
+        let mut script = self.header(address);
+        for (title, commands) in workspaces {
+            script.push_str(&self.workspace(title, commands));
+        }
+        script.push_str(self.footer());
+        script
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(Shell::Posix.quote("app"), "'app'");
+        assert_eq!(Shell::Posix.quote("it's $HOME"), r"'it'\''s $HOME'");
+        assert_eq!(Shell::PowerShell.quote("it's $HOME"), "'it''s $HOME'");
+    }
+
+    #[test]
+    fn test_for_path() {
+        assert_eq!(Shell::for_path(Path::new("cleanup.sh")), Shell::Posix);
+        assert_eq!(Shell::for_path(Path::new(r"C:\plans\cleanup.PS1")), Shell::PowerShell);
+        assert_eq!(Shell::for_path(Path::new("cleanup")), Shell::Posix);
+    }
+
+    #[test]
+    fn test_render() {
+        let shell = Shell::Posix;
+        let commands = vec![
+            shell.api("POST", "/workspaces/ws-1/actions/lock", Some(&json!({ "reason": "stale" }))),
+            shell.webhook("SLACK_WEBHOOK_URL", &json!({ "text": "bye" })),
+        ];
+        let script = shell.render("https://tfe.example.com", &[("acme/legacy (stale)".to_string(), commands)]);
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("TFE_ADDRESS=\"${TFE_ADDRESS:-https://tfe.example.com}\"\n"));
+        assert!(script.contains("\n# acme/legacy (stale)\n(\n    set -e\n    curl -sSf -X POST -H \"Authorization: Bearer $TFE_TOKEN\" \
+            -H 'Content-Type: application/vnd.api+json' \"$TFE_ADDRESS/api/v2\"'/workspaces/ws-1/actions/lock' -d '{\"reason\":\"stale\"}'\n"));
+        assert!(script.contains("\"${SLACK_WEBHOOK_URL:?set SLACK_WEBHOOK_URL to the webhook URL}\" -d '{\"text\":\"bye\"}'\n)\n\
+            [ $? -eq 0 ] || { echo 'acme/legacy (stale) failed; going on with the next workspace' >&2; failed=$((failed + 1)); }\n"));
+        assert!(script.ends_with("[ \"$failed\" -eq 0 ] || { echo \"$failed workspaces failed\" >&2; exit 1; }\n"));
+    }
+
+    #[test]
+    fn test_render_powershell() {
+        let shell = Shell::PowerShell;
+        let mut commands = vec![shell.api("POST", "/workspaces/ws-1/actions/lock", Some(&json!({ "reason": "it's stale" })))];
+        commands.extend(shell.download_state("/workspaces/ws-1/current-state-version", r"C:\backups\legacy.tfstate"));
+        commands.push(shell.run(r"C:\tools\terraform.exe", &["workspace", "delete", "legacy"]));
+        let script = shell.render("https://tfe.example.com", &[("acme/legacy".to_string(), commands)]);
+
+        assert!(script.contains("$ErrorActionPreference = 'Stop'\n"));
+        assert!(script.contains("if (-not $env:TFE_ADDRESS) { $env:TFE_ADDRESS = 'https://tfe.example.com' }\n"));
+        assert!(script.contains("\n# acme/legacy\ntry {\n    Invoke-RestMethod -Method POST -Headers $headers -ContentType 'application/vnd.api+json' \
+            -Uri (\"$env:TFE_ADDRESS/api/v2\" + '/workspaces/ws-1/actions/lock') -Body '{\"reason\":\"it''s stale\"}'\n"));
+        assert!(script.contains("$url = (Invoke-RestMethod -Method GET -Headers $headers"));
+        assert!(script.contains("Invoke-WebRequest -Headers $headers -Uri $url -OutFile 'C:\\backups\\legacy.tfstate'\n"));
+        assert!(script.contains("& 'C:\\tools\\terraform.exe' 'workspace' 'delete' 'legacy'; if ($LASTEXITCODE -ne 0) {"));
+        assert!(script.contains("} catch {\n    Write-Warning 'acme/legacy failed; going on with the next workspace'\n    $failed++\n}\n"));
+        assert!(!script.contains("curl"));
+    }
+}
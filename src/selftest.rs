@@ -0,0 +1,338 @@
+use crate::actions::{self, Action, ActionContext, Archive, Delete, Lock, PipelineResult, Tag};
+use crate::archive;
+use crate::doctor::Check;
+use crate::history::History;
+use crate::scan;
+use crate::staleness::Policy;
+use crate::tfe::{self, ApiError, TfeClient};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use md5::{Digest, Md5};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Names of the throwaway workspaces start with this, so a sandbox can be told apart from an
+/// organization in use and leftovers of interrupted runs can be found.
+pub const NAME_PREFIX: &str = "tfe-cleanup-selftest-";
+
+/// Settings of a deleted workspace a restore recreates it with.
+const RESTORED_ATTRIBUTES: &[&str] = &["name", "description", "terraform-version", "auto-apply", "working-directory", "tag-names"];
+
+/// Serial of the state each throwaway workspace is given, so the delete pipeline has a state to
+/// archive and the restore one to bring back.
+const STATE_SERIAL: i64 = 1;
+
+/// Names for `count` throwaway workspaces, unique to this run.
+pub fn workspace_names(count: usize) -> Vec<String> {
+    let run = Utc::now().format("%Y%m%d%H%M%S");
+    (1..=count).map(|i| format!("{}{}-{}", NAME_PREFIX, run, i)).collect()
+}
+
+/// Runs the lifecycle a cleanup puts stale workspaces through on throwaway workspaces named
+/// `names` in the sandbox organization `org`: create, scan, quarantine (lock and tag with
+/// `tag`), archive and delete, then restore from the archive. Each step is verified against the
+/// API and the first failure ends the lifecycle. The workspaces and their archived states are
+/// removed at the end whatever happened.
+pub async fn run(client: &TfeClient, org: &str, names: &[String], tag: &str) -> Result<Vec<Check>, Box<dyn Error + Send + Sync>> {
+    let mut checks = vec![sandbox(client, org).await];
+    if checks[0].passed() {
+        let history = History::open_in_memory()?;
+        let context = ActionContext { client, history: &history, events: None, kill_switch: None, windows: &[] };
+        let dir = archive_dir();
+        lifecycle(&context, org, names, tag, &dir, &mut checks).await;
+        checks.push(teardown(client, org, names, &dir).await);
+    }
+    Ok(checks)
+}
+
+/// Where this run archives the states of its throwaway workspaces.
+fn archive_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("tfe_cleanup_selftest-{}", std::process::id()))
+}
+
+/// Refuses organizations with workspaces the selftest didn't create.
+async fn sandbox(client: &TfeClient, org: &str) -> Check {
+    let outcome = match tfe::list_workspaces(client, org).await {
+        Ok(workspaces) => {
+            let foreign = workspaces.iter()
+                .filter(|workspace| !workspace["attributes"]["name"].as_str().unwrap_or("").starts_with(NAME_PREFIX))
+                .count();
+            if foreign == 0 {
+                Ok(format!("{} has no workspaces besides selftest ones", org))
+            } else {
+                Err(format!("{} has {} workspaces not created by selftest; use a dedicated sandbox organization", org, foreign))
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    Check::new("Sandbox", outcome)
+}
+
+/// Adds a check for the step, returning whether it passed.
+fn record(checks: &mut Vec<Check>, step: &str, outcome: Result<String, String>) -> bool {
+    checks.push(Check::new(step, outcome));
+    checks.last().is_some_and(Check::passed)
+}
+
+async fn lifecycle(context: &ActionContext<'_>, org: &str, names: &[String], tag: &str, dir: &Path, checks: &mut Vec<Check>) {
+    let client = context.client;
+    if !record(checks, "Create", create(client, org, names).await) {
+        return;
+    }
+
+    let workspaces = match flagged(client, org, names).await {
+        Ok(workspaces) => workspaces,
+        Err(e) => {
+            record(checks, "Scan", Err(e));
+            return;
+        }
+    };
+    if !record(checks, "Scan", Ok(format!("all {} workspaces flagged", names.len()))) {
+        return;
+    }
+    let snapshots: Vec<Value> = workspaces.iter().map(snapshot).collect();
+
+    let quarantine: Vec<Box<dyn Action>> = vec![Box::new(Lock), Box::new(Tag { tag: tag.to_string() })];
+    if !record(checks, "Quarantine", quarantined(context, &quarantine, &workspaces, tag).await) {
+        return;
+    }
+
+    // The self-test's own workspaces never manage resources, so nothing can be orphaned
+    let delete: Vec<Box<dyn Action>> = vec![
+        Box::new(Archive { dir: dir.to_path_buf() }),
+        Box::new(Delete { terraform_bin: None, delete_if_empty: true }),
+    ];
+    if !record(checks, "Delete", deleted(context, &delete, &workspaces, org).await) {
+        return;
+    }
+
+    record(checks, "Restore", restored(client, org, &snapshots, dir).await);
+}
+
+fn workspace_body(attributes: Value) -> Value {
+    json!({ "data": { "type": "workspaces", "attributes": attributes } })
+}
+
+/// A state without resources, which leaves the workspace safe to delete.
+fn throwaway_state(name: &str) -> Vec<u8> {
+    json!({ "version": 4, "serial": STATE_SERIAL, "lineage": name, "outputs": {}, "resources": [] }).to_string().into_bytes()
+}
+
+/// Makes `state` the workspace's current state. TFE only takes states for locked workspaces, so
+/// the workspace is locked for the upload.
+async fn upload_state(client: &TfeClient, workspace_id: &str, state: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let parsed: Value = serde_json::from_slice(state)?;
+    let attributes = json!({
+        "serial": parsed["serial"],
+        "lineage": parsed["lineage"],
+        "md5": format!("{:x}", Md5::digest(state)),
+        "state": STANDARD.encode(state),
+    });
+    client.post(&format!("/workspaces/{}/actions/lock", workspace_id), &json!({ "reason": "tfe_cleanup selftest" })).await?;
+    let uploaded = client.post(&format!("/workspaces/{}/state-versions", workspace_id),
+        &json!({ "data": { "type": "state-versions", "attributes": attributes } })).await;
+    client.post(&format!("/workspaces/{}/actions/unlock", workspace_id), &json!({})).await?;
+    uploaded.map(|_| ())
+}
+
+async fn create(client: &TfeClient, org: &str, names: &[String]) -> Result<String, String> {
+    for name in names {
+        let attributes = json!({ "name": name, "description": "Throwaway workspace of tfe_cleanup selftest" });
+        let created = client.post(&format!("/organizations/{}/workspaces", org), &workspace_body(attributes)).await
+            .map_err(|e| format!("cannot create {}: {}", name, e))?;
+        upload_state(client, created["data"]["id"].as_str().unwrap_or(""), &throwaway_state(name)).await
+            .map_err(|e| format!("cannot give {} a state: {}", name, e))?;
+    }
+    Ok(format!("created {}, each with a state", names.join(", ")))
+}
+
+/// Scans the organization with a zero-day threshold, which flags every workspace, and returns
+/// the throwaway ones as the scan listed them.
+async fn flagged(client: &TfeClient, org: &str, names: &[String]) -> Result<Vec<Value>, String> {
+    let policy = Policy { threshold_days: 0, ..Policy::default() };
+    let scan = scan::scan(client, &[org.to_string()], None, None, &policy, Utc::now(), |_, _| {}).await.map_err(|e| e.to_string())?;
+    if let Some(error) = scan.errors.values().next() {
+        return Err(error.clone());
+    }
+    names.iter()
+        .map(|name| scan.stale.iter()
+            .find(|workspace| workspace["attributes"]["name"] == name.as_str())
+            .cloned()
+            .ok_or_else(|| format!("{} was not flagged", name)))
+        .collect()
+}
+
+fn snapshot(workspace: &Value) -> Value {
+    RESTORED_ATTRIBUTES.iter()
+        .filter(|attribute| !workspace["attributes"][**attribute].is_null())
+        .map(|attribute| (attribute.to_string(), workspace["attributes"][*attribute].clone()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+async fn run_pipeline(context: &ActionContext<'_>, pipeline: &[Box<dyn Action>], workspace: &Value) -> Result<(), String> {
+    match actions::run_pipeline(pipeline, context, workspace).await.map_err(|e| e.to_string())? {
+        PipelineResult::Completed(_) => Ok(()),
+        _ => Err(format!("the pipeline did not complete for {}", workspace["attributes"]["name"])),
+    }
+}
+
+async fn quarantined(context: &ActionContext<'_>, pipeline: &[Box<dyn Action>], workspaces: &[Value], tag: &str) -> Result<String, String> {
+    for workspace in workspaces {
+        run_pipeline(context, pipeline, workspace).await?;
+        let id = workspace["id"].as_str().unwrap_or("");
+        let current = context.client.get(&format!("/workspaces/{}", id)).await.map_err(|e| e.to_string())?;
+        let attributes = &current["data"]["attributes"];
+        if attributes["locked"] != true {
+            return Err(format!("{} is not locked", id));
+        }
+        if !attributes["tag-names"].as_array().is_some_and(|tags| tags.iter().any(|t| t == tag)) {
+            return Err(format!("{} is not tagged {}", id, tag));
+        }
+    }
+    Ok(format!("locked and tagged {}", tag))
+}
+
+async fn deleted(context: &ActionContext<'_>, pipeline: &[Box<dyn Action>], workspaces: &[Value], org: &str) -> Result<String, String> {
+    for workspace in workspaces {
+        run_pipeline(context, pipeline, workspace).await?;
+        let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+        if tfe::workspace_exists(context.client, org, name).await.map_err(|e| e.to_string())? {
+            return Err(format!("{} still exists", name));
+        }
+    }
+    Ok("archived and deleted; gone from the API".to_string())
+}
+
+/// Recreates the deleted workspaces from their snapshots, unlocked and without the quarantine
+/// tag, and brings back the state the delete pipeline archived in `dir`.
+async fn restored(client: &TfeClient, org: &str, snapshots: &[Value], dir: &Path) -> Result<String, String> {
+    for snapshot in snapshots {
+        let name = snapshot["name"].as_str().unwrap_or("");
+        let created = client.post(&format!("/organizations/{}/workspaces", org), &workspace_body(snapshot.clone())).await
+            .map_err(|e| format!("cannot restore {}: {}", name, e))?;
+        let attributes = &created["data"]["attributes"];
+        if attributes["name"] != snapshot["name"] || attributes["locked"] == true {
+            return Err(format!("{} was not restored as it was", name));
+        }
+
+        let path = archive::archive_path(dir, org, name, STATE_SERIAL);
+        let archived = fs::read(&path).map_err(|e| format!("no archived state of {} at {}: {}", name, path.display(), e))?;
+        let id = created["data"]["id"].as_str().unwrap_or("");
+        upload_state(client, id, &archived).await.map_err(|e| format!("cannot restore the state of {}: {}", name, e))?;
+        let current = client.get(&format!("/workspaces/{}/current-state-version", id)).await.map_err(|e| e.to_string())?;
+        archive::verify_state(&archived, &current["data"]).map_err(|e| format!("the restored state of {} is not the archived one: {}", name, e))?;
+    }
+    Ok(format!("recreated {} workspaces with their archived state", snapshots.len()))
+}
+
+/// Deletes whatever is left of the throwaway workspaces and their archived states.
+async fn teardown(client: &TfeClient, org: &str, names: &[String], dir: &Path) -> Check {
+    let mut errors = Vec::new();
+    for name in names {
+        if let Err(e) = client.delete(&format!("/organizations/{}/workspaces/{}", org, name)).await {
+            if !matches!(e.downcast_ref::<ApiError>(), Some(api_err) if api_err.status == StatusCode::NOT_FOUND) {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+    if let Err(e) = fs::remove_dir_all(dir) {
+        if e.kind() != ErrorKind::NotFound {
+            errors.push(format!("{}: {}", dir.display(), e));
+        }
+    }
+    let outcome = if errors.is_empty() {
+        Ok(format!("removed the {} throwaway workspaces and their archives", names.len()))
+    } else {
+        Err(format!("delete these by hand: {}", errors.join("; ")))
+    };
+    Check::new("Teardown", outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let name = "tfe-cleanup-selftest-t-1";
+        let listed = json!({
+            "id": "ws-selftest",
+            "attributes": { "name": name, "description": "Throwaway", "created-at": "2020-01-01T00:00:00Z" },
+            "relationships": { "organization": { "data": { "id": "selftest-org" } } }
+        });
+        let _list = mock("GET", "/api/v2/organizations/selftest-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [listed] }).to_string())
+            .create();
+        let created = mock("POST", "/api/v2/organizations/selftest-org/workspaces")
+            .with_status(201)
+            .with_body(json!({ "data": { "id": "ws-selftest", "attributes": { "name": name, "locked": false } } }).to_string())
+            .expect(2)
+            .create();
+        let _lock = mock("POST", "/api/v2/workspaces/ws-selftest/actions/lock").with_status(200).with_body("{}").create();
+        let _unlock = mock("POST", "/api/v2/workspaces/ws-selftest/actions/unlock").with_status(200).with_body("{}").create();
+        let state = throwaway_state(name);
+        let md5 = format!("{:x}", Md5::digest(&state));
+        let uploaded = mock("POST", "/api/v2/workspaces/ws-selftest/state-versions")
+            .match_body(Matcher::PartialJson(json!({ "data": { "attributes": { "serial": STATE_SERIAL, "md5": md5, "state": STANDARD.encode(&state) } } })))
+            .with_status(201)
+            .with_body("{}")
+            .expect(2)
+            .create();
+        let _tag = mock("POST", "/api/v2/workspaces/ws-selftest/relationships/tags").with_status(204).create();
+        let _quarantined = mock("GET", "/api/v2/workspaces/ws-selftest")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "locked": true, "tag-names": ["stale"] } } }).to_string())
+            .create();
+        let _state_version = mock("GET", "/api/v2/workspaces/ws-selftest/current-state-version")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": {
+                "serial": STATE_SERIAL, "size": state.len(), "md5": md5,
+                "hosted-state-download-url": format!("{}/selftest-state", server_url())
+            } } }).to_string())
+            .create();
+        let _download = mock("GET", "/selftest-state").with_status(200).with_body(&state).create();
+        let _safe_delete = mock("POST", "/api/v2/organizations/selftest-org/workspaces/tfe-cleanup-selftest-t-1/actions/safe-delete")
+            .with_status(204)
+            .create();
+        let _gone = mock("GET", "/api/v2/organizations/selftest-org/workspaces/tfe-cleanup-selftest-t-1").with_status(404).create();
+        let teardown = mock("DELETE", "/api/v2/organizations/selftest-org/workspaces/tfe-cleanup-selftest-t-1")
+            .with_status(204)
+            .expect(1)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let checks = run(&client, "selftest-org", &[name.to_string()], "stale").await.unwrap();
+
+        let rendered = crate::doctor::render(&checks);
+        assert!(checks.iter().all(Check::passed), "{}", rendered);
+        let steps: Vec<&str> = checks.iter().map(|check| check.name.as_str()).collect();
+        assert_eq!(steps, vec!["Sandbox", "Create", "Scan", "Quarantine", "Delete", "Restore", "Teardown"]);
+        created.assert();
+        uploaded.assert();
+        teardown.assert();
+        assert!(!archive_dir().exists());
+    }
+
+    #[tokio::test]
+    async fn test_refuses_organizations_in_use() {
+        let _list = mock("GET", "/api/v2/organizations/busy-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "attributes": { "name": "billing-prod" } }] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let checks = run(&client, "busy-org", &workspace_names(1), "stale").await.unwrap();
+
+        assert_eq!(checks.len(), 1);
+        assert!(!checks[0].passed());
+    }
+}
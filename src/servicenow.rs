@@ -0,0 +1,175 @@
+use crate::{redact, tfe};
+use serde_json::{json, Value};
+use std::env;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// How often the change request's approval is checked while the cleanup waits for it.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the cleanup waits for an approval by default, in minutes.
+pub const DEFAULT_MAX_WAIT_MINUTES: u64 = 24 * 60;
+
+/// A change request created for a deletion plan.
+#[derive(Debug, PartialEq)]
+pub struct ChangeRequest {
+    pub sys_id: String,
+    pub number: String,
+}
+
+/// Creates change requests through the ServiceNow Table API and polls their approval.
+pub struct ServiceNow {
+    client: reqwest::Client,
+    base_url: String,
+    user: String,
+    password: String,
+}
+
+impl ServiceNow {
+    pub fn new(base_url: &str, user: &str, password: &str) -> ServiceNow {
+        redact::register(password);
+        ServiceNow {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Builds a client from `SERVICENOW_INSTANCE` (e.g. `https://acme.service-now.com`),
+    /// `SERVICENOW_USER` and `SERVICENOW_PASSWORD`.
+    pub fn from_env() -> Result<ServiceNow, Box<dyn Error + Send + Sync>> {
+        let var = |name: &str| env::var(name).map_err(|_| format!("{} not set in environment", name));
+        Ok(ServiceNow::new(&var("SERVICENOW_INSTANCE")?, &var("SERVICENOW_USER")?, &var("SERVICENOW_PASSWORD")?))
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder, path: &str) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let response = request
+            .basic_auth(&self.user, Some(&self.password))
+            .header("Accept", "application/json")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(format!("ServiceNow API returned {} for {}: {}", status, path, response.text().await.unwrap_or_default()).into());
+        }
+        Ok(response.json::<Value>().await?["result"].take())
+    }
+
+    /// Opens a normal change request listing every workspace in the deletion plan.
+    pub async fn create_change_request(&self, planned: &[Value]) -> Result<ChangeRequest, Box<dyn Error + Send + Sync>> {
+        let path = "/api/now/table/change_request";
+        let result = self.send(self.client.post(format!("{}{}", self.base_url, path)).json(&change_request_body(planned)), path).await?;
+
+        Ok(ChangeRequest {
+            sys_id: result["sys_id"].as_str().ok_or("ServiceNow returned no sys_id")?.to_string(),
+            number: result["number"].as_str().unwrap_or("").to_string(),
+        })
+    }
+
+    /// The current `approval` value of a change request, e.g. "requested" or "approved".
+    pub async fn approval(&self, change: &ChangeRequest) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let path = format!("/api/now/table/change_request/{}", change.sys_id);
+        let result = self.send(self.client.get(format!("{}{}", self.base_url, path))
+            .query(&[("sysparm_fields", "approval")]), &path).await?;
+        Ok(result["approval"].as_str().unwrap_or("").to_string())
+    }
+
+    /// Blocks until the change request is approved. A rejected change request, or one still
+    /// not approved after `max_wait`, is an error.
+    pub async fn wait_for_approval(&self, change: &ChangeRequest, interval: Duration, max_wait: Duration) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let started = Instant::now();
+        loop {
+            match self.approval(change).await?.as_str() {
+                "approved" => return Ok(()),
+                "rejected" => return Err(format!("change request {} was rejected", change.number).into()),
+                _ if started.elapsed() >= max_wait => {
+                    return Err(format!("change request {} was not approved within {} minutes", change.number, max_wait.as_secs() / 60).into());
+                }
+                state => eprintln!("Change request {} is '{}', checking again in {}s...", change.number, state, interval.as_secs()),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+fn change_request_body(planned: &[Value]) -> Value {
+    let plan: Vec<String> = planned.iter()
+        .map(|ws| format!("- {}/{}", tfe::workspace_org(ws), ws["attributes"]["name"].as_str().unwrap_or("")))
+        .collect();
+
+    json!({
+        "type": "normal",
+        "short_description": format!("Delete {} stale Terraform workspaces", planned.len()),
+        "description": format!("tfe_cleanup will delete the following workspaces once this change is approved:\n{}", plan.join("\n")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    fn workspace(org: &str, name: &str) -> Value {
+        json!({ "attributes": { "name": name }, "relationships": { "organization": { "data": { "id": org } } } })
+    }
+
+    #[test]
+    fn test_change_request_body() {
+        let body = change_request_body(&[workspace("acme", "old"), workspace("acme", "older")]);
+        assert_eq!(body["short_description"], "Delete 2 stale Terraform workspaces");
+        assert!(body["description"].as_str().unwrap().ends_with(":\n- acme/old\n- acme/older"));
+    }
+
+    #[tokio::test]
+    async fn test_create_and_wait_for_approval() {
+        let create = mock("POST", "/api/now/table/change_request")
+            .match_header("authorization", Matcher::Regex("^Basic ".into()))
+            .with_status(201)
+            .with_body(json!({ "result": { "sys_id": "cr-sys-1", "number": "CHG0001" } }).to_string())
+            .create();
+        let _approval = mock("GET", "/api/now/table/change_request/cr-sys-1")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "result": { "approval": "approved" } }).to_string())
+            .create();
+
+        let servicenow = ServiceNow::new(&server_url(), "svc", "secret");
+        let change = servicenow.create_change_request(&[workspace("acme", "old")]).await.unwrap();
+        assert_eq!(change, ChangeRequest { sys_id: "cr-sys-1".to_string(), number: "CHG0001".to_string() });
+
+        servicenow.wait_for_approval(&change, Duration::from_millis(1), Duration::from_secs(60)).await.unwrap();
+        create.assert();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_change_request_is_an_error() {
+        let _approval = mock("GET", "/api/now/table/change_request/cr-sys-rejected")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "result": { "approval": "rejected" } }).to_string())
+            .create();
+
+        let servicenow = ServiceNow::new(&server_url(), "svc", "secret");
+        let change = ChangeRequest { sys_id: "cr-sys-rejected".to_string(), number: "CHG0002".to_string() };
+
+        assert!(servicenow.wait_for_approval(&change, Duration::from_millis(1), Duration::from_secs(60)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_approval_gives_up() {
+        let approval = mock("GET", "/api/now/table/change_request/cr-sys-pending")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "result": { "approval": "requested" } }).to_string())
+            .expect_at_least(2)
+            .create();
+
+        let servicenow = ServiceNow::new(&server_url(), "svc", "secret");
+        let change = ChangeRequest { sys_id: "cr-sys-pending".to_string(), number: "CHG0003".to_string() };
+        let error = servicenow.wait_for_approval(&change, Duration::from_millis(5), Duration::from_millis(20)).await.unwrap_err();
+
+        assert!(error.to_string().starts_with("change request CHG0003 was not approved within"));
+        approval.assert();
+    }
+}
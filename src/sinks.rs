@@ -0,0 +1,696 @@
+use crate::config::{NotificationConfig, SinkConfig, SinkFormat, SmtpTls};
+use crate::notify::{self, ScanResults};
+use crate::redact::{self, Secret};
+use crate::report::{self, Column, ReportContext};
+use crate::{i18n, staleness, tfe, timefmt};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+/// Everything a sink may render: the stale workspaces with the selected CSV columns, and
+/// the typed results also given to notification templates.
+pub struct Report<'a> {
+    pub stale: &'a [Value],
+    pub columns: &'a [Column],
+    pub context: &'a ReportContext,
+    pub results: &'a ScanResults,
+}
+
+/// A destination for the report of a run. Several sinks can be active per run.
+#[async_trait]
+pub trait ReportSink: Send + Sync {
+    /// Short description used in progress and error messages, e.g. "JSON report.json".
+    fn describe(&self) -> String;
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Checks that the destination can be reached, without writing anything. `None` for sinks
+    /// with nothing to check ahead of a run.
+    async fn probe(&self) -> Option<Result<(), Box<dyn Error + Send + Sync>>> {
+        None
+    }
+}
+
+/// The configured sinks, or only stdout when none are configured. Email bodies fall back to the
+/// templates of `notifications`.
+pub fn from_config(configs: &[SinkConfig], notifications: &NotificationConfig) -> Vec<Box<dyn ReportSink>> {
+    if configs.is_empty() {
+        return vec![Box::new(StdoutSink)];
+    }
+
+    configs.iter()
+        .map(|config| -> Box<dyn ReportSink> {
+            match config {
+                SinkConfig::Stdout => Box::new(StdoutSink),
+                SinkConfig::Csv { path } => Box::new(CsvSink { path: path.clone() }),
+                SinkConfig::Json { path } => Box::new(JsonSink { path: path.clone() }),
+                SinkConfig::Html { path } => Box::new(HtmlSink { path: path.clone() }),
+                SinkConfig::S3 { bucket, key, region, endpoint, format } => Box::new(S3Sink {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    region: region.clone(),
+                    endpoint: endpoint.clone(),
+                    format: *format,
+                    credentials: None,
+                }),
+                SinkConfig::Webhook { url } => Box::new(WebhookSink { url: url.clone() }),
+                SinkConfig::Email { to, from, smtp_host, smtp_port, tls, subject, template, attach } => Box::new(EmailSink {
+                    to: to.clone(),
+                    from: from.clone(),
+                    smtp_host: smtp_host.clone(),
+                    smtp_port: *smtp_port,
+                    tls: *tls,
+                    subject: subject.clone().unwrap_or_else(|| DEFAULT_SUBJECT.to_string()),
+                    template: template.clone(),
+                    notifications: notifications.clone(),
+                    attach: attach.clone(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Prints one line per stale workspace; workspaces without activity data are listed separately.
+pub struct StdoutSink;
+
+#[async_trait]
+impl ReportSink for StdoutSink {
+    fn describe(&self) -> String {
+        "stdout".to_string()
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Results go to stdout, everything else to stderr
+        for (heading, lines) in text_sections(report) {
+            eprintln!("{}", heading);
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A workspace as `org/name`.
+fn qualified_name(workspace: &Value) -> String {
+    format!("{}/{}", tfe::workspace_org(workspace), workspace["attributes"]["name"].as_str().unwrap_or(""))
+}
+
+/// The stdout report as (heading, lines) sections. The sections of workspaces without activity
+/// data, of opted-out workspaces, of scan errors and of plaintext secrets are left out when
+/// there are none.
+fn text_sections(report: &Report<'_>) -> Vec<(String, Vec<String>)> {
+    let (now, timezone) = (report.context.now, report.context.timezone);
+    let (no_activity_data, with_activity): (Vec<&Value>, Vec<&Value>) = report.stale.iter()
+        .partition(|workspace| staleness::lacks_activity_data(workspace));
+
+    let mut sections = vec![(
+        i18n::format("report-stale", [("days", report.results.threshold_days.into())]),
+        with_activity.iter()
+            .map(|workspace| i18n::format("report-stale-workspace", [
+                ("workspace", qualified_name(workspace).into()),
+                ("when", timefmt::describe(workspace["attributes"]["last-activity-at"].as_str().unwrap_or(""), timezone, now).into()),
+            ]))
+            .collect(),
+    )];
+    if !no_activity_data.is_empty() {
+        sections.push((
+            i18n::format("report-no-activity-data", [("days", report.results.threshold_days.into())]),
+            no_activity_data.iter()
+                .map(|workspace| i18n::format("report-no-activity-data-workspace", [
+                    ("workspace", qualified_name(workspace).into()),
+                    ("when", timefmt::describe(workspace["attributes"]["created-at"].as_str().unwrap_or(""), timezone, now).into()),
+                ]))
+                .collect(),
+        ));
+    }
+    if !report.results.opted_out.is_empty() {
+        sections.push((i18n::text("report-opted-out"), report.results.opted_out.clone()));
+    }
+    if !report.results.errors.is_empty() {
+        sections.push((
+            i18n::text("report-errors"),
+            report.results.errors.iter().map(|error| format!("{}: {}", error.org, error.error)).collect(),
+        ));
+    }
+    if let Some(ages) = &report.results.ages {
+        sections.push((i18n::text("report-ages"), ages.render()));
+    }
+    if !report.results.plaintext_secrets.is_empty() {
+        sections.push((
+            i18n::text("report-secrets"),
+            report.results.plaintext_secrets.iter()
+                .map(|secret| format!("{}/{}  {} {}: {}", secret.org, secret.workspace,
+                    i18n::format("secret-variable", [("category", secret.category.as_str().into())]), secret.key, secret.kind))
+                .collect(),
+        ));
+    }
+    sections
+}
+
+/// Renders the stdout report as one string, headings included, e.g. for snapshot tests.
+#[cfg(test)]
+pub fn render_text(report: &Report<'_>) -> String {
+    text_sections(report).into_iter()
+        .flat_map(|(heading, lines)| std::iter::once(heading).chain(lines))
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// The report's columns as CSV.
+pub struct CsvSink {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl ReportSink for CsvSink {
+    fn describe(&self) -> String {
+        format!("CSV {}", self.path.display())
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        report::write_csv(File::create(&self.path)?, report.stale, report.columns, report.context)
+    }
+}
+
+/// The typed results as pretty-printed JSON.
+pub struct JsonSink {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl ReportSink for JsonSink {
+    fn describe(&self) -> String {
+        format!("JSON {}", self.path.display())
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        serde_json::to_writer_pretty(File::create(&self.path)?, report.results)?;
+        Ok(())
+    }
+}
+
+/// A standalone HTML page with the report's columns as a table.
+pub struct HtmlSink {
+    pub path: PathBuf,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn render_html(report: &Report<'_>) -> String {
+    let headers: String = report.columns.iter()
+        .map(|column| format!("<th>{}</th>", escape_html(&column.header())))
+        .collect();
+    let rows: String = report.stale.iter()
+        .map(|workspace| {
+            let cells: String = report.columns.iter()
+                .map(|column| format!("<td>{}</td>", escape_html(&report::value(*column, workspace, report.context))))
+                .collect();
+            format!("<tr>{}</tr>\n", cells)
+        })
+        .collect();
+    let errors = if report.results.errors.is_empty() {
+        String::new()
+    } else {
+        let items: String = report.results.errors.iter()
+            .map(|error| format!("<li>{}: {}</li>\n", escape_html(&error.org), escape_html(&error.error)))
+            .collect();
+        format!("<h2>{}</h2>\n<p>{}</p>\n<ul>\n{}</ul>\n",
+            escape_html(&i18n::text("html-errors")), escape_html(&i18n::text("html-errors-intro")), items)
+    };
+    let secrets = if report.results.plaintext_secrets.is_empty() {
+        String::new()
+    } else {
+        let items: String = report.results.plaintext_secrets.iter()
+            .map(|secret| format!("<li>{}/{}: {} <code>{}</code> ({})</li>\n", escape_html(&secret.org), escape_html(&secret.workspace),
+                escape_html(&i18n::format("secret-variable", [("category", secret.category.as_str().into())])),
+                escape_html(&secret.key), escape_html(&secret.kind)))
+            .collect();
+        format!("<h2>{}</h2>\n<p>{}</p>\n<ul>\n{}</ul>\n",
+            escape_html(&i18n::text("html-secrets")), escape_html(&i18n::text("html-secrets-intro")), items)
+    };
+
+    let title = escape_html(&i18n::text("html-title"));
+    let summary = i18n::format("html-summary", [
+        ("stale", report.results.stale_count.into()),
+        ("total", report.results.total_workspaces.into()),
+        ("days", report.results.threshold_days.into()),
+        ("generated", report.results.generated_at.as_str().into()),
+    ]);
+
+    format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n\
+        <h1>{}</h1>\n<p>{}</p>\n{}<table>\n<tr>{}</tr>\n{}</table>\n{}</body>\n</html>\n",
+        title, title, escape_html(&summary), errors, headers, rows, secrets)
+}
+
+#[async_trait]
+impl ReportSink for HtmlSink {
+    fn describe(&self) -> String {
+        format!("HTML {}", self.path.display())
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        fs::write(&self.path, render_html(report))?;
+        Ok(())
+    }
+}
+
+/// The report in a file format, with its content type and file extension.
+fn render_file(format: SinkFormat, report: &Report<'_>) -> Result<(Vec<u8>, &'static str, &'static str), Box<dyn Error + Send + Sync>> {
+    Ok(match format {
+        SinkFormat::Csv => {
+            let mut body = Vec::new();
+            report::write_csv(&mut body, report.stale, report.columns, report.context)?;
+            (body, "text/csv", "csv")
+        }
+        SinkFormat::Json => (serde_json::to_vec_pretty(report.results)?, "application/json", "json"),
+        SinkFormat::Html => (render_html(report).into_bytes(), "text/html; charset=utf-8", "html"),
+    })
+}
+
+/// Credentials S3 uploads are signed with.
+pub struct AwsCredentials {
+    pub access_key: String,
+    pub secret_key: Secret,
+    pub session_token: Option<Secret>,
+}
+
+impl AwsCredentials {
+    /// Reads the standard `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, if set,
+    /// `AWS_SESSION_TOKEN` environment variables.
+    pub fn from_env() -> Result<AwsCredentials, Box<dyn Error + Send + Sync>> {
+        Ok(AwsCredentials {
+            access_key: env::var("AWS_ACCESS_KEY_ID").map_err(|_| "AWS_ACCESS_KEY_ID not set in environment")?,
+            secret_key: Secret::new(env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| "AWS_SECRET_ACCESS_KEY not set in environment")?),
+            session_token: env::var("AWS_SESSION_TOKEN").ok().map(Secret::new),
+        })
+    }
+}
+
+/// Uploads the CSV, JSON or HTML report to S3.
+pub struct S3Sink {
+    pub bucket: String,
+    pub key: String,
+    pub region: String,
+    /// S3-compatible endpoint addressed path-style, e.g. for MinIO. Defaults to AWS.
+    pub endpoint: Option<String>,
+    pub format: SinkFormat,
+    /// Read from the environment when the report is written if not given.
+    pub credentials: Option<AwsCredentials>,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The HMAC-SHA256 of `data` under `key`, also used to sign events.
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Lowercase hex of `bytes`, as signatures are sent.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// The AWS Signature Version 4 signing key for a day, region and service.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+/// Percent-encodes an S3 object key for the canonical URI, keeping `/` separators.
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+impl S3Sink {
+    /// The object URL and the host header it is signed for.
+    fn target(&self) -> (String, String) {
+        match &self.endpoint {
+            Some(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint.split("://").last().unwrap_or(endpoint).to_string();
+                (format!("{}/{}/{}", endpoint, self.bucket, encode_key(&self.key)), host)
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", self.bucket, self.region);
+                (format!("https://{}/{}", host, encode_key(&self.key)), host)
+            }
+        }
+    }
+
+    /// Headers for a SigV4-signed PUT of `body`.
+    fn signed_headers(
+        &self,
+        body: &[u8],
+        now: DateTime<Utc>,
+        credentials: &AwsCredentials,
+    ) -> Vec<(String, String)> {
+        let (url, host) = self.target();
+        let path = url.splitn(4, '/').nth(3).map(|path| format!("/{}", path)).unwrap_or_else(|| "/".to_string());
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex_sha256(body);
+
+        let mut headers = vec![
+            ("host".to_string(), host),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.expose().to_string()));
+        }
+
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+        let signed_names = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_request = format!("PUT\n{}\n\n{}\n{}\n{}", path, canonical_headers, signed_names, payload_hash);
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex_sha256(canonical_request.as_bytes()));
+        let signature = hex(&hmac(&signing_key(credentials.secret_key.expose(), &date, &self.region, "s3"), string_to_sign.as_bytes()));
+
+        headers.retain(|(name, _)| name != "host");
+        headers.push(("authorization".to_string(), format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", credentials.access_key, scope, signed_names, signature)));
+        headers
+    }
+}
+
+#[async_trait]
+impl ReportSink for S3Sink {
+    fn describe(&self) -> String {
+        format!("S3 s3://{}/{}", self.bucket, self.key)
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let from_env;
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials,
+            None => {
+                from_env = AwsCredentials::from_env()?;
+                &from_env
+            }
+        };
+
+        let (body, content_type, _) = render_file(self.format, report)?;
+
+        let mut request = reqwest::Client::new().put(self.target().0).header("content-type", content_type);
+        for (name, value) in self.signed_headers(&body, Utc::now(), credentials) {
+            request = request.header(name, value);
+        }
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(format!("S3 returned {}: {}", status, response.text().await.unwrap_or_default()).into());
+        }
+        Ok(())
+    }
+}
+
+/// POSTs the typed results as JSON.
+pub struct WebhookSink {
+    pub url: Secret,
+}
+
+#[async_trait]
+impl ReportSink for WebhookSink {
+    fn describe(&self) -> String {
+        format!("webhook {}", redact::url(self.url.expose()))
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = reqwest::Client::new().post(self.url.expose()).json(report.results).send().await
+            .map_err(|e| e.without_url())?;
+        if !response.status().is_success() {
+            return Err(format!("webhook returned {}", response.status()).into());
+        }
+        Ok(())
+    }
+
+    async fn probe(&self) -> Option<Result<(), Box<dyn Error + Send + Sync>>> {
+        Some(notify::probe_url(self.url.expose()).await)
+    }
+}
+
+/// Subject of report emails unless configured.
+pub const DEFAULT_SUBJECT: &str = "TFE cleanup {{command}}: {{stale_count}} of {{total_workspaces}} workspaces stale";
+
+/// Mails the report to a distribution list: the notification text as the body, rendered with
+/// the sink's own template or else the notifications', and the report files as attachments.
+pub struct EmailSink {
+    pub to: Vec<String>,
+    pub from: String,
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    pub tls: SmtpTls,
+    pub subject: String,
+    pub template: Option<PathBuf>,
+    pub notifications: NotificationConfig,
+    pub attach: Vec<SinkFormat>,
+}
+
+impl EmailSink {
+    fn message(&self, report: &Report<'_>) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let mut builder = Message::builder()
+            .from(self.from.parse()?)
+            .subject(notify::render(&self.subject, report.results)?);
+        for recipient in &self.to {
+            builder = builder.to(recipient.parse()?);
+        }
+
+        let mut body = MultiPart::mixed()
+            .singlepart(SinglePart::plain(notify::render(&notify::template_for(&self.notifications, &self.template)?, report.results)?));
+        for format in &self.attach {
+            let (content, content_type, extension) = render_file(*format, report)?;
+            body = body.singlepart(Attachment::new(format!("stale_workspaces.{}", extension))
+                .body(content, ContentType::parse(content_type)?));
+        }
+        Ok(builder.multipart(body)?)
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, Box<dyn Error + Send + Sync>> {
+        let mut builder = match self.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.smtp_host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.smtp_host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.smtp_host),
+        };
+        if let Some(port) = self.smtp_port {
+            builder = builder.port(port);
+        }
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            redact::register(&password);
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(builder.build())
+    }
+}
+
+#[async_trait]
+impl ReportSink for EmailSink {
+    fn describe(&self) -> String {
+        format!("email to {}", self.to.join(", "))
+    }
+
+    async fn write(&self, report: &Report<'_>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.transport()?.send(self.message(report)?).await?;
+        Ok(())
+    }
+
+    async fn probe(&self) -> Option<Result<(), Box<dyn Error + Send + Sync>>> {
+        let connected = async { Ok::<_, Box<dyn Error + Send + Sync>>(self.transport()?.test_connection().await?) };
+        Some(match connected.await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("SMTP relay {} did not accept the connection", self.smtp_host).into()),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::staleness::Policy;
+    use crate::variables::PlaintextSecret;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    fn stale() -> Vec<Value> {
+        vec![json!({
+            "id": "ws-1",
+            "attributes": { "name": "old <app>", "last-activity-at": "2020-01-01T00:00:00Z" },
+            "relationships": { "organization": { "data": { "id": "acme" } } }
+        })]
+    }
+
+    #[test]
+    fn test_signing_key_matches_aws_example() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn test_encode_key() {
+        assert_eq!(encode_key("reports/2024 01/stale.csv"), "reports/2024%2001/stale.csv");
+    }
+
+    #[test]
+    fn test_render_html_escapes_values() {
+        let stale = stale();
+        let context = ReportContext::default();
+        let results = ScanResults::new("scan", &tfe::count_by_org(&stale), &stale, &Policy::default());
+        let report = Report { stale: &stale, columns: &[Column::Name, Column::Org], context: &context, results: &results };
+
+        let html = render_html(&report);
+
+        assert!(html.contains("<tr><th>Name</th><th>Organization</th></tr>"));
+        assert!(html.contains("<tr><td>old &lt;app&gt;</td><td>acme</td></tr>"));
+    }
+
+    #[test]
+    fn test_plaintext_secrets_section() {
+        let stale = stale();
+        let mut context = ReportContext::default();
+        context.plaintext_secrets.insert("ws-1".to_string(), vec![PlaintextSecret {
+            key: "AWS_ACCESS_KEY_ID".to_string(),
+            category: "env".to_string(),
+            kind: "AWS access key ID".to_string(),
+        }]);
+        let results = ScanResults::new("scan", &tfe::count_by_org(&stale), &stale, &Policy::default())
+            .with_plaintext_secrets(&stale, &context.plaintext_secrets);
+        let report = Report { stale: &stale, columns: &[Column::Name], context: &context, results: &results };
+
+        let text = render_text(&report);
+        assert!(text.contains("Potential plaintext secrets in non-sensitive variables:\nacme/old <app>  env variable AWS_ACCESS_KEY_ID: AWS access key ID\n"));
+        assert!(render_html(&report).contains("<li>acme/old &lt;app&gt;: env variable <code>AWS_ACCESS_KEY_ID</code> (AWS access key ID)</li>"));
+    }
+
+    #[tokio::test]
+    async fn test_s3_and_webhook_sinks_upload() {
+        let upload = mock("PUT", "/reports-bucket/tfe/stale.json")
+            .match_header("authorization", Matcher::Regex("^AWS4-HMAC-SHA256 Credential=AKIDTEST/".into()))
+            .match_header("x-amz-content-sha256", Matcher::Any)
+            .with_status(200)
+            .create();
+        let webhook = mock("POST", "/report-hook")
+            .match_body(Matcher::PartialJson(json!({ "stale_count": 1 })))
+            .with_status(204)
+            .create();
+
+        let stale = stale();
+        let context = ReportContext::default();
+        let results = ScanResults::new("scan", &tfe::count_by_org(&stale), &stale, &Policy::default());
+        let report = Report { stale: &stale, columns: report::DEFAULT_COLUMNS, context: &context, results: &results };
+
+        let sinks: Vec<Box<dyn ReportSink>> = vec![
+            Box::new(S3Sink {
+                bucket: "reports-bucket".to_string(),
+                key: "tfe/stale.json".to_string(),
+                region: "eu-west-1".to_string(),
+                endpoint: Some(server_url()),
+                format: SinkFormat::Json,
+                credentials: Some(AwsCredentials {
+                    access_key: "AKIDTEST".to_string(),
+                    secret_key: Secret::new("secret"),
+                    session_token: None,
+                }),
+            }),
+            Box::new(WebhookSink { url: Secret::new(format!("{}/report-hook", server_url())) }),
+        ];
+        for sink in &sinks {
+            sink.write(&report).await.unwrap();
+        }
+
+        upload.assert();
+        webhook.assert();
+    }
+
+    #[test]
+    fn test_email_message() {
+        let stale = stale();
+        let context = ReportContext::default();
+        let results = ScanResults::new("cleanup", &tfe::count_by_org(&stale), &stale, &Policy::default());
+        let report = Report { stale: &stale, columns: report::DEFAULT_COLUMNS, context: &context, results: &results };
+        let sink = EmailSink {
+            to: vec!["platform@example.com".to_string(), "finops@example.com".to_string()],
+            from: "tfe-cleanup@example.com".to_string(),
+            smtp_host: "smtp.example.com".to_string(),
+            smtp_port: None,
+            tls: SmtpTls::Starttls,
+            subject: DEFAULT_SUBJECT.to_string(),
+            template: None,
+            notifications: NotificationConfig::default(),
+            attach: vec![SinkFormat::Csv, SinkFormat::Html],
+        };
+
+        let message = String::from_utf8(sink.message(&report).unwrap().formatted()).unwrap();
+
+        assert!(message.contains("Subject: TFE cleanup cleanup: 1 of 1 workspaces stale"));
+        assert!(message.contains("To: platform@example.com, finops@example.com"));
+        assert!(message.contains("filename=\"stale_workspaces.csv\""));
+        assert!(message.contains("filename=\"stale_workspaces.html\""));
+        assert!(message.contains("- acme/old <app>, inactive for "));
+    }
+
+    #[test]
+    fn test_email_body_uses_the_configured_templates() {
+        let stale = stale();
+        let context = ReportContext::default();
+        let results = ScanResults::new("cleanup", &tfe::count_by_org(&stale), &stale, &Policy::default());
+        let report = Report { stale: &stale, columns: report::DEFAULT_COLUMNS, context: &context, results: &results };
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared.hbs");
+        fs::write(&shared, "Shared: {{stale_count}} stale").unwrap();
+        let own = dir.path().join("email.hbs");
+        fs::write(&own, "Email: {{stale_count}} stale").unwrap();
+        let notifications = NotificationConfig { template: Some(shared), ..NotificationConfig::default() };
+        let sink = |template: Option<PathBuf>| EmailSink {
+            to: vec!["platform@example.com".to_string()],
+            from: "tfe-cleanup@example.com".to_string(),
+            smtp_host: "smtp.example.com".to_string(),
+            smtp_port: None,
+            tls: SmtpTls::Starttls,
+            subject: DEFAULT_SUBJECT.to_string(),
+            template,
+            notifications: notifications.clone(),
+            attach: Vec::new(),
+        };
+
+        let message = String::from_utf8(sink(None).message(&report).unwrap().formatted()).unwrap();
+        assert!(message.contains("Shared: 1 stale"));
+        let message = String::from_utf8(sink(Some(own)).message(&report).unwrap().formatted()).unwrap();
+        assert!(message.contains("Email: 1 stale"));
+    }
+
+    #[test]
+    fn test_no_sinks_configured_means_stdout() {
+        let sinks = from_config(&[], &NotificationConfig::default());
+        assert_eq!(sinks.len(), 1);
+        assert_eq!(sinks[0].describe(), "stdout");
+    }
+}
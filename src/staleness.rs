@@ -0,0 +1,428 @@
+use crate::config::Config;
+use crate::human_activity;
+use crate::run_lock;
+use crate::tfe;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Workspaces without activity for longer than this are considered stale.
+pub const DEFAULT_THRESHOLD_DAYS: i64 = 90;
+
+/// Markers that opt a workspace out of cleanup unless the config sets its own.
+pub const DEFAULT_OPT_OUT_MARKERS: [&str; 2] = ["[keep]", "tfe-cleanup:ignore"];
+
+/// The rules deciding which workspaces are flagged for cleanup.
+#[derive(Debug)]
+pub struct Policy {
+    pub threshold_days: i64,
+    /// Threshold for workspaces without a VCS connection, if they are treated differently.
+    pub no_vcs_threshold_days: Option<i64>,
+    pub exclude: Vec<Regex>,
+    /// Workspaces excluded one by one, as `<org>/<name>`.
+    pub exclude_workspaces: HashSet<String>,
+    /// If not empty, only workspaces whose name matches one of these are considered.
+    pub include: Vec<Regex>,
+    /// Names of workspaces created per pull request; the first group is the PR number.
+    pub pull_request: Option<Regex>,
+    /// Text in a workspace's description or tags that opts it out of cleanup.
+    pub opt_out_markers: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            threshold_days: DEFAULT_THRESHOLD_DAYS,
+            no_vcs_threshold_days: None,
+            exclude: Vec::new(),
+            exclude_workspaces: HashSet::new(),
+            include: Vec::new(),
+            pull_request: None,
+            opt_out_markers: DEFAULT_OPT_OUT_MARKERS.map(String::from).to_vec(),
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_config(config: &Config) -> Result<Policy, String> {
+        let patterns = |kind: &str, patterns: &[String]| patterns.iter()
+            .map(|pattern| Regex::new(pattern).map_err(|e| format!("invalid {} pattern '{}': {}", kind, pattern, e)))
+            .collect::<Result<Vec<_>, _>>();
+        let exclude = patterns("exclude", &config.exclude)?;
+        let include = patterns("include", &config.include)?;
+        let pull_request = match &config.pull_requests {
+            Some(pull_requests) => patterns("pull request", std::slice::from_ref(&pull_requests.pattern))?.pop(),
+            None => None,
+        };
+
+        Ok(Policy {
+            threshold_days: config.stale_after_days,
+            no_vcs_threshold_days: config.no_vcs_stale_after_days,
+            exclude,
+            exclude_workspaces: config.exclude_workspaces.iter().cloned().collect(),
+            include,
+            pull_request,
+            opt_out_markers: config.opt_out_markers.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Flagged,
+    Kept,
+    Excluded,
+    OptedOut,
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Flagged => "FLAGGED",
+            Status::Kept => "KEPT",
+            Status::Excluded => "EXCLUDED",
+            Status::OptedOut => "OPTED OUT",
+        }
+    }
+}
+
+/// Whether a workspace is stale, the rule that decided it, and every rule evaluated on the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub status: Status,
+    pub rule: String,
+    pub trace: Vec<String>,
+}
+
+impl Verdict {
+    pub fn is_stale(&self) -> bool {
+        self.status == Status::Flagged
+    }
+
+    /// The verdict once a check made after the evaluation, e.g. of a pull request, flags the
+    /// workspace after all.
+    pub fn flag(self, rule: String) -> Verdict {
+        Verdict::decide(Status::Flagged, rule, self.trace)
+    }
+
+    fn decide(status: Status, rule: String, mut trace: Vec<String>) -> Verdict {
+        trace.push(rule.clone());
+        Verdict { status, rule, trace }
+    }
+}
+
+/// `last-human-activity-at` if the workspace was enriched with its human activity, even when
+/// none was found, otherwise `last-activity-at`.
+fn activity_attribute(workspace: &Value) -> &'static str {
+    match workspace["attributes"].get(human_activity::ATTRIBUTE) {
+        Some(_) => human_activity::ATTRIBUTE,
+        None => "last-activity-at",
+    }
+}
+
+/// The timestamp staleness is measured from: `last-activity-at`, or `created-at` for workspaces
+/// that never had any activity. Workspaces enriched with their human activity are measured from
+/// `last-human-activity-at` instead, falling back to `created-at` the same way. Returns the
+/// attribute name, its raw value and the parsed date.
+pub fn activity_basis(workspace: &Value) -> Option<(&'static str, &str, DateTime<FixedOffset>)> {
+    let activity = activity_attribute(workspace);
+    [activity, "created-at"].into_iter()
+        .find_map(|attribute| {
+            let value = workspace["attributes"][attribute].as_str()?;
+            DateTime::parse_from_rfc3339(value).ok().map(|date| (attribute, value, date))
+        })
+}
+
+/// Whether the workspace is connected to a VCS repository.
+pub fn is_vcs_backed(workspace: &Value) -> bool {
+    workspace["attributes"]["vcs-repo"].is_object()
+}
+
+/// Whether TFE reports no usable `last-activity-at` for the workspace.
+pub fn lacks_activity_data(workspace: &Value) -> bool {
+    workspace["attributes"]["last-activity-at"].as_str()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .is_none()
+}
+
+/// Where the workspace carries one of the opt-out markers, as a rule for the verdict.
+fn opt_out(workspace: &Value, markers: &[String]) -> Option<String> {
+    let description = workspace["attributes"]["description"].as_str().unwrap_or("");
+    let tags: Vec<&str> = workspace["attributes"]["tag-names"].as_array().into_iter().flatten()
+        .filter_map(Value::as_str)
+        .collect();
+
+    markers.iter().filter(|marker| !marker.is_empty()).find_map(|marker| {
+        if description.contains(marker.as_str()) {
+            return Some(format!("description contains opt-out marker '{}'", marker));
+        }
+        tags.iter()
+            .find(|tag| tag.contains(marker.as_str()))
+            .map(|tag| format!("tag '{}' contains opt-out marker '{}'", tag, marker))
+    })
+}
+
+/// The threshold the workspace is measured against: the no-VCS one for workspaces without a VCS
+/// connection when one is set.
+fn threshold_days(workspace: &Value, policy: &Policy) -> i64 {
+    match policy.no_vcs_threshold_days {
+        Some(days) if !is_vcs_backed(workspace) => days,
+        _ => policy.threshold_days,
+    }
+}
+
+/// Whether the policy could flag the workspace whatever its activity: it is neither excluded nor
+/// opted out, and was created before the threshold. Lets expensive lookups of its activity be
+/// skipped for the others.
+pub fn could_be_stale(workspace: &Value, policy: &Policy, now: DateTime<Utc>) -> bool {
+    if matches!(evaluate(workspace, policy, now).status, Status::Excluded | Status::OptedOut) {
+        return false;
+    }
+    let created = workspace["attributes"]["created-at"].as_str()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok());
+    created.is_none_or(|created| created < now - Duration::days(threshold_days(workspace, policy)))
+}
+
+/// Applies the policy to a workspace: excluded names and workspaces carrying an opt-out marker
+/// are never flagged, otherwise a workspace is stale when its last activity (or its creation,
+/// if it never had any) is older than the threshold. Workspaces without a VCS connection use the
+/// no-VCS threshold when one is set. Workspaces with neither timestamp are never flagged.
+pub fn evaluate(workspace: &Value, policy: &Policy, now: DateTime<Utc>) -> Verdict {
+    let name = workspace["attributes"]["name"].as_str().unwrap_or("");
+    let mut trace = Vec::new();
+
+    // Whether or not this run marks organizations, another one may
+    if name == run_lock::MARKER_WORKSPACE {
+        return Verdict::decide(Status::Excluded, format!("'{}' marks an organization as being cleaned up", name), trace);
+    }
+    let qualified = format!("{}/{}", tfe::workspace_org(workspace), name);
+    if policy.exclude_workspaces.contains(&qualified) {
+        return Verdict::decide(Status::Excluded, format!("workspace '{}' is excluded by name", qualified), trace);
+    }
+    if let Some(pattern) = policy.exclude.iter().find(|pattern| pattern.is_match(name)) {
+        return Verdict::decide(Status::Excluded,
+            format!("name '{}' matches exclusion pattern '{}'", name, pattern.as_str()), trace);
+    }
+    if !policy.exclude.is_empty() {
+        trace.push(format!("name '{}' matches none of {} exclusion patterns", name, policy.exclude.len()));
+    }
+    if !policy.include.is_empty() && !policy.include.iter().any(|pattern| pattern.is_match(name)) {
+        return Verdict::decide(Status::Excluded,
+            format!("name '{}' matches none of {} inclusion patterns", name, policy.include.len()), trace);
+    }
+    if let Some(rule) = opt_out(workspace, &policy.opt_out_markers) {
+        return Verdict::decide(Status::OptedOut, rule, trace);
+    }
+
+    let activity = activity_attribute(workspace);
+    let (attribute, timestamp, date) = match activity_basis(workspace) {
+        Some(basis) => basis,
+        None => return Verdict::decide(Status::Kept,
+            format!("no parseable {} or created-at; staleness not evaluated", activity), trace),
+    };
+    if attribute != activity {
+        trace.push(format!("no parseable {}; falling back to {}", activity, attribute));
+    }
+
+    let threshold_days = threshold_days(workspace, policy);
+    if policy.no_vcs_threshold_days.is_some() && !is_vcs_backed(workspace) {
+        trace.push(format!("no VCS connection; using the {}-day threshold for CLI-driven workspaces", threshold_days));
+    }
+
+    let age = (now - date.with_timezone(&Utc)).num_days();
+    let stale = date < now - Duration::days(threshold_days);
+    let (status, comparison) = if stale { (Status::Flagged, "older than") } else { (Status::Kept, "within") };
+    Verdict::decide(status,
+        format!("{} {} is {} days ago, {} the {}-day threshold", attribute, timestamp, age, comparison, threshold_days),
+        trace)
+}
+
+/// The workspaces the policy flags as stale now.
+pub fn filter_old_inactive_accounts(accounts: &[Value], policy: &Policy) -> Vec<Value> {
+    let now = Utc::now();
+
+    accounts.iter()
+        .filter(|account| evaluate(account, policy, now).is_stale())
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-06-01T00:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let old = json!({ "attributes": { "last-activity-at": "2024-01-01T00:00:00Z" } });
+        let recent = json!({ "attributes": { "last-activity-at": "2024-05-01T00:00:00Z" } });
+        let missing = json!({ "attributes": { "last-activity-at": null } });
+        let policy = Policy::default();
+
+        let verdict = evaluate(&old, &policy, now());
+        assert!(verdict.is_stale());
+        assert_eq!(verdict.rule, "last-activity-at 2024-01-01T00:00:00Z is 152 days ago, older than the 90-day threshold");
+
+        assert_eq!(evaluate(&recent, &policy, now()).status, Status::Kept);
+        assert_eq!(evaluate(&missing, &policy, now()).status, Status::Kept);
+    }
+
+    #[test]
+    fn test_evaluate_falls_back_to_created_at() {
+        let never_used = json!({ "attributes": { "last-activity-at": null, "created-at": "2024-01-01T00:00:00Z" } });
+        let new = json!({ "attributes": { "created-at": "2024-05-20T00:00:00Z" } });
+        let policy = Policy::default();
+
+        let verdict = evaluate(&never_used, &policy, now());
+        assert!(verdict.is_stale());
+        assert_eq!(verdict.trace, vec![
+            "no parseable last-activity-at; falling back to created-at",
+            "created-at 2024-01-01T00:00:00Z is 152 days ago, older than the 90-day threshold",
+        ]);
+        assert!(lacks_activity_data(&never_used));
+
+        assert_eq!(evaluate(&new, &policy, now()).status, Status::Kept);
+        assert!(!lacks_activity_data(&json!({ "attributes": { "last-activity-at": "2024-05-01T00:00:00Z" } })));
+    }
+
+    #[test]
+    fn test_evaluate_human_activity() {
+        let policy = Policy::default();
+        let bot_applied = json!({ "attributes": {
+            "last-activity-at": "2024-05-31T00:00:00Z", "last-human-activity-at": "2024-01-01T00:00:00Z"
+        } });
+        let verdict = evaluate(&bot_applied, &policy, now());
+        assert!(verdict.is_stale());
+        assert_eq!(verdict.rule, "last-human-activity-at 2024-01-01T00:00:00Z is 152 days ago, older than the 90-day threshold");
+        assert!(!lacks_activity_data(&bot_applied));
+
+        let untouched = json!({ "attributes": {
+            "last-activity-at": "2024-05-31T00:00:00Z", "last-human-activity-at": null, "created-at": "2024-05-01T00:00:00Z"
+        } });
+        let verdict = evaluate(&untouched, &policy, now());
+        assert_eq!(verdict.status, Status::Kept);
+        assert_eq!(verdict.trace[0], "no parseable last-human-activity-at; falling back to created-at");
+    }
+
+    #[test]
+    fn test_could_be_stale() {
+        let policy = Policy { exclude: vec![Regex::new("^prod-").unwrap()], opt_out_markers: vec!["[keep]".to_string()], ..Policy::default() };
+        let bot_applied = json!({ "attributes": { "name": "app", "last-activity-at": "2024-05-31T00:00:00Z", "created-at": "2023-01-01T00:00:00Z" } });
+        assert!(could_be_stale(&bot_applied, &policy, now()));
+        let new = json!({ "attributes": { "name": "app", "created-at": "2024-05-20T00:00:00Z" } });
+        assert!(!could_be_stale(&new, &policy, now()));
+        let excluded = json!({ "attributes": { "name": "prod-app", "created-at": "2023-01-01T00:00:00Z" } });
+        assert!(!could_be_stale(&excluded, &policy, now()));
+        let opted_out = json!({ "attributes": { "name": "app", "description": "[keep]", "created-at": "2023-01-01T00:00:00Z" } });
+        assert!(!could_be_stale(&opted_out, &policy, now()));
+    }
+
+    #[test]
+    fn test_evaluate_no_vcs_threshold() {
+        let policy = Policy { no_vcs_threshold_days: Some(30), ..Policy::default() };
+        let cli_driven = json!({ "attributes": { "last-activity-at": "2024-04-01T00:00:00Z", "vcs-repo": null } });
+        let vcs_backed = json!({ "attributes": {
+            "last-activity-at": "2024-04-01T00:00:00Z",
+            "vcs-repo": { "identifier": "acme/infra" }
+        } });
+
+        let verdict = evaluate(&cli_driven, &policy, now());
+        assert!(verdict.is_stale());
+        assert_eq!(verdict.trace[0], "no VCS connection; using the 30-day threshold for CLI-driven workspaces");
+        assert_eq!(evaluate(&vcs_backed, &policy, now()).status, Status::Kept);
+    }
+
+    #[test]
+    fn test_evaluate_exclusion_trace() {
+        let policy = Policy { exclude: vec![Regex::new("^prod-").unwrap()], ..Policy::default() };
+        let excluded = json!({ "attributes": { "name": "prod-db", "last-activity-at": "2020-01-01T00:00:00Z" } });
+        let flagged = json!({ "attributes": { "name": "sandbox", "last-activity-at": "2020-01-01T00:00:00Z" } });
+
+        let verdict = evaluate(&excluded, &policy, now());
+        assert_eq!(verdict.status, Status::Excluded);
+        assert_eq!(verdict.trace, vec!["name 'prod-db' matches exclusion pattern '^prod-'"]);
+
+        let verdict = evaluate(&flagged, &policy, now());
+        assert_eq!(verdict.status, Status::Flagged);
+        assert_eq!(verdict.trace.len(), 2);
+        assert_eq!(verdict.trace[0], "name 'sandbox' matches none of 1 exclusion patterns");
+
+        // Left by another run's lock, even when this one doesn't mark organizations
+        let marker = json!({ "attributes": { "name": run_lock::MARKER_WORKSPACE, "created-at": "2020-01-01T00:00:00Z" } });
+        assert_eq!(evaluate(&marker, &Policy::default(), now()).status, Status::Excluded);
+    }
+
+    #[test]
+    fn test_evaluate_excluded_workspaces() {
+        let policy = Policy { exclude_workspaces: HashSet::from(["acme/legacy".to_string()]), ..Policy::default() };
+        let workspace = |org: &str| json!({
+            "attributes": { "name": "legacy", "last-activity-at": "2020-01-01T00:00:00Z" },
+            "relationships": { "organization": { "data": { "id": org } } }
+        });
+
+        let verdict = evaluate(&workspace("acme"), &policy, now());
+        assert_eq!(verdict.status, Status::Excluded);
+        assert_eq!(verdict.rule, "workspace 'acme/legacy' is excluded by name");
+        assert_eq!(evaluate(&workspace("acme-eu"), &policy, now()).status, Status::Flagged);
+    }
+
+    #[test]
+    fn test_evaluate_inclusion() {
+        let policy = Policy { include: vec![Regex::new(r"^app-pr-\d+$").unwrap()], ..Policy::default() };
+        let included = json!({ "attributes": { "name": "app-pr-12", "last-activity-at": "2020-01-01T00:00:00Z" } });
+        let other = json!({ "attributes": { "name": "app-prod", "last-activity-at": "2020-01-01T00:00:00Z" } });
+
+        assert!(evaluate(&included, &policy, now()).is_stale());
+        let verdict = evaluate(&other, &policy, now());
+        assert_eq!(verdict.status, Status::Excluded);
+        assert_eq!(verdict.rule, "name 'app-prod' matches none of 1 inclusion patterns");
+    }
+
+    #[test]
+    fn test_verdict_flag() {
+        let kept = json!({ "attributes": { "name": "app-pr-7", "last-activity-at": "2024-05-31T00:00:00Z" } });
+        let verdict = evaluate(&kept, &Policy::default(), now());
+        let steps = verdict.trace.len();
+
+        let verdict = verdict.flag("pull request #7 of acme/app is merged".to_string());
+        assert!(verdict.is_stale());
+        assert_eq!(verdict.rule, "pull request #7 of acme/app is merged");
+        assert_eq!(verdict.trace.len(), steps + 1);
+    }
+
+    #[test]
+    fn test_evaluate_opt_out_markers() {
+        let policy = Policy::from_config(&Config::default()).unwrap();
+        let described = json!({ "attributes": {
+            "name": "demo", "description": "Conference demo [keep]", "last-activity-at": "2020-01-01T00:00:00Z"
+        } });
+        let tagged = json!({ "attributes": {
+            "name": "dr", "tag-names": ["team:sre", "tfe-cleanup:ignore"], "last-activity-at": "2020-01-01T00:00:00Z"
+        } });
+        let unmarked = json!({ "attributes": {
+            "name": "old", "description": "keep this?", "tag-names": ["team:sre"], "last-activity-at": "2020-01-01T00:00:00Z"
+        } });
+
+        let verdict = evaluate(&described, &policy, now());
+        assert_eq!(verdict.status, Status::OptedOut);
+        assert_eq!(verdict.rule, "description contains opt-out marker '[keep]'");
+        assert_eq!(evaluate(&tagged, &policy, now()).rule, "tag 'tfe-cleanup:ignore' contains opt-out marker 'tfe-cleanup:ignore'");
+        assert!(evaluate(&unmarked, &policy, now()).is_stale());
+        assert_eq!(evaluate(&described, &Policy::default(), now()).status, Status::OptedOut);
+        let without_markers = Policy { opt_out_markers: Vec::new(), ..Policy::default() };
+        assert!(evaluate(&described, &without_markers, now()).is_stale());
+    }
+
+    #[test]
+    fn test_policy_rejects_invalid_pattern() {
+        let config = Config { exclude: vec!["(".to_string()], ..Config::default() };
+        assert!(Policy::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::history::History;
+use crate::kill_switch::{self, KillSwitch};
+use crate::run_summary::Counter;
+use crate::tfe::{self, TfeClient};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+
+pub struct PruneOptions {
+    /// Newest versions of each kind kept in every workspace, whatever their age.
+    pub keep: usize,
+    pub older_than_days: i64,
+    pub dry_run: bool,
+}
+
+/// What a prune removed (or would remove in dry-run mode).
+#[derive(Debug, Default, PartialEq)]
+pub struct Pruned {
+    pub state_versions: usize,
+    /// Sum of the sizes reported for the pruned state versions.
+    pub state_bytes: u64,
+    /// Configuration versions have no reported size, so only their number is known.
+    pub configuration_versions: usize,
+}
+
+async fn state_versions(client: &TfeClient, org: &str, name: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+    client.get_all_with_query("/state-versions", &[("filter[workspace][name]", name), ("filter[organization][name]", org)]).await
+}
+
+/// Bytes of state TFE stores for a workspace: the sum of the sizes reported for its state
+/// versions. Configuration versions have no reported size, so they aren't counted.
+pub async fn state_bytes(client: &TfeClient, org: &str, name: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let versions = state_versions(client, org, name).await?;
+    Ok(versions.iter().filter_map(|version| version["attributes"]["size"].as_u64()).sum())
+}
+
+/// When a state or configuration version was made: state versions have `created-at`,
+/// configuration versions only their status timestamps.
+fn created_at(version: &Value) -> Option<DateTime<Utc>> {
+    let attributes = &version["attributes"];
+    let raw = attributes["created-at"].as_str().or_else(|| attributes["status-timestamps"]["uploaded-at"].as_str())?;
+    DateTime::parse_from_rfc3339(raw).ok().map(|date| date.with_timezone(&Utc))
+}
+
+/// The versions to prune: all but the `keep` newest, made before `cutoff` and with their data
+/// still stored. Versions without a date are kept.
+fn prunable(versions: &[Value], keep: usize, cutoff: DateTime<Utc>) -> Vec<&Value> {
+    let mut dated: Vec<(DateTime<Utc>, &Value)> = versions.iter()
+        .filter_map(|version| created_at(version).map(|date| (date, version)))
+        .collect();
+    dated.sort_by_key(|&(date, _)| std::cmp::Reverse(date));
+    dated.into_iter()
+        .skip(keep)
+        .filter(|(date, version)| {
+            *date < cutoff && !version["attributes"]["status"].as_str().unwrap_or("").starts_with("backing_data")
+        })
+        .map(|(_, version)| version)
+        .collect()
+}
+
+/// Soft-deletes the stored data of a state or configuration version; TFE can restore it until
+/// it is permanently deleted.
+async fn soft_delete(client: &TfeClient, kill_switch: Option<&KillSwitch>, kind: &str, id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    kill_switch::check(kill_switch).await?;
+    client.post(&format!("/{}/{}/actions/soft_delete_backing_data", kind, id), &json!({})).await?;
+    Ok(())
+}
+
+/// Prunes the old state and configuration versions of every workspace of `org`, keeping the
+/// newest of each and the configuration version a hibernated workspace is woken with. Needs
+/// TFE, where soft-deleting backing data is available.
+pub async fn prune_versions(
+    client: &TfeClient,
+    history: &History,
+    kill_switch: Option<&KillSwitch>,
+    org: &str,
+    options: &PruneOptions,
+) -> Result<Pruned, Box<dyn Error + Send + Sync>> {
+    let cutoff = Utc::now() - Duration::days(options.older_than_days);
+    let verb = if options.dry_run { "[dry-run] Would prune" } else { "Pruned" };
+    let mut pruned = Pruned::default();
+
+    for workspace in tfe::list_workspaces(client, org).await? {
+        let workspace_id = workspace["id"].as_str().unwrap_or("");
+        let name = workspace["attributes"]["name"].as_str().unwrap_or(workspace_id);
+
+        for version in prunable(&state_versions(client, org, name).await?, options.keep, cutoff) {
+            let id = version["id"].as_str().unwrap_or("");
+            let size = version["attributes"]["size"].as_u64().unwrap_or(0);
+            if !options.dry_run {
+                soft_delete(client, kill_switch, "state-versions", id).await?;
+                client.counters().add(Counter::ReclaimedBytes, size);
+            }
+            println!("{} state version {} of {}/{} ({})", verb, id, org, name, format_bytes(size));
+            pruned.state_versions += 1;
+            pruned.state_bytes += size;
+        }
+
+        let woken_with = history.hibernation(org, name)?.and_then(|hibernation| hibernation.configuration_version);
+        let configuration_versions = client.get_all(&format!("/workspaces/{}/configuration-versions", workspace_id)).await?;
+        for version in prunable(&configuration_versions, options.keep, cutoff) {
+            let id = version["id"].as_str().unwrap_or("");
+            if woken_with.as_deref() == Some(id) {
+                continue;
+            }
+            if !options.dry_run {
+                soft_delete(client, kill_switch, "configuration-versions", id).await?;
+            }
+            println!("{} configuration version {} of {}/{}", verb, id, org, name);
+            pruned.configuration_versions += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// A byte count in binary units, e.g. "512 B", "3.4 MiB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_prunable() {
+        let now = Utc::now();
+        let version = |id: &str, days: i64, status: &str| json!({
+            "id": id,
+            "attributes": { "created-at": (now - Duration::days(days)).to_rfc3339(), "status": status }
+        });
+        let versions = vec![
+            version("sv-old", 400, "finalized"),
+            version("sv-newest", 200, "finalized"),
+            version("sv-recent", 10, "finalized"),
+            version("sv-gone", 500, "backing_data_soft_deleted"),
+            version("sv-second", 300, "finalized"),
+            json!({ "id": "sv-undated", "attributes": {} }),
+        ];
+
+        let ids: Vec<&str> = prunable(&versions, 2, now - Duration::days(90)).iter().map(|v| v["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["sv-second", "sv-old"]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_versions() {
+        let old = (Utc::now() - Duration::days(400)).to_rfc3339();
+        let _workspaces = mock("GET", "/api/v2/organizations/prune-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "ws-prune", "attributes": { "name": "prune-ws" } }] }).to_string())
+            .create();
+        let _state_versions = mock("GET", "/api/v2/state-versions")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("filter[workspace][name]".into(), "prune-ws".into()),
+                Matcher::UrlEncoded("filter[organization][name]".into(), "prune-org".into()),
+            ]))
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "id": "sv-current", "attributes": { "created-at": old, "size": 4096 } },
+                { "id": "sv-old", "attributes": { "created-at": old, "size": 1024 } }
+            ] }).to_string())
+            .create();
+        let _configuration_versions = mock("GET", "/api/v2/workspaces/ws-prune/configuration-versions")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "id": "cv-current", "attributes": { "status-timestamps": { "uploaded-at": old } } },
+                { "id": "cv-woken-with", "attributes": { "status-timestamps": { "uploaded-at": old } } }
+            ] }).to_string())
+            .create();
+        let soft_deleted = mock("POST", "/api/v2/state-versions/sv-old/actions/soft_delete_backing_data")
+            .with_status(204)
+            .expect(1)
+            .create();
+        let kept = mock("POST", Matcher::Regex("^/api/v2/(state|configuration)-versions/(sv-current|cv-.*)/".into()))
+            .expect(0)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let history = History::open_in_memory().unwrap();
+        history.record_hibernation("prune-org", "prune-ws", Some("cv-woken-with")).unwrap();
+        let options = PruneOptions { keep: 1, older_than_days: 90, dry_run: false };
+        let pruned = prune_versions(&client, &history, None, "prune-org", &options).await.unwrap();
+
+        assert_eq!(pruned, Pruned { state_versions: 1, state_bytes: 1024, configuration_versions: 0 });
+        soft_deleted.assert();
+        kept.assert();
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[tokio::test]
+    async fn test_state_bytes() {
+        let _versions = mock("GET", "/api/v2/state-versions")
+            .match_query(Matcher::UrlEncoded("filter[workspace][name]".into(), "storage ws".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [
+                { "attributes": { "size": 2048 } },
+                { "attributes": { "size": 1024 } },
+                { "attributes": { "size": null } }
+            ] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        assert_eq!(state_bytes(&client, "storage-org", "storage ws").await.unwrap(), 3072);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::cache::{self, CacheStats, LookupCache};
+use crate::staleness::{self, Policy};
+use crate::tfe::{self, TfeClient};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+/// Bumped whenever a field is renamed or removed; the dashboard ingests this file nightly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub schema_version: u32,
+    pub generated_at: String,
+    pub stale_threshold_days: i64,
+    pub organizations: Vec<OrgSummary>,
+    /// How well the lookup cache did, to watch the hit rate of nightly runs.
+    pub lookup_cache: CacheStats,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OrgSummary {
+    pub name: String,
+    pub total_workspaces: usize,
+    pub stale_workspaces: usize,
+    pub stale_percent: f64,
+    pub average_age_days: f64,
+    pub total_resources: u64,
+    pub estimated_monthly_cost: f64,
+}
+
+/// Days since the workspace's last activity, if it reports one.
+fn age_days(workspace: &Value, now: DateTime<Utc>) -> Option<i64> {
+    let last_activity = workspace["attributes"]["last-activity-at"].as_str()?;
+    let last_activity = DateTime::parse_from_rfc3339(last_activity).ok()?;
+    Some((now - last_activity.with_timezone(&Utc)).num_days())
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Aggregates the workspaces of one organization. `costs` holds the estimated monthly cost
+/// of each workspace for which one is known.
+pub fn summarize_org(org: &str, workspaces: &[Value], costs: &[f64], policy: &Policy) -> OrgSummary {
+    let now = Utc::now();
+    let total = workspaces.len();
+    let stale = staleness::filter_old_inactive_accounts(workspaces, policy).len();
+    let ages: Vec<i64> = workspaces.iter().filter_map(|ws| age_days(ws, now)).collect();
+
+    OrgSummary {
+        name: org.to_string(),
+        total_workspaces: total,
+        stale_workspaces: stale,
+        stale_percent: if total == 0 { 0.0 } else { round2(stale as f64 * 100.0 / total as f64) },
+        average_age_days: if ages.is_empty() { 0.0 } else { round2(ages.iter().sum::<i64>() as f64 / ages.len() as f64) },
+        total_resources: workspaces.iter()
+            .map(|ws| ws["attributes"]["resource-count"].as_u64().unwrap_or(0))
+            .sum(),
+        estimated_monthly_cost: round2(costs.iter().sum()),
+    }
+}
+
+/// Reads the proposed monthly cost from the cost estimate of the workspace's current run.
+pub async fn estimated_monthly_cost(client: &TfeClient, workspace: &Value) -> Result<Option<f64>, Box<dyn Error + Send + Sync>> {
+    let run_id = match workspace["relationships"]["current-run"]["data"]["id"].as_str() {
+        Some(run_id) => run_id,
+        None => return Ok(None),
+    };
+    let run = client.get(&format!("/runs/{}?include=cost_estimate", run_id)).await?;
+
+    Ok(run["included"].as_array()
+        .and_then(|included| included.iter().find(|item| item["type"] == "cost-estimates"))
+        .and_then(|estimate| estimate["attributes"]["proposed-monthly-cost"].as_str())
+        .and_then(|cost| cost.parse::<f64>().ok()))
+}
+
+pub async fn build_summary(client: &TfeClient, cache: &LookupCache, orgs: &[String], policy: &Policy) -> Result<Summary, Box<dyn Error + Send + Sync>> {
+    let mut organizations = Vec::new();
+
+    for org in orgs {
+        let workspaces = tfe::list_workspaces(client, org).await?;
+        let mut costs = Vec::new();
+        for workspace in &workspaces {
+            if let Some(cost) = cache.get_or_fetch(workspace, cache::COST, || estimated_monthly_cost(client, workspace)).await? {
+                costs.push(cost);
+            }
+        }
+        organizations.push(summarize_org(org, &workspaces, &costs, policy));
+    }
+
+    Ok(Summary {
+        schema_version: SCHEMA_VERSION,
+        generated_at: Utc::now().to_rfc3339(),
+        stale_threshold_days: policy.threshold_days,
+        organizations,
+        lookup_cache: cache.stats(),
+    })
+}
+
+pub fn write_summary(summary: &Summary, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    serde_json::to_writer_pretty(File::create(path)?, summary)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    fn workspace(days_ago: i64, resources: u64) -> Value {
+        json!({
+            "attributes": {
+                "last-activity-at": (Utc::now() - Duration::days(days_ago)).to_rfc3339(),
+                "resource-count": resources
+            }
+        })
+    }
+
+    #[test]
+    fn test_summarize_org() {
+        let workspaces = vec![workspace(200, 10), workspace(100, 0), workspace(10, 5), workspace(30, 1)];
+
+        let summary = summarize_org("acme", &workspaces, &[12.5, 0.255], &Policy::default());
+
+        assert_eq!(summary, OrgSummary {
+            name: "acme".to_string(),
+            total_workspaces: 4,
+            stale_workspaces: 2,
+            stale_percent: 50.0,
+            average_age_days: 85.0,
+            total_resources: 16,
+            estimated_monthly_cost: 12.76,
+        });
+    }
+
+    #[test]
+    fn test_summarize_empty_org() {
+        let summary = summarize_org("empty", &[], &[], &Policy::default());
+        assert_eq!(summary.stale_percent, 0.0);
+        assert_eq!(summary.average_age_days, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_build_summary_reads_cost_estimates() {
+        let _workspaces = mock("GET", "/api/v2/organizations/summary-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({
+                "data": [{
+                    "id": "ws-summary",
+                    "attributes": { "resource-count": 3 },
+                    "relationships": { "current-run": { "data": { "id": "run-summary" } } }
+                }]
+            }).to_string())
+            .create();
+        let _run = mock("GET", "/api/v2/runs/run-summary")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({
+                "data": { "id": "run-summary" },
+                "included": [{ "type": "cost-estimates", "attributes": { "proposed-monthly-cost": "42.10" } }]
+            }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let cache = LookupCache::open_in_memory(chrono::Duration::hours(1)).unwrap();
+        let summary = build_summary(&client, &cache, &["summary-org".to_string()], &Policy::default()).await.unwrap();
+        let json = serde_json::to_value(&summary).unwrap();
+
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["organizations"][0]["total_resources"], 3);
+        assert_eq!(json["organizations"][0]["estimated_monthly_cost"], 42.1);
+        assert_eq!(json["lookup_cache"]["hits"], 0);
+    }
+}
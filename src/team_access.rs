@@ -0,0 +1,255 @@
+use crate::tfe::{self, ApiError, TfeClient};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+/// A workspace admin grant to a team that is gone or no longer used.
+#[derive(Debug, PartialEq)]
+pub struct StaleGrant {
+    pub org: String,
+    pub workspace: String,
+    pub access_id: String,
+    pub team_id: String,
+    pub team_name: String,
+    pub reason: String,
+}
+
+/// Why a team's admin grants are stale, or `None` if the team is in use. `team` is `None`
+/// when the team no longer exists; a team is unused when it has no members and its API
+/// token (if any) hasn't been used since `cutoff`.
+fn stale_reason(team: Option<&Value>, token: Option<&Value>, cutoff: DateTime<Utc>) -> Option<String> {
+    let team = match team {
+        Some(team) => team,
+        None => return Some("team no longer exists".to_string()),
+    };
+    if team["attributes"]["users-count"].as_u64().unwrap_or(0) > 0 {
+        return None;
+    }
+
+    let last_used = token
+        .and_then(|token| token["attributes"]["last-used-at"].as_str())
+        .and_then(|last_used| DateTime::parse_from_rfc3339(last_used).ok());
+    match last_used {
+        Some(last_used) if last_used >= cutoff => None,
+        Some(last_used) => Some(format!("no members, team token last used {}", last_used.to_rfc3339())),
+        None => Some("no members and no team token in use".to_string()),
+    }
+}
+
+/// Fetches a resource's `data`, mapping a 404 to `None`.
+async fn get_optional(client: &TfeClient, path: &str) -> Result<Option<Value>, Box<dyn Error + Send + Sync>> {
+    match client.get(path).await {
+        Ok(mut response) => Ok(Some(response["data"].take())),
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Lists admin grants on the workspaces of `org` to teams that no longer exist or that have
+/// had no members and no token use for `inactive_days`.
+pub async fn find_stale_admin_grants(
+    client: &TfeClient,
+    org: &str,
+    inactive_days: i64,
+) -> Result<Vec<StaleGrant>, Box<dyn Error + Send + Sync>> {
+    let cutoff = Utc::now() - Duration::days(inactive_days);
+    let mut teams: HashMap<String, (String, Option<String>)> = HashMap::new();
+    let mut findings = Vec::new();
+
+    for workspace in tfe::list_workspaces(client, org).await? {
+        let workspace_id = workspace["id"].as_str().unwrap_or("");
+        let workspace_name = workspace["attributes"]["name"].as_str().unwrap_or(workspace_id);
+        let grants = client.get_all(&format!("/team-workspaces?filter[workspace][id]={}", workspace_id)).await?;
+
+        for grant in grants.iter().filter(|grant| grant["attributes"]["access"] == "admin") {
+            let team_id = grant["relationships"]["team"]["data"]["id"].as_str().unwrap_or("").to_string();
+            if team_id.is_empty() {
+                continue;
+            }
+
+            if !teams.contains_key(&team_id) {
+                let team = get_optional(client, &format!("/teams/{}", team_id)).await?;
+                let token = match team {
+                    Some(_) => get_optional(client, &format!("/teams/{}/authentication-token", team_id)).await?,
+                    None => None,
+                };
+                let name = team.as_ref()
+                    .and_then(|team| team["attributes"]["name"].as_str())
+                    .unwrap_or("")
+                    .to_string();
+                teams.insert(team_id.clone(), (name, stale_reason(team.as_ref(), token.as_ref(), cutoff)));
+            }
+
+            if let (team_name, Some(reason)) = &teams[&team_id] {
+                findings.push(StaleGrant {
+                    org: org.to_string(),
+                    workspace: workspace_name.to_string(),
+                    access_id: grant["id"].as_str().unwrap_or("").to_string(),
+                    team_id: team_id.clone(),
+                    team_name: team_name.clone(),
+                    reason: reason.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Limits a run to the workspaces one team administers, for cleanups delegated to that team.
+#[derive(Debug)]
+pub struct TeamScope {
+    pub name: String,
+    /// The team's id in each organization it exists in.
+    team_ids: HashMap<String, String>,
+    /// Whether the team administers each workspace checked so far, by workspace id. Only kept
+    /// for the run, as it decides what the run may delete.
+    admin: Mutex<HashMap<String, bool>>,
+}
+
+impl TeamScope {
+    /// Looks the team up by name in each organization. Organizations without such a team are
+    /// reported on stderr and left out of `orgs`.
+    pub async fn resolve(client: &TfeClient, team: &str, orgs: &mut Vec<String>) -> Result<TeamScope, Box<dyn Error + Send + Sync>> {
+        let mut team_ids = HashMap::new();
+        for org in orgs.iter() {
+            let teams = client.get_all_with_query(&format!("/organizations/{}/teams", org), &[("filter[names]", team)]).await?;
+            match teams.iter().find(|found| found["attributes"]["name"] == team).and_then(|found| found["id"].as_str()) {
+                Some(id) => {
+                    team_ids.insert(org.clone(), id.to_string());
+                }
+                None => eprintln!("Skipping organization {}: it has no team {}", org, team),
+            }
+        }
+        orgs.retain(|org| team_ids.contains_key(org));
+        Ok(TeamScope { name: team.to_string(), team_ids, admin: Mutex::default() })
+    }
+
+    /// Whether the team has admin access to the workspace. Each workspace's grants are read
+    /// once; later checks of the same workspace, e.g. when it is also queued by hand, reuse the
+    /// answer.
+    pub async fn has_admin(&self, client: &TfeClient, workspace: &Value) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let Some(team_id) = self.team_ids.get(tfe::workspace_org(workspace)) else { return Ok(false) };
+        let workspace_id = workspace["id"].as_str().unwrap_or("");
+        if let Some(&admin) = self.admin.lock().unwrap().get(workspace_id) {
+            return Ok(admin);
+        }
+        let grants = client.get_all_with_query("/team-workspaces", &[("filter[workspace][id]", workspace_id)]).await?;
+        let admin = grants.iter().any(|grant| {
+            grant["attributes"]["access"] == "admin" && grant["relationships"]["team"]["data"]["id"].as_str() == Some(team_id)
+        });
+        self.admin.lock().unwrap().insert(workspace_id.to_string(), admin);
+        Ok(admin)
+    }
+}
+
+/// Removes a team's access to a workspace.
+pub async fn revoke(client: &TfeClient, grant: &StaleGrant) -> Result<(), Box<dyn Error + Send + Sync>> {
+    client.delete(&format!("/team-workspaces/{}", grant.access_id)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_stale_reason() {
+        let cutoff = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let members = json!({ "attributes": { "users-count": 2 } });
+        let empty = json!({ "attributes": { "users-count": 0 } });
+        let recent_token = json!({ "attributes": { "last-used-at": "2024-03-01T00:00:00Z" } });
+        let old_token = json!({ "attributes": { "last-used-at": "2023-03-01T00:00:00Z" } });
+
+        assert_eq!(stale_reason(None, None, cutoff), Some("team no longer exists".to_string()));
+        assert_eq!(stale_reason(Some(&members), None, cutoff), None);
+        assert_eq!(stale_reason(Some(&empty), Some(&recent_token), cutoff), None);
+        assert_eq!(stale_reason(Some(&empty), Some(&old_token), cutoff),
+            Some("no members, team token last used 2023-03-01T00:00:00+00:00".to_string()));
+        assert_eq!(stale_reason(Some(&empty), None, cutoff), Some("no members and no team token in use".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_find_stale_admin_grants() {
+        let _workspaces = mock("GET", "/api/v2/organizations/access-org/workspaces")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "ws-access", "attributes": { "name": "app" } }] }).to_string())
+            .create();
+        let _grants = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-access".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [
+                    { "id": "tws-gone", "attributes": { "access": "admin" }, "relationships": { "team": { "data": { "id": "team-gone" } } } },
+                    { "id": "tws-ops", "attributes": { "access": "admin" }, "relationships": { "team": { "data": { "id": "team-ops" } } } },
+                    { "id": "tws-read", "attributes": { "access": "read" }, "relationships": { "team": { "data": { "id": "team-gone" } } } }
+                ]
+            }).to_string())
+            .create();
+        let _gone = mock("GET", "/api/v2/teams/team-gone")
+            .with_status(404)
+            .create();
+        let _ops = mock("GET", "/api/v2/teams/team-ops")
+            .with_status(200)
+            .with_body(json!({ "data": { "attributes": { "name": "ops", "users-count": 3 } } }).to_string())
+            .create();
+        let _ops_token = mock("GET", "/api/v2/teams/team-ops/authentication-token")
+            .with_status(404)
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let findings = find_stale_admin_grants(&client, "access-org", 90).await.unwrap();
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].access_id, "tws-gone");
+        assert_eq!(findings[0].reason, "team no longer exists");
+    }
+
+    #[tokio::test]
+    async fn test_team_scope() {
+        let _teams = mock("GET", "/api/v2/organizations/scope-org/teams")
+            .match_query(Matcher::UrlEncoded("filter[names]".into(), "payments & billing".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "team-payments", "attributes": { "name": "payments & billing" } }] }).to_string())
+            .create();
+        let _other_teams = mock("GET", "/api/v2/organizations/scope-other-org/teams")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .create();
+        let grant = |team: &str, access: &str| json!({
+            "attributes": { "access": access },
+            "relationships": { "team": { "data": { "id": team } } }
+        });
+        let owned = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-scope-owned".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [grant("team-payments", "admin")] }).to_string())
+            .expect(1)
+            .create();
+        let _read_only = mock("GET", "/api/v2/team-workspaces")
+            .match_query(Matcher::UrlEncoded("filter[workspace][id]".into(), "ws-scope-read".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [grant("team-payments", "read"), grant("team-platform", "admin")] }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let mut orgs = vec!["scope-org".to_string(), "scope-other-org".to_string()];
+        let scope = TeamScope::resolve(&client, "payments & billing", &mut orgs).await.unwrap();
+        assert_eq!(orgs, vec!["scope-org"]);
+
+        let workspace = |id: &str| json!({ "id": id, "relationships": { "organization": { "data": { "id": "scope-org" } } } });
+        assert!(scope.has_admin(&client, &workspace("ws-scope-owned")).await.unwrap());
+        assert!(!scope.has_admin(&client, &workspace("ws-scope-read")).await.unwrap());
+        // Checked again, e.g. for a row queued by hand, from what was read before
+        assert!(scope.has_admin(&client, &workspace("ws-scope-owned")).await.unwrap());
+        owned.assert();
+    }
+}
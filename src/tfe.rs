@@ -0,0 +1,485 @@
+use crate::debug_bundle::DebugLog;
+use crate::profile;
+use crate::redact;
+use crate::run_summary::{Counter, Counters};
+use futures::stream::{self, Stream, TryStreamExt};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DEFAULT_ADDRESS: &str = "https://app.terraform.io";
+const PAGE_SIZE: u32 = 100;
+/// Rate-limited requests are retried this many times before the 429 is returned as an error.
+const MAX_RETRIES: u32 = 6;
+/// Upper bound of the doubling wait used when a 429 doesn't say how long to wait.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Longest `Retry-After` honoured, so a misbehaving proxy can't stall the run.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+/// How long a request may take, unless `TFE_TIMEOUT_SECS` says otherwise. A request that times
+/// out fails like any other error, without being retried.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Error returned when the TFE API answers with a non-success status.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub path: String,
+    pub body: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TFE API returned {} for {}: {}", self.status, self.path, redact::scrub(&self.body))
+    }
+}
+
+impl Error for ApiError {}
+
+/// Thin wrapper around reqwest that knows the TFE base URL and token. Clones share the
+/// connection pool.
+#[derive(Clone)]
+pub struct TfeClient {
+    client: reqwest::Client,
+    base_url: String,
+    headers: HeaderMap,
+    debug_log: Option<Arc<DebugLog>>,
+    counters: Arc<Counters>,
+    timeout: Duration,
+}
+
+impl fmt::Debug for TfeClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TfeClient")
+            .field("base_url", &self.base_url)
+            .field("token", &redact::REDACTED)
+            .finish()
+    }
+}
+
+impl TfeClient {
+    pub fn new(base_url: &str, token: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        redact::register(token);
+        // Sensitive header values are left out of reqwest's Debug output
+        let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token))?;
+        authorization.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/vnd.api+json"));
+
+        Ok(TfeClient {
+            client: reqwest::Client::builder().timeout(DEFAULT_TIMEOUT).build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers,
+            debug_log: None,
+            counters: Arc::default(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Gives up on requests taking longer than `timeout`.
+    pub(crate) fn with_timeout(mut self, timeout: Duration) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        self.client = reqwest::Client::builder().timeout(timeout).build()?;
+        self.timeout = timeout;
+        Ok(self)
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Records every API request in `debug_log`, for `--debug-bundle`.
+    pub(crate) fn with_debug_log(mut self, debug_log: Option<Arc<DebugLog>>) -> Self {
+        self.debug_log = debug_log;
+        self
+    }
+
+    /// Counts into `counters`, e.g. to share them with another client of the same run.
+    pub(crate) fn with_counters(mut self, counters: Arc<Counters>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// What the run this client belongs to has done so far.
+    pub fn counters(&self) -> &Arc<Counters> {
+        &self.counters
+    }
+
+    pub(crate) fn debug_log(&self) -> Option<&DebugLog> {
+        self.debug_log.as_deref()
+    }
+
+    /// Builds a client from `TFE_TOKEN`, the optional `TFE_ADDRESS` (for self-hosted TFE) and
+    /// the optional `TFE_TIMEOUT_SECS`.
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let token = env::var("TFE_TOKEN").map_err(|_| "TFE_TOKEN not set in environment")?;
+        let address = env::var("TFE_ADDRESS").unwrap_or_else(|_| DEFAULT_ADDRESS.to_string());
+        let timeout = match env::var("TFE_TIMEOUT_SECS") {
+            Ok(secs) => secs.trim().parse().ok().filter(|secs| *secs > 0).map(Duration::from_secs)
+                .ok_or_else(|| format!("TFE_TIMEOUT_SECS must be a positive whole number of seconds, not '{}'", secs))?,
+            Err(_) => DEFAULT_TIMEOUT,
+        };
+        TfeClient::new(&address, &token)?.with_timeout(timeout)
+    }
+
+    /// Paths are relative to `/api/v2`, except those already under `/api/` such as the
+    /// private registry's `/api/registry/private/v2`.
+    pub fn url(&self, path: &str) -> String {
+        if path.starts_with("/api/") {
+            format!("{}{}", self.base_url, path)
+        } else {
+            format!("{}/api/v2{}", self.base_url, path)
+        }
+    }
+
+    async fn check(path: &str, response: reqwest::Response) -> Result<reqwest::Response, Box<dyn Error + Send + Sync>> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(Box::new(ApiError { status, path: path.to_string(), body }))
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Sends a request, waiting and retrying while TFE answers 429 Too Many Requests. The wait
+    /// is the `Retry-After` the API asks for, up to `MAX_RETRY_AFTER`, or doubles from one
+    /// second without one.
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, Box<dyn Error + Send + Sync>> {
+        let mut backoff = Duration::from_secs(1);
+        for _ in 0..MAX_RETRIES {
+            let response = self.execute(request.try_clone().ok_or("request body cannot be retried")?).await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response);
+            }
+            let wait = retry_wait(response.headers(), backoff);
+            eprintln!("Rate limited by TFE; retrying in {:.1}s", wait.as_secs_f64());
+            self.counters.increment(Counter::RateLimitSleep);
+            tokio::time::sleep(wait).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        self.execute(request).await
+    }
+
+    /// Sends a request once, recording its latency for the profile and the request in the debug
+    /// log if there is one.
+    async fn execute(&self, request: RequestBuilder) -> Result<reqwest::Response, Box<dyn Error + Send + Sync>> {
+        self.counters.increment(Counter::ApiCall);
+        let request = request.build()?;
+        let (method, url) = (request.method().to_string(), request.url().clone());
+        let started = Instant::now();
+        let result = self.client.execute(request).await;
+        profile::record_request(started.elapsed());
+        if let Some(log) = &self.debug_log {
+            log.record_request(&method, &url, result.as_ref(), started.elapsed());
+        }
+        Ok(result?)
+    }
+
+    /// Like `get`, also returning the response headers, e.g. to read rate-limit headroom.
+    pub async fn get_with_headers(&self, path: &str) -> Result<(Value, HeaderMap), Box<dyn Error + Send + Sync>> {
+        let response = self.send(self.client.get(self.url(path)).headers(self.headers.clone())).await?;
+        let response = TfeClient::check(path, response).await?;
+        let headers = response.headers().clone();
+        Ok((response.json::<Value>().await?, headers))
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let response = self.send(self.client.get(self.url(path)).headers(self.headers.clone())).await?;
+        Ok(TfeClient::check(path, response).await?.json::<Value>().await?)
+    }
+
+    /// Fetches every page of a JSON:API collection and returns the concatenated `data` items.
+    pub async fn get_all(&self, path: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+        self.stream_all(path.to_string()).try_collect().await
+    }
+
+    /// Like `get_all`, with query parameters such as filters, encoded as they need to be, e.g.
+    /// `[("filter[names]", "Default Project")]`.
+    pub async fn get_all_with_query(&self, path: &str, query: &[(&str, &str)]) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+        let query = query.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        self.stream_pages(path.to_string(), query).try_collect().await
+    }
+
+    /// The `data` items of a JSON:API collection, fetched a page at a time as the stream is
+    /// consumed, so only one page is held in memory.
+    pub fn stream_all(&self, path: String) -> impl Stream<Item = Result<Value, Box<dyn Error + Send + Sync>>> + '_ {
+        self.stream_pages(path, Vec::new())
+    }
+
+    fn stream_pages(&self, path: String, query: Vec<(String, String)>) -> impl Stream<Item = Result<Value, Box<dyn Error + Send + Sync>>> + '_ {
+        stream::try_unfold(Some(1), move |page| {
+            let (path, query) = (path.clone(), query.clone());
+            async move {
+                let Some(page) = page else { return Ok(None) };
+                let request = self.client.get(self.url(&path))
+                    .headers(self.headers.clone())
+                    .query(&query)
+                    .query(&[("page[number]", page), ("page[size]", PAGE_SIZE)]);
+                let body = TfeClient::check(&path, self.send(request).await?).await?.json::<Value>().await?;
+
+                let next = body["meta"]["pagination"]["next-page"].as_u64().map(|next| next as u32);
+                let items = body["data"].as_array().cloned().unwrap_or_default();
+                Ok::<_, Box<dyn Error + Send + Sync>>(Some((stream::iter(items.into_iter().map(Ok)), next)))
+            }
+        })
+        .try_flatten()
+    }
+
+    pub async fn post(&self, path: &str, body: &Value) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let response = self.send(self.client.post(self.url(path)).headers(self.headers.clone()).json(body)).await?;
+        let text = TfeClient::check(path, response).await?.text().await?;
+        Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    pub async fn patch(&self, path: &str, body: &Value) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let response = self.send(self.client.patch(self.url(path)).headers(self.headers.clone()).json(body)).await?;
+        let text = TfeClient::check(path, response).await?.text().await?;
+        Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    /// Downloads raw bytes from an absolute URL, such as a state version's hosted download URL.
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        // Hosted download URLs are signed, so they are kept out of errors
+        let response = self.client.get(url)
+            .headers(self.headers.clone())
+            .send()
+            .await
+            .map_err(|e| e.without_url())?;
+        Ok(TfeClient::check(&redact::url(url), response).await?.bytes().await?.to_vec())
+    }
+
+    /// Deletes with a JSON:API body, as relationship endpoints such as tag removal expect.
+    pub async fn delete_with_body(&self, path: &str, body: &Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = self.send(self.client.delete(self.url(path)).headers(self.headers.clone()).json(body)).await?;
+        TfeClient::check(path, response).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = self.send(self.client.delete(self.url(path)).headers(self.headers.clone())).await?;
+        TfeClient::check(path, response).await?;
+        Ok(())
+    }
+}
+
+/// Lists the organizations visible to the token.
+pub async fn list_organizations(client: &TfeClient) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+    client.get_all("/organizations").await
+}
+
+/// Lists the workspaces of an organization.
+pub async fn list_workspaces(client: &TfeClient, org: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+    client.get_all(&format!("/organizations/{}/workspaces", org)).await
+}
+
+/// Streams the workspaces of an organization a page at a time.
+pub fn stream_workspaces<'a>(client: &'a TfeClient, org: &str) -> impl Stream<Item = Result<Value, Box<dyn Error + Send + Sync>>> + 'a {
+    client.stream_all(format!("/organizations/{}/workspaces", org))
+}
+
+/// Counts the workspaces of an organization without listing them.
+pub async fn count_workspaces(client: &TfeClient, org: &str) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let page = client.get(&format!("/organizations/{}/workspaces?page[size]=1", org)).await?;
+    page["meta"]["pagination"]["total-count"].as_u64()
+        .map(|count| count as usize)
+        .ok_or_else(|| format!("TFE returned no workspace count for {}", org).into())
+}
+
+/// Fetches a single workspace by organization and name.
+pub async fn get_workspace(client: &TfeClient, org: &str, name: &str) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    Ok(client.get(&format!("/organizations/{}/workspaces/{}", org, name)).await?["data"].take())
+}
+
+/// Whether a workspace still exists; a 404 means it has already been deleted.
+pub async fn workspace_exists(client: &TfeClient, org: &str, name: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    match get_workspace(client, org, name).await {
+        Ok(_) => Ok(true),
+        Err(e) => match e.downcast_ref::<ApiError>() {
+            Some(api_err) if api_err.status == StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(e),
+        },
+    }
+}
+
+/// How long to wait before retrying a 429: its `Retry-After` in seconds, capped at
+/// `MAX_RETRY_AFTER`, or `backoff` if it has none that makes sense.
+fn retry_wait(headers: &HeaderMap, backoff: Duration) -> Duration {
+    headers.get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(|secs| Duration::from_secs_f64(secs).min(MAX_RETRY_AFTER))
+        .unwrap_or(backoff)
+}
+
+/// Returns the name of the organization a workspace belongs to.
+pub fn workspace_org(workspace: &Value) -> &str {
+    workspace["relationships"]["organization"]["data"]["id"].as_str().unwrap_or("")
+}
+
+/// Number of workspaces per organization.
+pub type OrgTotals = BTreeMap<String, usize>;
+
+pub fn count_by_org(workspaces: &[Value]) -> OrgTotals {
+    let mut totals = OrgTotals::new();
+    for workspace in workspaces {
+        *totals.entry(workspace_org(workspace).to_string()).or_default() += 1;
+    }
+    totals
+}
+
+/// Splits an `<org>/<workspace>` reference into its two parts.
+pub fn parse_workspace_ref(reference: &str) -> Result<(String, String), String> {
+    match reference.split_once('/') {
+        Some((org, workspace)) if !org.is_empty() && !workspace.is_empty() => {
+            Ok((org.to_string(), workspace.to_string()))
+        }
+        _ => Err(format!("expected <org>/<workspace>, got '{}'", reference)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_rate_limited_requests_are_retried() {
+        let limited = mock("GET", "/api/v2/workspaces/ws-throttled")
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .expect(2)
+            .create();
+        let ok = mock("GET", "/api/v2/workspaces/ws-throttled")
+            .with_status(200)
+            .with_body(json!({ "data": { "id": "ws-throttled" } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let workspace = client.get("/workspaces/ws-throttled").await.unwrap();
+
+        assert_eq!(workspace["data"]["id"], "ws-throttled");
+        let summary = client.counters().summary(Instant::now());
+        assert_eq!((summary.api_calls, summary.rate_limit_sleeps), (3, 2));
+        limited.assert();
+        ok.assert();
+    }
+
+    #[test]
+    fn test_retry_wait() {
+        let backoff = Duration::from_secs(4);
+        let wait = |retry_after: &str| retry_wait(&HeaderMap::from_iter([(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap())]), backoff);
+
+        assert_eq!(wait("2.5"), Duration::from_millis(2500));
+        assert_eq!(wait("86400"), MAX_RETRY_AFTER);
+        assert_eq!(wait("-1"), backoff);
+        assert_eq!(wait("inf"), backoff);
+        assert_eq!(wait("Wed, 21 Oct 2015 07:28:00 GMT"), backoff);
+        assert_eq!(retry_wait(&HeaderMap::new(), backoff), backoff);
+    }
+
+    #[tokio::test]
+    async fn test_slow_requests_time_out() {
+        let _slow = mock("GET", "/api/v2/workspaces/ws-slow")
+            .with_status(200)
+            .with_body_from_fn(|body| {
+                std::thread::sleep(Duration::from_millis(300));
+                body.write_all(b"{}")
+            })
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap().with_timeout(Duration::from_millis(50)).unwrap();
+        let err = client.get("/workspaces/ws-slow").await.unwrap_err();
+
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert_eq!(client.timeout(), Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_get_all_follows_pagination() {
+        let first = mock("GET", "/api/v2/organizations/paged-org/workspaces")
+            .match_query(Matcher::UrlEncoded("page[number]".into(), "1".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [{ "id": "ws-1" }],
+                "meta": { "pagination": { "next-page": 2 } }
+            }).to_string())
+            .create();
+        let second = mock("GET", "/api/v2/organizations/paged-org/workspaces")
+            .match_query(Matcher::UrlEncoded("page[number]".into(), "2".into()))
+            .with_status(200)
+            .with_body(json!({
+                "data": [{ "id": "ws-2" }],
+                "meta": { "pagination": { "next-page": null } }
+            }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let workspaces = list_workspaces(&client, "paged-org").await.unwrap();
+
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(workspaces[1]["id"], "ws-2");
+        first.assert();
+        second.assert();
+    }
+
+    #[test]
+    fn test_parse_workspace_ref() {
+        assert_eq!(parse_workspace_ref("acme/app-prod").unwrap(), ("acme".to_string(), "app-prod".to_string()));
+        assert!(parse_workspace_ref("acme").is_err());
+        assert!(parse_workspace_ref("/app-prod").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_workspace_exists_treats_404_as_deleted() {
+        let _gone = mock("GET", "/api/v2/organizations/exists-org/workspaces/gone")
+            .with_status(404)
+            .create();
+        let _present = mock("GET", "/api/v2/organizations/exists-org/workspaces/present")
+            .with_status(200)
+            .with_body(json!({ "data": { "id": "ws-present" } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        assert!(!workspace_exists(&client, "exists-org", "gone").await.unwrap());
+        assert!(workspace_exists(&client, "exists-org", "present").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_count_workspaces_reads_the_total() {
+        let _m = mock("GET", "/api/v2/organizations/count-org/workspaces")
+            .match_query(Matcher::UrlEncoded("page[size]".into(), "1".into()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "ws-1" }], "meta": { "pagination": { "total-count": 42 } } }).to_string())
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+
+        assert_eq!(count_workspaces(&client, "count-org").await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_get_reports_api_errors() {
+        let _m = mock("GET", "/api/v2/organizations/forbidden-org")
+            .with_status(403)
+            .with_body("forbidden")
+            .create();
+
+        let client = TfeClient::new(&server_url(), "test-token").unwrap();
+        let err = client.get("/organizations/forbidden-org").await.unwrap_err();
+        let api_err = err.downcast_ref::<ApiError>().unwrap();
+
+        assert_eq!(api_err.status, StatusCode::FORBIDDEN);
+    }
+}
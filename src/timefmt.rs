@@ -0,0 +1,82 @@
+use crate::i18n;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+/// A duration in days in the largest unit that fits, as the count and the id of its message
+/// without the `age-` or `ago-` prefix.
+fn largest_unit(days: i64) -> (i64, &'static str) {
+    let days = days.max(0);
+    if days < 14 {
+        (days, "days")
+    } else if days < 60 {
+        (days / 7, "weeks")
+    } else if days < 730 {
+        (days / 30, "months")
+    } else {
+        (days / 365, "years")
+    }
+}
+
+/// Human-relative length of a duration in days in the language of this run, e.g. "3 days",
+/// "5 weeks", "7 months", "2 years".
+pub fn relative_days(days: i64) -> String {
+    let (count, unit) = largest_unit(days);
+    i18n::format(&format!("age-{}", unit), [("count", count.into())])
+}
+
+fn days_since(raw: &str, now: DateTime<Utc>) -> Option<i64> {
+    let then = DateTime::parse_from_rfc3339(raw).ok()?;
+    Some((now - then.with_timezone(&Utc)).num_days())
+}
+
+/// How long ago `raw` (RFC3339) was, relative to `now`.
+pub fn age(raw: &str, now: DateTime<Utc>) -> Option<String> {
+    days_since(raw, now).map(relative_days)
+}
+
+/// How long ago `raw` (RFC3339) was as a phrase, e.g. "4 years ago". Separate from `age` since
+/// languages like German inflect the unit: "4 Jahre", but "vor 4 Jahren".
+fn ago(raw: &str, now: DateTime<Utc>) -> Option<String> {
+    let (count, unit) = largest_unit(days_since(raw, now)?);
+    Some(i18n::format(&format!("ago-{}", unit), [("count", count.into())]))
+}
+
+/// The timestamp converted to `timezone`, e.g. "2019-12-31 19:00 EST".
+pub fn local(raw: &str, timezone: Tz) -> Option<String> {
+    let then = DateTime::parse_from_rfc3339(raw).ok()?;
+    Some(then.with_timezone(&timezone).format("%Y-%m-%d %H:%M %Z").to_string())
+}
+
+/// The raw timestamp followed by its local time and relative age, e.g.
+/// "2020-01-01T00:00:00Z (2019-12-31 19:00 EST, 4 years ago)". Unparseable input is returned as is.
+pub fn describe(raw: &str, timezone: Tz, now: DateTime<Utc>) -> String {
+    match (local(raw, timezone), ago(raw, now)) {
+        (Some(local), Some(ago)) => format!("{} ({}, {})", raw, local, ago),
+        _ => raw.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_relative_days() {
+        assert_eq!(relative_days(1), "1 day");
+        assert_eq!(relative_days(10), "10 days");
+        assert_eq!(relative_days(21), "3 weeks");
+        assert_eq!(relative_days(213), "7 months");
+        assert_eq!(relative_days(1461), "4 years");
+    }
+
+    #[test]
+    fn test_describe() {
+        let timezone: Tz = "America/New_York".parse().unwrap();
+        assert_eq!(describe("2020-01-01T00:00:00Z", timezone, now()), "2020-01-01T00:00:00Z (2019-12-31 19:00 EST, 4 years ago)");
+        assert_eq!(describe("", timezone, now()), "");
+    }
+}
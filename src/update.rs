@@ -0,0 +1,275 @@
+use chrono::Utc;
+use minisign_verify::{PublicKey, Signature};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where releases are published.
+pub const REPOSITORY: &str = "slg74/tfe_cleanup";
+pub const GITHUB_API: &str = "https://api.github.com";
+/// Set to anything to suppress the startup notice about newer versions.
+pub const NO_CHECK_VAR: &str = "TFE_CLEANUP_NO_UPDATE_CHECK";
+/// Release asset listing the SHA-256 of every binary, in `sha256sum` format.
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+/// Release asset holding the minisign signature of the checksums.
+const SIGNATURE_ASSET: &str = "SHA256SUMS.minisig";
+/// The minisign public key releases are signed with, embedded by release builds. Without it
+/// a binary can't verify releases, so it doesn't update itself.
+pub const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("TFE_CLEANUP_RELEASE_PUBLIC_KEY");
+/// The startup notice asks GitHub at most this often.
+const CHECK_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// A published release and its downloadable assets as `(name, url)`.
+#[derive(Debug)]
+pub struct Release {
+    pub tag: String,
+    pub assets: Vec<(String, String)>,
+}
+
+impl Release {
+    fn asset_url(&self, name: &str) -> Option<&str> {
+        self.assets.iter().find(|(asset, _)| asset == name).map(|(_, url)| url.as_str())
+    }
+}
+
+/// `major.minor.patch` of a version or tag such as `v1.2.3`.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim_start_matches('v').split(['.', '-', '+']).map(|part| part.parse::<u64>());
+    Some((parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?))
+}
+
+/// Whether `tag` is a later version than this binary.
+pub fn is_newer(tag: &str) -> bool {
+    match (parse_version(tag), parse_version(env!("CARGO_PKG_VERSION"))) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// The release asset built for this platform, e.g. `tfe_cleanup-linux-x86_64`.
+pub fn asset_name() -> String {
+    format!("tfe_cleanup-{}-{}{}", env::consts::OS, env::consts::ARCH, env::consts::EXE_SUFFIX)
+}
+
+fn http_client(timeout: Duration) -> Result<reqwest::Client, Box<dyn Error + Send + Sync>> {
+    // GitHub rejects requests without a user agent
+    Ok(reqwest::Client::builder()
+        .user_agent(concat!("tfe_cleanup/", env!("CARGO_PKG_VERSION")))
+        .timeout(timeout)
+        .build()?)
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<reqwest::Response, Box<dyn Error + Send + Sync>> {
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("{} returned {}", url, response.status()).into());
+    }
+    Ok(response)
+}
+
+pub async fn latest_release(api_base: &str, timeout: Duration) -> Result<Release, Box<dyn Error + Send + Sync>> {
+    let client = http_client(timeout)?;
+    let body: Value = fetch(&client, &format!("{}/repos/{}/releases/latest", api_base, REPOSITORY)).await?.json().await?;
+
+    Ok(Release {
+        tag: body["tag_name"].as_str().ok_or("release has no tag")?.to_string(),
+        assets: body["assets"].as_array().into_iter().flatten()
+            .filter_map(|asset| Some((asset["name"].as_str()?.to_string(), asset["browser_download_url"].as_str()?.to_string())))
+            .collect(),
+    })
+}
+
+/// The checksum listed for `asset` in a `sha256sum`-style file.
+fn expected_checksum(checksums: &str, asset: &str) -> Option<String> {
+    checksums.lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim().trim_start_matches('*') == asset)
+        .map(|(checksum, _)| checksum.to_lowercase())
+}
+
+/// Checks the minisign signature of `checksums` under `public_key` (base64, as in minisign's
+/// `.pub` files).
+fn verify_signature(checksums: &str, signature: &str, public_key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let public_key = PublicKey::from_base64(public_key).map_err(|e| format!("invalid release public key: {}", e))?;
+    let signature = Signature::decode(signature).map_err(|e| format!("invalid {}: {}", SIGNATURE_ASSET, e))?;
+    public_key.verify(checksums.as_bytes(), &signature, false)
+        .map_err(|e| format!("{} isn't signed with the release key: {}", CHECKSUMS_ASSET, e).into())
+}
+
+/// Downloads this platform's binary of the release and verifies it against the release's
+/// checksums, whose signature is checked against `public_key` first: checksums alone only
+/// prove the download matches what whoever controls the release uploaded.
+pub async fn download_verified(release: &Release, public_key: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let asset = asset_name();
+    let binary_url = release.asset_url(&asset).ok_or_else(|| format!("release {} has no {} binary", release.tag, asset))?;
+    let checksums_url = release.asset_url(CHECKSUMS_ASSET)
+        .ok_or_else(|| format!("release {} has no {}; refusing to install an unverified binary", release.tag, CHECKSUMS_ASSET))?;
+    let signature_url = release.asset_url(SIGNATURE_ASSET)
+        .ok_or_else(|| format!("release {} has no {}; refusing to install an unverified binary", release.tag, SIGNATURE_ASSET))?;
+
+    let client = http_client(Duration::from_secs(300))?;
+    let checksums = fetch(&client, checksums_url).await?.text().await?;
+    let signature = fetch(&client, signature_url).await?.text().await?;
+    verify_signature(&checksums, &signature, public_key)?;
+    let expected = expected_checksum(&checksums, &asset)
+        .ok_or_else(|| format!("{} of release {} has no entry for {}", CHECKSUMS_ASSET, release.tag, asset))?;
+    let binary = fetch(&client, binary_url).await?.bytes().await?.to_vec();
+
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if actual != expected {
+        return Err(format!("checksum mismatch for {}: expected {}, got {}", asset, expected, actual).into());
+    }
+    Ok(binary)
+}
+
+/// Swaps the executable at `path` for `binary`. The old file is moved aside first, which also
+/// works on Windows where a running executable can't be overwritten.
+pub fn replace_executable(path: &Path, binary: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let staged = path.with_extension("new");
+    let previous = path.with_extension("old");
+    fs::write(&staged, binary)?;
+    fs::set_permissions(&staged, fs::metadata(path)?.permissions())?;
+
+    let _ = fs::remove_file(&previous);
+    fs::rename(path, &previous)?;
+    if let Err(e) = fs::rename(&staged, path) {
+        fs::rename(&previous, path)?;
+        return Err(e.into());
+    }
+    // Fails on Windows while the old binary is still running; it is removed on the next update
+    let _ = fs::remove_file(&previous);
+    Ok(())
+}
+
+fn check_cache() -> PathBuf {
+    env::temp_dir().join("tfe_cleanup_update_check")
+}
+
+/// The latest release tag, from a cache refreshed at most daily. `None` if GitHub can't be
+/// reached quickly; the notice must never get in the way of a run.
+async fn cached_latest_tag(api_base: &str) -> Option<String> {
+    let now = Utc::now().timestamp();
+    if let Ok(cached) = fs::read_to_string(check_cache()) {
+        if let Some((checked_at, tag)) = cached.trim().split_once(' ') {
+            if checked_at.parse::<i64>().is_ok_and(|checked_at| now - checked_at < CHECK_INTERVAL_SECS) {
+                return Some(tag.to_string());
+            }
+        }
+    }
+
+    let release = latest_release(api_base, Duration::from_secs(2)).await.ok()?;
+    let _ = fs::write(check_cache(), format!("{} {}", now, release.tag));
+    Some(release.tag)
+}
+
+/// Prints a notice on stderr when a newer version has been released, unless suppressed with
+/// `TFE_CLEANUP_NO_UPDATE_CHECK`.
+pub async fn notify_if_outdated(api_base: &str) {
+    if env::var_os(NO_CHECK_VAR).is_some() {
+        return;
+    }
+    if let Some(tag) = cached_latest_tag(api_base).await {
+        if is_newer(&tag) {
+            eprintln!("tfe_cleanup {} is available (this is {}); run `tfe_cleanup self-update` to install it, \
+                or set {} to silence this notice.", tag, env!("CARGO_PKG_VERSION"), NO_CHECK_VAR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v1.12.3"), Some((1, 12, 3)));
+        assert_eq!(parse_version("0.2.0-rc.1"), Some((0, 2, 0)));
+        assert_eq!(parse_version("nightly"), None);
+        assert!(is_newer("v99.0.0"));
+        assert!(!is_newer(concat!("v", env!("CARGO_PKG_VERSION"))));
+    }
+
+    #[test]
+    fn test_expected_checksum() {
+        let checksums = "ABC123  tfe_cleanup-linux-x86_64\ndef456 *tfe_cleanup-windows-x86_64.exe\n";
+        assert_eq!(expected_checksum(checksums, "tfe_cleanup-linux-x86_64").as_deref(), Some("abc123"));
+        assert_eq!(expected_checksum(checksums, "tfe_cleanup-windows-x86_64.exe").as_deref(), Some("def456"));
+        assert_eq!(expected_checksum(checksums, "tfe_cleanup-macos-aarch64"), None);
+    }
+
+    /// A minisign key pair made for these tests, and its signature of `SIGNED_CHECKSUMS`.
+    const TEST_PUBLIC_KEY: &str = "RWQBAgMEBQYHCAOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4";
+    const TEST_BINARY: &[u8] = b"new tfe_cleanup binary";
+    const SIGNED_CHECKSUMS: &str = "\
+        TEST_CHECKSUM  tfe_cleanup-linux-x86_64\n\
+        TEST_CHECKSUM  tfe_cleanup-linux-aarch64\n\
+        TEST_CHECKSUM  tfe_cleanup-macos-x86_64\n\
+        TEST_CHECKSUM  tfe_cleanup-macos-aarch64\n\
+        TEST_CHECKSUM  tfe_cleanup-windows-x86_64.exe\n";
+    const TEST_SIGNATURE: &str = "untrusted comment: signature from tfe_cleanup test key
+RUQBAgMEBQYHCI0egsfDYiFTpLNoaGuLQ+7zgRTnurlgqqYoHAzqxF9bNk3wfauHOTgh5RQHl1F/t9jCPQY88vJMh75MX2cCfw8=
+trusted comment: timestamp:1700000000\tfile:SHA256SUMS\thashed
+NjQaJKs024kVAmx428oftaP4xoEkxux/ypAVIFX1xVEJ4rlHWVlKNax8udUdn++1fOaCIRgX2ZWlALleXFmNDQ==
+";
+
+    fn signed_checksums() -> String {
+        SIGNED_CHECKSUMS.replace("TEST_CHECKSUM", &format!("{:x}", Sha256::digest(TEST_BINARY)))
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        verify_signature(&signed_checksums(), TEST_SIGNATURE, TEST_PUBLIC_KEY).unwrap();
+        let forged = format!("{}0000  tfe_cleanup-linux-x86_64\n", signed_checksums());
+        assert!(verify_signature(&forged, TEST_SIGNATURE, TEST_PUBLIC_KEY).unwrap_err().to_string().contains("isn't signed"));
+    }
+
+    #[tokio::test]
+    async fn test_download_verified() {
+        let _release = mock("GET", "/repos/slg74/tfe_cleanup/releases/latest")
+            .with_status(200)
+            .with_body(json!({
+                "tag_name": "v9.9.9",
+                "assets": [
+                    { "name": asset_name(), "browser_download_url": format!("{}/download/binary", server_url()) },
+                    { "name": "SHA256SUMS", "browser_download_url": format!("{}/download/sums", server_url()) },
+                    { "name": "SHA256SUMS.minisig", "browser_download_url": format!("{}/download/sums.minisig", server_url()) }
+                ]
+            }).to_string())
+            .create();
+        let _binary = mock("GET", "/download/binary").with_status(200).with_body(TEST_BINARY).create();
+        let _sums = mock("GET", "/download/sums").with_status(200).with_body(signed_checksums()).create();
+        let _signature = mock("GET", "/download/sums.minisig").with_status(200).with_body(TEST_SIGNATURE).create();
+
+        let release = latest_release(&server_url(), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(release.tag, "v9.9.9");
+        assert_eq!(download_verified(&release, TEST_PUBLIC_KEY).await.unwrap(), TEST_BINARY);
+
+        let tampered = Release { tag: release.tag.clone(), assets: vec![
+            (asset_name(), format!("{}/download/sums", server_url())),
+            ("SHA256SUMS".to_string(), format!("{}/download/sums", server_url())),
+            ("SHA256SUMS.minisig".to_string(), format!("{}/download/sums.minisig", server_url())),
+        ] };
+        assert!(download_verified(&tampered, TEST_PUBLIC_KEY).await.unwrap_err().to_string().contains("checksum mismatch"));
+
+        let unsigned = Release { tag: release.tag.clone(), assets: release.assets[..2].to_vec() };
+        assert!(download_verified(&unsigned, TEST_PUBLIC_KEY).await.unwrap_err().to_string().contains("has no SHA256SUMS.minisig"));
+    }
+
+    #[test]
+    fn test_replace_executable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tfe_cleanup");
+        fs::write(&path, "old").unwrap();
+
+        replace_executable(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!path.with_extension("new").exists());
+    }
+}
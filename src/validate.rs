@@ -0,0 +1,252 @@
+use crate::config::{ActionKind, Config, ContactSourceConfig, SinkConfig};
+use crate::notify;
+use crate::redact::Secret;
+use crate::sinks;
+use crate::window;
+use handlebars::Template;
+use lettre::message::Mailbox;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+fn check_template(setting: &str, template: &str) -> Result<(), String> {
+    Template::compile(template).map(|_| ()).map_err(|e| format!("{}: invalid template: {}", setting, e))
+}
+
+fn check_template_file(setting: &str, path: &Path) -> Result<(), String> {
+    let template = fs::read_to_string(path).map_err(|e| format!("{}: cannot read {}: {}", setting, path.display(), e))?;
+    check_template(setting, &template)
+}
+
+/// Webhooks are credentials, so problems with them never quote the value.
+fn check_webhook(setting: &str, url: &Secret) -> Result<(), String> {
+    match url.expose().split_once("://") {
+        Some(("https" | "http", rest)) if !rest.is_empty() => Ok(()),
+        _ => Err(format!("{}: not an http(s) URL", setting)),
+    }
+}
+
+/// Everything wrong with a config that parses, such as patterns that don't compile or
+/// thresholds that contradict each other, one message per problem naming the setting.
+pub fn problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (setting, patterns) in [("exclude", &config.exclude), ("include", &config.include)] {
+        for pattern in patterns {
+            if let Err(e) = Regex::new(pattern) {
+                problems.push(format!("{}: invalid pattern '{}': {}", setting, pattern, e));
+            }
+        }
+    }
+
+    if let Some(pull_requests) = &config.pull_requests {
+        match Regex::new(&pull_requests.pattern) {
+            Ok(pattern) if pattern.captures_len() < 2 => problems.push(format!(
+                "pull_requests.pattern: '{}' needs a group capturing the pull request number", pull_requests.pattern)),
+            Ok(_) => {}
+            Err(e) => problems.push(format!("pull_requests.pattern: invalid pattern '{}': {}", pull_requests.pattern, e)),
+        }
+        if !matches!(pull_requests.provider.as_str(), "github" | "gitlab") {
+            problems.push(format!("pull_requests.provider: must be github or gitlab, not '{}'", pull_requests.provider));
+        }
+    }
+
+    if config.stale_after_days < 1 {
+        problems.push(format!("stale_after_days: must be at least 1, not {}", config.stale_after_days));
+    }
+    if let Some(days) = config.no_vcs_stale_after_days {
+        if days < 1 {
+            problems.push(format!("no_vcs_stale_after_days: must be at least 1, not {}", days));
+        } else if days > config.stale_after_days {
+            problems.push(format!("no_vcs_stale_after_days: {} is meant to be stricter than stale_after_days ({})",
+                days, config.stale_after_days));
+        }
+    }
+
+    if let Err(e) = window::parse_windows(&config.deletion_windows) {
+        problems.push(format!("deletion_windows: {}", e));
+    }
+
+    let notifications = &config.notifications;
+    for (setting, url) in [("notifications.slack_webhook", &notifications.slack_webhook), ("notifications.teams_webhook", &notifications.teams_webhook)] {
+        if let Some(url) = url {
+            problems.extend(check_webhook(setting, url).err());
+        }
+    }
+    for (setting, path) in [
+        ("notifications.template", &notifications.template),
+        ("notifications.slack_template", &notifications.slack_template),
+        ("notifications.teams_template", &notifications.teams_template),
+    ] {
+        if let Some(path) = path {
+            problems.extend(check_template_file(setting, path).err());
+        }
+    }
+
+    for (index, sink) in config.sinks.iter().enumerate() {
+        let setting = format!("sinks[{}]", index);
+        match sink {
+            SinkConfig::Webhook { url } => problems.extend(check_webhook(&setting, url).err()),
+            SinkConfig::Email { to, from, subject, template, .. } => {
+                if to.is_empty() {
+                    problems.push(format!("{}: email sink has no recipients", setting));
+                }
+                for address in to.iter().chain([from]) {
+                    if let Err(e) = address.parse::<Mailbox>() {
+                        problems.push(format!("{}: invalid address '{}': {}", setting, address, e));
+                    }
+                }
+                if let Some(subject) = subject {
+                    problems.extend(check_template(&format!("{}.subject", setting), subject).err());
+                }
+                if let Some(template) = template {
+                    problems.extend(check_template_file(&format!("{}.template", setting), template).err());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (index, source) in config.contacts.iter().enumerate() {
+        let setting = format!("contacts[{}]", index);
+        match source {
+            ContactSourceConfig::Csv { path } if !path.exists() => problems.push(format!("{}: {} does not exist", setting, path.display())),
+            ContactSourceConfig::Scim { url, .. } if !url.starts_with("https://") && !url.starts_with("http://") =>
+                problems.push(format!("{}: '{}' is not an http(s) URL", setting, url)),
+            ContactSourceConfig::Ldap { url, .. } if !url.starts_with("ldap://") && !url.starts_with("ldaps://") =>
+                problems.push(format!("{}: '{}' is not an ldap:// or ldaps:// URL", setting, url)),
+            _ => {}
+        }
+    }
+
+    let has_channel = notifications.slack_webhook.is_some() || notifications.teams_webhook.is_some();
+    for (category, pipeline) in [("stale", &config.actions.stale), ("no_activity_data", &config.actions.no_activity_data), ("no_vcs", &config.actions.no_vcs)] {
+        let setting = format!("actions.{}", category);
+        if pipeline.contains(&ActionKind::Notify) && !has_channel {
+            problems.push(format!("{}: notify needs notifications.slack_webhook or notifications.teams_webhook", setting));
+        }
+        // Nothing can be done to a workspace once it is gone
+        if let Some(position) = pipeline.iter().position(|kind| *kind == ActionKind::Delete) {
+            if position + 1 < pipeline.len() {
+                problems.push(format!("{}: delete must be the last action", setting));
+            }
+        }
+    }
+
+    for org in &config.organizations.allow {
+        if config.organizations.deny.contains(org) {
+            problems.push(format!("organizations: {} is both allowed and denied", org));
+        }
+    }
+
+    problems
+}
+
+/// Contacts the notification channels and every report sink that can be checked without
+/// sending anything, as `(what, outcome)`.
+pub async fn probe(config: &Config) -> Vec<(String, Result<(), String>)> {
+    let mut outcomes = Vec::new();
+    let notifications = &config.notifications;
+    for (channel, url) in [("Slack", &notifications.slack_webhook), ("Teams", &notifications.teams_webhook)] {
+        if let Some(url) = url {
+            let outcome = notify::probe_url(url.expose()).await.map_err(|e| e.to_string());
+            outcomes.push((format!("{} notifications", channel), outcome));
+        }
+    }
+    for sink in sinks::from_config(&config.sinks, &config.notifications) {
+        if let Some(outcome) = sink.probe().await {
+            outcomes.push((sink.describe(), outcome.map_err(|e| e.to_string())));
+        }
+    }
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_default_config_has_no_problems() {
+        assert!(problems(&Config::default()).is_empty());
+    }
+
+    #[test]
+    fn test_problems() {
+        let config = Config::parse(r#"
+            stale_after_days = 30
+            no_vcs_stale_after_days = 60
+            exclude = ["^prod-", "("]
+
+            [[deletion_windows]]
+            days = ["someday"]
+            start = "02:00"
+            end = "05:00"
+
+            [notifications]
+            template = "/nonexistent/template.hbs"
+
+            [[sinks]]
+            type = "email"
+            to = []
+            from = "not an address"
+            smtp_host = "smtp.example.com"
+            subject = "{{#if stale_count}}"
+
+            [[contacts]]
+            type = "ldap"
+            url = "ldap.example.com"
+            bind_dn = "cn=tfe-cleanup,dc=example,dc=com"
+            password = "bind-password"
+            base_dn = "dc=example,dc=com"
+
+            [actions]
+            stale = ["delete", "tag"]
+            no_vcs = ["notify"]
+
+            [organizations]
+            allow = ["acme"]
+            deny = ["acme"]
+        "#).unwrap();
+
+        let problems = problems(&config);
+        let setting = |prefix: &str| problems.iter().filter(|problem| problem.starts_with(prefix)).count();
+
+        assert_eq!(setting("exclude: invalid pattern '('"), 1);
+        assert_eq!(setting("no_vcs_stale_after_days: 60 is meant to be stricter"), 1);
+        assert_eq!(setting("deletion_windows: invalid day 'someday'"), 1);
+        assert_eq!(setting("notifications.template: cannot read"), 1);
+        assert_eq!(setting("sinks[0]: email sink has no recipients"), 1);
+        assert_eq!(setting("sinks[0]: invalid address 'not an address'"), 1);
+        assert_eq!(setting("sinks[0].subject: invalid template"), 1);
+        assert_eq!(setting("contacts[0]: 'ldap.example.com' is not an ldap:// or ldaps:// URL"), 1);
+        assert_eq!(setting("actions.stale: delete must be the last action"), 1);
+        assert_eq!(setting("actions.no_vcs: notify needs"), 1);
+        assert_eq!(setting("organizations: acme is both allowed and denied"), 1);
+        assert_eq!(problems.len(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let _hook = mock("HEAD", "/validate-probe-hook").with_status(405).create();
+        let config = Config::parse(&format!(r#"
+            [notifications]
+            slack_webhook = "{}/validate-probe-hook"
+
+            [[sinks]]
+            type = "webhook"
+            url = "http://127.0.0.1:1/unreachable"
+
+            [[sinks]]
+            type = "csv"
+            path = "stale.csv"
+        "#, server_url())).unwrap();
+
+        let outcomes = probe(&config).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0], ("Slack notifications".to_string(), Ok(())));
+        assert_eq!(outcomes[1].0, "webhook http://127.0.0.1:1/[redacted]");
+        assert!(outcomes[1].1.is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config::WindowConfig;
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+
+/// A recurring UTC time range during which deletions are allowed. A window whose end is
+/// before its start wraps past midnight and belongs to the day it starts on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeletionWindow {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl DeletionWindow {
+    pub fn from_config(config: &WindowConfig) -> Result<DeletionWindow, String> {
+        let days = config.days.iter()
+            .map(|day| day.parse::<Weekday>().map_err(|_| format!("invalid day '{}' in deletion window", day)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if days.is_empty() {
+            return Err("deletion window has no days".to_string());
+        }
+
+        let parse_time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M")
+            .map_err(|_| format!("invalid time '{}' in deletion window, expected HH:MM", time));
+
+        Ok(DeletionWindow { days, start: parse_time(&config.start)?, end: parse_time(&config.end)? })
+    }
+
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let time = at.time();
+        let today = at.weekday();
+
+        if self.start <= self.end {
+            self.days.contains(&today) && time >= self.start && time < self.end
+        } else {
+            (self.days.contains(&today) && time >= self.start)
+                || (self.days.contains(&today.pred()) && time < self.end)
+        }
+    }
+
+    /// The first time at or after `after` when this window opens.
+    fn next_opening(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        (0..=7)
+            .map(|offset| (after + Duration::days(offset)).date_naive())
+            .filter(|date| self.days.contains(&date.weekday()))
+            .map(|date| date.and_time(self.start).and_utc())
+            .find(|opening| *opening >= after)
+            .expect("a weekly window opens within eight days")
+    }
+}
+
+/// Parses every configured window.
+pub fn parse_windows(configs: &[WindowConfig]) -> Result<Vec<DeletionWindow>, String> {
+    configs.iter().map(DeletionWindow::from_config).collect()
+}
+
+/// Deletions are allowed when no windows are configured or `at` falls inside one.
+pub fn is_open(windows: &[DeletionWindow], at: DateTime<Utc>) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(at))
+}
+
+/// Fails once every window has closed, so a run that started inside one stops before its next
+/// deletion instead of running on past the end of the window.
+pub fn check(windows: &[DeletionWindow], at: DateTime<Utc>) -> Result<(), String> {
+    if is_open(windows, at) {
+        return Ok(());
+    }
+    Err(format!("the deletion window has closed, stopping before further deletions; the next window opens at {}",
+        next_open(windows, at).to_rfc3339()))
+}
+
+/// When deletions are next allowed: `at` itself if a window is open, otherwise the
+/// earliest upcoming opening.
+pub fn next_open(windows: &[DeletionWindow], at: DateTime<Utc>) -> DateTime<Utc> {
+    if is_open(windows, at) {
+        return at;
+    }
+    windows.iter().map(|window| window.next_opening(at)).min().unwrap_or(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: &[&str], start: &str, end: &str) -> DeletionWindow {
+        DeletionWindow::from_config(&WindowConfig {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+        }).unwrap()
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_weekday_window() {
+        let windows = vec![window(&["mon", "tue", "wed", "thu", "fri"], "02:00", "05:00")];
+
+        // 2024-01-01 is a Monday
+        assert!(is_open(&windows, at("2024-01-01T03:00:00Z")));
+        assert!(!is_open(&windows, at("2024-01-01T05:00:00Z")));
+        assert!(!is_open(&windows, at("2024-01-06T03:00:00Z")));
+    }
+
+    #[test]
+    fn test_window_wrapping_midnight() {
+        let windows = vec![window(&["fri"], "22:00", "02:00")];
+
+        assert!(is_open(&windows, at("2024-01-05T23:00:00Z")));
+        assert!(is_open(&windows, at("2024-01-06T01:00:00Z")));
+        assert!(!is_open(&windows, at("2024-01-07T01:00:00Z")));
+    }
+
+    #[test]
+    fn test_next_open() {
+        let windows = vec![window(&["mon", "tue", "wed", "thu", "fri"], "02:00", "05:00")];
+
+        assert_eq!(next_open(&windows, at("2024-01-01T06:00:00Z")), at("2024-01-02T02:00:00Z"));
+        assert_eq!(next_open(&windows, at("2024-01-05T06:00:00Z")), at("2024-01-08T02:00:00Z"));
+        assert_eq!(next_open(&windows, at("2024-01-01T03:00:00Z")), at("2024-01-01T03:00:00Z"));
+    }
+
+    #[test]
+    fn test_check() {
+        let windows = vec![window(&["mon"], "02:00", "05:00")];
+
+        assert!(check(&windows, at("2024-01-01T04:59:00Z")).is_ok());
+        let closed = check(&windows, at("2024-01-01T05:00:00Z")).unwrap_err();
+        assert!(closed.contains("closed"), "{}", closed);
+        assert!(closed.ends_with("2024-01-08T02:00:00+00:00"), "{}", closed);
+    }
+
+    #[test]
+    fn test_no_windows_is_always_open() {
+        assert!(is_open(&[], Utc::now()));
+    }
+
+    #[test]
+    fn test_invalid_window() {
+        let config = WindowConfig { days: vec!["someday".to_string()], start: "02:00".to_string(), end: "05:00".to_string() };
+        assert!(DeletionWindow::from_config(&config).is_err());
+    }
+}